@@ -18,6 +18,9 @@ use core::ops::Range;
 ///
 /// any: whether there are free bits remaining
 /// test: whether a specific bit is free
+///
+/// alloc_contiguous: allocate `size` contiguous free bits, aligned to `1 << align_log2`.
+/// They are freed by `insert`.
 pub trait BitAlloc: Default {
     const CAP: usize;
     fn alloc(&mut self) -> Option<usize>;
@@ -26,6 +29,24 @@ pub trait BitAlloc: Default {
     fn remove(&mut self, range: Range<usize>);
     fn any(&self) -> bool;
     fn test(&self, key: usize) -> bool;
+
+    fn alloc_contiguous(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        let align = 1 << align_log2;
+        let mut base = 0;
+        while base + size <= Self::CAP {
+            match (base..base + size).find(|&key| !self.test(key)) {
+                Some(key) => {
+                    // restart after the used bit
+                    base = (key + align) & !(align - 1);
+                }
+                None => {
+                    self.remove(base..base + size);
+                    return Some(base);
+                }
+            }
+        }
+        None
+    }
 }
 
 pub type BitAlloc256 = BitAllocCascade16<BitAlloc16>;
@@ -218,4 +239,19 @@ mod tests {
         }
         assert!(ba.alloc().is_none());
     }
+
+    #[test]
+    fn bitalloc_contiguous() {
+        let mut ba = BitAlloc4K::default();
+        ba.insert(0..4096);
+        ba.remove(100..101);
+        assert_eq!(ba.alloc_contiguous(200, 8), Some(256));
+        for i in 256..456 {
+            assert!(!ba.test(i));
+        }
+        assert_eq!(ba.alloc_contiguous(100, 0), Some(0));
+        assert_eq!(ba.alloc_contiguous(4096, 0), None);
+        ba.insert(256..456);
+        assert_eq!(ba.alloc_contiguous(512, 9), Some(512));
+    }
 }
@@ -5,6 +5,9 @@ pub type PhysAddr = usize;
 
 pub const PAGE_SIZE: usize = 1 << 12;
 
+/// Size of a huge page, i.e. a page mapped by a second level entry
+pub const HUGE_PAGE_SIZE: usize = 1 << 21;
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Page {
     number: usize,
@@ -17,6 +17,8 @@ pub use crate::addr::*;
 
 pub enum VMError {
     InvalidPtr,
+    InvalidArg,
+    NoMemory,
 }
 
 pub type VMResult<T> = Result<T, VMError>;
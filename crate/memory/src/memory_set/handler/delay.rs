@@ -44,6 +44,18 @@ impl<T: FrameAllocator> MemoryHandler for Delay<T> {
         entry.set_target(frame);
         entry.set_present(true);
         entry.update();
+        // anonymous memory must read as zero
+        pt.get_page_slice_mut(addr).iter_mut().for_each(|x| *x = 0);
+        true
+    }
+
+    fn discard(&self, pt: &mut PageTable, addr: VirtAddr) -> bool {
+        let entry = pt.get_entry(addr).expect("failed to get entry");
+        if entry.present() {
+            self.allocator.dealloc(entry.target());
+            entry.set_present(false);
+            entry.update();
+        }
         true
     }
 }
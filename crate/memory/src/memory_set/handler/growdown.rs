@@ -0,0 +1,51 @@
+use super::*;
+
+/// Lazily allocated stack, which is grown down by `MemorySet::grow_down`.
+///
+/// Pages above `eager_start` are allocated on map, so that the kernel can
+/// write there before the page table is active. Others are allocated on
+/// their first access.
+#[derive(Debug, Clone)]
+pub struct GrowDown<T: FrameAllocator> {
+    delay: Delay<T>,
+    eager_start: VirtAddr,
+}
+
+impl<T: FrameAllocator> MemoryHandler for GrowDown<T> {
+    fn box_clone(&self) -> Box<MemoryHandler> {
+        Box::new(self.clone())
+    }
+
+    fn map(&self, pt: &mut PageTable, addr: VirtAddr, attr: &MemoryAttr) {
+        if addr >= self.eager_start {
+            self.delay.map_eager(pt, addr, attr);
+        } else {
+            self.delay.map(pt, addr, attr);
+        }
+    }
+
+    fn map_eager(&self, pt: &mut PageTable, addr: VirtAddr, attr: &MemoryAttr) {
+        self.delay.map_eager(pt, addr, attr);
+    }
+
+    fn unmap(&self, pt: &mut PageTable, addr: VirtAddr) {
+        self.delay.unmap(pt, addr);
+    }
+
+    fn handle_page_fault(&self, pt: &mut PageTable, addr: VirtAddr) -> bool {
+        self.delay.handle_page_fault(pt, addr)
+    }
+
+    fn discard(&self, pt: &mut PageTable, addr: VirtAddr) -> bool {
+        self.delay.discard(pt, addr)
+    }
+}
+
+impl<T: FrameAllocator> GrowDown<T> {
+    pub fn new(allocator: T, eager_start: VirtAddr) -> Self {
+        GrowDown {
+            delay: Delay::new(allocator),
+            eager_start,
+        }
+    }
+}
@@ -0,0 +1,20 @@
+use super::*;
+
+/// Inaccessible gap, e.g. below a stack.
+/// Nothing is mapped, so any access is an unhandled page fault.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Guard;
+
+impl MemoryHandler for Guard {
+    fn box_clone(&self) -> Box<MemoryHandler> {
+        Box::new(self.clone())
+    }
+
+    fn map(&self, _pt: &mut PageTable, _addr: VirtAddr, _attr: &MemoryAttr) {}
+
+    fn unmap(&self, _pt: &mut PageTable, _addr: VirtAddr) {}
+
+    fn handle_page_fault(&self, _pt: &mut PageTable, _addr: VirtAddr) -> bool {
+        false
+    }
+}
@@ -0,0 +1,118 @@
+use super::*;
+
+/// Anonymous memory backed by huge frames of `HUGE_PAGE_SIZE`.
+/// The area must be aligned to `HUGE_PAGE_SIZE`,
+/// each huge page is mapped when its first page is mapped.
+/// Where the page table can't map huge pages, the frames are mapped as normal pages.
+/// Huge pages whose frames can't be allocated are left not present,
+/// and allocated again on access.
+/// The area can't be split inside a huge page.
+#[derive(Debug, Clone)]
+pub struct Huge<T: FrameAllocator> {
+    allocator: T,
+}
+
+impl<T: FrameAllocator> MemoryHandler for Huge<T> {
+    fn box_clone(&self) -> Box<MemoryHandler> {
+        Box::new(self.clone())
+    }
+
+    fn map(&self, pt: &mut PageTable, addr: VirtAddr, attr: &MemoryAttr) {
+        if addr % HUGE_PAGE_SIZE != 0 {
+            // mapped along with the head of its huge page
+            return;
+        }
+        match self.allocator.alloc_contiguous(HUGE_PAGE_SIZE) {
+            Some(target) => map_frames(pt, addr, target, attr),
+            None => {
+                for page_addr in (addr..addr + HUGE_PAGE_SIZE).step_by(PAGE_SIZE) {
+                    let entry = pt.map(page_addr, 0);
+                    entry.set_present(false);
+                    attr.apply(entry);
+                }
+            }
+        }
+    }
+
+    fn unmap(&self, pt: &mut PageTable, addr: VirtAddr) {
+        if addr % HUGE_PAGE_SIZE != 0 {
+            return;
+        }
+        let entry = pt.get_entry(addr).expect("fail to get entry");
+        let (target, huge, present) = (entry.target(), entry.is_huge(), entry.present());
+        // unmap the way it was mapped, before the frames are freed
+        if huge {
+            pt.unmap_huge(addr);
+        } else {
+            unmap_pages(pt, addr);
+        }
+        if present {
+            self.allocator.dealloc_contiguous(target, HUGE_PAGE_SIZE);
+        }
+    }
+
+    fn handle_page_fault(&self, pt: &mut PageTable, addr: VirtAddr) -> bool {
+        let addr = addr & !(HUGE_PAGE_SIZE - 1);
+        let entry = pt.get_entry(addr).expect("fail to get entry");
+        if entry.present() {
+            return false;
+        }
+        let attr = attr_of(entry);
+        let target = match self.allocator.alloc_contiguous(HUGE_PAGE_SIZE) {
+            Some(target) => target,
+            None => return false,
+        };
+        unmap_pages(pt, addr);
+        map_frames(pt, addr, target, &attr);
+        true
+    }
+
+    fn page_size(&self) -> usize {
+        HUGE_PAGE_SIZE
+    }
+}
+
+impl<T: FrameAllocator> Huge<T> {
+    pub fn new(allocator: T) -> Self {
+        Huge { allocator }
+    }
+}
+
+/// Map the huge page at `addr` to the contiguous frames at `target`, filled with zero
+fn map_frames(pt: &mut PageTable, addr: VirtAddr, target: PhysAddr, attr: &MemoryAttr) {
+    if pt.map_huge(addr, target).is_none() {
+        // fall back to normal pages on the contiguous frames
+        for offset in (0..HUGE_PAGE_SIZE).step_by(PAGE_SIZE) {
+            pt.map(addr + offset, target + offset);
+        }
+    }
+    // anonymous memory must read as zero
+    for page_addr in (addr..addr + HUGE_PAGE_SIZE).step_by(PAGE_SIZE) {
+        pt.get_page_slice_mut(page_addr)
+            .iter_mut()
+            .for_each(|x| *x = 0);
+        attr.apply(pt.get_entry(page_addr).expect("failed to get entry"));
+    }
+}
+
+/// Unmap the normal pages of the huge page at `addr`
+fn unmap_pages(pt: &mut PageTable, addr: VirtAddr) {
+    for page_addr in (addr..addr + HUGE_PAGE_SIZE).step_by(PAGE_SIZE) {
+        // PageTable::unmap requires page to be present
+        let entry = pt.get_entry(page_addr).expect("fail to get entry");
+        entry.set_present(true);
+        pt.unmap(page_addr);
+    }
+}
+
+/// Attributes of a user page from its entry
+fn attr_of(entry: &Entry) -> MemoryAttr {
+    let mut attr = MemoryAttr::default().user();
+    if !entry.writable() {
+        attr = attr.readonly();
+    }
+    if entry.execute() {
+        attr = attr.execute();
+    }
+    attr
+}
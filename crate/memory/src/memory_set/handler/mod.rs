@@ -21,6 +21,18 @@ pub trait MemoryHandler: Debug + 'static {
     /// Handle page fault on `addr`
     /// Return true if success, false if error
     fn handle_page_fault(&self, pt: &mut PageTable, addr: VirtAddr) -> bool;
+
+    /// Drop the frame of `addr`, so that it will be allocated again on next access
+    /// Return false if the page can not be dropped
+    fn discard(&self, _pt: &mut PageTable, _addr: VirtAddr) -> bool {
+        // override this when pages are allocated lazily
+        false
+    }
+
+    /// Size of the pages mapped at once, the area can only be split at their boundaries
+    fn page_size(&self) -> usize {
+        PAGE_SIZE
+    }
 }
 
 impl Clone for Box<MemoryHandler> {
@@ -32,13 +44,32 @@ impl Clone for Box<MemoryHandler> {
 pub trait FrameAllocator: Debug + Clone + 'static {
     fn alloc(&self) -> Option<PhysAddr>;
     fn dealloc(&self, target: PhysAddr);
+
+    /// Allocate `size` bytes of physically contiguous frames, aligned to `size`
+    /// `size` must be a power of two multiple of `PAGE_SIZE`
+    fn alloc_contiguous(&self, _size: usize) -> Option<PhysAddr> {
+        None
+    }
+
+    /// Free the frames allocated by `alloc_contiguous`
+    fn dealloc_contiguous(&self, target: PhysAddr, size: usize) {
+        for frame in (target..target + size).step_by(PAGE_SIZE) {
+            self.dealloc(frame);
+        }
+    }
 }
 
 mod byframe;
 mod delay;
+mod growdown;
+mod guard;
+mod huge;
 mod linear;
 //mod swap;
 
 pub use self::byframe::ByFrame;
 pub use self::delay::Delay;
+pub use self::growdown::GrowDown;
+pub use self::guard::Guard;
+pub use self::huge::Huge;
 pub use self::linear::Linear;
@@ -46,6 +46,22 @@ impl MemoryArea {
             self.end_addr - self.start_addr,
         )
     }
+    /// Get the start address of the memory area
+    pub fn start_addr(&self) -> VirtAddr {
+        self.start_addr
+    }
+    /// Get the end address of the memory area
+    pub fn end_addr(&self) -> VirtAddr {
+        self.end_addr
+    }
+    /// Get the name of the memory area
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+    /// Get the attribute of the memory area
+    pub fn attr(&self) -> MemoryAttr {
+        self.attr
+    }
     /*
      **  @brief  test whether a virtual address is in the memory area
      **  @param  addr: VirtAddr       the virtual address to test
@@ -166,6 +182,18 @@ impl MemoryAttr {
         self.mmio = value;
         self
     }
+    /// Whether the memory is accessible from user mode
+    pub fn is_user(&self) -> bool {
+        self.user
+    }
+    /// Whether the memory is readonly
+    pub fn is_readonly(&self) -> bool {
+        self.readonly
+    }
+    /// Whether the memory is executable
+    pub fn is_execute(&self) -> bool {
+        self.execute
+    }
     /// Apply the attributes to page table entry, then update it.
     /// NOTE: You may need to set present manually.
     pub fn apply(&self, entry: &mut Entry) {
@@ -182,6 +210,8 @@ impl MemoryAttr {
 pub struct MemorySet<T: InactivePageTable> {
     areas: Vec<MemoryArea>,
     page_table: T,
+    /// Range a grow-down area may grow in, see `reserve_grow_down`
+    grow_reserve: Option<(VirtAddr, VirtAddr)>,
 }
 
 impl<T: InactivePageTable> MemorySet<T> {
@@ -193,12 +223,14 @@ impl<T: InactivePageTable> MemorySet<T> {
         MemorySet {
             areas: Vec::new(),
             page_table: T::new(),
+            grow_reserve: None,
         }
     }
     pub fn new_bare() -> Self {
         MemorySet {
             areas: Vec::new(),
             page_table: T::new_bare(),
+            grow_reserve: None,
         }
     }
     /// Check the pointer is within the readable memory
@@ -215,7 +247,7 @@ impl<T: InactivePageTable> MemorySet<T> {
             .iter()
             .find(|area| area.check_read_array(ptr, count))
             .map(|_| ())
-            .ok_or(VMError::InvalidPtr)
+            .or_else(|_| self.check_grow_reserve(ptr as usize, unsafe { ptr.add(count) } as usize))
     }
     /// Check the array is within the writable memory
     pub fn check_write_array<S>(&self, ptr: *mut S, count: usize) -> VMResult<()> {
@@ -223,7 +255,21 @@ impl<T: InactivePageTable> MemorySet<T> {
             .iter()
             .find(|area| area.check_write_array(ptr, count))
             .map(|_| ())
-            .ok_or(VMError::InvalidPtr)
+            .or_else(|_| self.check_grow_reserve(ptr as usize, unsafe { ptr.add(count) } as usize))
+    }
+    /// Check [`start_addr`, `end_addr`) is within the range a grow-down area may grow in,
+    /// which becomes accessible once it is grown there on access.
+    fn check_grow_reserve(&self, start_addr: VirtAddr, end_addr: VirtAddr) -> VMResult<()> {
+        match self.grow_reserve {
+            Some((start, end)) if start_addr >= start && end_addr <= end => Ok(()),
+            _ => Err(VMError::InvalidPtr),
+        }
+    }
+    /// Set [`start_addr`, `end_addr`) as the range a grow-down area, like a stack,
+    /// may grow in. The checks of pointers accept it before the area is grown there,
+    /// the caller grows it on page faults.
+    pub fn reserve_grow_down(&mut self, start_addr: VirtAddr, end_addr: VirtAddr) {
+        self.grow_reserve = Some((start_addr, end_addr));
     }
     /// Check the null-end C string is within the readable memory, and is valid.
     /// If so, clone it to a String.
@@ -240,16 +286,30 @@ impl<T: InactivePageTable> MemorySet<T> {
     /// Return the start address of found free area.
     /// Used for mmap.
     pub fn find_free_area(&self, addr_hint: usize, len: usize) -> VirtAddr {
+        self.find_free_area_aligned(addr_hint, len, PAGE_SIZE)
+    }
+    /// Find a free area with hint address `addr_hint` and length `len`,
+    /// whose start address is aligned to `align`, which is a power of two
+    pub fn find_free_area_aligned(&self, addr_hint: usize, len: usize, align: usize) -> VirtAddr {
         // brute force:
         // try each area's end address as the start
         core::iter::once(addr_hint)
             .chain(self.areas.iter().map(|area| area.end_addr))
-            .map(|addr| (addr + PAGE_SIZE - 1) & !(PAGE_SIZE - 1)) // round up a page
+            .map(|addr| (addr + align - 1) & !(align - 1)) // round up to alignment
             .find(|&addr| self.test_free_area(addr, addr + len))
             .expect("failed to find free area ???")
     }
+    /// Test if the area containing `addr` can be split there,
+    /// i.e. `addr` is at the boundary of a page of its handler.
+    /// True if `addr` is not in any area.
+    pub fn can_split_at(&self, addr: VirtAddr) -> bool {
+        self.areas
+            .iter()
+            .find(|area| area.contains(addr))
+            .map_or(true, |area| addr % area.handler.page_size() == 0)
+    }
     /// Test if [`start_addr`, `end_addr`) is a free area
-    fn test_free_area(&self, start_addr: usize, end_addr: usize) -> bool {
+    pub fn test_free_area(&self, start_addr: usize, end_addr: usize) -> bool {
         self.areas
             .iter()
             .find(|area| area.is_overlap_with(start_addr, end_addr))
@@ -303,6 +363,7 @@ impl<T: InactivePageTable> MemorySet<T> {
 
     /*
      **  @brief  remove the memory area from the memory set and split existed ones when necessary
+     **          the caller should check `can_split_at` the boundaries first
      **  @param  area: MemoryArea     the memory area to remove
      **  @retval none
      */
@@ -391,6 +452,301 @@ impl<T: InactivePageTable> MemorySet<T> {
         }
     }
 
+    /// Grow or shrink the area starting at `start_addr`, so that it ends at `new_end_addr`.
+    /// New pages are mapped by the area's handler, dropped pages are unmapped.
+    /// The area is removed if `new_end_addr` equals `start_addr`.
+    /// Return false if there is no such area, or the grown part is not free.
+    pub fn resize(&mut self, start_addr: VirtAddr, new_end_addr: VirtAddr) -> bool {
+        assert!(start_addr <= new_end_addr, "invalid memory area");
+        let i = match self.areas.iter().position(|area| area.start_addr == start_addr) {
+            Some(i) => i,
+            None => return false,
+        };
+        let old_end = page_round_up(self.areas[i].end_addr);
+        let new_end = page_round_up(new_end_addr);
+        if new_end > old_end && !self.test_free_area(old_end, new_end) {
+            return false;
+        }
+        if new_end == start_addr {
+            let area = self.areas.remove(i);
+            self.page_table.edit(|pt| area.unmap(pt));
+            return true;
+        }
+        let area = &mut self.areas[i];
+        if new_end > old_end {
+            self.page_table.edit(|pt| {
+                for page in Page::range_of(old_end, new_end) {
+                    area.handler.map(pt, page.start_address(), &area.attr);
+                }
+            });
+        } else if new_end < old_end {
+            self.page_table.edit(|pt| {
+                for page in Page::range_of(new_end, old_end) {
+                    area.handler.unmap(pt, page.start_address());
+                }
+            });
+        }
+        area.end_addr = new_end_addr;
+        true
+    }
+
+    /// Grow the area starting at `start_addr` down to `new_start_addr`, like a stack.
+    /// `new_start_addr` should be page aligned. New pages are mapped by the area's handler.
+    /// Return false if there is no such area, or the grown part is not free.
+    pub fn grow_down(&mut self, start_addr: VirtAddr, new_start_addr: VirtAddr) -> bool {
+        let i = match self.areas.iter().position(|area| area.start_addr == start_addr) {
+            Some(i) => i,
+            None => return false,
+        };
+        if new_start_addr >= start_addr || !self.test_free_area(new_start_addr, start_addr) {
+            return false;
+        }
+        let area = &mut self.areas[i];
+        self.page_table.edit(|pt| {
+            for page in Page::range_of(new_start_addr, start_addr) {
+                area.handler.map(pt, page.start_address(), &area.attr);
+            }
+        });
+        area.start_addr = new_start_addr;
+        true
+    }
+
+    /// Change the attribute of [`start_addr`, `end_addr`), like `mprotect`.
+    /// Areas crossing the boundaries are split first.
+    /// Return false if some page in the range is not in any area,
+    /// or an area can't be split at the boundaries.
+    pub fn protect(&mut self, start_addr: VirtAddr, end_addr: VirtAddr, attr: MemoryAttr) -> bool {
+        let end_addr = page_round_up(end_addr);
+        if start_addr >= end_addr {
+            return true;
+        }
+        let covered = Page::range_of(start_addr, end_addr)
+            .all(|page| self.areas.iter().any(|area| area.contains(page.start_address())));
+        if !covered || !self.can_split_at(start_addr) || !self.can_split_at(end_addr) {
+            return false;
+        }
+        self.split_at(start_addr);
+        self.split_at(end_addr);
+        let Self {
+            ref mut page_table,
+            ref mut areas,
+        } = self;
+        page_table.edit(|pt| {
+            for area in areas
+                .iter_mut()
+                .filter(|area| area.start_addr >= start_addr && area.start_addr < end_addr)
+            {
+                area.attr = attr;
+                for page in Page::range_of(area.start_addr, area.end_addr) {
+                    if let Some(entry) = pt.get_entry(page.start_address()) {
+                        attr.apply(entry);
+                    }
+                }
+            }
+        });
+        true
+    }
+
+    /// Remap [`old_addr`, `old_addr + old_size`) to a range of `new_size` bytes, like `mremap`.
+    /// The range must be inside one area, and is split out from it first.
+    /// If `new_addr` is given, the pages are moved there, replacing any old mapping.
+    /// Otherwise they are resized in place, or moved to a free area if `may_move` is set.
+    /// The request is checked before anything is changed.
+    /// Return the start address of the new range, or the error:
+    /// `InvalidPtr` if the old range is not inside one area,
+    /// `InvalidArg` if the new range overlaps the old one, or the area has huge pages,
+    /// `NoMemory` if it can not be resized in place and `may_move` is not set.
+    pub fn remap(
+        &mut self,
+        old_addr: VirtAddr,
+        old_size: usize,
+        new_size: usize,
+        new_addr: Option<VirtAddr>,
+        may_move: bool,
+    ) -> VMResult<VirtAddr> {
+        let old_end = page_round_up(old_addr + old_size);
+        let area = self
+            .areas
+            .iter()
+            .find(|area| area.start_addr <= old_addr && old_end <= page_round_up(area.end_addr))
+            .ok_or(VMError::InvalidPtr)?;
+        // pages are moved one by one
+        if area.handler.page_size() != PAGE_SIZE {
+            return Err(VMError::InvalidArg);
+        }
+        match new_addr {
+            Some(new_addr) => {
+                if new_addr < old_end && old_addr < new_addr + new_size {
+                    return Err(VMError::InvalidArg);
+                }
+                self.split_at(old_addr);
+                self.split_at(old_end);
+                self.pop_with_split(new_addr, new_addr + new_size);
+                self.move_area(old_addr, new_addr);
+                self.resize(new_addr, new_addr + new_size);
+                Ok(new_addr)
+            }
+            None => {
+                let new_end = page_round_up(old_addr + new_size);
+                let in_place = new_end <= old_end || self.test_free_area(old_end, new_end);
+                if !in_place && !may_move {
+                    return Err(VMError::NoMemory);
+                }
+                self.split_at(old_addr);
+                self.split_at(old_end);
+                if in_place {
+                    self.resize(old_addr, old_addr + new_size);
+                    return Ok(old_addr);
+                }
+                let new_addr = self.find_free_area(old_addr, new_size);
+                self.move_area(old_addr, new_addr);
+                self.resize(new_addr, new_addr + new_size);
+                Ok(new_addr)
+            }
+        }
+    }
+
+    /// Split the area containing `addr` into two at `addr`, which should be page aligned.
+    /// Nothing happens if `addr` is already the start of an area, or not in any area.
+    fn split_at(&mut self, addr: VirtAddr) {
+        let i = self
+            .areas
+            .iter()
+            .position(|area| area.contains(addr) && area.start_addr != addr);
+        if let Some(i) = i {
+            let area = &mut self.areas[i];
+            let right = MemoryArea {
+                start_addr: addr,
+                end_addr: area.end_addr,
+                attr: area.attr,
+                handler: area.handler.box_clone(),
+                name: area.name,
+            };
+            area.end_addr = addr;
+            self.areas.insert(i + 1, right);
+        }
+    }
+
+    /// Move the area starting at `start_addr` to `new_start_addr`,
+    /// keeping the frames (or the absence of them) behind each page.
+    /// The target range must be free.
+    fn move_area(&mut self, start_addr: VirtAddr, new_start_addr: VirtAddr) {
+        let area = self
+            .areas
+            .iter_mut()
+            .find(|area| area.start_addr == start_addr)
+            .expect("no memory area found");
+        let len = area.end_addr - area.start_addr;
+        let attr = area.attr;
+        self.page_table.edit(|pt| {
+            for page in Page::range_of(start_addr, start_addr + len) {
+                let addr = page.start_address();
+                let (target, present) = match pt.get_entry(addr) {
+                    Some(entry) => (entry.target(), entry.present()),
+                    None => continue,
+                };
+                let entry = pt.map(addr - start_addr + new_start_addr, target);
+                entry.set_present(present);
+                attr.apply(entry);
+                // PageTable::unmap requires page to be present
+                let entry = pt.get_entry(addr).expect("failed to get entry");
+                entry.set_present(true);
+                pt.unmap(addr);
+            }
+        });
+        area.start_addr = new_start_addr;
+        area.end_addr = new_start_addr + len;
+    }
+
+    /// Drop the frames behind [`start_addr`, `end_addr`), like `MADV_DONTNEED`.
+    /// Pages of lazily allocated areas are filled with zero on next access,
+    /// others are left untouched.
+    pub fn discard(&mut self, start_addr: VirtAddr, end_addr: VirtAddr) {
+        let Self {
+            ref mut page_table,
+            ref areas,
+        } = self;
+        page_table.edit(|pt| {
+            for area in areas.iter() {
+                for page in Page::range_of(start_addr, end_addr) {
+                    let addr = page.start_address();
+                    if area.contains(addr) {
+                        area.handler.discard(pt, addr);
+                    }
+                }
+            }
+        });
+    }
+
+    /// Allocate frames for [`start_addr`, `end_addr`) ahead of access, like `MADV_WILLNEED`.
+    pub fn prefault(&mut self, start_addr: VirtAddr, end_addr: VirtAddr) {
+        for page in Page::range_of(start_addr, end_addr) {
+            let addr = page.start_address();
+            let present = self
+                .page_table
+                .edit(|pt| pt.get_entry(addr).map(|entry| entry.present()));
+            if present == Some(false) {
+                self.handle_page_fault(addr);
+            }
+        }
+    }
+
+    /// Test whether each page in [`start_addr`, `end_addr`) is resident in memory, like `mincore`.
+    /// Return `None` if some page is not in any area.
+    pub fn resident_pages(&mut self, start_addr: VirtAddr, end_addr: VirtAddr) -> Option<Vec<bool>> {
+        let Self {
+            ref mut page_table,
+            ref areas,
+        } = self;
+        page_table.edit(|pt| {
+            Page::range_of(start_addr, end_addr)
+                .map(|page| {
+                    let addr = page.start_address();
+                    areas.iter().find(|area| area.contains(addr))?;
+                    Some(pt.get_entry(addr).map_or(false, |entry| entry.present()))
+                })
+                .collect()
+        })
+    }
+
+    /// Clone the areas with a new page table, for fork.
+    /// Unlike `clone`, the pages not allocated yet stay so in the new one.
+    /// The content of the others is left to the caller to copy.
+    pub fn clone_lazy(&mut self) -> Self {
+        let Self {
+            ref mut page_table,
+            ref areas,
+        } = self;
+        let resident: Vec<bool> = page_table.edit(|pt| {
+            areas
+                .iter()
+                .flat_map(|area| Page::range_of(area.start_addr, area.end_addr))
+                .map(|page| {
+                    let entry = pt.get_entry(page.start_address());
+                    entry.map_or(false, |entry| entry.present())
+                })
+                .collect()
+        });
+        let mut page_table = T::new();
+        page_table.edit(|pt| {
+            let mut resident = resident.into_iter();
+            for area in self.areas.iter() {
+                for page in Page::range_of(area.start_addr, area.end_addr) {
+                    let addr = page.start_address();
+                    match resident.next().unwrap() {
+                        true => area.handler.map_eager(pt, addr, &area.attr),
+                        false => area.handler.map(pt, addr, &area.attr),
+                    }
+                }
+            }
+        });
+        MemorySet {
+            areas: self.areas.clone(),
+            page_table,
+            grow_reserve: self.grow_reserve,
+        }
+    }
+
     /*
      **  @brief  get iterator of the memory area
      **  @retval impl Iterator<Item=&MemoryArea>
@@ -486,6 +842,7 @@ impl<T: InactivePageTable> Clone for MemorySet<T> {
         MemorySet {
             areas: self.areas.clone(),
             page_table,
+            grow_reserve: self.grow_reserve,
         }
     }
 }
@@ -501,3 +858,307 @@ impl<T: InactivePageTable> Debug for MemorySet<T> {
         f.debug_list().entries(self.areas.iter()).finish()
     }
 }
+
+/// Round up `addr` to the start of next page
+fn page_round_up(addr: VirtAddr) -> VirtAddr {
+    (addr + PAGE_SIZE - 1) & !(PAGE_SIZE - 1)
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::handler::*;
+    use super::*;
+    use alloc::sync::Arc;
+    use std::sync::Mutex;
+
+    /// Frames of the mock page table, shared by the clones
+    #[derive(Debug, Clone)]
+    pub struct MockFrameAllocator(Arc<Mutex<Vec<PhysAddr>>>);
+
+    impl MockFrameAllocator {
+        pub fn new() -> Self {
+            let frames = (0..16).rev().map(|i| i * PAGE_SIZE).collect();
+            MockFrameAllocator(Arc::new(Mutex::new(frames)))
+        }
+        pub fn free_count(&self) -> usize {
+            self.0.lock().unwrap().len()
+        }
+    }
+
+    impl FrameAllocator for MockFrameAllocator {
+        fn alloc(&self) -> Option<PhysAddr> {
+            self.0.lock().unwrap().pop()
+        }
+        fn dealloc(&self, target: PhysAddr) {
+            self.0.lock().unwrap().push(target);
+        }
+    }
+
+    type MockMemorySet = MemorySet<MockInactivePageTable>;
+
+    fn ranges(ms: &MockMemorySet) -> Vec<(VirtAddr, VirtAddr)> {
+        let mut ranges: Vec<_> = ms.iter().map(|a| (a.start_addr, a.end_addr)).collect();
+        ranges.sort();
+        ranges
+    }
+
+    fn read(ms: &mut MockMemorySet, addr: VirtAddr) -> u8 {
+        let mut data = 0;
+        ms.edit(|pt| data = pt.read(addr));
+        data
+    }
+
+    fn write(ms: &mut MockMemorySet, addr: VirtAddr, data: u8) {
+        ms.edit(|pt| pt.write(addr, data));
+    }
+
+    #[test]
+    fn split_at() {
+        let allocator = MockFrameAllocator::new();
+        let mut ms = MockMemorySet::new();
+        let attr = MemoryAttr::default().user();
+        ms.push(0x1000, 0x4000, attr, Delay::new(allocator), "test");
+        ms.split_at(0x2000);
+        assert_eq!(ranges(&ms), vec![(0x1000, 0x2000), (0x2000, 0x4000)]);
+        // at the start of an area, or outside of any
+        ms.split_at(0x2000);
+        ms.split_at(0x8000);
+        assert_eq!(ranges(&ms), vec![(0x1000, 0x2000), (0x2000, 0x4000)]);
+    }
+
+    #[test]
+    fn move_area() {
+        let allocator = MockFrameAllocator::new();
+        let mut ms = MockMemorySet::new();
+        let attr = MemoryAttr::default().user();
+        ms.push(
+            0x1000,
+            0x3000,
+            attr,
+            ByFrame::new(allocator.clone()),
+            "test",
+        );
+        write(&mut ms, 0x1000, 1);
+        write(&mut ms, 0x2fff, 2);
+        ms.move_area(0x1000, 0x8000);
+        assert_eq!(ranges(&ms), vec![(0x8000, 0xa000)]);
+        assert_eq!(read(&mut ms, 0x8000), 1);
+        assert_eq!(read(&mut ms, 0x9fff), 2);
+        let mut present = true;
+        ms.edit(|pt| present = pt.get_entry(0x1000).unwrap().present());
+        assert!(!present);
+        // the frames move with the pages
+        assert_eq!(allocator.free_count(), 14);
+    }
+
+    #[test]
+    fn remap_in_place() {
+        let allocator = MockFrameAllocator::new();
+        let mut ms = MockMemorySet::new();
+        let attr = MemoryAttr::default().user();
+        ms.push(0x1000, 0x3000, attr, Delay::new(allocator), "test");
+        assert_eq!(
+            ms.remap(0x1000, 0x2000, 0x4000, None, false).ok(),
+            Some(0x1000)
+        );
+        assert_eq!(ranges(&ms), vec![(0x1000, 0x5000)]);
+        assert_eq!(
+            ms.remap(0x1000, 0x4000, 0x1000, None, false).ok(),
+            Some(0x1000)
+        );
+        assert_eq!(ranges(&ms), vec![(0x1000, 0x2000)]);
+    }
+
+    #[test]
+    fn remap_move() {
+        let allocator = MockFrameAllocator::new();
+        let mut ms = MockMemorySet::new();
+        let attr = MemoryAttr::default().user();
+        ms.push(0x1000, 0x3000, attr, Delay::new(allocator.clone()), "test");
+        ms.push(0x3000, 0x4000, attr, Delay::new(allocator), "next");
+        ms.prefault(0x1000, 0x3000);
+        write(&mut ms, 0x1000, 3);
+        // blocked by the next area
+        match ms.remap(0x1000, 0x2000, 0x3000, None, false) {
+            Err(VMError::NoMemory) => {}
+            _ => panic!("remap should fail without MAYMOVE"),
+        }
+        assert_eq!(ranges(&ms), vec![(0x1000, 0x3000), (0x3000, 0x4000)]);
+        let new_addr = ms.remap(0x1000, 0x2000, 0x3000, None, true).ok().unwrap();
+        assert_eq!(new_addr, 0x4000);
+        assert_eq!(ranges(&ms), vec![(0x3000, 0x4000), (0x4000, 0x7000)]);
+        assert_eq!(read(&mut ms, 0x4000), 3);
+    }
+
+    #[test]
+    fn remap_fixed() {
+        let allocator = MockFrameAllocator::new();
+        let mut ms = MockMemorySet::new();
+        let attr = MemoryAttr::default().user();
+        ms.push(0x1000, 0x3000, attr, Delay::new(allocator.clone()), "test");
+        ms.push(0x8000, 0xa000, attr, Delay::new(allocator), "old");
+        ms.prefault(0x1000, 0x2000);
+        write(&mut ms, 0x1000, 4);
+        // the overlap fails without changing anything
+        match ms.remap(0x1000, 0x2000, 0x2000, Some(0x2000), true) {
+            Err(VMError::InvalidArg) => {}
+            _ => panic!("remap should fail on overlap"),
+        }
+        assert_eq!(ranges(&ms), vec![(0x1000, 0x3000), (0x8000, 0xa000)]);
+        // only a part is moved, replacing the old mapping
+        assert_eq!(
+            ms.remap(0x1000, 0x1000, 0x1000, Some(0x9000), true).ok(),
+            Some(0x9000)
+        );
+        assert_eq!(
+            ranges(&ms),
+            vec![(0x2000, 0x3000), (0x8000, 0x9000), (0x9000, 0xa000)]
+        );
+        assert_eq!(read(&mut ms, 0x9000), 4);
+    }
+
+    #[test]
+    fn remap_unmapped() {
+        let allocator = MockFrameAllocator::new();
+        let mut ms = MockMemorySet::new();
+        let attr = MemoryAttr::default().user();
+        ms.push(0x1000, 0x3000, attr, Delay::new(allocator), "test");
+        match ms.remap(0x2000, 0x2000, 0x1000, None, true) {
+            Err(VMError::InvalidPtr) => {}
+            _ => panic!("remap should fail across the end of the area"),
+        }
+        assert_eq!(ranges(&ms), vec![(0x1000, 0x3000)]);
+    }
+
+    #[test]
+    fn prefault_and_resident_pages() {
+        let allocator = MockFrameAllocator::new();
+        let mut ms = MockMemorySet::new();
+        let attr = MemoryAttr::default().user();
+        ms.push(0x1000, 0x4000, attr, Delay::new(allocator.clone()), "test");
+        assert_eq!(ms.resident_pages(0x1000, 0x4000), Some(vec![false; 3]));
+        ms.prefault(0x2000, 0x4000);
+        assert_eq!(
+            ms.resident_pages(0x1000, 0x4000),
+            Some(vec![false, true, true])
+        );
+        assert_eq!(allocator.free_count(), 14);
+        assert_eq!(read(&mut ms, 0x2000), 0);
+        // a page not in any area
+        assert_eq!(ms.resident_pages(0x3000, 0x5000), None);
+    }
+
+    #[test]
+    fn discard() {
+        let allocator = MockFrameAllocator::new();
+        let mut ms = MockMemorySet::new();
+        let attr = MemoryAttr::default().user();
+        ms.push(0x1000, 0x3000, attr, Delay::new(allocator.clone()), "test");
+        ms.prefault(0x1000, 0x3000);
+        write(&mut ms, 0x1000, 5);
+        ms.discard(0x1000, 0x2000);
+        assert_eq!(ms.resident_pages(0x1000, 0x3000), Some(vec![false, true]));
+        assert_eq!(allocator.free_count(), 15);
+        // filled with zero on next access
+        assert!(ms.handle_page_fault(0x1000));
+        assert_eq!(read(&mut ms, 0x1000), 0);
+    }
+
+    #[test]
+    fn grow_down() {
+        let allocator = MockFrameAllocator::new();
+        let mut ms = MockMemorySet::new();
+        let attr = MemoryAttr::default().user();
+        ms.push(0x1000, 0x2000, attr, Delay::new(allocator.clone()), "below");
+        ms.push(
+            0x4000,
+            0x6000,
+            attr,
+            GrowDown::new(allocator, 0x5000),
+            "stack",
+        );
+        assert!(ms.grow_down(0x4000, 0x3000));
+        assert_eq!(ranges(&ms), vec![(0x1000, 0x2000), (0x3000, 0x6000)]);
+        // the grown pages are lazy
+        assert_eq!(
+            ms.resident_pages(0x3000, 0x6000),
+            Some(vec![false, false, true])
+        );
+        assert!(!ms.grow_down(0x3000, 0x1000));
+        assert!(!ms.grow_down(0x8000, 0x7000));
+        assert_eq!(ranges(&ms), vec![(0x1000, 0x2000), (0x3000, 0x6000)]);
+    }
+
+    #[test]
+    fn check_grow_reserve() {
+        let allocator = MockFrameAllocator::new();
+        let mut ms = MockMemorySet::new();
+        let attr = MemoryAttr::default().user();
+        ms.push(
+            0x4000,
+            0x6000,
+            attr,
+            GrowDown::new(allocator, 0x5000),
+            "stack",
+        );
+        assert!(ms.check_write_array(0x3ff0 as *mut u8, 0x20).is_err());
+        ms.reserve_grow_down(0x2000, 0x6000);
+        assert!(ms.check_write_array(0x3ff0 as *mut u8, 0x20).is_ok());
+        assert!(ms.check_read_array(0x2000 as *const u8, 0x4000).is_ok());
+        assert!(ms.check_read_array(0x1ff0 as *const u8, 0x20).is_err());
+        assert!(ms.check_read_array(0x5ff0 as *const u8, 0x20).is_err());
+    }
+
+    #[test]
+    fn clone_lazy() {
+        let allocator = MockFrameAllocator::new();
+        let mut ms = MockMemorySet::new();
+        let attr = MemoryAttr::default().user();
+        ms.push(0x1000, 0x4000, attr, Delay::new(allocator.clone()), "test");
+        ms.prefault(0x2000, 0x3000);
+        let mut clone = ms.clone_lazy();
+        assert_eq!(ranges(&clone), vec![(0x1000, 0x4000)]);
+        assert_eq!(
+            clone.resident_pages(0x1000, 0x4000),
+            Some(vec![false, true, false])
+        );
+        assert_eq!(allocator.free_count(), 14);
+    }
+
+    /// Pages of `.0` bytes mapping nothing, like huge pages
+    #[derive(Debug, Clone)]
+    struct BigPages(usize);
+
+    impl MemoryHandler for BigPages {
+        fn box_clone(&self) -> Box<MemoryHandler> {
+            Box::new(self.clone())
+        }
+        fn map(&self, _pt: &mut PageTable, _addr: VirtAddr, _attr: &MemoryAttr) {}
+        fn unmap(&self, _pt: &mut PageTable, _addr: VirtAddr) {}
+        fn handle_page_fault(&self, _pt: &mut PageTable, _addr: VirtAddr) -> bool {
+            false
+        }
+        fn page_size(&self) -> usize {
+            self.0
+        }
+    }
+
+    #[test]
+    fn big_pages_are_not_split() {
+        let mut ms = MockMemorySet::new();
+        let attr = MemoryAttr::default().user();
+        ms.push(0x2000, 0x6000, attr, BigPages(0x2000), "big");
+        assert!(!ms.can_split_at(0x3000));
+        assert!(ms.can_split_at(0x4000));
+        assert!(ms.can_split_at(0x8000));
+        assert!(!ms.protect(0x3000, 0x4000, attr.readonly()));
+        assert_eq!(ranges(&ms), vec![(0x2000, 0x6000)]);
+        assert!(ms.protect(0x4000, 0x6000, attr.readonly()));
+        assert_eq!(ranges(&ms), vec![(0x2000, 0x4000), (0x4000, 0x6000)]);
+        match ms.remap(0x2000, 0x2000, 0x4000, None, true) {
+            Err(VMError::InvalidArg) => {}
+            _ => panic!("remap should fail on big pages"),
+        }
+        assert_eq!(ranges(&ms), vec![(0x2000, 0x4000), (0x4000, 0x6000)]);
+    }
+}
@@ -27,6 +27,9 @@ pub struct MockEntry {
     writable_shared: bool,
     readonly_shared: bool,
     swapped: bool,
+    user: bool,
+    execute: bool,
+    mmio: u8,
 }
 
 impl Entry for MockEntry {
@@ -82,22 +85,22 @@ impl Entry for MockEntry {
         self.swapped = value;
     }
     fn user(&self) -> bool {
-        unimplemented!()
+        self.user
     }
-    fn set_user(&mut self, _value: bool) {
-        unimplemented!()
+    fn set_user(&mut self, value: bool) {
+        self.user = value;
     }
     fn execute(&self) -> bool {
-        unimplemented!()
+        self.execute
     }
-    fn set_execute(&mut self, _value: bool) {
-        unimplemented!()
+    fn set_execute(&mut self, value: bool) {
+        self.execute = value;
     }
     fn mmio(&self) -> u8 {
-        unimplemented!()
+        self.mmio
     }
-    fn set_mmio(&mut self, _value: u8) {
-        unimplemented!()
+    fn set_mmio(&mut self, value: u8) {
+        self.mmio = value;
     }
 }
 
@@ -138,6 +141,29 @@ impl PageTable for MockPageTable {
     }
 }
 
+/// A mock inactive page table, which is always editable
+pub struct MockInactivePageTable(Box<MockPageTable>);
+
+impl InactivePageTable for MockInactivePageTable {
+    type Active = MockPageTable;
+
+    fn new_bare() -> Self {
+        MockInactivePageTable(Box::new(MockPageTable::new()))
+    }
+    fn map_kernel(&mut self) {}
+    fn token(&self) -> usize {
+        0
+    }
+    unsafe fn set_token(_token: usize) {}
+    fn active_token() -> usize {
+        0
+    }
+    fn flush_tlb() {}
+    fn edit<T>(&mut self, f: impl FnOnce(&mut Self::Active) -> T) -> T {
+        f(&mut self.0)
+    }
+}
+
 impl MockPageTable {
     /*
      **  @brief  create a new MockPageTable
@@ -4,7 +4,7 @@
 
 pub use self::ext::*;
 #[cfg(test)]
-pub use self::mock_page_table::MockPageTable;
+pub use self::mock_page_table::{MockInactivePageTable, MockPageTable};
 use super::*;
 
 mod ext;
@@ -23,8 +23,25 @@ pub trait PageTable {
 
     /// Get the page table entry of a page of virual address `addr`
     /// If its page do not exist, return `None`
+    /// If it is inside a huge page, return the entry of the huge page
     fn get_entry(&mut self, addr: VirtAddr) -> Option<&mut Entry>;
 
+    /// Map a huge page of virtual address `addr` to the frame of physics address `target`
+    /// Both of them must be aligned to `HUGE_PAGE_SIZE`
+    /// Return the page table entry of the huge page,
+    /// or `None` if huge page is not supported, then nothing is mapped
+    fn map_huge(&mut self, _addr: VirtAddr, _target: PhysAddr) -> Option<&mut Entry> {
+        None
+    }
+
+    /// Unmap a huge page of virtual address `addr`
+    /// When huge page is not supported, unmap the pages inside it one by one
+    fn unmap_huge(&mut self, addr: VirtAddr) {
+        for page_addr in (addr..addr + HUGE_PAGE_SIZE).step_by(PAGE_SIZE) {
+            self.unmap(page_addr);
+        }
+    }
+
     /// Get a mutable reference of the content of a page of virtual address `addr`
     /// Used for testing with mock
     fn get_page_slice_mut<'a>(&mut self, addr: VirtAddr) -> &'a mut [u8] {
@@ -97,6 +114,11 @@ pub trait Entry {
     fn set_execute(&mut self, value: bool);
     fn mmio(&self) -> u8;
     fn set_mmio(&mut self, value: u8);
+
+    /// Whether it maps a huge page, see `PageTable::map_huge`
+    fn is_huge(&self) -> bool {
+        false
+    }
 }
 
 /// An inactive page table
@@ -17,3 +17,4 @@ mod timer;
 
 pub use crate::processor::Processor;
 pub use crate::thread_pool::*;
+pub use crate::timer::Timer;
@@ -40,10 +40,10 @@ pub fn sleep(dur: Duration) {
     trace!("sleep: {:?} ticks", time);
     processor().manager().sleep(current().id(), time);
     park();
+}
 
-    fn dur_to_ticks(dur: Duration) -> usize {
-        return dur.as_secs() as usize * 100 + dur.subsec_nanos() as usize / 10_000_000;
-    }
+fn dur_to_ticks(dur: Duration) -> usize {
+    return dur.as_secs() as usize * 100 + dur.subsec_nanos() as usize / 10_000_000;
 }
 
 /// Spawns a new thread, returning a JoinHandle for it.
@@ -123,6 +123,18 @@ pub fn park_action(f: impl FnOnce()) {
     processor().yield_now();
 }
 
+/// Like `park_action`, but the thread is also woken up after `dur`.
+pub fn park_timeout_action(dur: Duration, f: impl FnOnce()) {
+    trace!("park_timeout: {:?}", dur);
+    let tid = current().id();
+    // at least one tick, as 0 means forever
+    let time = dur_to_ticks(dur).max(1);
+    processor().manager().sleep(tid, time);
+    f();
+    processor().yield_now();
+    processor().manager().cancel_sleep(tid);
+}
+
 /// A handle to a thread.
 pub struct Thread {
     tid: usize,
@@ -188,6 +188,11 @@ impl ThreadPool {
         }
     }
 
+    /// Stop the timer started by `sleep` for `tid`, if it has not fired yet
+    pub fn cancel_sleep(&self, tid: Tid) {
+        self.timer.lock().stop(Event::Wakeup(tid));
+    }
+
     pub fn wakeup(&self, tid: Tid) {
         let mut proc_lock = self.threads[tid].lock();
         if let Some(mut proc) = proc_lock.as_mut() {
@@ -2,7 +2,7 @@
 
 use super::paging::MMIOType;
 use crate::consts::{KERNEL_OFFSET, MEMORY_OFFSET};
-use crate::memory::{init_heap, Linear, MemoryAttr, MemorySet, FRAME_ALLOCATOR};
+use crate::memory::{init_heap, Linear, MemoryAttr, MemorySet, FRAME_ALLOCATOR, TOTAL_FRAMES};
 use aarch64::regs::*;
 use log::*;
 use rcore_memory::PAGE_SIZE;
@@ -18,13 +18,16 @@ pub fn init() {
 fn init_frame_allocator() {
     use bit_allocator::BitAlloc;
     use core::ops::Range;
+    use core::sync::atomic::Ordering;
 
     let end = super::board::probe_memory()
         .expect("failed to find memory map")
         .1;
     let start = (_end as u64 + PAGE_SIZE as u64).wrapping_sub(KERNEL_OFFSET as u64) as usize;
     let mut ba = FRAME_ALLOCATOR.lock();
-    ba.insert(to_range(start, end));
+    let range = to_range(start, end);
+    TOTAL_FRAMES.fetch_add(range.len(), Ordering::Relaxed);
+    ba.insert(range);
     info!("FrameAllocator init end");
 
     /// Transform memory area `[start, end)` to integer range for `FrameAllocator`
@@ -28,6 +28,7 @@ pub extern "C" fn rust_main() -> ! {
     driver::init();
     println!("{}", LOGO);
 
+    crate::fs::init();
     crate::process::init();
 
     crate::kmain();
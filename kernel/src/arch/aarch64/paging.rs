@@ -44,6 +44,11 @@ impl PageTable for ActivePageTable {
             ((vaddr >> 9) & 0o777_777_777_7770) | (RECURSIVE_INDEX << 39) | (vaddr & KERNEL_OFFSET);
         Some(unsafe { &mut *(entry_addr as *mut PageEntry) })
     }
+
+    fn map_huge(&mut self, _addr: usize, _target: usize) -> Option<&mut Entry> {
+        // not supported yet, `Huge` maps normal pages on the contiguous frames instead
+        None
+    }
 }
 
 impl PageTableExt for ActivePageTable {
@@ -1,3 +1,5 @@
+/// The free running timer, whose low bits vary with the timing of events.
+/// Not random by itself.
 pub fn rand() -> u64 {
-    return 0;
+    super::board::timer::get_cycle()
 }
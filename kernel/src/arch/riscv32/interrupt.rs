@@ -99,6 +99,7 @@ fn try_process_serial() -> bool {
 }
 
 fn try_process_drivers() -> bool {
+    crate::fs::add_entropy(0);
     for driver in DRIVERS.read().iter() {
         if driver.try_handle_interrupt(None) == true {
             return true;
@@ -47,6 +47,7 @@ pub extern "C" fn rust_main(hartid: usize, dtb: usize, hart_mask: usize) -> ! {
     unsafe {
         board::init_external_interrupt();
     }
+    crate::fs::init();
     crate::process::init();
 
     unsafe {
@@ -54,6 +54,11 @@ impl PageTable for ActivePageTable {
             None
         }
     }
+
+    fn map_huge(&mut self, _addr: usize, _target: usize) -> Option<&mut Entry> {
+        // not supported yet, `Huge` maps normal pages on the contiguous frames instead
+        None
+    }
 }
 
 impl PageTableExt for ActivePageTable {}
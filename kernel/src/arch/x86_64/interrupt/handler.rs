@@ -96,6 +96,7 @@ pub extern "C" fn rust_trap(tf: &mut TrapFrame) {
                 COM2 => com2(),
                 IDE => ide(),
                 _ => {
+                    crate::fs::add_entropy(irq as u64);
                     for driver in DRIVERS.read().iter() {
                         if driver.try_handle_interrupt(Some(irq.into())) == true {
                             debug!("driver processed interrupt");
@@ -2,10 +2,13 @@ use crate::consts::KERNEL_OFFSET;
 use bit_allocator::BitAlloc;
 // Depends on kernel
 use super::{BootInfo, MemoryRegionType};
-use crate::memory::{active_table, init_heap, FRAME_ALLOCATOR, alloc_frame};
+use crate::memory::{
+    active_table, init_heap, FrameAllocator, GlobalFrameAlloc, FRAME_ALLOCATOR, TOTAL_FRAMES,
+};
 use crate::HEAP_ALLOCATOR;
-use rcore_memory::PAGE_SIZE;
+use rcore_memory::{HUGE_PAGE_SIZE, PAGE_SIZE};
 use alloc::vec::Vec;
+use core::sync::atomic::Ordering;
 use log::*;
 use once::*;
 use rcore_memory::paging::*;
@@ -24,9 +27,10 @@ fn init_frame_allocator(boot_info: &BootInfo) {
     let mut ba = FRAME_ALLOCATOR.lock();
     for region in boot_info.memory_map.iter() {
         if region.region_type == MemoryRegionType::Usable {
-            ba.insert(
-                region.range.start_frame_number as usize..region.range.end_frame_number as usize,
-            );
+            let range =
+                region.range.start_frame_number as usize..region.range.end_frame_number as usize;
+            TOTAL_FRAMES.fetch_add(range.len(), Ordering::Relaxed);
+            ba.insert(range);
         }
     }
 }
@@ -43,26 +47,24 @@ fn init_device_vm_map() {
         .update();
 }
 
+/// Map 64 MiB of huge frames linearly at `KERNEL_OFFSET + 0xe0000000` for the heap
 fn enlarge_heap() {
     let mut page_table = active_table();
-    let mut addrs = Vec::new();
+    let mut addrs: Vec<(usize, usize)> = Vec::new();
     let va_offset = KERNEL_OFFSET + 0xe0000000;
-    for i in 0..16384 {
-        let page = alloc_frame().unwrap();
-        let va = KERNEL_OFFSET + 0xe0000000 + page;
+    for _ in 0..(16384 * PAGE_SIZE / HUGE_PAGE_SIZE) {
+        let page = GlobalFrameAlloc.alloc_contiguous(HUGE_PAGE_SIZE).unwrap();
+        let va = va_offset + page;
+        page_table.map_huge(va, page).unwrap().update();
         if let Some((ref mut addr, ref mut len)) = addrs.last_mut() {
-            if *addr - PAGE_SIZE == va {
-                *len += PAGE_SIZE;
-                *addr -= PAGE_SIZE;
+            if *addr + *len == va {
+                *len += HUGE_PAGE_SIZE;
                 continue;
             }
         }
-        addrs.push((va, PAGE_SIZE));
+        addrs.push((va, HUGE_PAGE_SIZE));
     }
     for (addr, len) in addrs.into_iter() {
-        for va in (addr..(addr+len)).step_by(PAGE_SIZE) {
-            page_table.map(va, va - va_offset).update();
-        }
         info!("Adding {:#X} {:#X} to heap", addr, len);
         unsafe {
             HEAP_ALLOCATOR
@@ -48,6 +48,7 @@ pub extern "C" fn _start(boot_info: &'static BootInfo) -> ! {
 
     crate::drivers::init();
 
+    crate::fs::init();
     crate::process::init();
 
     AP_CAN_INIT.store(true, Ordering::Relaxed);
@@ -8,7 +8,7 @@ use x86_64::registers::control::{Cr3, Cr3Flags};
 use x86_64::structures::paging::{
     frame::PhysFrame as Frame,
     mapper::{Mapper, RecursivePageTable},
-    page::{Page, PageRange, Size4KiB},
+    page::{Page, PageRange, Size2MiB, Size4KiB},
     page_table::{PageTable as x86PageTable, PageTableEntry, PageTableFlags as EF},
     FrameAllocator, FrameDeallocator,
 };
@@ -72,9 +72,33 @@ impl PageTable for ActivePageTable {
             if unsafe { !(*entry).present() } {
                 return None;
             }
+            if level == 2 && unsafe { (*entry).is_huge() } {
+                return unsafe { Some(&mut *entry) };
+            }
         }
         unsafe { Some(&mut *(get_entry_ptr(addr, 1))) }
     }
+
+    fn map_huge(&mut self, addr: usize, target: usize) -> Option<&mut Entry> {
+        let flags = EF::PRESENT | EF::WRITABLE | EF::NO_EXECUTE;
+        let page: Page<Size2MiB> = Page::containing_address(x86_64::VirtAddr::new(addr as u64));
+        let frame: Frame<Size2MiB> = Frame::containing_address(PhysAddr::new(target as u64));
+        // fails if the range is already mapped, maybe by normal pages
+        let flush = unsafe {
+            self.0
+                .map_to(page, frame, flags, &mut FrameAllocatorForX86)
+                .ok()?
+        };
+        flush.flush();
+        unsafe { Some(&mut *(get_entry_ptr(addr, 2))) }
+    }
+
+    fn unmap_huge(&mut self, addr: usize) {
+        let page: Page<Size2MiB> = Page::containing_address(x86_64::VirtAddr::new(addr as u64));
+        if let Ok((_, flush)) = self.0.unmap(page) {
+            flush.flush();
+        }
+    }
 }
 
 impl PageTableExt for ActivePageTable {
@@ -93,7 +117,9 @@ impl ActivePageTable {
 impl Entry for PageEntry {
     fn update(&mut self) {
         use x86_64::{instructions::tlb::flush, VirtAddr};
-        let addr = VirtAddr::new_unchecked((self as *const _ as u64) << 9);
+        // a huge page entry is in P2 table, one more level up
+        let shift = if self.is_huge() { 18 } else { 9 };
+        let addr = VirtAddr::new_unchecked((self as *const _ as u64) << shift);
         flush(addr);
     }
     fn accessed(&self) -> bool {
@@ -154,7 +180,8 @@ impl Entry for PageEntry {
         self.as_flags().set(EF::USER_ACCESSIBLE, value);
         if value {
             let mut addr = self as *const _ as usize;
-            for _ in 0..3 {
+            let upper_levels = if self.is_huge() { 2 } else { 3 };
+            for _ in 0..upper_levels {
                 // Upper level entry
                 addr = ((addr >> 9) & 0o777_777_777_7770) | 0xffffff80_00000000;
                 // set USER_ACCESSIBLE
@@ -172,6 +199,9 @@ impl Entry for PageEntry {
         0
     }
     fn set_mmio(&mut self, _value: u8) {}
+    fn is_huge(&self) -> bool {
+        self.0.flags().contains(EF::HUGE_PAGE)
+    }
 }
 
 fn get_entry_ptr(addr: usize, level: u8) -> *mut PageEntry {
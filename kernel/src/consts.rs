@@ -6,3 +6,8 @@ pub const MAX_CPU_NUM: usize = 8;
 pub const MAX_PROCESS_NUM: usize = 128;
 
 pub const USEC_PER_TICK: usize = 10000;
+
+/// Size of user stack mapped when the process is created, the rest grows on demand
+pub const USER_STACK_INIT_SIZE: usize = 0x4000; // 16 KB
+/// Size of the inaccessible gap below user stack
+pub const USER_STACK_GUARD_SIZE: usize = 0x10000; // 64 KB
@@ -219,6 +219,40 @@ impl Driver for VirtIOGpuDriver {
     fn get_id(&self) -> String {
         format!("virtio_gpu")
     }
+
+    fn read_framebuffer(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let driver = self.0.lock();
+        let frame_buffer = driver.frame_buffer();
+        if offset >= frame_buffer.len() {
+            return 0;
+        }
+        let len = buf.len().min(frame_buffer.len() - offset);
+        buf[..len].copy_from_slice(&frame_buffer[offset..offset + len]);
+        len
+    }
+
+    fn write_framebuffer(&self, offset: usize, buf: &[u8]) -> usize {
+        let mut driver = self.0.lock();
+        let frame_buffer = driver.frame_buffer();
+        if offset >= frame_buffer.len() {
+            return 0;
+        }
+        let len = buf.len().min(frame_buffer.len() - offset);
+        frame_buffer[offset..offset + len].copy_from_slice(&buf[..len]);
+
+        // ensure header page is mapped
+        let header_addr = driver.header as *mut _ as usize;
+        active_table().map_if_not_exists(header_addr, header_addr);
+        flush_frame_buffer_to_screen(&mut driver);
+        len
+    }
+}
+
+impl VirtIOGpu {
+    fn frame_buffer(&self) -> &'static mut [u8] {
+        let size = self.rect.width * self.rect.height * 4;
+        unsafe { slice::from_raw_parts_mut(self.frame_buffer as *mut u8, size as usize) }
+    }
 }
 
 fn request(driver: &mut VirtIOGpu) {
@@ -1 +1,12 @@
 pub mod virtio_input;
+
+/// Input event in the layout of `struct input_event` of Linux
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct InputEvent {
+    pub sec: usize,
+    pub usec: usize,
+    pub event_type: u16,
+    pub code: u16,
+    pub value: u32,
+}
@@ -1,3 +1,4 @@
+use alloc::collections::VecDeque;
 use alloc::prelude::*;
 use alloc::sync::Arc;
 use alloc::vec;
@@ -19,7 +20,8 @@ use crate::memory::active_table;
 use crate::sync::SpinNoIrqLock as Mutex;
 
 use super::super::bus::virtio_mmio::*;
-use super::super::{DeviceType, Driver, DRIVERS};
+use super::super::{DeviceType, Driver, DRIVERS, INPUT_ACTIVITY};
+use super::InputEvent;
 
 struct VirtIOInput {
     interrupt_parent: u32,
@@ -29,8 +31,13 @@ struct VirtIOInput {
     queues: [VirtIOVirtqueue; 2],
     x: isize,
     y: isize,
+    /// Events not read yet, the oldest are dropped when full
+    events: VecDeque<InputEvent>,
 }
 
+/// Max number of pending events
+const MAX_PENDING_EVENTS: usize = 64;
+
 const VIRTIO_INPUT_CFG_UNSET: u8 = 0x00;
 const VIRTIO_INPUT_CFG_ID_NAME: u8 = 0x01;
 const VIRTIO_INPUT_CFG_ID_SERIAL: u8 = 0x02;
@@ -144,12 +151,24 @@ impl VirtIOInput {
                         self.y += event.value as isize;
                     }
                     trace!("got {}", event);
+                    if self.events.len() == MAX_PENDING_EVENTS {
+                        self.events.pop_front();
+                    }
+                    let msec = crate::trap::uptime_msec();
+                    self.events.push_back(InputEvent {
+                        sec: msec / 1000,
+                        usec: msec % 1000 * 1000,
+                        event_type: event.event_type,
+                        code: event.code,
+                        value: event.value,
+                    });
                     self.queues[VIRTIO_QUEUE_EVENT].add(&input, &output, 0);
                 } else {
                     break;
                 }
             }
             println!("mouse is at x {} y {}", self.x, self.y);
+            INPUT_ACTIVITY.notify_all();
             return true;
         }
         return false;
@@ -168,6 +187,14 @@ impl Driver for VirtIOInputDriver {
     fn get_id(&self) -> String {
         String::from("virtio_input")
     }
+
+    fn pop_input_event(&self) -> Option<InputEvent> {
+        self.0.lock().events.pop_front()
+    }
+
+    fn has_input_event(&self) -> bool {
+        !self.0.lock().events.is_empty()
+    }
 }
 
 pub fn virtio_input_init(node: &Node) {
@@ -206,6 +233,7 @@ pub fn virtio_input_init(node: &Node) {
         queues,
         x: 0,
         y: 0,
+        events: VecDeque::new(),
     };
 
     let buffer = vec![VirtIOInputEvent::default(); queue_num];
@@ -6,6 +6,7 @@ use smoltcp::wire::{EthernetAddress, Ipv4Address};
 use spin::RwLock;
 
 use self::block::virtio_blk::VirtIOBlkDriver;
+pub use self::input::InputEvent;
 use crate::sync::Condvar;
 
 #[allow(dead_code)]
@@ -64,6 +65,28 @@ pub trait Driver: Send + Sync {
     fn poll(&self) {
         unimplemented!("not a net driver")
     }
+
+    // gpu related drivers should implement these
+    // read the frame buffer from `offset`, return the length read
+    fn read_framebuffer(&self, _offset: usize, _buf: &mut [u8]) -> usize {
+        unimplemented!("not a gpu driver")
+    }
+
+    // write the frame buffer from `offset` and show it, return the length written
+    fn write_framebuffer(&self, _offset: usize, _buf: &[u8]) -> usize {
+        unimplemented!("not a gpu driver")
+    }
+
+    // input related drivers should implement these
+    // pop the earliest pending input event
+    fn pop_input_event(&self) -> Option<InputEvent> {
+        unimplemented!("not an input driver")
+    }
+
+    // whether an input event is pending
+    fn has_input_event(&self) -> bool {
+        unimplemented!("not an input driver")
+    }
 }
 
 lazy_static! {
@@ -75,6 +98,8 @@ lazy_static! {
 
 lazy_static! {
     pub static ref SOCKET_ACTIVITY: Condvar = Condvar::new();
+    /// Notified when input devices receive events
+    pub static ref INPUT_ACTIVITY: Condvar = Condvar::new();
 }
 
 #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
@@ -0,0 +1,122 @@
+//! File systems of the files which are in no directory, like pipes and eventfds
+//!
+//! They have nothing but an empty root, and are only reported by statfs.
+
+use alloc::{string::String, sync::Arc};
+use core::any::Any;
+
+use rcore_fs::vfs::*;
+
+use super::statfs::{FsStat, ANON_INODE_FS_MAGIC, PIPEFS_MAGIC};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AnonFS {
+    /// pipefs, of the pipes made by pipe()
+    Pipe,
+    /// anon_inodefs, of eventfd, timerfd and signalfd
+    AnonInode,
+}
+
+lazy_static! {
+    pub static ref PIPE_FS: Arc<AnonFS> = Arc::new(AnonFS::Pipe);
+    pub static ref ANON_INODE_FS: Arc<AnonFS> = Arc::new(AnonFS::AnonInode);
+}
+
+impl AnonFS {
+    pub fn statfs(&self) -> FsStat {
+        match self {
+            AnonFS::Pipe => FsStat::pseudo(PIPEFS_MAGIC),
+            AnonFS::AnonInode => FsStat::pseudo(ANON_INODE_FS_MAGIC),
+        }
+    }
+}
+
+impl FileSystem for AnonFS {
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn root_inode(&self) -> Arc<INode> {
+        Arc::new(AnonRoot(*self))
+    }
+
+    fn info(&self) -> &'static FsInfo {
+        static INFO: FsInfo = FsInfo { max_file_size: 0 };
+        &INFO
+    }
+}
+
+/// The empty root directory of `AnonFS`
+struct AnonRoot(AnonFS);
+
+impl INode for AnonRoot {
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize> {
+        Err(FsError::IsDir)
+    }
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(FsError::IsDir)
+    }
+    fn metadata(&self) -> Result<Metadata> {
+        Ok(Metadata {
+            dev: 0,
+            inode: 1,
+            size: 0,
+            blk_size: 0,
+            blocks: 0,
+            atime: Timespec { sec: 0, nsec: 0 },
+            mtime: Timespec { sec: 0, nsec: 0 },
+            ctime: Timespec { sec: 0, nsec: 0 },
+            type_: FileType::Dir,
+            mode: 0o500,
+            nlinks: 2,
+            uid: 0,
+            gid: 0,
+        })
+    }
+    fn chmod(&self, _mode: u16) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+    fn sync_all(&self) -> Result<()> {
+        Ok(())
+    }
+    fn sync_data(&self) -> Result<()> {
+        Ok(())
+    }
+    fn resize(&self, _len: usize) -> Result<()> {
+        Err(FsError::IsDir)
+    }
+    fn create(&self, _name: &str, _type_: FileType, _mode: u32) -> Result<Arc<INode>> {
+        Err(FsError::NotSupported)
+    }
+    fn unlink(&self, _name: &str) -> Result<()> {
+        Err(FsError::EntryNotFound)
+    }
+    fn link(&self, _name: &str, _other: &Arc<INode>) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+    fn move_(&self, _old_name: &str, _target: &Arc<INode>, _new_name: &str) -> Result<()> {
+        Err(FsError::EntryNotFound)
+    }
+    fn find(&self, name: &str) -> Result<Arc<INode>> {
+        match name {
+            "." | ".." => Ok(Arc::new(AnonRoot(self.0))),
+            _ => Err(FsError::EntryNotFound),
+        }
+    }
+    fn get_entry(&self, id: usize) -> Result<String> {
+        match id {
+            0 => Ok(String::from(".")),
+            1 => Ok(String::from("..")),
+            _ => Err(FsError::EntryNotFound),
+        }
+    }
+    fn fs(&self) -> Arc<FileSystem> {
+        match self.0 {
+            AnonFS::Pipe => PIPE_FS.clone(),
+            AnonFS::AnonInode => ANON_INODE_FS.clone(),
+        }
+    }
+    fn as_any_ref(&self) -> &Any {
+        self
+    }
+}
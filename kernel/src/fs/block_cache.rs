@@ -0,0 +1,383 @@
+//! Page cache of block devices
+//!
+//! Block drivers access the device a sector at a time,
+//! so file systems on them go through a cache of pages instead.
+//! The caches of all devices share one budget of pages.
+//! Written pages are kept dirty until they are evicted or synced,
+//! or written back periodically by a kernel thread.
+//! A dirty page which can't be written back is kept with a warning,
+//! so the caches may stay beyond the budget until it can.
+//! Sequential reads make the cache read ahead a growing number of pages.
+
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use core::cmp::{max, min};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+
+use rcore_fs::dev::Device;
+use rcore_fs::vfs::{FsError, Result};
+use rcore_memory::PAGE_SIZE;
+use spin::RwLock;
+
+use crate::consts::KERNEL_HEAP_SIZE;
+use crate::drivers::BLK_DRIVERS;
+use crate::sync::SpinNoIrqLock as Mutex;
+use crate::thread;
+
+/// Max number of pages cached for all devices, a sixteenth of the kernel heap
+const CACHE_PAGES: usize = KERNEL_HEAP_SIZE / 16 / PAGE_SIZE;
+/// Number of pages cached for all devices
+static CACHED_PAGES: AtomicUsize = AtomicUsize::new(0);
+/// Max number of pages read ahead at once
+const READAHEAD_MAX: usize = 32;
+/// Interval of writing back the dirty pages
+const WRITEBACK_INTERVAL: Duration = Duration::from_secs(5);
+
+struct CachePage {
+    data: Vec<u8>,
+    /// Number of bytes on the device, less than the page size at its end
+    len: usize,
+    dirty: bool,
+    /// Increased on every write, to tell whether a copy being written back is still current
+    version: u64,
+    /// Key in the LRU list
+    last_used: u64,
+}
+
+/// A copy of a dirty page, written back without the cache locked
+struct PageCopy {
+    id: usize,
+    data: Vec<u8>,
+    version: u64,
+}
+
+#[derive(Default)]
+struct CacheInner {
+    pages: BTreeMap<usize, CachePage>,
+    /// Pages by the time of their last use, the least recently used first
+    lru: BTreeMap<u64, usize>,
+    /// Increased on every use of a page
+    clock: u64,
+    /// The page following the last read one, to detect sequential reads
+    next_read: usize,
+    /// Number of pages to read ahead on the next miss of a sequential read
+    readahead: usize,
+    /// Number of bytes known to be on the device, from the reads so far
+    extent: usize,
+    /// Increased on every write back, after which pages read before may be stale
+    writebacks: u64,
+}
+
+/// A block device with its pages cached
+///
+/// The device is never accessed with the cache locked.
+pub struct BlockCache {
+    device: Arc<Device>,
+    inner: Mutex<CacheInner>,
+}
+
+impl BlockCache {
+    fn new(device: Arc<Device>) -> Self {
+        BlockCache {
+            device,
+            inner: Mutex::new(CacheInner::default()),
+        }
+    }
+
+    /// Write the dirty pages back to the device
+    pub fn sync(&self) -> Result<()> {
+        let dirty = self.inner.lock().dirty_pages();
+        let mut result = Ok(());
+        for copy in dirty {
+            if let Err(e) = self.write_back(copy) {
+                result = Err(e);
+            }
+        }
+        result
+    }
+
+    /// Write `copy` to the device, then mark its page clean if it is still current.
+    /// On failure the page is kept dirty.
+    fn write_back(&self, copy: PageCopy) -> Result<()> {
+        let result = match self.device.write_at(copy.id * PAGE_SIZE, &copy.data) {
+            Some(len) if len == copy.data.len() => Ok(()),
+            _ => Err(FsError::DeviceError),
+        };
+        let mut inner = self.inner.lock();
+        inner.writebacks += 1;
+        let current = match inner.pages.get(&copy.id) {
+            Some(page) => page.version == copy.version,
+            None => false,
+        };
+        if current && result.is_ok() {
+            inner.pages.get_mut(&copy.id).unwrap().dirty = false;
+        }
+        result
+    }
+
+    /// Drop the least recently used pages while the caches are beyond the budget,
+    /// writing back the dirty ones to drop them on the next pass.
+    /// Stop once a write back fails, leaving its page dirty.
+    fn shrink(&self) {
+        loop {
+            let dirty = self.inner.lock().evict();
+            if dirty.is_empty() {
+                return;
+            }
+            let mut failed = false;
+            for copy in dirty {
+                let id = copy.id;
+                if let Err(e) = self.write_back(copy) {
+                    warn!("block cache: write back of page {} failed: {:?}", id, e);
+                    failed = true;
+                }
+            }
+            if failed {
+                return;
+            }
+        }
+    }
+
+    /// Read pages from `id` into the cache,
+    /// return whether page `id` is on the device
+    fn load(&self, id: usize, count: usize) -> Result<bool> {
+        let writebacks = self.inner.lock().writebacks;
+        let mut buf = vec![0u8; count * PAGE_SIZE];
+        let len = match self.device.read_at(id * PAGE_SIZE, &mut buf) {
+            Some(len) => len,
+            // the read ahead pages may be beyond the end of the device
+            None if count > 1 => return self.load(id, 1),
+            None => return Err(FsError::DeviceError),
+        };
+        {
+            let mut inner = self.inner.lock();
+            inner.extent = max(inner.extent, id * PAGE_SIZE + len);
+            // a page written back and dropped since is newer on the device,
+            // the caller reads again if the page is missing
+            if inner.writebacks == writebacks {
+                for (i, data) in buf.chunks(PAGE_SIZE).enumerate() {
+                    let start = i * PAGE_SIZE;
+                    if start >= len {
+                        break;
+                    }
+                    // pages cached since may be dirty
+                    if !inner.pages.contains_key(&(id + i)) {
+                        let page_len = min(len - start, PAGE_SIZE);
+                        inner.insert(id + i, data.to_vec(), page_len);
+                    }
+                }
+            }
+        }
+        shrink_caches(self);
+        Ok(len > 0)
+    }
+}
+
+impl CacheInner {
+    /// Mark page `id` as the most recently used
+    fn touch(&mut self, id: usize) {
+        self.clock += 1;
+        let clock = self.clock;
+        let page = self.pages.get_mut(&id).unwrap();
+        self.lru.remove(&page.last_used);
+        page.last_used = clock;
+        self.lru.insert(clock, id);
+    }
+
+    fn insert(&mut self, id: usize, data: Vec<u8>, len: usize) {
+        let page = CachePage {
+            data,
+            len,
+            dirty: false,
+            version: 0,
+            last_used: 0,
+        };
+        if self.pages.insert(id, page).is_none() {
+            CACHED_PAGES.fetch_add(1, Ordering::Relaxed);
+        }
+        self.touch(id);
+    }
+
+    fn remove(&mut self, id: usize) {
+        if let Some(page) = self.pages.remove(&id) {
+            self.lru.remove(&page.last_used);
+            CACHED_PAGES.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    fn copy_page(&self, id: usize) -> PageCopy {
+        let page = &self.pages[&id];
+        PageCopy {
+            id,
+            data: page.data[..page.len].to_vec(),
+            version: page.version,
+        }
+    }
+
+    fn dirty_pages(&self) -> Vec<PageCopy> {
+        let dirty = self.pages.iter().filter(|(_, page)| page.dirty);
+        dirty.map(|(&id, _)| self.copy_page(id)).collect()
+    }
+
+    /// Drop the least recently used clean pages beyond the budget.
+    /// Return copies of the dirty ones passed over, to be written back before they are dropped.
+    fn evict(&mut self) -> Vec<PageCopy> {
+        let mut excess = CACHED_PAGES
+            .load(Ordering::Relaxed)
+            .saturating_sub(CACHE_PAGES);
+        let mut victims = Vec::new();
+        let mut dirty = Vec::new();
+        for &id in self.lru.values() {
+            if excess == 0 {
+                break;
+            }
+            match self.pages[&id].dirty {
+                // enough to be dropped on the next pass
+                true if dirty.len() < excess => dirty.push(self.copy_page(id)),
+                true => {}
+                false => {
+                    victims.push(id);
+                    excess -= 1;
+                }
+            }
+        }
+        for id in victims {
+            self.remove(id);
+        }
+        dirty
+    }
+
+    /// Note a read of page `id`, reading ahead if the reads are sequential.
+    /// Return the number of pages to load from `id`, or 0 if it is cached.
+    fn start_read(&mut self, id: usize) -> usize {
+        let sequential = id == self.next_read;
+        self.next_read = id + 1;
+        self.readahead = match sequential {
+            true => min((self.readahead * 2).max(4), READAHEAD_MAX),
+            false => 0,
+        };
+        if self.pages.contains_key(&id) {
+            self.touch(id);
+            return 0;
+        }
+        1 + self.readahead
+    }
+}
+
+impl Device for BlockCache {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Option<usize> {
+        let mut pos = 0;
+        while pos < buf.len() {
+            let id = (offset + pos) / PAGE_SIZE;
+            let begin = (offset + pos) % PAGE_SIZE;
+            let count = self.inner.lock().start_read(id);
+            if count > 0 && !self.load(id, count).ok()? {
+                break;
+            }
+            let inner = self.inner.lock();
+            let page = match inner.pages.get(&id) {
+                Some(page) => page,
+                // dropped or not cached by the load, read it again
+                None => continue,
+            };
+            // a short page is the end of the device
+            if begin >= page.len {
+                break;
+            }
+            let len = min(page.len - begin, buf.len() - pos);
+            buf[pos..pos + len].copy_from_slice(&page.data[begin..begin + len]);
+            pos += len;
+        }
+        Some(pos)
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Option<usize> {
+        let mut pos = 0;
+        while pos < buf.len() {
+            let id = (offset + pos) / PAGE_SIZE;
+            let begin = (offset + pos) % PAGE_SIZE;
+            let len = min(PAGE_SIZE - begin, buf.len() - pos);
+            let mut inner = self.inner.lock();
+            if !inner.pages.contains_key(&id) {
+                if len == PAGE_SIZE && (id + 1) * PAGE_SIZE <= inner.extent {
+                    // the whole page is overwritten and on the device, no need to read it
+                    inner.insert(id, vec![0u8; PAGE_SIZE], PAGE_SIZE);
+                } else {
+                    // the read also tells whether the page is on the device
+                    drop(inner);
+                    if !self.load(id, 1).ok()? {
+                        break;
+                    }
+                    continue;
+                }
+            }
+            inner.touch(id);
+            let page = inner.pages.get_mut(&id).unwrap();
+            if begin >= page.len {
+                break;
+            }
+            let len = min(len, page.len - begin);
+            page.data[begin..begin + len].copy_from_slice(&buf[pos..pos + len]);
+            page.dirty = true;
+            page.version += 1;
+            pos += len;
+        }
+        shrink_caches(self);
+        Some(pos)
+    }
+}
+
+lazy_static! {
+    /// All block caches, with the index of their driver in `BLK_DRIVERS` if any
+    static ref CACHES: RwLock<Vec<(Option<usize>, Arc<BlockCache>)>> = RwLock::new(Vec::new());
+}
+
+/// Cache the block device `device`, which is not in `BLK_DRIVERS`
+pub fn cache_device(device: Arc<Device>) -> Arc<BlockCache> {
+    let cache = Arc::new(BlockCache::new(device));
+    CACHES.write().push((None, cache.clone()));
+    cache
+}
+
+/// The cached device of the `index`-th block driver
+pub fn blk_device(index: usize) -> Option<Arc<BlockCache>> {
+    let mut caches = CACHES.write();
+    if let Some((_, cache)) = caches.iter().find(|(i, _)| *i == Some(index)) {
+        return Some(cache.clone());
+    }
+    let driver = BLK_DRIVERS.read().get(index)?.clone();
+    let cache = Arc::new(BlockCache::new(driver));
+    caches.push((Some(index), cache.clone()));
+    Some(cache)
+}
+
+/// Drop pages of the caches while they are beyond the budget, those of `first` first
+fn shrink_caches(first: &BlockCache) {
+    first.shrink();
+    let caches: Vec<_> = CACHES.read().iter().map(|(_, c)| c.clone()).collect();
+    for cache in caches {
+        if CACHED_PAGES.load(Ordering::Relaxed) <= CACHE_PAGES {
+            return;
+        }
+        cache.shrink();
+    }
+}
+
+/// Write back the dirty pages of all block devices
+pub fn sync_block_caches() -> Result<()> {
+    let caches: Vec<_> = CACHES.read().iter().map(|(_, c)| c.clone()).collect();
+    for cache in caches {
+        cache.sync()?;
+    }
+    Ok(())
+}
+
+/// Kernel thread writing back the dirty pages periodically
+pub extern "C" fn writeback_thread(_arg: usize) -> ! {
+    loop {
+        thread::sleep(WRITEBACK_INTERVAL);
+        if let Err(e) = sync_block_caches() {
+            warn!("block cache: write back failed: {:?}", e);
+        }
+    }
+}
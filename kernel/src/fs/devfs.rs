@@ -0,0 +1,401 @@
+//! Device file system
+//!
+//! Device nodes are generated from the driver lists when they are looked up.
+//!
+//! /dev/random and /dev/urandom are the same generator, seeded with the timing of interrupts.
+//! It is NOT cryptographically secure, don't use it for keys.
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+use core::any::Any;
+use core::mem::size_of;
+use core::slice;
+
+use rcore_fs::dev::Device;
+use rcore_fs::vfs::*;
+
+use super::poll::{PollStatus, Pollable};
+use super::{blk_device, STDIN, STDOUT};
+use crate::drivers::{
+    DeviceType, Driver, InputEvent, BLK_DRIVERS, DRIVERS, INPUT_ACTIVITY, NET_DRIVERS,
+};
+use crate::sync::Condvar;
+use crate::sync::SpinNoIrqLock as Mutex;
+
+pub struct DevFS;
+
+lazy_static! {
+    pub static ref DEV_FS: Arc<DevFS> = Arc::new(DevFS);
+}
+
+impl FileSystem for DevFS {
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn root_inode(&self) -> Arc<INode> {
+        Arc::new(DevINode::Root)
+    }
+
+    fn info(&self) -> &'static FsInfo {
+        static INFO: FsInfo = FsInfo { max_file_size: 0 };
+        &INFO
+    }
+}
+
+/// INode of devfs
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DevINode {
+    /// /dev
+    Root,
+    /// /dev/input
+    InputDir,
+    /// /dev/shm, an empty directory to mount tmpfs on
+    ShmDir,
+    /// /dev/net
+    NetDir,
+    Null,
+    Zero,
+    Random,
+    URandom,
+    Console,
+    /// /dev/fb<n>, the n-th gpu in `DRIVERS`
+    Framebuffer(usize),
+    /// /dev/vd<x>, the x-th in `BLK_DRIVERS`
+    Block(usize),
+    /// /dev/input/event<n>, the n-th input device in `DRIVERS`
+    InputEvent(usize),
+    /// /dev/net/<interface name>, the n-th in `NET_DRIVERS`.
+    /// Network interfaces are used through sockets, the node only shows them.
+    NetInterface(usize),
+}
+
+const STATIC_NODES: [(&str, DevINode); 8] = [
+    ("null", DevINode::Null),
+    ("zero", DevINode::Zero),
+    ("random", DevINode::Random),
+    ("urandom", DevINode::URandom),
+    ("console", DevINode::Console),
+    ("input", DevINode::InputDir),
+    ("shm", DevINode::ShmDir),
+    ("net", DevINode::NetDir),
+];
+
+/// Get the `index`-th driver of `type_` in `DRIVERS`
+fn driver_of_type(type_: DeviceType, index: usize) -> Option<Arc<Driver>> {
+    DRIVERS
+        .read()
+        .iter()
+        .filter(|driver| driver.device_type() == type_)
+        .nth(index)
+        .cloned()
+}
+
+fn count_of_type(type_: DeviceType) -> usize {
+    DRIVERS
+        .read()
+        .iter()
+        .filter(|driver| driver.device_type() == type_)
+        .count()
+}
+
+/// Parse the index after `prefix` in `name`, like "fb0"
+fn parse_index(name: &str, prefix: &str) -> Option<usize> {
+    if !name.starts_with(prefix) {
+        return None;
+    }
+    name[prefix.len()..].parse::<usize>().ok()
+}
+
+impl DevINode {
+    fn type_(&self) -> FileType {
+        match self {
+            DevINode::Root | DevINode::InputDir | DevINode::ShmDir | DevINode::NetDir => {
+                FileType::Dir
+            }
+            DevINode::Block(_) => FileType::BlockDevice,
+            _ => FileType::CharDevice,
+        }
+    }
+
+    fn inode_id(&self) -> usize {
+        match *self {
+            DevINode::Root => 1,
+            DevINode::InputDir => 2,
+            DevINode::Null => 3,
+            DevINode::Zero => 4,
+            DevINode::Random => 5,
+            DevINode::URandom => 6,
+            DevINode::Console => 7,
+            DevINode::NetDir => 8,
+            DevINode::ShmDir => 9,
+            DevINode::Framebuffer(i) => 0x100 + i,
+            DevINode::Block(i) => 0x200 + i,
+            DevINode::InputEvent(i) => 0x300 + i,
+            DevINode::NetInterface(i) => 0x400 + i,
+        }
+    }
+
+    fn entries(&self) -> Result<Vec<String>> {
+        let mut entries = vec![String::from("."), String::from("..")];
+        match self {
+            DevINode::Root => {
+                entries.extend(STATIC_NODES.iter().map(|(name, _)| String::from(*name)));
+                for i in 0..count_of_type(DeviceType::Gpu) {
+                    entries.push(format!("fb{}", i));
+                }
+                for i in 0..BLK_DRIVERS.read().len() {
+                    entries.push(format!("vd{}", (b'a' + i as u8) as char));
+                }
+            }
+            DevINode::InputDir => {
+                for i in 0..count_of_type(DeviceType::Input) {
+                    entries.push(format!("event{}", i));
+                }
+            }
+            DevINode::ShmDir => {}
+            DevINode::NetDir => {
+                entries.extend(NET_DRIVERS.read().iter().map(|driver| driver.get_ifname()));
+            }
+            _ => return Err(FsError::NotDir),
+        }
+        Ok(entries)
+    }
+
+    fn find_child(&self, name: &str) -> Result<DevINode> {
+        match (*self, name) {
+            (DevINode::Root, ".") | (DevINode::Root, "..") => Ok(DevINode::Root),
+            (DevINode::InputDir, ".") => Ok(DevINode::InputDir),
+            (DevINode::InputDir, "..") => Ok(DevINode::Root),
+            (DevINode::ShmDir, ".") => Ok(DevINode::ShmDir),
+            (DevINode::ShmDir, "..") => Ok(DevINode::Root),
+            (DevINode::NetDir, ".") => Ok(DevINode::NetDir),
+            (DevINode::NetDir, "..") => Ok(DevINode::Root),
+            (DevINode::Root, _) => {
+                if let Some(&(_, node)) = STATIC_NODES.iter().find(|(n, _)| *n == name) {
+                    return Ok(node);
+                }
+                if let Some(i) = parse_index(name, "fb") {
+                    if i < count_of_type(DeviceType::Gpu) {
+                        return Ok(DevINode::Framebuffer(i));
+                    }
+                }
+                if name.len() == 3 && name.starts_with("vd") {
+                    let i = name.as_bytes()[2].wrapping_sub(b'a') as usize;
+                    if i < BLK_DRIVERS.read().len() {
+                        return Ok(DevINode::Block(i));
+                    }
+                }
+                Err(FsError::EntryNotFound)
+            }
+            (DevINode::InputDir, _) => match parse_index(name, "event") {
+                Some(i) if i < count_of_type(DeviceType::Input) => Ok(DevINode::InputEvent(i)),
+                _ => Err(FsError::EntryNotFound),
+            },
+            (DevINode::ShmDir, _) => Err(FsError::EntryNotFound),
+            (DevINode::NetDir, _) => NET_DRIVERS
+                .read()
+                .iter()
+                .position(|driver| driver.get_ifname() == name)
+                .map(DevINode::NetInterface)
+                .ok_or(FsError::EntryNotFound),
+            _ => Err(FsError::NotDir),
+        }
+    }
+}
+
+impl INode for DevINode {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        match *self {
+            DevINode::Root | DevINode::InputDir | DevINode::ShmDir | DevINode::NetDir => {
+                Err(FsError::IsDir)
+            }
+            DevINode::Null => Ok(0),
+            DevINode::Zero => {
+                buf.iter_mut().for_each(|x| *x = 0);
+                Ok(buf.len())
+            }
+            DevINode::Random | DevINode::URandom => {
+                fill_random(buf);
+                Ok(buf.len())
+            }
+            DevINode::Console => STDIN.read_at(offset, buf),
+            DevINode::Framebuffer(i) => {
+                let driver = driver_of_type(DeviceType::Gpu, i).ok_or(FsError::DeviceError)?;
+                Ok(driver.read_framebuffer(offset, buf))
+            }
+            DevINode::Block(i) => {
+                let device = blk_device(i).ok_or(FsError::DeviceError)?;
+                device.read_at(offset, buf).ok_or(FsError::DeviceError)
+            }
+            DevINode::InputEvent(i) => {
+                let driver = driver_of_type(DeviceType::Input, i).ok_or(FsError::DeviceError)?;
+                // only whole events are read
+                let mut len = 0;
+                while buf.len() - len >= size_of::<InputEvent>() {
+                    match driver.pop_input_event() {
+                        Some(event) => {
+                            let bytes = unsafe {
+                                slice::from_raw_parts(
+                                    &event as *const InputEvent as *const u8,
+                                    size_of::<InputEvent>(),
+                                )
+                            };
+                            buf[len..len + bytes.len()].copy_from_slice(bytes);
+                            len += bytes.len();
+                        }
+                        None => break,
+                    }
+                }
+                Ok(len)
+            }
+            DevINode::NetInterface(_) => Err(FsError::NotSupported),
+        }
+    }
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        match *self {
+            DevINode::Root | DevINode::InputDir | DevINode::ShmDir | DevINode::NetDir => {
+                Err(FsError::IsDir)
+            }
+            // writing to random devices is allowed, but the data is ignored
+            DevINode::Null | DevINode::Zero | DevINode::Random | DevINode::URandom => Ok(buf.len()),
+            DevINode::Console => STDOUT.write_at(offset, buf),
+            DevINode::Framebuffer(i) => {
+                let driver = driver_of_type(DeviceType::Gpu, i).ok_or(FsError::DeviceError)?;
+                Ok(driver.write_framebuffer(offset, buf))
+            }
+            DevINode::Block(i) => {
+                let device = blk_device(i).ok_or(FsError::DeviceError)?;
+                device.write_at(offset, buf).ok_or(FsError::DeviceError)
+            }
+            DevINode::InputEvent(_) | DevINode::NetInterface(_) => Err(FsError::NotSupported),
+        }
+    }
+    fn metadata(&self) -> Result<Metadata> {
+        let type_ = self.type_();
+        Ok(Metadata {
+            dev: 0,
+            inode: self.inode_id(),
+            size: 0,
+            blk_size: 0,
+            blocks: 0,
+            atime: Timespec { sec: 0, nsec: 0 },
+            mtime: Timespec { sec: 0, nsec: 0 },
+            ctime: Timespec { sec: 0, nsec: 0 },
+            type_,
+            mode: match type_ {
+                FileType::Dir => 0o755,
+                FileType::BlockDevice => 0o660,
+                _ => 0o666,
+            },
+            nlinks: 1,
+            uid: 0,
+            gid: 0,
+        })
+    }
+    fn chmod(&self, _mode: u16) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+    fn sync_all(&self) -> Result<()> {
+        self.sync_data()
+    }
+    fn sync_data(&self) -> Result<()> {
+        match *self {
+            DevINode::Block(i) => blk_device(i).ok_or(FsError::DeviceError)?.sync(),
+            _ => Ok(()),
+        }
+    }
+    fn resize(&self, _len: usize) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+    fn create(&self, _name: &str, _type_: FileType, _mode: u32) -> Result<Arc<INode>> {
+        Err(FsError::NotSupported)
+    }
+    fn unlink(&self, _name: &str) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+    fn link(&self, _name: &str, _other: &Arc<INode>) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+    fn move_(&self, _old_name: &str, _target: &Arc<INode>, _new_name: &str) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+    fn find(&self, name: &str) -> Result<Arc<INode>> {
+        Ok(Arc::new(self.find_child(name)?))
+    }
+    fn get_entry(&self, id: usize) -> Result<String> {
+        self.entries()?
+            .into_iter()
+            .nth(id)
+            .ok_or(FsError::EntryNotFound)
+    }
+    fn fs(&self) -> Arc<FileSystem> {
+        DEV_FS.clone()
+    }
+    fn as_any_ref(&self) -> &Any {
+        self
+    }
+}
+
+impl Pollable for DevINode {
+    fn poll(&self) -> PollStatus {
+        match *self {
+            DevINode::Console => PollStatus {
+                read: STDIN.can_read(),
+                write: true,
+                ..PollStatus::default()
+            },
+            DevINode::InputEvent(i) => PollStatus {
+                read: driver_of_type(DeviceType::Input, i)
+                    .map_or(false, |driver| driver.has_input_event()),
+                ..PollStatus::default()
+            },
+            _ => PollStatus::READY,
+        }
+    }
+    fn wait_queue(&self) -> Option<&Condvar> {
+        match *self {
+            DevINode::Console => Some(&STDIN.pushed),
+            DevINode::InputEvent(_) => Some(&*INPUT_ACTIVITY),
+            _ => None,
+        }
+    }
+    fn try_read(&self, offset: usize, buf: &mut [u8]) -> Option<Result<usize>> {
+        match *self {
+            DevINode::Console => STDIN.try_read(offset, buf),
+            _ => Some(self.read_at(offset, buf)),
+        }
+    }
+}
+
+lazy_static! {
+    /// State of the random number generator, seeded by `arch::rand`
+    /// and mixed with the entropy of interrupts by `add_entropy`
+    static ref RANDOM_STATE: Mutex<u64> = Mutex::new(crate::arch::rand::rand() | 1);
+}
+
+/// Mix the timing of an interrupt, and `value` about it, into the random number generator
+pub fn add_entropy(value: u64) {
+    let sample = crate::arch::rand::rand() ^ value.rotate_left(32);
+    let mut state = RANDOM_STATE.lock();
+    *state = (*state ^ sample)
+        .rotate_left(7)
+        .wrapping_mul(0x9e37_79b9_7f4a_7c15);
+}
+
+/// Fill `buf` with xorshift64* random numbers, mixed with `arch::rand`.
+/// Predictable by anyone who can observe the interrupts, so not cryptographic.
+fn fill_random(buf: &mut [u8]) {
+    let mut state = RANDOM_STATE.lock();
+    *state ^= crate::arch::rand::rand();
+    if *state == 0 {
+        *state = 0x2545_f491_4f6c_dd1d;
+    }
+    for chunk in buf.chunks_mut(8) {
+        *state ^= *state >> 12;
+        *state ^= *state << 25;
+        *state ^= *state >> 27;
+        let value = state.wrapping_mul(0x2545_f491_4f6c_dd1d);
+        let len = chunk.len();
+        chunk.copy_from_slice(&value.to_le_bytes()[..len]);
+    }
+}
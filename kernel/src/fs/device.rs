@@ -1,6 +1,9 @@
 //! Implement Device
 
+use alloc::sync::Arc;
+
 use rcore_fs::dev::*;
+use rcore_fs::vfs::INode;
 use spin::RwLock;
 
 #[cfg(target_arch = "x86_64")]
@@ -33,6 +36,24 @@ impl Device for MemBuf {
     }
 }
 
+/// A regular file used as a device, e.g. to mount a file system image
+pub struct LoopDevice(Arc<INode>);
+
+impl LoopDevice {
+    pub fn new(inode: Arc<INode>) -> Self {
+        LoopDevice(inode)
+    }
+}
+
+impl Device for LoopDevice {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Option<usize> {
+        self.0.read_at(offset, buf).ok()
+    }
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Option<usize> {
+        self.0.write_at(offset, buf).ok()
+    }
+}
+
 #[cfg(target_arch = "x86_64")]
 impl BlockDevice for ide::IDE {
     const BLOCK_SIZE_LOG2: u8 = 9;
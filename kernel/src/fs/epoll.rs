@@ -0,0 +1,152 @@
+//! Interest list of an epoll instance
+
+use alloc::{collections::BTreeMap, vec::Vec};
+
+use super::PollStatus;
+use crate::sync::SpinNoIrqLock as Mutex;
+use crate::syscall::SysError;
+
+pub const EPOLLIN: u32 = 0x001;
+pub const EPOLLOUT: u32 = 0x004;
+pub const EPOLLERR: u32 = 0x008;
+pub const EPOLLHUP: u32 = 0x010;
+/// Disable the interest after one event is reported, until `EPOLL_CTL_MOD`
+pub const EPOLLONESHOT: u32 = 1 << 30;
+/// Report only when the file becomes ready
+pub const EPOLLET: u32 = 1 << 31;
+
+/// `struct epoll_event`, which is packed on x86_64
+#[repr(C)]
+#[cfg_attr(target_arch = "x86_64", repr(packed))]
+#[derive(Debug, Clone, Copy)]
+pub struct EpollEvent {
+    pub events: u32,
+    pub data: u64,
+}
+
+struct EpollInterest {
+    event: EpollEvent,
+    /// Events ready when last checked, to find edges in edge-triggered mode
+    last_ready: u32,
+    /// Generation of the wait queue of the file when last checked.
+    /// Once it changes, the file may have been not ready in between,
+    /// so `last_ready` no longer hides an edge.
+    last_generation: usize,
+}
+
+/// An epoll instance, shared by the fds referring to it
+#[derive(Default)]
+pub struct Epoll {
+    /// Interests by fd
+    interests: Mutex<BTreeMap<usize, EpollInterest>>,
+}
+
+impl Epoll {
+    pub fn new() -> Self {
+        Epoll::default()
+    }
+
+    pub fn add(&self, fd: usize, event: EpollEvent) -> Result<(), SysError> {
+        let mut interests = self.interests.lock();
+        if interests.contains_key(&fd) {
+            return Err(SysError::EEXIST);
+        }
+        let interest = EpollInterest {
+            event,
+            last_ready: 0,
+            last_generation: 0,
+        };
+        interests.insert(fd, interest);
+        Ok(())
+    }
+
+    pub fn modify(&self, fd: usize, event: EpollEvent) -> Result<(), SysError> {
+        let mut interests = self.interests.lock();
+        let interest = interests.get_mut(&fd).ok_or(SysError::ENOENT)?;
+        interest.event = event;
+        interest.last_ready = 0;
+        Ok(())
+    }
+
+    pub fn delete(&self, fd: usize) -> Result<(), SysError> {
+        self.interests.lock().remove(&fd).ok_or(SysError::ENOENT)?;
+        Ok(())
+    }
+
+    /// The fds in the interest list
+    pub fn fds(&self) -> Vec<usize> {
+        self.interests.lock().keys().cloned().collect()
+    }
+
+    /// Collect at most `max` events to report
+    ///
+    /// `poll_fd` gives the status of an fd with the generation of its wait queue,
+    /// or `None` if it has been closed, in which case it is removed from the interest list.
+    pub fn collect(
+        &self,
+        max: usize,
+        mut poll_fd: impl FnMut(usize) -> Option<(PollStatus, usize)>,
+    ) -> Vec<EpollEvent> {
+        let mut interests = self.interests.lock();
+        let mut events = Vec::new();
+        let mut closed = Vec::new();
+        for (&fd, interest) in interests.iter_mut() {
+            let wanted = interest.event.events & !(EPOLLET | EPOLLONESHOT);
+            if wanted == 0 {
+                // disabled by EPOLLONESHOT
+                continue;
+            }
+            let (status, generation) = match poll_fd(fd) {
+                Some(polled) => polled,
+                None => {
+                    closed.push(fd);
+                    continue;
+                }
+            };
+            let ready = status_events(status) & (wanted | EPOLLERR | EPOLLHUP);
+            if generation != interest.last_generation {
+                interest.last_ready = 0;
+            }
+            let report = match interest.event.events & EPOLLET != 0 {
+                true => ready & !interest.last_ready,
+                false => ready,
+            };
+            if report != 0 && events.len() == max {
+                // keep the edge for the next call
+                continue;
+            }
+            interest.last_ready = ready;
+            interest.last_generation = generation;
+            if report != 0 {
+                events.push(EpollEvent {
+                    events: report,
+                    data: interest.event.data,
+                });
+                if interest.event.events & EPOLLONESHOT != 0 {
+                    interest.event.events &= EPOLLET | EPOLLONESHOT;
+                }
+            }
+        }
+        for fd in closed {
+            interests.remove(&fd);
+        }
+        events
+    }
+}
+
+fn status_events(status: PollStatus) -> u32 {
+    let mut events = 0;
+    if status.read {
+        events |= EPOLLIN;
+    }
+    if status.write {
+        events |= EPOLLOUT;
+    }
+    if status.error {
+        events |= EPOLLERR;
+    }
+    if status.hangup {
+        events |= EPOLLHUP;
+    }
+    events
+}
@@ -0,0 +1,97 @@
+//! Implement INode for eventfd, a counter to notify events through a file
+
+use alloc::{string::String, sync::Arc};
+use core::any::Any;
+
+use rcore_fs::vfs::*;
+
+use super::poll::{PollStatus, Pollable};
+use super::ANON_INODE_FS;
+use crate::sync::Condvar;
+use crate::sync::SpinNoIrqLock as Mutex;
+
+/// The largest value of the counter
+const MAX_COUNT: u64 = u64::max_value() - 1;
+
+pub struct EventFd {
+    count: Mutex<u64>,
+    /// Reads take 1 from the counter instead of all of it
+    semaphore: bool,
+    changed: Condvar,
+}
+
+impl EventFd {
+    pub fn new(count: u64, semaphore: bool) -> Self {
+        EventFd {
+            count: Mutex::new(count),
+            semaphore,
+            changed: Condvar::new(),
+        }
+    }
+}
+
+impl INode for EventFd {
+    /// Read the counter as a u64, waiting until it is not zero
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            if let Some(result) = self.try_read(offset, buf) {
+                return result;
+            }
+            self.changed.wait_unless(|| self.poll().read);
+        }
+    }
+
+    /// Add a u64 to the counter, waiting until it does not overflow
+    fn write_at(&self, _offset: usize, buf: &[u8]) -> Result<usize> {
+        if buf.len() < 8 {
+            return Err(FsError::InvalidParam);
+        }
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&buf[..8]);
+        let value = u64::from_ne_bytes(bytes);
+        if value > MAX_COUNT {
+            return Err(FsError::InvalidParam);
+        }
+        let mut count = self.count.lock();
+        while *count > MAX_COUNT - value {
+            count = self.changed.wait(count);
+        }
+        *count += value;
+        drop(count);
+        self.changed.notify_all();
+        Ok(8)
+    }
+    impl_inode!(ANON_INODE_FS);
+}
+
+impl Pollable for EventFd {
+    fn poll(&self) -> PollStatus {
+        let count = *self.count.lock();
+        PollStatus {
+            read: count > 0,
+            write: count < MAX_COUNT,
+            ..PollStatus::default()
+        }
+    }
+    fn wait_queue(&self) -> Option<&Condvar> {
+        Some(&self.changed)
+    }
+    fn try_read(&self, _offset: usize, buf: &mut [u8]) -> Option<Result<usize>> {
+        if buf.len() < 8 {
+            return Some(Err(FsError::InvalidParam));
+        }
+        let mut count = self.count.lock();
+        if *count == 0 {
+            return None;
+        }
+        let value = match self.semaphore {
+            true => 1,
+            false => *count,
+        };
+        *count -= value;
+        drop(count);
+        self.changed.notify_all();
+        buf[..8].copy_from_slice(&value.to_ne_bytes());
+        Some(Ok(8))
+    }
+}
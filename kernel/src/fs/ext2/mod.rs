@@ -0,0 +1,1135 @@
+//! ext2 file system
+//!
+//! Every change of the bitmaps, group descriptors, superblock and inodes
+//! is written through to the device.
+
+use alloc::{
+    collections::BTreeMap,
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::any::Any;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use rcore_fs::dev::Device;
+use rcore_fs::vfs::*;
+use spin::{Mutex, RwLock};
+
+use self::structs::*;
+use super::{atime_outdated, FsStat};
+use crate::syscall::current_time;
+
+mod structs;
+
+pub struct Ext2FS {
+    device: Arc<Device>,
+    sb: RwLock<SuperBlock>,
+    groups: RwLock<Vec<GroupDesc>>,
+    block_size: usize,
+    /// Serialize allocations, which update bitmaps, descriptors and the superblock
+    alloc_lock: Mutex<()>,
+    /// Opened INodes by inode number
+    inodes: RwLock<BTreeMap<usize, Weak<INodeImpl>>>,
+    /// Copy of the root inode, which is opened without reading the disk
+    root: RwLock<DiskINode>,
+    /// Serialize renames, which lock two directories
+    rename_lock: Mutex<()>,
+    self_ptr: Weak<Ext2FS>,
+}
+
+impl Ext2FS {
+    pub fn open(device: Arc<Device>) -> Result<Arc<Self>> {
+        let mut raw = vec![0u8; SUPERBLOCK_SIZE];
+        read_device(&device, SUPERBLOCK_OFFSET, &mut raw)?;
+        let sb = SuperBlock::parse(raw).ok_or(FsError::WrongFs)?;
+        let block_size = sb.block_size;
+        let mut table = vec![0u8; sb.group_count() * GROUP_DESC_SIZE];
+        read_device(&device, (sb.first_data_block + 1) * block_size, &mut table)?;
+        let groups: Vec<GroupDesc> = table.chunks(GROUP_DESC_SIZE).map(GroupDesc::parse).collect();
+        // the bitmaps and the inode table of each group must be in the volume
+        let table_blocks = (sb.inodes_per_group * sb.inode_size + block_size - 1) / block_size;
+        let in_volume = |block: u32, count: usize| {
+            block as usize >= sb.first_data_block && block as usize + count <= sb.blocks_count
+        };
+        let valid = groups.iter().all(|group| {
+            in_volume(group.block_bitmap, 1)
+                && in_volume(group.inode_bitmap, 1)
+                && in_volume(group.inode_table, table_blocks)
+        });
+        if !valid {
+            warn!("ext2: group descriptors out of the volume");
+            return Err(FsError::WrongFs);
+        }
+        info!(
+            "ext2: {} blocks of {} bytes in {} groups",
+            sb.blocks_count,
+            block_size,
+            groups.len()
+        );
+        let inode_size = sb.inode_size;
+        let fs = Ext2FS {
+            device,
+            sb: RwLock::new(sb),
+            groups: RwLock::new(groups),
+            block_size,
+            alloc_lock: Mutex::new(()),
+            inodes: RwLock::new(BTreeMap::new()),
+            root: RwLock::new(DiskINode::new(0, inode_size)),
+            rename_lock: Mutex::new(()),
+            self_ptr: Weak::default(),
+        };
+        let root = fs.read_disk_inode(ROOT_INO)?;
+        if !root.is_dir() || root.links_count == 0 {
+            warn!("ext2: root is not a directory");
+            return Err(FsError::WrongFs);
+        }
+        *fs.root.write() = root;
+        Ok(fs.wrap())
+    }
+
+    /// Wrap pure Ext2FS with Arc, and set its weak pointer to itself
+    fn wrap(self) -> Arc<Self> {
+        let fs = Arc::new(self);
+        let weak = Arc::downgrade(&fs);
+        let ptr = Arc::into_raw(fs) as *mut Self;
+        unsafe {
+            (*ptr).self_ptr = weak;
+            Arc::from_raw(ptr)
+        }
+    }
+
+    /// Usage of blocks and inodes from the superblock
+    pub fn statfs(&self) -> FsStat {
+        let sb = self.sb.read();
+        let free = sb.free_blocks() as usize;
+        FsStat {
+            magic: MAGIC as usize,
+            block_size: self.block_size,
+            blocks: sb.blocks_count,
+            blocks_free: free,
+            blocks_avail: free.saturating_sub(sb.reserved_blocks() as usize),
+            files: sb.inodes_count,
+            files_free: sb.free_inodes() as usize,
+            name_max: MAX_NAME_LEN,
+        }
+    }
+
+    fn read_bytes(&self, offset: usize, buf: &mut [u8]) -> Result<()> {
+        read_device(&self.device, offset, buf)
+    }
+
+    fn write_bytes(&self, offset: usize, buf: &[u8]) -> Result<()> {
+        match self.device.write_at(offset, buf) {
+            Some(len) if len == buf.len() => Ok(()),
+            _ => Err(FsError::DeviceError),
+        }
+    }
+
+    fn block_offset(&self, block: u32) -> usize {
+        block as usize * self.block_size
+    }
+
+    /// Read the `index`-th pointer in the indirect `block`
+    fn read_ptr(&self, block: u32, index: usize) -> Result<u32> {
+        let mut buf = [0u8; 4];
+        self.read_bytes(self.block_offset(block) + index * 4, &mut buf)?;
+        Ok(read_u32(&buf, 0))
+    }
+
+    fn write_ptr(&self, block: u32, index: usize, value: u32) -> Result<()> {
+        self.write_bytes(self.block_offset(block) + index * 4, &value.to_le_bytes())
+    }
+
+    fn write_superblock(&self, sb: &SuperBlock) -> Result<()> {
+        self.write_bytes(SUPERBLOCK_OFFSET, &sb.raw)
+    }
+
+    fn write_group(&self, index: usize, group: &GroupDesc) -> Result<()> {
+        let first_data_block = self.sb.read().first_data_block;
+        let offset = (first_data_block + 1) * self.block_size + index * GROUP_DESC_SIZE;
+        let mut raw = [0u8; GROUP_DESC_SIZE];
+        self.read_bytes(offset, &mut raw)?;
+        group.write_to(&mut raw);
+        self.write_bytes(offset, &raw)
+    }
+
+    /// Allocate a zeroed block, preferring the block group `goal`
+    fn alloc_block(&self, goal: usize) -> Result<u32> {
+        let _lock = self.alloc_lock.lock();
+        let (first_data_block, blocks_count, blocks_per_group) = {
+            let sb = self.sb.read();
+            (sb.first_data_block, sb.blocks_count, sb.blocks_per_group)
+        };
+        let mut groups = self.groups.write();
+        let count = groups.len();
+        let mut bitmap = vec![0u8; self.block_size];
+        for i in 0..count {
+            let g = (goal + i) % count;
+            if groups[g].free_blocks == 0 {
+                continue;
+            }
+            self.read_bytes(self.block_offset(groups[g].block_bitmap), &mut bitmap)?;
+            let first = first_data_block + g * blocks_per_group;
+            let len = blocks_per_group.min(blocks_count - first);
+            let bit = match find_zero_bit(&bitmap, len) {
+                Some(bit) => bit,
+                None => continue,
+            };
+            bitmap[bit / 8] |= 1 << (bit % 8);
+            self.write_bytes(self.block_offset(groups[g].block_bitmap), &bitmap)?;
+            groups[g].free_blocks -= 1;
+            self.write_group(g, &groups[g])?;
+            let mut sb = self.sb.write();
+            let free = sb.free_blocks();
+            sb.set_free_blocks(free - 1);
+            self.write_superblock(&sb)?;
+            drop(sb);
+            let block = (first + bit) as u32;
+            self.write_bytes(self.block_offset(block), &vec![0u8; self.block_size])?;
+            return Ok(block);
+        }
+        Err(FsError::NoDeviceSpace)
+    }
+
+    fn free_block(&self, block: u32) -> Result<()> {
+        let _lock = self.alloc_lock.lock();
+        let (first_data_block, blocks_count, blocks_per_group) = {
+            let sb = self.sb.read();
+            (sb.first_data_block, sb.blocks_count, sb.blocks_per_group)
+        };
+        if (block as usize) < first_data_block || block as usize >= blocks_count {
+            warn!("ext2: freeing block {} out of the volume", block);
+            return Err(FsError::DeviceError);
+        }
+        let index = block as usize - first_data_block;
+        let (g, bit) = (index / blocks_per_group, index % blocks_per_group);
+        let mut groups = self.groups.write();
+        self.clear_bit(groups[g].block_bitmap, bit)?;
+        groups[g].free_blocks += 1;
+        self.write_group(g, &groups[g])?;
+        let mut sb = self.sb.write();
+        let free = sb.free_blocks();
+        sb.set_free_blocks(free + 1);
+        self.write_superblock(&sb)
+    }
+
+    /// Allocate an inode number, preferring the block group `goal`
+    fn alloc_inode(&self, goal: usize, is_dir: bool) -> Result<usize> {
+        let _lock = self.alloc_lock.lock();
+        let (inodes_per_group, inodes_count, first_ino) = {
+            let sb = self.sb.read();
+            (sb.inodes_per_group, sb.inodes_count, sb.first_ino)
+        };
+        let mut groups = self.groups.write();
+        let count = groups.len();
+        let mut bitmap = vec![0u8; self.block_size];
+        for i in 0..count {
+            let g = (goal + i) % count;
+            if groups[g].free_inodes == 0 {
+                continue;
+            }
+            self.read_bytes(self.block_offset(groups[g].inode_bitmap), &mut bitmap)?;
+            let len = inodes_per_group.min(inodes_count - g * inodes_per_group);
+            // the reserved inodes are skipped
+            let skip = if g == 0 { first_ino - 1 } else { 0 };
+            let bit = match (skip..len).find(|&bit| bitmap[bit / 8] & (1 << (bit % 8)) == 0) {
+                Some(bit) => bit,
+                None => continue,
+            };
+            bitmap[bit / 8] |= 1 << (bit % 8);
+            self.write_bytes(self.block_offset(groups[g].inode_bitmap), &bitmap)?;
+            groups[g].free_inodes -= 1;
+            if is_dir {
+                groups[g].used_dirs += 1;
+            }
+            self.write_group(g, &groups[g])?;
+            let mut sb = self.sb.write();
+            let free = sb.free_inodes();
+            sb.set_free_inodes(free - 1);
+            self.write_superblock(&sb)?;
+            return Ok(g * inodes_per_group + bit + 1);
+        }
+        Err(FsError::NoDeviceSpace)
+    }
+
+    fn free_inode(&self, ino: usize, is_dir: bool) -> Result<()> {
+        let _lock = self.alloc_lock.lock();
+        let (inodes_per_group, inodes_count) = {
+            let sb = self.sb.read();
+            (sb.inodes_per_group, sb.inodes_count)
+        };
+        if ino == 0 || ino > inodes_count {
+            warn!("ext2: freeing inode {} out of the volume", ino);
+            return Err(FsError::DeviceError);
+        }
+        let (g, bit) = ((ino - 1) / inodes_per_group, (ino - 1) % inodes_per_group);
+        let mut groups = self.groups.write();
+        self.clear_bit(groups[g].inode_bitmap, bit)?;
+        groups[g].free_inodes += 1;
+        if is_dir {
+            groups[g].used_dirs -= 1;
+        }
+        self.write_group(g, &groups[g])?;
+        let mut sb = self.sb.write();
+        let free = sb.free_inodes();
+        sb.set_free_inodes(free + 1);
+        self.write_superblock(&sb)
+    }
+
+    fn clear_bit(&self, bitmap: u32, bit: usize) -> Result<()> {
+        let offset = self.block_offset(bitmap) + bit / 8;
+        let mut byte = [0u8];
+        self.read_bytes(offset, &mut byte)?;
+        byte[0] &= !(1 << (bit % 8));
+        self.write_bytes(offset, &byte)
+    }
+
+    fn group_of(&self, ino: usize) -> usize {
+        (ino - 1) / self.sb.read().inodes_per_group
+    }
+
+    fn inode_offset(&self, ino: usize) -> usize {
+        let (inodes_per_group, inode_size) = {
+            let sb = self.sb.read();
+            (sb.inodes_per_group, sb.inode_size)
+        };
+        let table = self.groups.read()[(ino - 1) / inodes_per_group].inode_table;
+        self.block_offset(table) + (ino - 1) % inodes_per_group * inode_size
+    }
+
+    fn read_disk_inode(&self, ino: usize) -> Result<DiskINode> {
+        let (inodes_count, inode_size) = {
+            let sb = self.sb.read();
+            (sb.inodes_count, sb.inode_size)
+        };
+        // a broken directory entry
+        if ino == 0 || ino > inodes_count {
+            warn!("ext2: inode {} out of the volume", ino);
+            return Err(FsError::DeviceError);
+        }
+        let mut raw = vec![0u8; inode_size];
+        self.read_bytes(self.inode_offset(ino), &mut raw)?;
+        Ok(DiskINode::parse(raw))
+    }
+
+    fn write_disk_inode(&self, ino: usize, disk: &DiskINode) -> Result<()> {
+        if ino == ROOT_INO {
+            *self.root.write() = disk.clone();
+        }
+        self.write_bytes(self.inode_offset(ino), &disk.to_bytes())
+    }
+
+    /// Get the opened INode of `ino`, or load it from the disk
+    fn get_inode(&self, ino: usize) -> Result<Arc<INodeImpl>> {
+        let mut inodes = self.inodes.write();
+        if let Some(inode) = inodes.get(&ino).and_then(|inode| inode.upgrade()) {
+            return Ok(inode);
+        }
+        let disk = self.read_disk_inode(ino)?;
+        let inode = Arc::new(INodeImpl {
+            id: ino,
+            fs: self.self_ptr.upgrade().unwrap(),
+            inner: RwLock::new(disk),
+            atime_dirty: AtomicBool::new(false),
+        });
+        inodes.insert(ino, Arc::downgrade(&inode));
+        Ok(inode)
+    }
+
+    fn pointers_per_block(&self) -> usize {
+        self.block_size / 4
+    }
+
+    /// Pointer slot in the inode and indexes in indirect blocks of file block `index`
+    fn block_path(&self, index: usize) -> Result<(usize, Vec<usize>)> {
+        if index < DIRECT_BLOCKS {
+            return Ok((index, Vec::new()));
+        }
+        let ptrs = self.pointers_per_block();
+        let mut index = index - DIRECT_BLOCKS;
+        let mut level = 1;
+        let mut span = ptrs;
+        while index >= span {
+            index -= span;
+            if level == 3 {
+                return Err(FsError::InvalidParam);
+            }
+            level += 1;
+            span = span.saturating_mul(ptrs);
+        }
+        let mut path = Vec::with_capacity(level);
+        for _ in 0..level {
+            span /= ptrs;
+            path.push(index / span);
+            index %= span;
+        }
+        Ok((DIRECT_BLOCKS - 1 + level, path))
+    }
+
+    /// Get the block of file block `index`, 0 for a hole
+    fn get_block(&self, disk: &DiskINode, index: usize) -> Result<u32> {
+        let (slot, path) = self.block_path(index)?;
+        let mut block = disk.block_ptr(slot);
+        for index in path {
+            if block == 0 {
+                break;
+            }
+            block = self.read_ptr(block, index)?;
+        }
+        Ok(block)
+    }
+
+    /// Allocate a block counted in the inode `ino`
+    fn new_block(&self, ino: usize, disk: &mut DiskINode) -> Result<u32> {
+        let block = self.alloc_block(self.group_of(ino))?;
+        disk.sectors += (self.block_size / 512) as u32;
+        Ok(block)
+    }
+
+    fn release_block(&self, disk: &mut DiskINode, block: u32) -> Result<()> {
+        self.free_block(block)?;
+        disk.sectors -= (self.block_size / 512) as u32;
+        Ok(())
+    }
+
+    /// Get the block of file block `index`, allocate it and indirect blocks if missing
+    fn get_or_alloc_block(&self, ino: usize, disk: &mut DiskINode, index: usize) -> Result<u32> {
+        let (slot, path) = self.block_path(index)?;
+        let mut block = disk.block_ptr(slot);
+        if block == 0 {
+            block = self.new_block(ino, disk)?;
+            disk.set_block_ptr(slot, block);
+        }
+        for index in path {
+            let mut next = self.read_ptr(block, index)?;
+            if next == 0 {
+                next = self.new_block(ino, disk)?;
+                self.write_ptr(block, index, next)?;
+            }
+            block = next;
+        }
+        Ok(block)
+    }
+
+    /// Free the file blocks from `start`
+    fn free_blocks_from(&self, disk: &mut DiskINode, start: usize) -> Result<()> {
+        for slot in start.min(DIRECT_BLOCKS)..DIRECT_BLOCKS {
+            let block = disk.block_ptr(slot);
+            if block != 0 {
+                self.release_block(disk, block)?;
+                disk.set_block_ptr(slot, 0);
+            }
+        }
+        let ptrs = self.pointers_per_block();
+        let mut base = DIRECT_BLOCKS;
+        let mut span = ptrs;
+        for level in 1..=3 {
+            let slot = DIRECT_BLOCKS - 1 + level;
+            let block = disk.block_ptr(slot);
+            if block != 0 && start < base.saturating_add(span) {
+                let freed = self.free_tree(disk, block, level, start.saturating_sub(base))?;
+                if freed {
+                    disk.set_block_ptr(slot, 0);
+                }
+            }
+            base = base.saturating_add(span);
+            span = span.saturating_mul(ptrs);
+        }
+        Ok(())
+    }
+
+    /// Free the data blocks from `start` in the indirect tree of `block`,
+    /// return whether the whole tree is freed
+    fn free_tree(&self, disk: &mut DiskINode, block: u32, level: usize, start: usize) -> Result<bool> {
+        if level == 0 {
+            self.release_block(disk, block)?;
+            return Ok(true);
+        }
+        let ptrs = self.pointers_per_block();
+        let span = (1..level).fold(1usize, |span, _| span.saturating_mul(ptrs));
+        let mut table = vec![0u8; self.block_size];
+        self.read_bytes(self.block_offset(block), &mut table)?;
+        let mut changed = false;
+        for i in 0..ptrs {
+            let child = read_u32(&table, i * 4);
+            let child_base = i.saturating_mul(span);
+            if child == 0 || child_base.saturating_add(span) <= start {
+                continue;
+            }
+            if self.free_tree(disk, child, level - 1, start.saturating_sub(child_base))? {
+                write_u32(&mut table, i * 4, 0);
+                changed = true;
+            }
+        }
+        if start == 0 {
+            self.release_block(disk, block)?;
+            return Ok(true);
+        }
+        if changed {
+            self.write_bytes(self.block_offset(block), &table)?;
+        }
+        Ok(false)
+    }
+
+    fn read_data(&self, disk: &DiskINode, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let size = disk.size as usize;
+        if offset >= size {
+            return Ok(0);
+        }
+        let end = size.min(offset + buf.len());
+        let mut pos = offset;
+        while pos < end {
+            let block_offset = pos % self.block_size;
+            let len = (self.block_size - block_offset).min(end - pos);
+            let dst = &mut buf[pos - offset..pos - offset + len];
+            match self.get_block(disk, pos / self.block_size)? {
+                // a hole
+                0 => dst.iter_mut().for_each(|x| *x = 0),
+                block => self.read_bytes(self.block_offset(block) + block_offset, dst)?,
+            }
+            pos += len;
+        }
+        Ok(end - offset)
+    }
+
+    /// Write `buf` at `offset` of the file, the size is not changed
+    fn write_data(&self, ino: usize, disk: &mut DiskINode, offset: usize, buf: &[u8]) -> Result<()> {
+        let end = offset + buf.len();
+        let mut pos = offset;
+        while pos < end {
+            let block_offset = pos % self.block_size;
+            let len = (self.block_size - block_offset).min(end - pos);
+            let block = self.get_or_alloc_block(ino, disk, pos / self.block_size)?;
+            self.write_bytes(
+                self.block_offset(block) + block_offset,
+                &buf[pos - offset..pos - offset + len],
+            )?;
+            pos += len;
+        }
+        Ok(())
+    }
+
+    /// Change the size of file, the extended part is a hole
+    fn truncate(&self, disk: &mut DiskINode, len: usize) -> Result<()> {
+        if len < disk.size as usize {
+            let first_freed = (len + self.block_size - 1) / self.block_size;
+            self.free_blocks_from(disk, first_freed)?;
+            // clear the tail of the last block, so the data won't appear when extended
+            if len % self.block_size != 0 {
+                let block = self.get_block(disk, len / self.block_size)?;
+                if block != 0 {
+                    let tail = self.block_size - len % self.block_size;
+                    self.write_bytes(self.block_offset(block) + len % self.block_size, &vec![0u8; tail])?;
+                }
+            }
+        }
+        disk.size = len as u64;
+        Ok(())
+    }
+
+    fn read_dir(&self, disk: &DiskINode) -> Result<Vec<u8>> {
+        let mut data = vec![0u8; disk.size as usize];
+        self.read_data(disk, 0, &mut data)?;
+        Ok(data)
+    }
+
+    /// Find the entry of `name` in directory
+    fn lookup(&self, disk: &DiskINode, name: &str) -> Result<DirEntry> {
+        let data = self.read_dir(disk)?;
+        dir_entries(&data)
+            .into_iter()
+            .find(|entry| entry.ino != 0 && entry.name(&data) == name.as_bytes())
+            .ok_or(FsError::EntryNotFound)
+    }
+
+    fn file_type_byte(&self, type_: FileType) -> u8 {
+        if self.sb.read().feature_incompat & INCOMPAT_FILETYPE != 0 {
+            dir_entry_type(type_)
+        } else {
+            0
+        }
+    }
+
+    /// Add an entry of `name` to `ino` in directory `dir_ino`
+    fn add_entry(
+        &self,
+        dir_ino: usize,
+        dir: &mut DiskINode,
+        name: &str,
+        ino: usize,
+        type_: FileType,
+    ) -> Result<()> {
+        let type_byte = self.file_type_byte(type_);
+        let need = dir_entry_len(name.len());
+        // the hashed index is not maintained, let fsck rebuild it
+        dir.flags &= !INDEX_FL;
+        dir.touch_modified(now());
+        let data = self.read_dir(dir)?;
+        for entry in dir_entries(&data) {
+            let used = match entry.ino {
+                0 => 0,
+                _ => dir_entry_len(entry.name_len),
+            };
+            if entry.rec_len < used + need {
+                continue;
+            }
+            let mut buf = vec![0u8; used + need];
+            if used == 0 {
+                write_dir_entry(&mut buf, ino, entry.rec_len, name.as_bytes(), type_byte);
+            } else {
+                // split the free space after the entry
+                buf[..used].copy_from_slice(&data[entry.offset..entry.offset + used]);
+                write_u16(&mut buf, 4, used as u16);
+                let rec_len = entry.rec_len - used;
+                write_dir_entry(&mut buf[used..], ino, rec_len, name.as_bytes(), type_byte);
+            }
+            self.write_data(dir_ino, dir, entry.offset, &buf)?;
+            return self.write_disk_inode(dir_ino, dir);
+        }
+        // append a block
+        let offset = dir.size as usize;
+        let mut buf = vec![0u8; self.block_size];
+        write_dir_entry(&mut buf, ino, self.block_size, name.as_bytes(), type_byte);
+        self.write_data(dir_ino, dir, offset, &buf)?;
+        dir.size += self.block_size as u64;
+        self.write_disk_inode(dir_ino, dir)
+    }
+
+    /// Remove the entry of `name` in directory
+    fn remove_entry(&self, dir_ino: usize, dir: &mut DiskINode, name: &str) -> Result<()> {
+        dir.flags &= !INDEX_FL;
+        dir.touch_modified(now());
+        let data = self.read_dir(dir)?;
+        let entries = dir_entries(&data);
+        let i = entries
+            .iter()
+            .position(|entry| entry.ino != 0 && entry.name(&data) == name.as_bytes())
+            .ok_or(FsError::EntryNotFound)?;
+        let entry = &entries[i];
+        if entry.offset % self.block_size == 0 {
+            // the first in a block is marked unused
+            self.write_data(dir_ino, dir, entry.offset, &[0u8; 4])?;
+        } else {
+            // merge into the previous one
+            let prev = &entries[i - 1];
+            let rec_len = (prev.rec_len + entry.rec_len) as u16;
+            self.write_data(dir_ino, dir, prev.offset + 4, &rec_len.to_le_bytes())?;
+        }
+        self.write_disk_inode(dir_ino, dir)
+    }
+
+    /// Whether a directory has only "." and ".."
+    fn is_empty_dir(&self, disk: &DiskINode) -> Result<bool> {
+        let data = self.read_dir(disk)?;
+        Ok(dir_entries(&data).iter().all(|entry| {
+            entry.ino == 0 || entry.name(&data) == b"." || entry.name(&data) == b".."
+        }))
+    }
+}
+
+/// Current time in seconds since the epoch
+fn now() -> u32 {
+    current_time().sec as u32
+}
+
+fn read_device(device: &Arc<Device>, offset: usize, buf: &mut [u8]) -> Result<()> {
+    match device.read_at(offset, buf) {
+        Some(len) if len == buf.len() => Ok(()),
+        _ => Err(FsError::DeviceError),
+    }
+}
+
+fn find_zero_bit(bitmap: &[u8], len: usize) -> Option<usize> {
+    (0..len).find(|&bit| bitmap[bit / 8] & (1 << (bit % 8)) == 0)
+}
+
+/// All entries in directory `data`, including unused ones
+fn dir_entries(data: &[u8]) -> Vec<DirEntry> {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    while offset + DIR_ENTRY_HEADER <= data.len() {
+        let entry = DirEntry::parse(data, offset);
+        if entry.rec_len < DIR_ENTRY_HEADER
+            || offset + entry.rec_len > data.len()
+            || entry.name_len + DIR_ENTRY_HEADER > entry.rec_len
+        {
+            warn!("ext2: broken directory entry at {}", offset);
+            break;
+        }
+        offset += entry.rec_len;
+        entries.push(entry);
+    }
+    entries
+}
+
+fn check_name(name: &str) -> Result<()> {
+    if name.is_empty() || name == "." || name == ".." || name.len() > MAX_NAME_LEN || name.contains('/') {
+        return Err(FsError::InvalidParam);
+    }
+    Ok(())
+}
+
+impl FileSystem for Ext2FS {
+    fn sync(&self) -> Result<()> {
+        // everything else is written through
+        let inodes: Vec<_> = self
+            .inodes
+            .read()
+            .values()
+            .filter_map(|inode| inode.upgrade())
+            .collect();
+        for inode in inodes {
+            inode.sync_atime()?;
+        }
+        Ok(())
+    }
+
+    fn root_inode(&self) -> Arc<INode> {
+        let mut inodes = self.inodes.write();
+        if let Some(inode) = inodes.get(&ROOT_INO).and_then(|inode| inode.upgrade()) {
+            return inode;
+        }
+        // the copy is up to date, since the root inode is written through
+        let inode = Arc::new(INodeImpl {
+            id: ROOT_INO,
+            fs: self.self_ptr.upgrade().unwrap(),
+            inner: RwLock::new(self.root.read().clone()),
+            atime_dirty: AtomicBool::new(false),
+        });
+        inodes.insert(ROOT_INO, Arc::downgrade(&inode));
+        inode
+    }
+
+    fn info(&self) -> &'static FsInfo {
+        static INFO: FsInfo = FsInfo {
+            max_file_size: usize::max_value(),
+        };
+        &INFO
+    }
+}
+
+pub struct INodeImpl {
+    /// Inode number
+    id: usize,
+    fs: Arc<Ext2FS>,
+    inner: RwLock<DiskINode>,
+    /// The access time in `inner` is newer than on the disk,
+    /// since reads leave it to be written on sync
+    atime_dirty: AtomicBool,
+}
+
+impl INodeImpl {
+    pub fn chown(&self, uid: usize, gid: usize) -> Result<()> {
+        let mut disk = self.inner.write();
+        disk.uid = uid as u32;
+        disk.gid = gid as u32;
+        disk.ctime = now();
+        self.fs.write_disk_inode(self.id, &disk)
+    }
+
+    pub fn set_times(&self, atime: Option<Timespec>, mtime: Option<Timespec>) -> Result<()> {
+        let mut disk = self.inner.write();
+        if let Some(atime) = atime {
+            disk.atime = atime.sec as u32;
+        }
+        if let Some(mtime) = mtime {
+            disk.mtime = mtime.sec as u32;
+        }
+        disk.ctime = now();
+        self.fs.write_disk_inode(self.id, &disk)
+    }
+
+    /// Record the device number like Linux: in the first block pointer
+    /// if both numbers fit in a byte, else in the second one
+    pub fn set_rdev(&self, rdev: usize) -> Result<()> {
+        let mut disk = self.inner.write();
+        if !disk.is_device() {
+            return Err(FsError::InvalidParam);
+        }
+        let (old, new) = match rdev < 0x10000 {
+            true => (rdev as u32, 0),
+            false => (0, rdev as u32),
+        };
+        disk.set_block_ptr(0, old);
+        disk.set_block_ptr(1, new);
+        self.fs.write_disk_inode(self.id, &disk)
+    }
+
+    pub fn rdev(&self) -> usize {
+        let disk = self.inner.read();
+        if !disk.is_device() {
+            return 0;
+        }
+        match disk.block_ptr(0) {
+            0 => disk.block_ptr(1) as usize,
+            old => old as usize,
+        }
+    }
+
+    pub fn statfs(&self) -> FsStat {
+        self.fs.statfs()
+    }
+
+    /// Write the inode if only its access time has changed
+    fn sync_atime(&self) -> Result<()> {
+        if self.atime_dirty.swap(false, Ordering::Relaxed) {
+            let disk = self.inner.read();
+            if let Err(err) = self.fs.write_disk_inode(self.id, &disk) {
+                self.atime_dirty.store(true, Ordering::Relaxed);
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    fn check_dir(disk: &DiskINode) -> Result<()> {
+        if !disk.is_dir() {
+            return Err(FsError::NotDir);
+        }
+        if disk.links_count == 0 {
+            return Err(FsError::DirRemoved);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for INodeImpl {
+    /// Free the unlinked inode when it is closed
+    fn drop(&mut self) {
+        let mut disk = self.inner.write();
+        if disk.links_count != 0 {
+            if self.atime_dirty.load(Ordering::Relaxed) {
+                if let Err(err) = self.fs.write_disk_inode(self.id, &disk) {
+                    warn!(
+                        "ext2: failed to write atime of inode {}: {:?}",
+                        self.id, err
+                    );
+                }
+            }
+            return;
+        }
+        let is_dir = disk.is_dir();
+        let result = (|| -> Result<()> {
+            if !disk.is_fast_symlink() {
+                self.fs.free_blocks_from(&mut disk, 0)?;
+            }
+            disk.size = 0;
+            disk.dtime = now();
+            self.fs.write_disk_inode(self.id, &disk)?;
+            self.fs.free_inode(self.id, is_dir)
+        })();
+        if let Err(err) = result {
+            warn!("ext2: failed to free inode {}: {:?}", self.id, err);
+        }
+    }
+}
+
+impl INode for INodeImpl {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let disk = self.inner.read();
+        if disk.is_dir() {
+            return Err(FsError::IsDir);
+        }
+        if disk.is_fast_symlink() {
+            let size = disk.size as usize;
+            if offset >= size {
+                return Ok(0);
+            }
+            let len = buf.len().min(size - offset);
+            buf[..len].copy_from_slice(&disk.block[offset..offset + len]);
+            return Ok(len);
+        }
+        let len = self.fs.read_data(&disk, offset, buf)?;
+        let now = now();
+        let (atime, mtime, ctime) = (disk.atime as i64, disk.mtime as i64, disk.ctime as i64);
+        drop(disk);
+        if atime_outdated(atime, mtime, ctime, now as i64) {
+            // written on sync or when the inode is dropped
+            self.inner.write().atime = now;
+            self.atime_dirty.store(true, Ordering::Relaxed);
+        }
+        Ok(len)
+    }
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        let mut disk = self.inner.write();
+        if disk.is_dir() {
+            return Err(FsError::IsDir);
+        }
+        let end = offset + buf.len();
+        disk.touch_modified(now());
+        if disk.type_() == FileType::SymLink && disk.sectors == 0 {
+            if end < BLOCK_POINTERS_SIZE {
+                // short target is stored in the inode
+                disk.block[offset..end].copy_from_slice(buf);
+                disk.size = disk.size.max(end as u64);
+                self.fs.write_disk_inode(self.id, &disk)?;
+                return Ok(buf.len());
+            }
+            // move the target to a block
+            let size = disk.size as usize;
+            let target = disk.block[..size].to_vec();
+            disk.block = [0; BLOCK_POINTERS_SIZE];
+            self.fs.write_data(self.id, &mut disk, 0, &target)?;
+        }
+        self.fs.write_data(self.id, &mut disk, offset, buf)?;
+        if end as u64 > disk.size {
+            disk.size = end as u64;
+        }
+        self.fs.write_disk_inode(self.id, &disk)?;
+        Ok(buf.len())
+    }
+    fn metadata(&self) -> Result<Metadata> {
+        let disk = self.inner.read();
+        let time = |sec: u32| Timespec {
+            sec: sec as i64,
+            nsec: 0,
+        };
+        Ok(Metadata {
+            dev: 0,
+            inode: self.id,
+            size: disk.size as usize,
+            blk_size: self.fs.block_size,
+            blocks: disk.sectors as usize,
+            atime: time(disk.atime),
+            mtime: time(disk.mtime),
+            ctime: time(disk.ctime),
+            type_: disk.type_(),
+            mode: disk.mode & !S_IFMT,
+            nlinks: disk.links_count as usize,
+            uid: disk.uid as usize,
+            gid: disk.gid as usize,
+        })
+    }
+    fn chmod(&self, mode: u16) -> Result<()> {
+        let mut disk = self.inner.write();
+        disk.mode = (disk.mode & S_IFMT) | (mode & !S_IFMT);
+        disk.ctime = now();
+        self.fs.write_disk_inode(self.id, &disk)
+    }
+    fn sync_all(&self) -> Result<()> {
+        self.sync_atime()
+    }
+    fn sync_data(&self) -> Result<()> {
+        Ok(())
+    }
+    fn resize(&self, len: usize) -> Result<()> {
+        let mut disk = self.inner.write();
+        if disk.type_() != FileType::File {
+            return Err(FsError::NotFile);
+        }
+        self.fs.truncate(&mut disk, len)?;
+        disk.touch_modified(now());
+        self.fs.write_disk_inode(self.id, &disk)
+    }
+    fn create(&self, name: &str, type_: FileType, mode: u32) -> Result<Arc<INode>> {
+        let mut dir = self.inner.write();
+        Self::check_dir(&dir)?;
+        check_name(name)?;
+        if self.fs.lookup(&dir, name).is_ok() {
+            return Err(FsError::EntryExist);
+        }
+        let is_dir = type_ == FileType::Dir;
+        let ino = self.fs.alloc_inode(self.fs.group_of(self.id), is_dir)?;
+        let inode_size = self.fs.sb.read().inode_size;
+        let mut disk = DiskINode::new(type_mode(type_) | (mode as u16 & !S_IFMT), inode_size);
+        let now = now();
+        disk.atime = now;
+        disk.touch_modified(now);
+        if is_dir {
+            let type_byte = self.fs.file_type_byte(FileType::Dir);
+            let block_size = self.fs.block_size;
+            let mut block = vec![0u8; block_size];
+            write_dir_entry(&mut block, ino, 12, b".", type_byte);
+            write_dir_entry(&mut block[12..], self.id, block_size - 12, b"..", type_byte);
+            self.fs.write_data(ino, &mut disk, 0, &block)?;
+            disk.size = block_size as u64;
+            disk.links_count = 2;
+        }
+        self.fs.write_disk_inode(ino, &disk)?;
+        let inode = self.fs.get_inode(ino)?;
+        if let Err(err) = self.fs.add_entry(self.id, &mut dir, name, ino, type_) {
+            // free it when dropped
+            inode.inner.write().links_count = 0;
+            return Err(err);
+        }
+        if is_dir {
+            dir.links_count += 1;
+            self.fs.write_disk_inode(self.id, &dir)?;
+        }
+        Ok(inode)
+    }
+    fn unlink(&self, name: &str) -> Result<()> {
+        let mut dir = self.inner.write();
+        Self::check_dir(&dir)?;
+        if name == "." || name == ".." {
+            return Err(FsError::IsDir);
+        }
+        let entry = self.fs.lookup(&dir, name)?;
+        let inode = self.fs.get_inode(entry.ino)?;
+        let mut disk = inode.inner.write();
+        if disk.is_dir() {
+            if !self.fs.is_empty_dir(&disk)? {
+                return Err(FsError::DirNotEmpty);
+            }
+            // ".." of the child
+            dir.links_count -= 1;
+            disk.links_count = 0;
+        } else {
+            disk.links_count -= 1;
+        }
+        disk.ctime = now();
+        self.fs.remove_entry(self.id, &mut dir, name)?;
+        self.fs.write_disk_inode(inode.id, &disk)?;
+        // the inode is freed when the last reference is dropped
+        Ok(())
+    }
+    fn link(&self, name: &str, other: &Arc<INode>) -> Result<()> {
+        let other = other
+            .as_any_ref()
+            .downcast_ref::<INodeImpl>()
+            .ok_or(FsError::NotSameFs)?;
+        if !Arc::ptr_eq(&self.fs, &other.fs) {
+            return Err(FsError::NotSameFs);
+        }
+        // directories can't be linked, so `other` is not `self`
+        if other.inner.read().is_dir() {
+            return Err(FsError::IsDir);
+        }
+        let mut dir = self.inner.write();
+        Self::check_dir(&dir)?;
+        check_name(name)?;
+        if self.fs.lookup(&dir, name).is_ok() {
+            return Err(FsError::EntryExist);
+        }
+        let mut disk = other.inner.write();
+        if disk.links_count == 0 {
+            return Err(FsError::EntryNotFound);
+        }
+        self.fs
+            .add_entry(self.id, &mut dir, name, other.id, disk.type_())?;
+        disk.links_count += 1;
+        disk.ctime = now();
+        self.fs.write_disk_inode(other.id, &disk)
+    }
+    fn move_(&self, old_name: &str, target: &Arc<INode>, new_name: &str) -> Result<()> {
+        let target = target
+            .as_any_ref()
+            .downcast_ref::<INodeImpl>()
+            .ok_or(FsError::NotSameFs)?;
+        if !Arc::ptr_eq(&self.fs, &target.fs) {
+            return Err(FsError::NotSameFs);
+        }
+        check_name(new_name)?;
+        if old_name == "." || old_name == ".." {
+            return Err(FsError::IsDir);
+        }
+        let _rename = self.fs.rename_lock.lock();
+        let same_dir = self.id == target.id;
+
+        // a directory can't be moved into itself or its descendants
+        let old_ino = self.fs.lookup(&self.inner.read(), old_name)?.ino;
+        if !same_dir {
+            let mut ino = target.id;
+            while ino != self.id && ino != ROOT_INO {
+                if ino == old_ino {
+                    return Err(FsError::InvalidParam);
+                }
+                let dir = self.fs.get_inode(ino)?;
+                let parent = self.fs.lookup(&dir.inner.read(), "..")?.ino;
+                ino = parent;
+            }
+        }
+
+        let mut dir = self.inner.write();
+        Self::check_dir(&dir)?;
+        let old = self.fs.lookup(&dir, old_name)?;
+        let inode = self.fs.get_inode(old.ino)?;
+        let type_ = inode.inner.read().type_();
+        let is_dir = type_ == FileType::Dir;
+        let mut target_dir = if same_dir {
+            None
+        } else {
+            Some(target.inner.write())
+        };
+        {
+            let new_dir = match target_dir {
+                Some(ref mut target_dir) => &mut **target_dir,
+                None => &mut *dir,
+            };
+            Self::check_dir(new_dir)?;
+            if let Ok(existing) = self.fs.lookup(new_dir, new_name) {
+                if existing.ino == old.ino {
+                    // they are the same file
+                    return Ok(());
+                }
+                if existing.ino == self.id {
+                    // it contains the old one
+                    return Err(FsError::DirNotEmpty);
+                }
+                let existing_inode = self.fs.get_inode(existing.ino)?;
+                let mut disk = existing_inode.inner.write();
+                match (is_dir, disk.is_dir()) {
+                    (true, false) => return Err(FsError::NotDir),
+                    (false, true) => return Err(FsError::IsDir),
+                    (true, true) => {
+                        if !self.fs.is_empty_dir(&disk)? {
+                            return Err(FsError::DirNotEmpty);
+                        }
+                        disk.links_count = 0;
+                        new_dir.links_count -= 1;
+                    }
+                    (false, false) => disk.links_count -= 1,
+                }
+                disk.ctime = now();
+                self.fs.remove_entry(target.id, new_dir, new_name)?;
+                self.fs.write_disk_inode(existing.ino, &disk)?;
+            }
+            self.fs
+                .add_entry(target.id, new_dir, new_name, old.ino, type_)?;
+        }
+        self.fs.remove_entry(self.id, &mut dir, old_name)?;
+        let mut disk = inode.inner.write();
+        disk.ctime = now();
+        self.fs.write_disk_inode(old.ino, &disk)?;
+        if let Some(ref mut target_dir) = target_dir {
+            if is_dir {
+                // update ".." of the moved directory
+                let dotdot = self.fs.lookup(&disk, "..")?;
+                let ino = (target.id as u32).to_le_bytes();
+                self.fs.write_data(old.ino, &mut disk, dotdot.offset, &ino)?;
+                dir.links_count -= 1;
+                target_dir.links_count += 1;
+                self.fs.write_disk_inode(self.id, &dir)?;
+                self.fs.write_disk_inode(target.id, target_dir)?;
+            }
+        }
+        Ok(())
+    }
+    fn find(&self, name: &str) -> Result<Arc<INode>> {
+        let dir = self.inner.read();
+        Self::check_dir(&dir)?;
+        let ino = match name {
+            "." => self.id,
+            _ => self.fs.lookup(&dir, name)?.ino,
+        };
+        Ok(self.fs.get_inode(ino)?)
+    }
+    fn get_entry(&self, id: usize) -> Result<String> {
+        let dir = self.inner.read();
+        Self::check_dir(&dir)?;
+        let data = self.fs.read_dir(&dir)?;
+        dir_entries(&data)
+            .into_iter()
+            .filter(|entry| entry.ino != 0)
+            .nth(id)
+            .map(|entry| String::from_utf8_lossy(entry.name(&data)).into_owned())
+            .ok_or(FsError::EntryNotFound)
+    }
+    fn fs(&self) -> Arc<FileSystem> {
+        self.fs.clone()
+    }
+    fn as_any_ref(&self) -> &Any {
+        self
+    }
+}
@@ -0,0 +1,379 @@
+//! On-disk structures of ext2
+
+use alloc::vec::Vec;
+
+use rcore_fs::vfs::FileType;
+
+pub const SUPERBLOCK_OFFSET: usize = 1024;
+pub const SUPERBLOCK_SIZE: usize = 1024;
+pub const MAGIC: u16 = 0xef53;
+pub const ROOT_INO: usize = 2;
+/// Max length of a file name in bytes
+pub const MAX_NAME_LEN: usize = 255;
+/// Size of the block pointers in an inode
+pub const BLOCK_POINTERS_SIZE: usize = 60;
+pub const DIRECT_BLOCKS: usize = 12;
+pub const GROUP_DESC_SIZE: usize = 32;
+
+/// Incompatible features we support
+pub const INCOMPAT_FILETYPE: u32 = 0x0002;
+const INCOMPAT_SUPPORTED: u32 = INCOMPAT_FILETYPE;
+/// Read-only compatible features we support: sparse superblocks, large files
+const RO_COMPAT_SUPPORTED: u32 = 0x0001 | 0x0002;
+
+/// Directory with hashed index, which we don't maintain
+pub const INDEX_FL: u32 = 0x1000;
+
+pub const S_IFMT: u16 = 0o170_000;
+const S_IFSOCK: u16 = 0o140_000;
+const S_IFLNK: u16 = 0o120_000;
+const S_IFREG: u16 = 0o100_000;
+const S_IFBLK: u16 = 0o060_000;
+const S_IFDIR: u16 = 0o040_000;
+const S_IFCHR: u16 = 0o020_000;
+const S_IFIFO: u16 = 0o010_000;
+
+pub fn read_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+pub fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([buf[offset], buf[offset + 1], buf[offset + 2], buf[offset + 3]])
+}
+
+pub fn write_u16(buf: &mut [u8], offset: usize, value: u16) {
+    buf[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+pub fn write_u32(buf: &mut [u8], offset: usize, value: u32) {
+    buf[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// The superblock, the raw bytes are kept to write it back
+#[derive(Clone)]
+pub struct SuperBlock {
+    pub raw: Vec<u8>,
+    pub inodes_count: usize,
+    pub blocks_count: usize,
+    pub first_data_block: usize,
+    pub block_size: usize,
+    pub blocks_per_group: usize,
+    pub inodes_per_group: usize,
+    pub first_ino: usize,
+    pub inode_size: usize,
+    pub feature_incompat: u32,
+}
+
+impl SuperBlock {
+    /// Parse the superblock, return `None` if it is not a supported ext2
+    pub fn parse(raw: Vec<u8>) -> Option<Self> {
+        if read_u16(&raw, 56) != MAGIC {
+            return None;
+        }
+        let rev_level = read_u32(&raw, 76);
+        let (first_ino, inode_size) = match rev_level {
+            0 => (11, 128),
+            _ => (read_u32(&raw, 84) as usize, read_u16(&raw, 88) as usize),
+        };
+        let feature_incompat = if rev_level == 0 { 0 } else { read_u32(&raw, 96) };
+        let feature_ro_compat = if rev_level == 0 { 0 } else { read_u32(&raw, 100) };
+        if feature_incompat & !INCOMPAT_SUPPORTED != 0 {
+            warn!("ext2: unsupported incompatible features {:#x}", feature_incompat);
+            return None;
+        }
+        if feature_ro_compat & !RO_COMPAT_SUPPORTED != 0 {
+            warn!("ext2: unsupported features {:#x}", feature_ro_compat);
+            return None;
+        }
+        // blocks are at most 64 KiB
+        let log_block_size = read_u32(&raw, 24);
+        if log_block_size > 6 {
+            return None;
+        }
+        let sb = SuperBlock {
+            inodes_count: read_u32(&raw, 0) as usize,
+            blocks_count: read_u32(&raw, 4) as usize,
+            first_data_block: read_u32(&raw, 20) as usize,
+            block_size: 1024 << log_block_size,
+            blocks_per_group: read_u32(&raw, 32) as usize,
+            inodes_per_group: read_u32(&raw, 40) as usize,
+            first_ino,
+            inode_size,
+            feature_incompat,
+            raw,
+        };
+        // a group has a block of bitmap for each of its blocks and inodes
+        let bits = sb.block_size * 8;
+        let valid = sb.blocks_per_group != 0
+            && sb.blocks_per_group <= bits
+            && sb.inodes_per_group != 0
+            && sb.inodes_per_group <= bits
+            && sb.inode_size >= 128
+            && sb.inode_size.is_power_of_two()
+            && sb.inode_size <= sb.block_size
+            && sb.first_ino > ROOT_INO
+            && sb.first_data_block < sb.blocks_count;
+        if !valid || sb.inodes_count != sb.group_count() * sb.inodes_per_group {
+            return None;
+        }
+        Some(sb)
+    }
+
+    pub fn group_count(&self) -> usize {
+        (self.blocks_count - self.first_data_block + self.blocks_per_group - 1)
+            / self.blocks_per_group
+    }
+
+    /// Blocks only the superuser may allocate
+    pub fn reserved_blocks(&self) -> u32 {
+        read_u32(&self.raw, 8)
+    }
+
+    pub fn free_blocks(&self) -> u32 {
+        read_u32(&self.raw, 12)
+    }
+
+    pub fn set_free_blocks(&mut self, count: u32) {
+        write_u32(&mut self.raw, 12, count);
+    }
+
+    pub fn free_inodes(&self) -> u32 {
+        read_u32(&self.raw, 16)
+    }
+
+    pub fn set_free_inodes(&mut self, count: u32) {
+        write_u32(&mut self.raw, 16, count);
+    }
+}
+
+/// Block group descriptor
+#[derive(Debug, Clone)]
+pub struct GroupDesc {
+    pub block_bitmap: u32,
+    pub inode_bitmap: u32,
+    pub inode_table: u32,
+    pub free_blocks: u16,
+    pub free_inodes: u16,
+    pub used_dirs: u16,
+}
+
+impl GroupDesc {
+    pub fn parse(buf: &[u8]) -> Self {
+        GroupDesc {
+            block_bitmap: read_u32(buf, 0),
+            inode_bitmap: read_u32(buf, 4),
+            inode_table: read_u32(buf, 8),
+            free_blocks: read_u16(buf, 12),
+            free_inodes: read_u16(buf, 14),
+            used_dirs: read_u16(buf, 16),
+        }
+    }
+
+    /// Write the fields to `buf`, keeping the reserved bytes
+    pub fn write_to(&self, buf: &mut [u8]) {
+        write_u32(buf, 0, self.block_bitmap);
+        write_u32(buf, 4, self.inode_bitmap);
+        write_u32(buf, 8, self.inode_table);
+        write_u16(buf, 12, self.free_blocks);
+        write_u16(buf, 14, self.free_inodes);
+        write_u16(buf, 16, self.used_dirs);
+    }
+}
+
+/// Inode on disk, the raw bytes are kept to write it back
+#[derive(Clone)]
+pub struct DiskINode {
+    pub raw: Vec<u8>,
+    pub mode: u16,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u64,
+    pub atime: u32,
+    pub ctime: u32,
+    pub mtime: u32,
+    pub dtime: u32,
+    pub links_count: u16,
+    /// Number of 512-byte sectors allocated, including indirect blocks
+    pub sectors: u32,
+    pub flags: u32,
+    /// Block pointers, or the target of a fast symlink
+    pub block: [u8; BLOCK_POINTERS_SIZE],
+}
+
+impl DiskINode {
+    pub fn new(mode: u16, inode_size: usize) -> Self {
+        DiskINode {
+            raw: vec![0; inode_size],
+            mode,
+            uid: 0,
+            gid: 0,
+            size: 0,
+            atime: 0,
+            ctime: 0,
+            mtime: 0,
+            dtime: 0,
+            links_count: 1,
+            sectors: 0,
+            flags: 0,
+            block: [0; BLOCK_POINTERS_SIZE],
+        }
+    }
+
+    pub fn parse(raw: Vec<u8>) -> Self {
+        let mode = read_u16(&raw, 0);
+        let mut size = read_u32(&raw, 4) as u64;
+        if mode & S_IFMT == S_IFREG {
+            // the high 32 bits of size for regular files
+            size |= (read_u32(&raw, 108) as u64) << 32;
+        }
+        let mut block = [0u8; BLOCK_POINTERS_SIZE];
+        block.copy_from_slice(&raw[40..40 + BLOCK_POINTERS_SIZE]);
+        DiskINode {
+            mode,
+            uid: read_u16(&raw, 2) as u32 | (read_u16(&raw, 120) as u32) << 16,
+            gid: read_u16(&raw, 24) as u32 | (read_u16(&raw, 122) as u32) << 16,
+            size,
+            atime: read_u32(&raw, 8),
+            ctime: read_u32(&raw, 12),
+            mtime: read_u32(&raw, 16),
+            dtime: read_u32(&raw, 20),
+            links_count: read_u16(&raw, 26),
+            sectors: read_u32(&raw, 28),
+            flags: read_u32(&raw, 32),
+            block,
+            raw,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut raw = self.raw.clone();
+        write_u16(&mut raw, 0, self.mode);
+        write_u16(&mut raw, 2, self.uid as u16);
+        write_u32(&mut raw, 4, self.size as u32);
+        write_u32(&mut raw, 8, self.atime);
+        write_u32(&mut raw, 12, self.ctime);
+        write_u32(&mut raw, 16, self.mtime);
+        write_u32(&mut raw, 20, self.dtime);
+        write_u16(&mut raw, 24, self.gid as u16);
+        write_u16(&mut raw, 26, self.links_count);
+        write_u32(&mut raw, 28, self.sectors);
+        write_u32(&mut raw, 32, self.flags);
+        raw[40..40 + BLOCK_POINTERS_SIZE].copy_from_slice(&self.block);
+        if self.mode & S_IFMT == S_IFREG {
+            write_u32(&mut raw, 108, (self.size >> 32) as u32);
+        }
+        write_u16(&mut raw, 120, (self.uid >> 16) as u16);
+        write_u16(&mut raw, 122, (self.gid >> 16) as u16);
+        raw
+    }
+
+    /// Record a modification of the content at `now`
+    pub fn touch_modified(&mut self, now: u32) {
+        self.mtime = now;
+        self.ctime = now;
+    }
+
+    /// The `index`-th block pointer
+    pub fn block_ptr(&self, index: usize) -> u32 {
+        read_u32(&self.block, index * 4)
+    }
+
+    pub fn set_block_ptr(&mut self, index: usize, block: u32) {
+        write_u32(&mut self.block, index * 4, block);
+    }
+
+    pub fn type_(&self) -> FileType {
+        match self.mode & S_IFMT {
+            S_IFDIR => FileType::Dir,
+            S_IFLNK => FileType::SymLink,
+            S_IFCHR => FileType::CharDevice,
+            S_IFBLK => FileType::BlockDevice,
+            S_IFIFO => FileType::NamedPipe,
+            S_IFSOCK => FileType::Socket,
+            _ => FileType::File,
+        }
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.mode & S_IFMT == S_IFDIR
+    }
+
+    /// Whether it is a device file, whose block pointers hold the device number
+    pub fn is_device(&self) -> bool {
+        let type_ = self.mode & S_IFMT;
+        type_ == S_IFCHR || type_ == S_IFBLK
+    }
+
+    /// Whether it is a symlink with the target stored in the block pointers
+    pub fn is_fast_symlink(&self) -> bool {
+        self.mode & S_IFMT == S_IFLNK && self.sectors == 0 && self.size < BLOCK_POINTERS_SIZE as u64
+    }
+}
+
+/// Mode bits of file type
+pub fn type_mode(type_: FileType) -> u16 {
+    match type_ {
+        FileType::File => S_IFREG,
+        FileType::Dir => S_IFDIR,
+        FileType::SymLink => S_IFLNK,
+        FileType::CharDevice => S_IFCHR,
+        FileType::BlockDevice => S_IFBLK,
+        FileType::NamedPipe => S_IFIFO,
+        FileType::Socket => S_IFSOCK,
+    }
+}
+
+/// File type in directory entries
+pub fn dir_entry_type(type_: FileType) -> u8 {
+    match type_ {
+        FileType::File => 1,
+        FileType::Dir => 2,
+        FileType::CharDevice => 3,
+        FileType::BlockDevice => 4,
+        FileType::NamedPipe => 5,
+        FileType::Socket => 6,
+        FileType::SymLink => 7,
+    }
+}
+
+/// Size of the directory entry header
+pub const DIR_ENTRY_HEADER: usize = 8;
+
+/// Space used by a directory entry with name of `name_len`
+pub fn dir_entry_len(name_len: usize) -> usize {
+    (DIR_ENTRY_HEADER + name_len + 3) & !3
+}
+
+/// A directory entry at `offset` of the directory
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    pub offset: usize,
+    pub ino: usize,
+    pub rec_len: usize,
+    pub name_len: usize,
+}
+
+impl DirEntry {
+    pub fn parse(data: &[u8], offset: usize) -> Self {
+        DirEntry {
+            offset,
+            ino: read_u32(data, offset) as usize,
+            rec_len: read_u16(data, offset + 4) as usize,
+            name_len: data[offset + 6] as usize,
+        }
+    }
+
+    pub fn name<'a>(&self, data: &'a [u8]) -> &'a [u8] {
+        let start = self.offset + DIR_ENTRY_HEADER;
+        &data[start..start + self.name_len]
+    }
+}
+
+/// Write a directory entry header and name to `buf`
+pub fn write_dir_entry(buf: &mut [u8], ino: usize, rec_len: usize, name: &[u8], type_: u8) {
+    write_u32(buf, 0, ino as u32);
+    write_u16(buf, 4, rec_len as u16);
+    buf[6] = name.len() as u8;
+    buf[7] = type_;
+    buf[DIR_ENTRY_HEADER..DIR_ENTRY_HEADER + name.len()].copy_from_slice(name);
+}
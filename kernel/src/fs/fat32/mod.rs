@@ -0,0 +1,973 @@
+//! FAT32 file system with VFAT long names
+//!
+//! Files have no inode on FAT, an INode is identified by the position
+//! of its directory entry, and the entry is updated whenever the file changes.
+//! Hard links and symlinks are not supported.
+
+use alloc::{
+    collections::BTreeMap,
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::any::Any;
+
+use rcore_fs::dev::Device;
+use rcore_fs::vfs::*;
+use spin::{Mutex, RwLock};
+
+use self::structs::*;
+use super::{FsStat, MSDOS_SUPER_MAGIC};
+use crate::syscall::current_time;
+
+mod structs;
+
+/// INode id of the root directory, which has no directory entry
+const ROOT_ID: usize = 1;
+
+pub struct Fat32FS {
+    device: Arc<Device>,
+    /// Byte offset of the file system on the device
+    base: usize,
+    bpb: BootSector,
+    /// Where to start searching for a free cluster
+    next_free: Mutex<u32>,
+    /// Opened INodes by the position of their entries
+    inodes: RwLock<BTreeMap<usize, Weak<INodeImpl>>>,
+    /// Clusters of the root directory, which grow with the root INode
+    root_clusters: Mutex<Vec<u32>>,
+    /// Serialize renames, which lock two directories
+    rename_lock: Mutex<()>,
+    self_ptr: Weak<Fat32FS>,
+}
+
+impl Fat32FS {
+    /// Open a FAT32 file system on `device`,
+    /// or its first FAT32 partition if it has an MBR
+    pub fn open(device: Arc<Device>) -> Result<Arc<Self>> {
+        let mut sector = [0u8; SECTOR_SIZE];
+        read_device(&device, 0, &mut sector)?;
+        let base = match BootSector::parse(&sector) {
+            Some(_) => 0,
+            None => {
+                let start = find_fat32_partition(&sector).ok_or(FsError::WrongFs)?;
+                read_device(&device, start * SECTOR_SIZE, &mut sector)?;
+                start * SECTOR_SIZE
+            }
+        };
+        let bpb = BootSector::parse(&sector).ok_or(FsError::WrongFs)?;
+        // the last sector must be on the device
+        read_device(
+            &device,
+            base + (bpb.total_sectors - 1) * bpb.bytes_per_sector,
+            &mut vec![0u8; bpb.bytes_per_sector],
+        )?;
+        info!(
+            "fat32: {} clusters of {} bytes",
+            bpb.cluster_count(),
+            bpb.cluster_size()
+        );
+        let fs = Fat32FS {
+            device,
+            base,
+            bpb,
+            next_free: Mutex::new(2),
+            inodes: RwLock::new(BTreeMap::new()),
+            root_clusters: Mutex::new(Vec::new()),
+            rename_lock: Mutex::new(()),
+            self_ptr: Weak::default(),
+        };
+        let root_clusters = fs.read_chain(fs.bpb.root_cluster)?;
+        fs.dir_entries(&root_clusters)?;
+        *fs.root_clusters.lock() = root_clusters;
+        Ok(fs.wrap())
+    }
+
+    /// Wrap pure Fat32FS with Arc, and set its weak pointer to itself
+    fn wrap(self) -> Arc<Self> {
+        let fs = Arc::new(self);
+        let weak = Arc::downgrade(&fs);
+        let ptr = Arc::into_raw(fs) as *mut Self;
+        unsafe {
+            (*ptr).self_ptr = weak;
+            Arc::from_raw(ptr)
+        }
+    }
+
+    /// Usage of clusters, counted from the first FAT.
+    /// FAT has no inodes, so the number of files is unlimited.
+    pub fn statfs(&self) -> Result<FsStat> {
+        let end = self.bpb.cluster_count() + 2;
+        let per_sector = self.bpb.bytes_per_sector / 4;
+        let mut sector = vec![0u8; self.bpb.bytes_per_sector];
+        let mut free = 0;
+        for sector_index in 0..(end + per_sector - 1) / per_sector {
+            let offset = self.bpb.fat_offset(0) + sector_index * self.bpb.bytes_per_sector;
+            self.read_bytes(offset, &mut sector)?;
+            let first = sector_index * per_sector;
+            free += (first.max(2)..end.min(first + per_sector))
+                .filter(|&cluster| read_u32(&sector, (cluster - first) * 4) & CLUSTER_MASK == 0)
+                .count();
+        }
+        Ok(FsStat {
+            magic: MSDOS_SUPER_MAGIC,
+            block_size: self.bpb.cluster_size(),
+            blocks: end - 2,
+            blocks_free: free,
+            blocks_avail: free,
+            files: 0,
+            files_free: 0,
+            name_max: MAX_NAME_LEN,
+        })
+    }
+
+    fn read_bytes(&self, offset: usize, buf: &mut [u8]) -> Result<()> {
+        read_device(&self.device, self.base + offset, buf)
+    }
+
+    fn write_bytes(&self, offset: usize, buf: &[u8]) -> Result<()> {
+        match self.device.write_at(self.base + offset, buf) {
+            Some(len) if len == buf.len() => Ok(()),
+            _ => Err(FsError::DeviceError),
+        }
+    }
+
+    fn fat_entry(&self, cluster: u32) -> Result<u32> {
+        let mut buf = [0u8; 4];
+        self.read_bytes(self.bpb.fat_offset(0) + cluster as usize * 4, &mut buf)?;
+        Ok(read_u32(&buf, 0) & CLUSTER_MASK)
+    }
+
+    /// Set the entry of `cluster` in all FATs
+    fn set_fat_entry(&self, cluster: u32, value: u32) -> Result<()> {
+        for i in 0..self.bpb.num_fats {
+            let offset = self.bpb.fat_offset(i) + cluster as usize * 4;
+            let mut buf = [0u8; 4];
+            self.read_bytes(offset, &mut buf)?;
+            // the high 4 bits are reserved
+            let value = (read_u32(&buf, 0) & !CLUSTER_MASK) | (value & CLUSTER_MASK);
+            self.write_bytes(offset, &value.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn is_valid_cluster(&self, cluster: u32) -> bool {
+        cluster >= 2 && (cluster as usize) < self.bpb.cluster_count() + 2
+    }
+
+    /// Read the cluster chain starting at `first`
+    fn read_chain(&self, first: u32) -> Result<Vec<u32>> {
+        let mut clusters = Vec::new();
+        let mut cluster = first;
+        while cluster < END_OF_CHAIN && cluster != 0 {
+            if !self.is_valid_cluster(cluster) || clusters.len() > self.bpb.cluster_count() {
+                warn!("fat32: broken cluster chain from {}", first);
+                return Err(FsError::DeviceError);
+            }
+            clusters.push(cluster);
+            cluster = self.fat_entry(cluster)?;
+        }
+        Ok(clusters)
+    }
+
+    /// Allocate a zeroed cluster at the end of a chain
+    fn alloc_cluster(&self) -> Result<u32> {
+        let mut next = self.next_free.lock();
+        let count = self.bpb.cluster_count();
+        let per_sector = self.bpb.bytes_per_sector / 4;
+        let mut sector = vec![0u8; self.bpb.bytes_per_sector];
+        let mut loaded = None;
+        for i in 0..count {
+            let cluster = 2 + (*next as usize - 2 + i) % count;
+            let sector_index = cluster / per_sector;
+            if loaded != Some(sector_index) {
+                let offset = self.bpb.fat_offset(0) + sector_index * self.bpb.bytes_per_sector;
+                self.read_bytes(offset, &mut sector)?;
+                loaded = Some(sector_index);
+            }
+            if read_u32(&sector, cluster % per_sector * 4) & CLUSTER_MASK == 0 {
+                let cluster = cluster as u32;
+                self.set_fat_entry(cluster, CLUSTER_MASK)?;
+                self.write_bytes(
+                    self.bpb.cluster_offset(cluster),
+                    &vec![0u8; self.bpb.cluster_size()],
+                )?;
+                *next = cluster + 1;
+                return Ok(cluster);
+            }
+        }
+        Err(FsError::NoDeviceSpace)
+    }
+
+    fn free_clusters(&self, clusters: &[u32]) -> Result<()> {
+        for &cluster in clusters {
+            self.set_fat_entry(cluster, 0)?;
+        }
+        Ok(())
+    }
+
+    /// Read the data at `offset` of the file in `clusters`
+    fn read_clusters(&self, clusters: &[u32], offset: usize, buf: &mut [u8]) -> Result<()> {
+        let cluster_size = self.bpb.cluster_size();
+        let mut pos = 0;
+        while pos < buf.len() {
+            let cluster = clusters[(offset + pos) / cluster_size];
+            let cluster_offset = (offset + pos) % cluster_size;
+            let len = (cluster_size - cluster_offset).min(buf.len() - pos);
+            self.read_bytes(
+                self.bpb.cluster_offset(cluster) + cluster_offset,
+                &mut buf[pos..pos + len],
+            )?;
+            pos += len;
+        }
+        Ok(())
+    }
+
+    /// Write the data at `offset` of the file in `clusters`
+    fn write_clusters(&self, clusters: &[u32], offset: usize, buf: &[u8]) -> Result<()> {
+        let cluster_size = self.bpb.cluster_size();
+        let mut pos = 0;
+        while pos < buf.len() {
+            let cluster = clusters[(offset + pos) / cluster_size];
+            let cluster_offset = (offset + pos) % cluster_size;
+            let len = (cluster_size - cluster_offset).min(buf.len() - pos);
+            self.write_bytes(
+                self.bpb.cluster_offset(cluster) + cluster_offset,
+                &buf[pos..pos + len],
+            )?;
+            pos += len;
+        }
+        Ok(())
+    }
+
+    /// Position of the `index`-th entry of the directory in `clusters`
+    fn entry_pos(&self, clusters: &[u32], index: usize) -> usize {
+        let offset = index * DIR_ENTRY_SIZE;
+        let cluster_size = self.bpb.cluster_size();
+        self.bpb.cluster_offset(clusters[offset / cluster_size]) + offset % cluster_size
+    }
+
+    /// Read all raw entries of the directory in `clusters`
+    fn read_dir(&self, clusters: &[u32]) -> Result<Vec<u8>> {
+        let mut data = vec![0u8; clusters.len() * self.bpb.cluster_size()];
+        self.read_clusters(clusters, 0, &mut data)?;
+        Ok(data)
+    }
+
+    /// Parse the entries of the directory in `clusters`, without "." and ".."
+    fn dir_entries(&self, clusters: &[u32]) -> Result<Vec<DirEntry>> {
+        let data = self.read_dir(clusters)?;
+        let mut entries = Vec::new();
+        let mut long_names = Vec::new();
+        let mut long_first = 0;
+        let mut long_count = 0;
+        let mut long_checksum = 0;
+        for (index, raw) in data.chunks(DIR_ENTRY_SIZE).enumerate() {
+            match raw[0] {
+                // no more entries
+                0 => break,
+                DELETED => {
+                    long_names.clear();
+                    continue;
+                }
+                _ => {}
+            }
+            if is_long_entry(raw) {
+                let order = (raw[0] & !LAST_LONG_ENTRY) as usize;
+                if raw[0] & LAST_LONG_ENTRY != 0 {
+                    long_names.clear();
+                    long_first = index;
+                    long_count = order;
+                    long_checksum = raw[13];
+                } else if long_names.is_empty() || order + long_names.len() != long_count {
+                    // an orphan
+                    long_names.clear();
+                    continue;
+                }
+                long_names.push(long_entry_chars(raw));
+                continue;
+            }
+            let entry = ShortEntry::parse(raw);
+            if entry.attr & ATTR_VOLUME_ID != 0 || entry.is_dot() {
+                long_names.clear();
+                continue;
+            }
+            let long_name = if !long_names.is_empty()
+                && long_names.len() == long_count
+                && long_checksum == checksum(&entry.name)
+            {
+                decode_long_name(&long_names)
+            } else {
+                None
+            };
+            let first = if long_name.is_some() { long_first } else { index };
+            entries.push(DirEntry {
+                name: long_name.unwrap_or_else(|| entry.display_name()),
+                first,
+                index,
+                entry,
+            });
+            long_names.clear();
+        }
+        Ok(entries)
+    }
+}
+
+fn read_device(device: &Arc<Device>, offset: usize, buf: &mut [u8]) -> Result<()> {
+    match device.read_at(offset, buf) {
+        Some(len) if len == buf.len() => Ok(()),
+        _ => Err(FsError::DeviceError),
+    }
+}
+
+impl FileSystem for Fat32FS {
+    fn sync(&self) -> Result<()> {
+        // everything is written through
+        Ok(())
+    }
+
+    fn root_inode(&self) -> Arc<INode> {
+        let fs = self.self_ptr.upgrade().unwrap();
+        let mut inodes = self.inodes.write();
+        if let Some(inode) = inodes.get(&ROOT_ID).and_then(|inode| inode.upgrade()) {
+            return inode;
+        }
+        let entry = ShortEntry::new([b' '; 11], ATTR_DIRECTORY, self.bpb.root_cluster);
+        let clusters = self.root_clusters.lock().clone();
+        let inode = INodeImpl::new(fs, None, entry, clusters, None);
+        inodes.insert(ROOT_ID, Arc::downgrade(&inode));
+        inode
+    }
+
+    fn info(&self) -> &'static FsInfo {
+        static INFO: FsInfo = FsInfo {
+            max_file_size: 0xffff_ffff,
+        };
+        &INFO
+    }
+}
+
+/// A parsed directory entry
+struct DirEntry {
+    name: String,
+    /// Index of the first slot, including long name entries
+    first: usize,
+    /// Index of the short entry
+    index: usize,
+    entry: ShortEntry,
+}
+
+pub struct INodeImpl {
+    fs: Arc<Fat32FS>,
+    inner: RwLock<INodeInner>,
+}
+
+struct INodeInner {
+    /// Position of the directory entry, `None` for the root.
+    /// It is also the inode id, and changes when the file is moved.
+    pos: Option<usize>,
+    entry: ShortEntry,
+    clusters: Vec<u32>,
+    parent: Option<Arc<INodeImpl>>,
+    this: Weak<INodeImpl>,
+    /// Unlinked while opened, free the clusters when dropped
+    removed: bool,
+}
+
+impl INodeImpl {
+    pub fn statfs(&self) -> Result<FsStat> {
+        self.fs.statfs()
+    }
+
+    pub fn set_times(&self, atime: Option<Timespec>, mtime: Option<Timespec>) -> Result<()> {
+        let mut inner = self.inner.write();
+        if let Some(atime) = atime {
+            inner.entry.set_atime(atime.sec);
+        }
+        if let Some(mtime) = mtime {
+            inner.entry.set_mtime(mtime.sec);
+        }
+        inner.entry.set_ctime(current_time().sec);
+        self.sync_entry(&inner)
+    }
+
+    fn new(
+        fs: Arc<Fat32FS>,
+        pos: Option<usize>,
+        entry: ShortEntry,
+        clusters: Vec<u32>,
+        parent: Option<Arc<INodeImpl>>,
+    ) -> Arc<Self> {
+        let inode = Arc::new(INodeImpl {
+            fs,
+            inner: RwLock::new(INodeInner {
+                pos,
+                entry,
+                clusters,
+                parent,
+                this: Weak::new(),
+                removed: false,
+            }),
+        });
+        inode.inner.write().this = Arc::downgrade(&inode);
+        inode
+    }
+
+    /// Get the INode of `entry` at `pos` in this directory
+    fn child(&self, inner: &INodeInner, pos: usize, entry: &ShortEntry) -> Result<Arc<INodeImpl>> {
+        let mut inodes = self.fs.inodes.write();
+        if let Some(inode) = inodes.get(&pos).and_then(|inode| inode.upgrade()) {
+            return Ok(inode);
+        }
+        let clusters = self.fs.read_chain(entry.cluster)?;
+        let inode = INodeImpl::new(
+            self.fs.clone(),
+            Some(pos),
+            entry.clone(),
+            clusters,
+            inner.this.upgrade(),
+        );
+        inodes.insert(pos, Arc::downgrade(&inode));
+        Ok(inode)
+    }
+
+    /// Write the entry back to the parent directory
+    fn sync_entry(&self, inner: &INodeInner) -> Result<()> {
+        match inner.pos {
+            Some(pos) => self.fs.write_bytes(pos, &inner.entry.to_bytes()),
+            None => Ok(()),
+        }
+    }
+
+    /// Extend or truncate the cluster chain to `count` clusters
+    fn resize_chain(&self, inner: &mut INodeInner, count: usize) -> Result<()> {
+        while inner.clusters.len() < count {
+            let cluster = self.fs.alloc_cluster()?;
+            match inner.clusters.last() {
+                Some(&last) => self.fs.set_fat_entry(last, cluster)?,
+                None => inner.entry.cluster = cluster,
+            }
+            inner.clusters.push(cluster);
+        }
+        if inner.clusters.len() > count {
+            let freed = inner.clusters.split_off(count);
+            match inner.clusters.last() {
+                Some(&last) => self.fs.set_fat_entry(last, CLUSTER_MASK)?,
+                None => inner.entry.cluster = 0,
+            }
+            self.fs.free_clusters(&freed)?;
+        }
+        if inner.pos.is_none() {
+            *self.fs.root_clusters.lock() = inner.clusters.clone();
+        }
+        Ok(())
+    }
+
+    /// Change the size of a file, the extended part reads as zero
+    fn set_size(&self, inner: &mut INodeInner, len: usize) -> Result<()> {
+        if len > 0xffff_ffff {
+            return Err(FsError::InvalidParam);
+        }
+        let cluster_size = self.fs.bpb.cluster_size();
+        let old = inner.entry.size as usize;
+        let allocated = inner.clusters.len() * cluster_size;
+        if len > old && old < allocated {
+            // clear the stale data after the old end, new clusters are zeroed
+            let end = len.min(allocated);
+            self.fs
+                .write_clusters(&inner.clusters, old, &vec![0u8; end - old])?;
+        }
+        self.resize_chain(inner, (len + cluster_size - 1) / cluster_size)?;
+        inner.entry.size = len as u32;
+        self.sync_entry(inner)
+    }
+
+    fn check_dir(inner: &INodeInner) -> Result<()> {
+        if !inner.entry.is_dir() {
+            return Err(FsError::NotDir);
+        }
+        if inner.removed {
+            return Err(FsError::DirRemoved);
+        }
+        Ok(())
+    }
+
+    fn find_entry(&self, inner: &INodeInner, name: &str) -> Result<DirEntry> {
+        self.fs
+            .dir_entries(&inner.clusters)?
+            .into_iter()
+            // names are case insensitive
+            .find(|entry| eq_ignore_case(&entry.name, name))
+            .ok_or(FsError::EntryNotFound)
+    }
+
+    /// Add `entry` with `name` to this directory, return where it is.
+    /// The short name of `entry` is generated from `name`.
+    fn insert_entry(
+        &self,
+        inner: &mut INodeInner,
+        name: &str,
+        entry: &mut ShortEntry,
+    ) -> Result<DirEntry> {
+        let entries = self.fs.dir_entries(&inner.clusters)?;
+        let long_name: Vec<u16> = match exact_short_name(name) {
+            Some((short, case)) if entries.iter().all(|e| e.entry.name != short) => {
+                entry.name = short;
+                entry.case = case;
+                Vec::new()
+            }
+            _ => {
+                entry.name = generate_short_name(name, |short| {
+                    entries.iter().any(|e| &e.entry.name == short)
+                })
+                .ok_or(FsError::NoDeviceSpace)?;
+                entry.case = 0;
+                name.encode_utf16().collect()
+            }
+        };
+        let long_count = (long_name.len() + LONG_NAME_CHARS - 1) / LONG_NAME_CHARS;
+        let count = long_count + 1;
+
+        // find `count` free slots in a row
+        let data = self.fs.read_dir(&inner.clusters)?;
+        let slots = data.len() / DIR_ENTRY_SIZE;
+        let mut run_start = 0;
+        let mut run = 0;
+        for i in 0..slots {
+            match data[i * DIR_ENTRY_SIZE] {
+                0 | DELETED => {
+                    if run == 0 {
+                        run_start = i;
+                    }
+                    run += 1;
+                    if run == count {
+                        break;
+                    }
+                }
+                _ => run = 0,
+            }
+        }
+        let start = if run > 0 { run_start } else { slots };
+        if start + count > slots {
+            // extend the directory with zeroed clusters
+            let per_cluster = self.fs.bpb.cluster_size() / DIR_ENTRY_SIZE;
+            let new_slots = start + count - slots;
+            let clusters = inner.clusters.len() + (new_slots + per_cluster - 1) / per_cluster;
+            self.resize_chain(inner, clusters)?;
+        }
+
+        let sum = checksum(&entry.name);
+        for i in 0..long_count {
+            let order = long_count - i;
+            let raw = long_entry(&long_name, order, i == 0, sum);
+            let pos = self.fs.entry_pos(&inner.clusters, start + i);
+            self.fs.write_bytes(pos, &raw)?;
+        }
+        let index = start + long_count;
+        let pos = self.fs.entry_pos(&inner.clusters, index);
+        self.fs.write_bytes(pos, &entry.to_bytes())?;
+        inner.entry.touch_modified(current_time().sec);
+        self.sync_entry(inner)?;
+        Ok(DirEntry {
+            name: String::from(name),
+            first: start,
+            index,
+            entry: entry.clone(),
+        })
+    }
+
+    /// Write "." and ".." to `cluster` of a new subdirectory
+    fn init_dir(&self, inner: &INodeInner, cluster: u32) -> Result<()> {
+        // ".." points to cluster 0 in subdirectories of the root
+        let parent = match inner.pos {
+            Some(_) => inner.entry.cluster,
+            None => 0,
+        };
+        let mut dots = [0u8; DIR_ENTRY_SIZE * 2];
+        let dot = ShortEntry::new(*b".          ", ATTR_DIRECTORY, cluster);
+        let dotdot = ShortEntry::new(*b"..         ", ATTR_DIRECTORY, parent);
+        dots[..DIR_ENTRY_SIZE].copy_from_slice(&dot.to_bytes());
+        dots[DIR_ENTRY_SIZE..].copy_from_slice(&dotdot.to_bytes());
+        self.fs
+            .write_bytes(self.fs.bpb.cluster_offset(cluster), &dots)
+    }
+
+    /// Mark the slots of `entry` deleted
+    fn remove_entry(&self, inner: &mut INodeInner, entry: &DirEntry) -> Result<()> {
+        for i in entry.first..=entry.index {
+            let pos = self.fs.entry_pos(&inner.clusters, i);
+            self.fs.write_bytes(pos, &[DELETED])?;
+        }
+        inner.entry.touch_modified(current_time().sec);
+        self.sync_entry(inner)
+    }
+
+    /// Remove `entry` and release its clusters, unless it is still opened
+    fn delete_entry(&self, inner: &mut INodeInner, entry: &DirEntry) -> Result<()> {
+        if entry.entry.is_dir() {
+            let clusters = self.fs.read_chain(entry.entry.cluster)?;
+            if !self.fs.dir_entries(&clusters)?.is_empty() {
+                return Err(FsError::DirNotEmpty);
+            }
+        }
+        self.remove_entry(inner, entry)?;
+        let pos = self.fs.entry_pos(&inner.clusters, entry.index);
+        let opened = self
+            .fs
+            .inodes
+            .write()
+            .remove(&pos)
+            .and_then(|inode| inode.upgrade());
+        match opened {
+            Some(inode) => inode.inner.write().removed = true,
+            None => {
+                let clusters = self.fs.read_chain(entry.entry.cluster)?;
+                self.fs.free_clusters(&clusters)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for INodeImpl {
+    fn drop(&mut self) {
+        let inner = self.inner.read();
+        if inner.removed {
+            if let Err(err) = self.fs.free_clusters(&inner.clusters) {
+                warn!("fat32: failed to free clusters: {:?}", err);
+            }
+        }
+    }
+}
+
+fn eq_ignore_case(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a
+            .chars()
+            .zip(b.chars())
+            .all(|(a, b)| a.to_lowercase().eq(b.to_lowercase()))
+}
+
+fn check_name(name: &str) -> Result<()> {
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(FsError::InvalidParam);
+    }
+    let invalid = |c: char| (c as u32) < 0x20 || "\"*/:<>?\\|".contains(c);
+    if name.encode_utf16().count() > MAX_NAME_LEN || name.chars().any(invalid) {
+        return Err(FsError::InvalidParam);
+    }
+    Ok(())
+}
+
+impl INode for INodeImpl {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let inner = self.inner.read();
+        if inner.entry.is_dir() {
+            return Err(FsError::IsDir);
+        }
+        let size = inner.entry.size as usize;
+        if offset >= size {
+            return Ok(0);
+        }
+        let len = buf.len().min(size - offset);
+        self.fs
+            .read_clusters(&inner.clusters, offset, &mut buf[..len])?;
+        // only the date of access is recorded
+        let now = current_time().sec;
+        let outdated = inner.entry.adate < to_fat_time(now).0;
+        drop(inner);
+        if outdated {
+            let mut inner = self.inner.write();
+            inner.entry.set_atime(now);
+            if let Err(err) = self.sync_entry(&inner) {
+                warn!("fat32: failed to update access date: {:?}", err);
+            }
+        }
+        Ok(len)
+    }
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        let mut inner = self.inner.write();
+        if inner.entry.is_dir() {
+            return Err(FsError::IsDir);
+        }
+        let end = offset + buf.len();
+        inner.entry.touch_modified(current_time().sec);
+        if end > inner.entry.size as usize {
+            self.set_size(&mut inner, end)?;
+        } else {
+            self.sync_entry(&inner)?;
+        }
+        self.fs.write_clusters(&inner.clusters, offset, buf)?;
+        Ok(buf.len())
+    }
+    fn metadata(&self) -> Result<Metadata> {
+        let inner = self.inner.read();
+        let entry = &inner.entry;
+        let cluster_size = self.fs.bpb.cluster_size();
+        let type_ = if entry.is_dir() {
+            FileType::Dir
+        } else {
+            FileType::File
+        };
+        let time = |date, time| Timespec {
+            sec: to_unix_time(date, time),
+            nsec: 0,
+        };
+        Ok(Metadata {
+            dev: 0,
+            inode: inner.pos.unwrap_or(ROOT_ID),
+            size: match type_ {
+                FileType::Dir => inner.clusters.len() * cluster_size,
+                _ => entry.size as usize,
+            },
+            blk_size: cluster_size,
+            blocks: inner.clusters.len(),
+            atime: time(entry.adate, 0),
+            mtime: time(entry.mdate, entry.mtime),
+            ctime: time(entry.cdate, entry.ctime),
+            type_,
+            // FAT has only a read only attribute
+            mode: if entry.attr & ATTR_READ_ONLY != 0 {
+                0o555
+            } else {
+                0o755
+            },
+            nlinks: match type_ {
+                FileType::Dir => 2,
+                _ => 1,
+            },
+            uid: 0,
+            gid: 0,
+        })
+    }
+    fn chmod(&self, mode: u16) -> Result<()> {
+        let mut inner = self.inner.write();
+        if mode & 0o222 == 0 {
+            inner.entry.attr |= ATTR_READ_ONLY;
+        } else {
+            inner.entry.attr &= !ATTR_READ_ONLY;
+        }
+        inner.entry.set_ctime(current_time().sec);
+        self.sync_entry(&inner)
+    }
+    fn sync_all(&self) -> Result<()> {
+        Ok(())
+    }
+    fn sync_data(&self) -> Result<()> {
+        Ok(())
+    }
+    fn resize(&self, len: usize) -> Result<()> {
+        let mut inner = self.inner.write();
+        if inner.entry.is_dir() {
+            return Err(FsError::IsDir);
+        }
+        inner.entry.touch_modified(current_time().sec);
+        self.set_size(&mut inner, len)
+    }
+    fn create(&self, name: &str, type_: FileType, mode: u32) -> Result<Arc<INode>> {
+        let mut inner = self.inner.write();
+        Self::check_dir(&inner)?;
+        check_name(name)?;
+        if self.find_entry(&inner, name).is_ok() {
+            return Err(FsError::EntryExist);
+        }
+        let mut entry = match type_ {
+            FileType::File => ShortEntry::new([b' '; 11], ATTR_ARCHIVE, 0),
+            FileType::Dir => ShortEntry::new([b' '; 11], ATTR_DIRECTORY, self.fs.alloc_cluster()?),
+            _ => return Err(FsError::NotSupported),
+        };
+        if mode & 0o222 == 0 {
+            entry.attr |= ATTR_READ_ONLY;
+        }
+        let now = current_time().sec;
+        entry.set_atime(now);
+        entry.touch_modified(now);
+        let result = match type_ {
+            FileType::Dir => self.init_dir(&inner, entry.cluster),
+            _ => Ok(()),
+        };
+        let inserted = result.and_then(|_| self.insert_entry(&mut inner, name, &mut entry));
+        let new = match inserted {
+            Ok(new) => new,
+            Err(err) => {
+                // the new directory is not in any entry yet
+                if entry.cluster != 0 {
+                    self.fs.free_clusters(&[entry.cluster])?;
+                }
+                return Err(err);
+            }
+        };
+        let pos = self.fs.entry_pos(&inner.clusters, new.index);
+        Ok(self.child(&inner, pos, &entry)?)
+    }
+    fn unlink(&self, name: &str) -> Result<()> {
+        let mut inner = self.inner.write();
+        Self::check_dir(&inner)?;
+        if name == "." || name == ".." {
+            return Err(FsError::IsDir);
+        }
+        let entry = self.find_entry(&inner, name)?;
+        self.delete_entry(&mut inner, &entry)
+    }
+    fn link(&self, _name: &str, _other: &Arc<INode>) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+    fn move_(&self, old_name: &str, target: &Arc<INode>, new_name: &str) -> Result<()> {
+        let target = target
+            .as_any_ref()
+            .downcast_ref::<INodeImpl>()
+            .ok_or(FsError::NotSameFs)?;
+        if !Arc::ptr_eq(&self.fs, &target.fs) {
+            return Err(FsError::NotSameFs);
+        }
+        check_name(new_name)?;
+        if old_name == "." || old_name == ".." {
+            return Err(FsError::IsDir);
+        }
+        let _rename = self.fs.rename_lock.lock();
+        let same_dir = core::ptr::eq(self, target);
+
+        // a directory can't be moved into itself or its descendants
+        let old_pos = {
+            let inner = self.inner.read();
+            let old = self.find_entry(&inner, old_name)?;
+            self.fs.entry_pos(&inner.clusters, old.index)
+        };
+        if !same_dir {
+            let mut dir = target.inner.read().this.upgrade();
+            while let Some(inode) = dir {
+                if core::ptr::eq(&*inode, self) {
+                    break;
+                }
+                let inner = inode.inner.read();
+                if inner.pos == Some(old_pos) {
+                    return Err(FsError::InvalidParam);
+                }
+                dir = inner.parent.clone();
+            }
+        }
+
+        let mut inner = self.inner.write();
+        Self::check_dir(&inner)?;
+        let old = self.find_entry(&inner, old_name)?;
+        let mut target_inner = if same_dir {
+            None
+        } else {
+            Some(target.inner.write())
+        };
+        let new_dir = match target_inner {
+            Some(ref mut target_inner) => &mut **target_inner,
+            None => &mut *inner,
+        };
+        Self::check_dir(new_dir)?;
+        // check the entry to replace before changing anything
+        let existing = match target.find_entry(new_dir, new_name) {
+            Ok(ref existing) if same_dir && existing.index == old.index => None,
+            Ok(existing) => {
+                match (old.entry.is_dir(), existing.entry.is_dir()) {
+                    (true, false) => return Err(FsError::NotDir),
+                    (false, true) => return Err(FsError::IsDir),
+                    (true, true) => {
+                        let clusters = self.fs.read_chain(existing.entry.cluster)?;
+                        if !self.fs.dir_entries(&clusters)?.is_empty() {
+                            return Err(FsError::DirNotEmpty);
+                        }
+                    }
+                    (false, false) => {}
+                }
+                Some(existing)
+            }
+            Err(FsError::EntryNotFound) => None,
+            Err(err) => return Err(err),
+        };
+        // add the new entry first, so that the file is not lost if it fails
+        let mut entry = old.entry.clone();
+        entry.set_ctime(current_time().sec);
+        let new = target.insert_entry(new_dir, new_name, &mut entry)?;
+        if let Some(existing) = existing {
+            if let Err(err) = target.delete_entry(new_dir, &existing) {
+                target.remove_entry(new_dir, &new)?;
+                return Err(err);
+            }
+        }
+        let new_pos = self.fs.entry_pos(&new_dir.clusters, new.index);
+        let new_parent = new_dir.this.upgrade();
+        // ".." of a subdirectory of the root points to cluster 0
+        let new_parent_cluster = match new_dir.pos {
+            Some(_) => new_dir.entry.cluster,
+            None => 0,
+        };
+        self.remove_entry(&mut inner, &old)?;
+        if old.entry.is_dir() && !same_dir {
+            // update ".." of the moved directory
+            let clusters = self.fs.read_chain(old.entry.cluster)?;
+            let pos = self.fs.entry_pos(&clusters, 1);
+            let mut raw = [0u8; DIR_ENTRY_SIZE];
+            self.fs.read_bytes(pos, &mut raw)?;
+            let mut dotdot = ShortEntry::parse(&raw);
+            dotdot.cluster = new_parent_cluster;
+            self.fs.write_bytes(pos, &dotdot.to_bytes())?;
+        }
+        // the opened INode follows its entry,
+        // don't lock it with the table locked, which may dead lock with `child`
+        let moved = self
+            .fs
+            .inodes
+            .write()
+            .remove(&old_pos)
+            .and_then(|inode| inode.upgrade());
+        if let Some(inode) = moved {
+            {
+                let mut moved = inode.inner.write();
+                moved.pos = Some(new_pos);
+                moved.entry.name = entry.name;
+                moved.entry.case = entry.case;
+                moved.entry.cdate = entry.cdate;
+                moved.entry.ctime = entry.ctime;
+                moved.parent = new_parent;
+            }
+            self.fs
+                .inodes
+                .write()
+                .insert(new_pos, Arc::downgrade(&inode));
+        }
+        Ok(())
+    }
+    fn find(&self, name: &str) -> Result<Arc<INode>> {
+        let inner = self.inner.read();
+        Self::check_dir(&inner)?;
+        match name {
+            "." => Ok(inner.this.upgrade().unwrap()),
+            ".." => match inner.parent {
+                Some(ref parent) => Ok(parent.clone()),
+                None => Ok(inner.this.upgrade().unwrap()),
+            },
+            _ => {
+                let entry = self.find_entry(&inner, name)?;
+                let pos = self.fs.entry_pos(&inner.clusters, entry.index);
+                Ok(self.child(&inner, pos, &entry.entry)?)
+            }
+        }
+    }
+    fn get_entry(&self, id: usize) -> Result<String> {
+        let inner = self.inner.read();
+        Self::check_dir(&inner)?;
+        match id {
+            0 => Ok(String::from(".")),
+            1 => Ok(String::from("..")),
+            _ => self
+                .fs
+                .dir_entries(&inner.clusters)?
+                .into_iter()
+                .nth(id - 2)
+                .map(|entry| entry.name)
+                .ok_or(FsError::EntryNotFound),
+        }
+    }
+    fn fs(&self) -> Arc<FileSystem> {
+        self.fs.clone()
+    }
+    fn as_any_ref(&self) -> &Any {
+        self
+    }
+}
@@ -0,0 +1,588 @@
+//! On-disk structures of FAT32
+
+use alloc::{string::String, vec::Vec};
+
+pub const SECTOR_SIZE: usize = 512;
+pub const DIR_ENTRY_SIZE: usize = 32;
+
+pub const ATTR_READ_ONLY: u8 = 0x01;
+pub const ATTR_VOLUME_ID: u8 = 0x08;
+pub const ATTR_DIRECTORY: u8 = 0x10;
+pub const ATTR_ARCHIVE: u8 = 0x20;
+/// Attribute of long name entries
+pub const ATTR_LONG_NAME: u8 = 0x0f;
+const ATTR_LONG_NAME_MASK: u8 = 0x3f;
+
+/// First byte of a deleted entry
+pub const DELETED: u8 = 0xe5;
+/// Flag in the order of the last long name entry, which is the first on disk
+pub const LAST_LONG_ENTRY: u8 = 0x40;
+/// Number of UCS-2 characters in a long name entry
+pub const LONG_NAME_CHARS: usize = 13;
+/// Max number of UCS-2 characters in a long name
+pub const MAX_NAME_LEN: usize = 255;
+/// Offsets of the characters in a long name entry
+const LONG_NAME_OFFSETS: [usize; LONG_NAME_CHARS] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+
+/// Flags of lower case base and extension in `ShortEntry::case`
+const CASE_LOWER_BASE: u8 = 0x08;
+const CASE_LOWER_EXT: u8 = 0x10;
+
+/// Only the low 28 bits of a FAT entry are used
+pub const CLUSTER_MASK: u32 = 0x0fff_ffff;
+/// FAT entries not less than it mark the end of a chain
+pub const END_OF_CHAIN: u32 = 0x0fff_fff8;
+
+fn read_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+pub fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([buf[offset], buf[offset + 1], buf[offset + 2], buf[offset + 3]])
+}
+
+fn write_u16(buf: &mut [u8], offset: usize, value: u16) {
+    buf[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn write_u32(buf: &mut [u8], offset: usize, value: u32) {
+    buf[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// BIOS parameter block in the boot sector
+#[derive(Debug, Clone)]
+pub struct BootSector {
+    pub bytes_per_sector: usize,
+    pub sectors_per_cluster: usize,
+    pub reserved_sectors: usize,
+    pub num_fats: usize,
+    pub sectors_per_fat: usize,
+    pub total_sectors: usize,
+    pub root_cluster: u32,
+}
+
+impl BootSector {
+    /// Parse the boot sector, return `None` if it is not FAT32
+    pub fn parse(buf: &[u8]) -> Option<Self> {
+        if buf[510] != 0x55 || buf[511] != 0xaa {
+            return None;
+        }
+        let bytes_per_sector = read_u16(buf, 11) as usize;
+        let sectors_per_cluster = buf[13] as usize;
+        let root_entries = read_u16(buf, 17);
+        let sectors_per_fat_16 = read_u16(buf, 22);
+        let total_sectors = match read_u16(buf, 19) {
+            0 => read_u32(buf, 32) as usize,
+            n => n as usize,
+        };
+        let bpb = BootSector {
+            bytes_per_sector,
+            sectors_per_cluster,
+            reserved_sectors: read_u16(buf, 14) as usize,
+            num_fats: buf[16] as usize,
+            sectors_per_fat: read_u32(buf, 36) as usize,
+            total_sectors,
+            root_cluster: read_u32(buf, 44),
+        };
+        let fats_end = (bpb.num_fats)
+            .checked_mul(bpb.sectors_per_fat)
+            .and_then(|sectors| sectors.checked_add(bpb.reserved_sectors));
+        // FAT12 and FAT16 have a fixed root directory and 16-bit FAT size
+        let valid = bytes_per_sector.is_power_of_two()
+            && bytes_per_sector >= SECTOR_SIZE
+            && bytes_per_sector <= 4096
+            && sectors_per_cluster.is_power_of_two()
+            && bpb.num_fats != 0
+            && root_entries == 0
+            && sectors_per_fat_16 == 0
+            && bpb.sectors_per_fat != 0
+            && fats_end.map_or(false, |end| end < total_sectors);
+        // the FAT must cover all clusters, including the root directory
+        if valid
+            && bpb.sectors_per_fat
+                >= (bpb.cluster_count() + 2 + bytes_per_sector / 4 - 1) / (bytes_per_sector / 4)
+            && bpb.root_cluster >= 2
+            && (bpb.root_cluster as usize) < bpb.cluster_count() + 2
+        {
+            Some(bpb)
+        } else {
+            None
+        }
+    }
+
+    pub fn cluster_size(&self) -> usize {
+        self.bytes_per_sector * self.sectors_per_cluster
+    }
+
+    /// Byte offset of the `index`-th FAT
+    pub fn fat_offset(&self, index: usize) -> usize {
+        (self.reserved_sectors + index * self.sectors_per_fat) * self.bytes_per_sector
+    }
+
+    fn data_sector(&self) -> usize {
+        self.reserved_sectors + self.num_fats * self.sectors_per_fat
+    }
+
+    /// Byte offset of `cluster`, the first data cluster is 2
+    pub fn cluster_offset(&self, cluster: u32) -> usize {
+        (self.data_sector() + (cluster as usize - 2) * self.sectors_per_cluster)
+            * self.bytes_per_sector
+    }
+
+    /// Number of data clusters
+    pub fn cluster_count(&self) -> usize {
+        (self.total_sectors - self.data_sector()) / self.sectors_per_cluster
+    }
+}
+
+/// Find the first FAT32 partition in the MBR, return its start sector
+pub fn find_fat32_partition(mbr: &[u8]) -> Option<usize> {
+    if mbr[510] != 0x55 || mbr[511] != 0xaa {
+        return None;
+    }
+    (0..4)
+        .map(|i| &mbr[446 + i * 16..446 + (i + 1) * 16])
+        // FAT32 with CHS or LBA addressing
+        .find(|entry| entry[4] == 0x0b || entry[4] == 0x0c)
+        .map(|entry| read_u32(entry, 8) as usize)
+}
+
+/// 8.3 directory entry
+#[derive(Debug, Clone, Default)]
+pub struct ShortEntry {
+    pub name: [u8; 11],
+    pub attr: u8,
+    pub case: u8,
+    pub ctime: u16,
+    pub cdate: u16,
+    pub adate: u16,
+    pub mtime: u16,
+    pub mdate: u16,
+    pub cluster: u32,
+    pub size: u32,
+}
+
+impl ShortEntry {
+    pub fn new(name: [u8; 11], attr: u8, cluster: u32) -> Self {
+        ShortEntry {
+            name,
+            attr,
+            cluster,
+            // 1980-01-01
+            cdate: 0x21,
+            adate: 0x21,
+            mdate: 0x21,
+            ..ShortEntry::default()
+        }
+    }
+
+    pub fn parse(buf: &[u8]) -> Self {
+        let mut name = [0u8; 11];
+        name.copy_from_slice(&buf[..11]);
+        ShortEntry {
+            name,
+            attr: buf[11],
+            case: buf[12],
+            ctime: read_u16(buf, 14),
+            cdate: read_u16(buf, 16),
+            adate: read_u16(buf, 18),
+            mtime: read_u16(buf, 22),
+            mdate: read_u16(buf, 24),
+            cluster: (read_u16(buf, 20) as u32) << 16 | read_u16(buf, 26) as u32,
+            size: read_u32(buf, 28),
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; DIR_ENTRY_SIZE] {
+        let mut buf = [0u8; DIR_ENTRY_SIZE];
+        buf[..11].copy_from_slice(&self.name);
+        buf[11] = self.attr;
+        buf[12] = self.case;
+        write_u16(&mut buf, 14, self.ctime);
+        write_u16(&mut buf, 16, self.cdate);
+        write_u16(&mut buf, 18, self.adate);
+        write_u16(&mut buf, 20, (self.cluster >> 16) as u16);
+        write_u16(&mut buf, 22, self.mtime);
+        write_u16(&mut buf, 24, self.mdate);
+        write_u16(&mut buf, 26, self.cluster as u16);
+        write_u32(&mut buf, 28, self.size);
+        buf
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.attr & ATTR_DIRECTORY != 0
+    }
+
+    /// Set the access date to `secs` since the UNIX epoch
+    pub fn set_atime(&mut self, secs: i64) {
+        self.adate = to_fat_time(secs).0;
+    }
+
+    pub fn set_mtime(&mut self, secs: i64) {
+        let (date, time) = to_fat_time(secs);
+        self.mdate = date;
+        self.mtime = time;
+    }
+
+    /// The creation time is used as the change time, like Linux does
+    pub fn set_ctime(&mut self, secs: i64) {
+        let (date, time) = to_fat_time(secs);
+        self.cdate = date;
+        self.ctime = time;
+    }
+
+    /// Record a modification of the content at `secs`
+    pub fn touch_modified(&mut self, secs: i64) {
+        self.set_mtime(secs);
+        self.set_ctime(secs);
+    }
+
+    /// Whether it is "." or ".."
+    pub fn is_dot(&self) -> bool {
+        &self.name == b".          " || &self.name == b"..         "
+    }
+
+    /// Name like "README.TXT", lower cased as the case flags say
+    pub fn display_name(&self) -> String {
+        let mut name = self.name;
+        if name[0] == 0x05 {
+            // 0xe5 in the first byte is escaped
+            name[0] = DELETED;
+        }
+        let lower = |bytes: &[u8], flag: u8| -> String {
+            bytes
+                .iter()
+                .take_while(|&&c| c != b' ')
+                .map(|&c| {
+                    if self.case & flag != 0 {
+                        c.to_ascii_lowercase() as char
+                    } else {
+                        c as char
+                    }
+                })
+                .collect()
+        };
+        let base = lower(&name[..8], CASE_LOWER_BASE);
+        let ext = lower(&name[8..], CASE_LOWER_EXT);
+        if ext.is_empty() {
+            base
+        } else {
+            base + "." + &ext
+        }
+    }
+}
+
+/// Checksum of the short name, stored in its long name entries
+pub fn checksum(name: &[u8; 11]) -> u8 {
+    name.iter()
+        .fold(0u8, |sum, &c| ((sum & 1) << 7).wrapping_add(sum >> 1).wrapping_add(c))
+}
+
+pub fn is_long_entry(buf: &[u8]) -> bool {
+    buf[11] & ATTR_LONG_NAME_MASK == ATTR_LONG_NAME
+}
+
+/// Characters in a long name entry
+pub fn long_entry_chars(buf: &[u8]) -> [u16; LONG_NAME_CHARS] {
+    let mut chars = [0u16; LONG_NAME_CHARS];
+    for (c, &offset) in chars.iter_mut().zip(LONG_NAME_OFFSETS.iter()) {
+        *c = read_u16(buf, offset);
+    }
+    chars
+}
+
+/// Build the long name entry of `order` (from 1) for `name`
+pub fn long_entry(name: &[u16], order: usize, last: bool, checksum: u8) -> [u8; DIR_ENTRY_SIZE] {
+    let mut buf = [0u8; DIR_ENTRY_SIZE];
+    buf[0] = order as u8 | if last { LAST_LONG_ENTRY } else { 0 };
+    buf[11] = ATTR_LONG_NAME;
+    buf[13] = checksum;
+    for (i, &offset) in LONG_NAME_OFFSETS.iter().enumerate() {
+        let index = (order - 1) * LONG_NAME_CHARS + i;
+        // terminated by 0 and padded with 0xffff
+        let c = match index {
+            _ if index < name.len() => name[index],
+            _ if index == name.len() => 0,
+            _ => 0xffff,
+        };
+        write_u16(&mut buf, offset, c);
+    }
+    buf
+}
+
+/// Decode a long name from its entries in disk order
+pub fn decode_long_name(entries: &[[u16; LONG_NAME_CHARS]]) -> Option<String> {
+    let chars: Vec<u16> = entries
+        .iter()
+        .rev()
+        .flat_map(|chars| chars.iter().cloned())
+        .take_while(|&c| c != 0)
+        .collect();
+    String::from_utf16(&chars).ok()
+}
+
+/// Characters allowed in short names besides letters and digits
+const SHORT_NAME_SPECIAL: &[u8] = b"$%'-_@~`!(){}^#&";
+
+fn is_short_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || SHORT_NAME_SPECIAL.contains(&c)
+}
+
+/// Try to store `name` as an 8.3 name without long name entries,
+/// which requires the base and extension each to be in a single case
+pub fn exact_short_name(name: &str) -> Option<([u8; 11], u8)> {
+    let (base, ext) = match name.rfind('.') {
+        Some(pos) => (&name[..pos], &name[pos + 1..]),
+        None => (name, ""),
+    };
+    if base.is_empty() || base.len() > 8 || ext.len() > 3 || name.ends_with('.') {
+        return None;
+    }
+    let mut short = [b' '; 11];
+    let mut case = 0;
+    if fill_short_part(base, &mut short[..8])? {
+        case |= CASE_LOWER_BASE;
+    }
+    if fill_short_part(ext, &mut short[8..])? {
+        case |= CASE_LOWER_EXT;
+    }
+    if short[0] == DELETED {
+        short[0] = 0x05;
+    }
+    Some((short, case))
+}
+
+/// Copy `part` of a name to `dst` in upper case,
+/// return whether it was lower case, or `None` if it is not valid in a short name
+fn fill_short_part(part: &str, dst: &mut [u8]) -> Option<bool> {
+    let bytes = part.as_bytes();
+    if !bytes.iter().all(|&c| is_short_char(c)) {
+        return None;
+    }
+    let has_lower = bytes.iter().any(|c| c.is_ascii_lowercase());
+    let has_upper = bytes.iter().any(|c| c.is_ascii_uppercase());
+    if has_lower && has_upper {
+        return None;
+    }
+    for (d, c) in dst.iter_mut().zip(bytes.iter()) {
+        *d = c.to_ascii_uppercase();
+    }
+    Some(has_lower)
+}
+
+/// Generate a short name like "LONGNA~1.TXT" for `name`,
+/// `exists` tells whether a short name is used in the directory
+pub fn generate_short_name(name: &str, exists: impl Fn(&[u8; 11]) -> bool) -> Option<[u8; 11]> {
+    let convert = |part: &str, max: usize| -> Vec<u8> {
+        part.bytes()
+            .filter(|&c| c != b' ' && c != b'.')
+            .map(|c| {
+                if is_short_char(c) {
+                    c.to_ascii_uppercase()
+                } else {
+                    b'_'
+                }
+            })
+            .take(max)
+            .collect()
+    };
+    let trimmed = name.trim_start_matches('.');
+    let (base, ext) = match trimmed.rfind('.') {
+        Some(pos) => (convert(&trimmed[..pos], 8), convert(&trimmed[pos + 1..], 3)),
+        None => (convert(trimmed, 8), Vec::new()),
+    };
+    let base = if base.is_empty() { vec![b'_'] } else { base };
+    for n in 1..1_000_000usize {
+        let tail = format!("~{}", n);
+        let keep = base.len().min(8 - tail.len());
+        let mut short = [b' '; 11];
+        short[..keep].copy_from_slice(&base[..keep]);
+        short[keep..keep + tail.len()].copy_from_slice(tail.as_bytes());
+        short[8..8 + ext.len()].copy_from_slice(&ext);
+        if !exists(&short) {
+            return Some(short);
+        }
+    }
+    None
+}
+
+/// Convert FAT date and time to seconds since the UNIX epoch
+pub fn to_unix_time(date: u16, time: u16) -> i64 {
+    let year = 1980 + (date >> 9) as i64;
+    let month = ((date >> 5) & 0xf) as i64;
+    let day = (date & 0x1f) as i64;
+    if month == 0 || day == 0 {
+        return 0;
+    }
+    // days from civil, shifting the year to start in March
+    let (y, m) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = y / 400;
+    let yoe = y - era * 400;
+    let doy = (153 * m + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+    let secs =
+        (time >> 11) as i64 * 3600 + ((time >> 5) & 0x3f) as i64 * 60 + (time & 0x1f) as i64 * 2;
+    days * 86400 + secs
+}
+
+/// Convert seconds since the UNIX epoch to FAT date and time,
+/// clamped to the years 1980 to 2107 which FAT can represent
+pub fn to_fat_time(secs: i64) -> (u16, u16) {
+    // 1980-01-01 00:00:00
+    if secs < 315_532_800 {
+        return (0x21, 0);
+    }
+    // civil from days, the year starts in March
+    let days = secs / 86400;
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = era * 400 + yoe + if month <= 2 { 1 } else { 0 };
+    if year > 2107 {
+        // 2107-12-31 23:59:58
+        return (0xff9f, 0xbf7d);
+    }
+    let secs = secs % 86400;
+    let date = ((year - 1980) << 9 | month << 5 | day) as u16;
+    let time = ((secs / 3600) << 11 | (secs / 60 % 60) << 5 | (secs % 60 / 2)) as u16;
+    (date, time)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Boot sector of a 64 MiB FAT32 image with 512-byte clusters
+    fn boot_sector() -> [u8; SECTOR_SIZE] {
+        let mut buf = [0u8; SECTOR_SIZE];
+        write_u16(&mut buf, 11, 512);
+        buf[13] = 1;
+        write_u16(&mut buf, 14, 32);
+        buf[16] = 2;
+        write_u32(&mut buf, 32, 131_072);
+        write_u32(&mut buf, 36, 1024);
+        write_u32(&mut buf, 44, 2);
+        buf[510] = 0x55;
+        buf[511] = 0xaa;
+        buf
+    }
+
+    #[test]
+    fn parse_boot_sector() {
+        let bpb = BootSector::parse(&boot_sector()).unwrap();
+        assert_eq!(bpb.cluster_size(), 512);
+        assert_eq!(bpb.fat_offset(1), (32 + 1024) * 512);
+        assert_eq!(bpb.cluster_offset(2), (32 + 2 * 1024) * 512);
+        assert_eq!(bpb.cluster_count(), 131_072 - 32 - 2 * 1024);
+    }
+
+    #[test]
+    fn reject_invalid_boot_sector() {
+        // FAT16 has root entries
+        let mut buf = boot_sector();
+        write_u16(&mut buf, 17, 512);
+        assert!(BootSector::parse(&buf).is_none());
+        // the FAT doesn't cover all clusters
+        let mut buf = boot_sector();
+        write_u32(&mut buf, 36, 16);
+        assert!(BootSector::parse(&buf).is_none());
+        // the FATs are larger than the volume
+        let mut buf = boot_sector();
+        write_u32(&mut buf, 36, 0x8000_0000);
+        assert!(BootSector::parse(&buf).is_none());
+        // the root directory is out of the volume
+        let mut buf = boot_sector();
+        write_u32(&mut buf, 44, 0x0fff_fff0);
+        assert!(BootSector::parse(&buf).is_none());
+        let mut buf = boot_sector();
+        buf[511] = 0;
+        assert!(BootSector::parse(&buf).is_none());
+    }
+
+    #[test]
+    fn short_entry_round_trip() {
+        let mut entry = ShortEntry::new(*b"README  TXT", ATTR_ARCHIVE, 0x0012_3456);
+        entry.size = 1234;
+        entry.set_mtime(1_000_000_000);
+        let parsed = ShortEntry::parse(&entry.to_bytes());
+        assert_eq!(parsed.name, entry.name);
+        assert_eq!(parsed.cluster, 0x0012_3456);
+        assert_eq!(parsed.size, 1234);
+        assert_eq!((parsed.mdate, parsed.mtime), (entry.mdate, entry.mtime));
+        assert_eq!(parsed.display_name(), "README.TXT");
+    }
+
+    #[test]
+    fn exact_short_names() {
+        assert_eq!(exact_short_name("README.TXT"), Some((*b"README  TXT", 0)));
+        let (name, case) = exact_short_name("readme.txt").unwrap();
+        assert_eq!(&name, b"README  TXT");
+        let mut entry = ShortEntry::new(name, ATTR_ARCHIVE, 0);
+        entry.case = case;
+        assert_eq!(entry.display_name(), "readme.txt");
+        // mixed case, too long or invalid characters need a long name
+        assert_eq!(exact_short_name("ReadMe.txt"), None);
+        assert_eq!(exact_short_name("longername.txt"), None);
+        assert_eq!(exact_short_name("a.html"), None);
+        assert_eq!(exact_short_name("a b"), None);
+        assert_eq!(exact_short_name("a.b.c"), None);
+        assert_eq!(exact_short_name(".profile"), None);
+    }
+
+    #[test]
+    fn generated_short_names() {
+        let name = generate_short_name("Long File Name.html", |_| false);
+        assert_eq!(&name.unwrap(), b"LONGFI~1HTM");
+        let name = generate_short_name("Long File Name.html", |short| short == b"LONGFI~1HTM");
+        assert_eq!(&name.unwrap(), b"LONGFI~2HTM");
+        let name = generate_short_name(".bashrc", |_| false);
+        assert_eq!(&name.unwrap(), b"BASHRC~1   ");
+        let name = generate_short_name("a+b", |_| false);
+        assert_eq!(&name.unwrap(), b"A_B~1      ");
+    }
+
+    #[test]
+    fn long_name_round_trip() {
+        let name = "a long name with ünicode.txt";
+        let chars: Vec<u16> = name.encode_utf16().collect();
+        let count = (chars.len() + LONG_NAME_CHARS - 1) / LONG_NAME_CHARS;
+        let sum = checksum(b"ALONGN~1TXT");
+        // the last entry comes first on disk
+        let entries: Vec<[u16; LONG_NAME_CHARS]> = (0..count)
+            .map(|i| {
+                let raw = long_entry(&chars, count - i, i == 0, sum);
+                assert!(is_long_entry(&raw));
+                assert_eq!(raw[13], sum);
+                long_entry_chars(&raw)
+            })
+            .collect();
+        assert_eq!(decode_long_name(&entries).unwrap(), name);
+    }
+
+    #[test]
+    fn fat_times() {
+        // 2000-02-29 12:34:56
+        assert_eq!(
+            to_fat_time(951_827_696),
+            (20 << 9 | 2 << 5 | 29, 12 << 11 | 34 << 5 | 28)
+        );
+        // 1980-12-31 23:59:59, in a leap year
+        assert_eq!(
+            to_fat_time(347_155_199),
+            (12 << 5 | 31, 23 << 11 | 59 << 5 | 29)
+        );
+        // out of range
+        assert_eq!(to_fat_time(0), (0x21, 0));
+        assert_eq!(to_fat_time(5_000_000_000), (0xff9f, 0xbf7d));
+    }
+}
@@ -4,11 +4,20 @@ use alloc::{string::String, sync::Arc};
 
 use rcore_fs::vfs::{FsError, INode, Metadata, Result};
 
+use super::lock::FlockOwner;
+use super::poll::{poll_inode, try_read_inode, PollStatus};
+use crate::sync::SpinNoIrqLock as Mutex;
+
 #[derive(Clone)]
 pub struct FileHandle {
     inode: Arc<INode>,
     offset: u64,
-    options: OpenOptions,
+    /// Status flags, shared with the duplicates made by dup and fork
+    options: Arc<Mutex<OpenOptions>>,
+    /// Path when it was opened, or a description like "pipe"
+    path: String,
+    /// Owner of the flock lock, shared with the duplicates
+    flock: Arc<FlockOwner>,
 }
 
 #[derive(Debug, Clone)]
@@ -17,6 +26,8 @@ pub struct OpenOptions {
     pub write: bool,
     /// Before each write, the file offset is positioned at the end of the file.
     pub append: bool,
+    /// Reads fail with `EAGAIN` instead of waiting for data.
+    pub nonblock: bool,
 }
 
 #[derive(Debug)]
@@ -27,11 +38,13 @@ pub enum SeekFrom {
 }
 
 impl FileHandle {
-    pub fn new(inode: Arc<INode>, options: OpenOptions) -> Self {
+    pub fn new(inode: Arc<INode>, options: OpenOptions, path: String) -> Self {
         FileHandle {
+            flock: Arc::new(FlockOwner::new(&inode)),
             inode,
             offset: 0,
-            options,
+            options: Arc::new(Mutex::new(options)),
+            path,
         }
     }
 
@@ -42,15 +55,28 @@ impl FileHandle {
     }
 
     pub fn read_at(&mut self, offset: usize, buf: &mut [u8]) -> Result<usize> {
-        if !self.options.read {
+        if !self.options.lock().read {
             return Err(FsError::InvalidParam); // FIXME: => EBADF
         }
         let len = self.inode.read_at(offset, buf)?;
         Ok(len)
     }
 
+    /// Read like `read` if that would not wait, or return `None`
+    pub fn try_read(&mut self, buf: &mut [u8]) -> Option<Result<usize>> {
+        if !self.options.lock().read {
+            return Some(Err(FsError::InvalidParam)); // FIXME: => EBADF
+        }
+        let result = try_read_inode(&*self.inode, self.offset as usize, buf)?;
+        if let Ok(len) = result {
+            self.offset += len as u64;
+        }
+        Some(result)
+    }
+
     pub fn write(&mut self, buf: &[u8]) -> Result<usize> {
-        let offset = match self.options.append {
+        let append = self.options.lock().append;
+        let offset = match append {
             true => self.inode.metadata()?.size as u64,
             false => self.offset,
         } as usize;
@@ -60,13 +86,18 @@ impl FileHandle {
     }
 
     pub fn write_at(&mut self, offset: usize, buf: &[u8]) -> Result<usize> {
-        if !self.options.write {
+        if !self.options.lock().write {
             return Err(FsError::InvalidParam); // FIXME: => EBADF
         }
         let len = self.inode.write_at(offset, buf)?;
         Ok(len)
     }
 
+    /// Whether `other` is a duplicate of this, made by dup or fork
+    pub fn same_open_file(&self, other: &FileHandle) -> bool {
+        Arc::ptr_eq(&self.options, &other.options)
+    }
+
     pub fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
         self.offset = match pos {
             SeekFrom::Start(offset) => offset,
@@ -77,7 +108,7 @@ impl FileHandle {
     }
 
     pub fn set_len(&mut self, len: u64) -> Result<()> {
-        if !self.options.write {
+        if !self.options.lock().write {
             return Err(FsError::InvalidParam); // FIXME: => EBADF
         }
         self.inode.resize(len as usize)?;
@@ -96,12 +127,40 @@ impl FileHandle {
         self.inode.metadata()
     }
 
-    pub fn lookup_follow(&self, path: &str, max_follow: usize) -> Result<Arc<INode>> {
-        self.inode.lookup_follow(path, max_follow)
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn options(&self) -> OpenOptions {
+        self.options.lock().clone()
+    }
+
+    /// Change the flags `fcntl(F_SETFL)` is allowed to change, for all the duplicates
+    pub fn set_status_flags(&mut self, append: bool, nonblock: bool) {
+        let mut options = self.options.lock();
+        options.append = append;
+        options.nonblock = nonblock;
+    }
+
+    pub fn poll(&self) -> PollStatus {
+        poll_inode(&*self.inode)
+    }
+
+    /// Whether a write now would wait for room
+    pub fn write_would_block(&self) -> bool {
+        !self.poll().write
+    }
+
+    pub fn flock_owner(&self) -> Arc<FlockOwner> {
+        self.flock.clone()
+    }
+
+    pub fn inode(&self) -> Arc<INode> {
+        self.inode.clone()
     }
 
     pub fn read_entry(&mut self) -> Result<String> {
-        if !self.options.read {
+        if !self.options.lock().read {
             return Err(FsError::InvalidParam); // FIXME: => EBADF
         }
         let name = self.inode.get_entry(self.offset as usize)?;
@@ -1,9 +1,10 @@
 use core::fmt;
 
-use super::FileHandle;
+use super::{Epoll, FileHandle, PollStatus};
 use crate::net::Socket;
-use crate::syscall::SysResult;
+use crate::syscall::{SysError, SysResult};
 use alloc::boxed::Box;
+use alloc::sync::Arc;
 
 // TODO: merge FileLike to FileHandle ?
 // TODO: fix dup and remove Clone
@@ -11,23 +12,66 @@ use alloc::boxed::Box;
 pub enum FileLike {
     File(FileHandle),
     Socket(Box<dyn Socket>),
+    Epoll(Arc<Epoll>),
 }
 
 impl FileLike {
     pub fn read(&mut self, buf: &mut [u8]) -> SysResult {
         let len = match self {
-            FileLike::File(file) => file.read(buf)?,
-            FileLike::Socket(socket) => socket.read(buf).0?,
+            FileLike::File(file) => read_file(file, buf)?,
+            FileLike::Socket(socket) => {
+                if socket.read_would_block() {
+                    return Err(SysError::EAGAIN);
+                }
+                socket.read(buf).0?
+            }
+            FileLike::Epoll(_) => return Err(SysError::EINVAL),
         };
         Ok(len)
     }
     pub fn write(&mut self, buf: &[u8]) -> SysResult {
         let len = match self {
-            FileLike::File(file) => file.write(buf)?,
+            FileLike::File(file) => {
+                if file.options().nonblock && file.write_would_block() {
+                    return Err(SysError::EAGAIN);
+                }
+                let len = file.write(buf)?;
+                if len == 0 && !buf.is_empty() && file.poll().error {
+                    // a pipe without readers
+                    return Err(SysError::EPIPE);
+                }
+                len
+            }
             FileLike::Socket(socket) => socket.write(buf, None)?,
+            FileLike::Epoll(_) => return Err(SysError::EINVAL),
         };
         Ok(len)
     }
+    pub fn poll(&self) -> PollStatus {
+        match self {
+            FileLike::File(file) => file.poll(),
+            FileLike::Socket(socket) => {
+                let (read, write, hangup) = socket.poll();
+                PollStatus {
+                    read,
+                    write,
+                    error: false,
+                    hangup,
+                }
+            }
+            // can not be added to another epoll instance
+            FileLike::Epoll(_) => PollStatus::default(),
+        }
+    }
+}
+
+/// Read `file`, failing with EAGAIN instead of waiting if it is nonblocking
+pub fn read_file(file: &mut FileHandle, buf: &mut [u8]) -> SysResult {
+    match file.try_read(buf) {
+        Some(result) => Ok(result?),
+        None if file.options().nonblock => Err(SysError::EAGAIN),
+        None => Ok(file.read(buf)?),
+    }
 }
 
 impl fmt::Debug for FileLike {
@@ -35,6 +79,7 @@ impl fmt::Debug for FileLike {
         match self {
             FileLike::File(_) => write!(f, "File"),
             FileLike::Socket(_) => write!(f, "Socket"),
+            FileLike::Epoll(_) => write!(f, "Epoll"),
         }
     }
 }
@@ -0,0 +1,354 @@
+//! Advisory file locks of flock and fcntl
+//!
+//! Locks are kept by the address of the INode, which stays the same while the file is open.
+//! A flock lock covers the whole file and belongs to an open file,
+//! so it is shared by the duplicates of a file handle and released when the last one is closed.
+//! A record lock of fcntl covers a byte range and belongs to a process,
+//! so it is released when the process closes any fd of the file, or exits.
+//! The two kinds of locks don't conflict with each other.
+//! Waiting for a lock is interrupted by a caught signal.
+
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+    vec::Vec,
+};
+use core::cmp::{max, min};
+use rcore_fs::vfs::INode;
+
+use crate::process::{process, SignalQueue};
+use crate::sync::SpinNoIrqLock as Mutex;
+use crate::sync::{Condvar, MutexGuard, SpinNoIrq};
+use crate::syscall::SysError;
+use crate::thread;
+
+/// A record lock of fcntl on the bytes in `start..end`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordLock {
+    /// pid of the owner
+    pub pid: usize,
+    pub start: u64,
+    /// `u64::max_value()` if the lock extends to the end of the file however it grows
+    pub end: u64,
+    /// Exclusive write lock, or shared read lock
+    pub write: bool,
+}
+
+impl RecordLock {
+    fn overlaps(&self, start: u64, end: u64) -> bool {
+        self.start < end && start < self.end
+    }
+
+    fn conflicts(&self, other: &RecordLock) -> bool {
+        self.pid != other.pid
+            && self.overlaps(other.start, other.end)
+            && (self.write || other.write)
+    }
+}
+
+/// Locks on a file
+#[derive(Default)]
+struct FileLocks {
+    /// Owners of the flock locks, and whether each one is exclusive
+    flocks: Vec<(usize, bool)>,
+    records: Vec<RecordLock>,
+}
+
+impl FileLocks {
+    fn is_empty(&self) -> bool {
+        self.flocks.is_empty() && self.records.is_empty()
+    }
+
+    /// Remove the record locks of `pid` in `start..end`,
+    /// splitting the ones across the boundaries.
+    /// Return whether any lock is removed.
+    fn remove_records(&mut self, pid: usize, start: u64, end: u64) -> bool {
+        let mut kept = Vec::new();
+        let mut removed = false;
+        for lock in self.records.drain(..) {
+            if lock.pid != pid || !lock.overlaps(start, end) {
+                kept.push(lock);
+                continue;
+            }
+            removed = true;
+            if lock.start < start {
+                kept.push(RecordLock { end: start, ..lock });
+            }
+            if lock.end > end {
+                kept.push(RecordLock { start: end, ..lock });
+            }
+        }
+        self.records = kept;
+        removed
+    }
+
+    /// Add a record lock, merging it with the adjacent ones of the same owner and type
+    fn insert_record(&mut self, mut lock: RecordLock) {
+        self.records.retain(|other| {
+            let adjacent = other.end == lock.start || other.start == lock.end;
+            if other.pid == lock.pid && other.write == lock.write && adjacent {
+                lock.start = min(lock.start, other.start);
+                lock.end = max(lock.end, other.end);
+                false
+            } else {
+                true
+            }
+        });
+        self.records.push(lock);
+    }
+}
+
+#[derive(Default)]
+struct LockTable {
+    files: BTreeMap<usize, FileLocks>,
+    /// Threads waiting in F_SETLKW by tid, with their pid and the owner of the lock
+    /// each one waits for
+    waiting: BTreeMap<usize, (usize, usize)>,
+}
+
+impl LockTable {
+    /// Whether `pid` waiting for `owner` would close a cycle of waiting processes
+    fn would_deadlock(&self, pid: usize, owner: usize) -> bool {
+        // the owners which `owner` waits for, directly or not
+        let mut owners = vec![owner];
+        let mut visited = BTreeSet::new();
+        while let Some(owner) = owners.pop() {
+            if owner == pid {
+                return true;
+            }
+            if !visited.insert(owner) {
+                continue;
+            }
+            let next = self
+                .waiting
+                .values()
+                .filter(|&&(waiter, _)| waiter == owner)
+                .map(|&(_, next)| next);
+            owners.extend(next);
+        }
+        false
+    }
+
+    /// Drop the entry of `key` if no lock is left
+    fn cleanup(&mut self, key: usize) {
+        if self.files.get(&key).map_or(false, |file| file.is_empty()) {
+            self.files.remove(&key);
+        }
+    }
+}
+
+lazy_static! {
+    static ref LOCKS: Mutex<LockTable> = Mutex::new(LockTable::default());
+    /// Notified when a lock is released or downgraded
+    static ref UNLOCKED: Condvar = Condvar::new();
+}
+
+type LockGuard = MutexGuard<'static, LockTable, SpinNoIrq>;
+
+/// Wait without `table` locked until a lock is released, then lock it again.
+/// Fail with EINTR if a signal has been caught since `caught` was taken from `signals`.
+fn wait_unlocked(
+    table: LockGuard,
+    signals: &SignalQueue,
+    caught: usize,
+) -> Result<LockGuard, SysError> {
+    // on the queues before the checks, so that no notification is missed
+    let token = Arc::new(thread::current());
+    UNLOCKED.register(&token);
+    signals.interrupted.register(&token);
+    drop(table);
+    if signals.caught() == caught {
+        Condvar::wait_registered(&[&*UNLOCKED, &signals.interrupted], &token, None);
+    }
+    UNLOCKED.unregister(&token);
+    signals.interrupted.unregister(&token);
+    if signals.caught() != caught {
+        return Err(SysError::EINTR);
+    }
+    Ok(LOCKS.lock())
+}
+
+fn inode_key(inode: &Arc<INode>) -> usize {
+    &**inode as *const INode as *const u8 as usize
+}
+
+/// Owner of the flock lock of an open file, shared by the duplicates of its handle.
+/// The lock is released when it is dropped.
+pub struct FlockOwner {
+    inode: usize,
+}
+
+impl FlockOwner {
+    pub fn new(inode: &Arc<INode>) -> Self {
+        FlockOwner {
+            inode: inode_key(inode),
+        }
+    }
+
+    fn id(&self) -> usize {
+        self as *const FlockOwner as usize
+    }
+}
+
+impl Drop for FlockOwner {
+    fn drop(&mut self) {
+        unlock_flock(self);
+    }
+}
+
+/// Remove the flock lock of `owner`, return whether it held one
+fn remove_flock(table: &mut LockTable, owner: &FlockOwner) -> bool {
+    let id = owner.id();
+    let removed = match table.files.get_mut(&owner.inode) {
+        Some(file) => {
+            let len = file.flocks.len();
+            file.flocks.retain(|&(other, _)| other != id);
+            file.flocks.len() != len
+        }
+        None => false,
+    };
+    table.cleanup(owner.inode);
+    removed
+}
+
+/// Place a shared or exclusive flock lock for `owner`, converting the one it holds.
+/// Wait for the conflicting locks to be released, or fail with EAGAIN if `nonblock`.
+pub fn flock(owner: &FlockOwner, exclusive: bool, nonblock: bool) -> Result<(), SysError> {
+    let id = owner.id();
+    let signals = process().signals.clone();
+    let caught = signals.caught();
+    let mut table = LOCKS.lock();
+    // like Linux, the conversion is not atomic
+    if remove_flock(&mut table, owner) {
+        UNLOCKED.notify_all();
+    }
+    loop {
+        let conflict = match table.files.get(&owner.inode) {
+            Some(file) => file.flocks.iter().any(|&(_, other)| other || exclusive),
+            None => false,
+        };
+        if !conflict {
+            break;
+        }
+        if nonblock {
+            return Err(SysError::EAGAIN);
+        }
+        table = wait_unlocked(table, &signals, caught)?;
+    }
+    let file = table.files.entry(owner.inode).or_default();
+    file.flocks.push((id, exclusive));
+    Ok(())
+}
+
+/// Release the flock lock of `owner` if it holds one
+pub fn unlock_flock(owner: &FlockOwner) {
+    let mut table = LOCKS.lock();
+    if remove_flock(&mut table, owner) {
+        UNLOCKED.notify_all();
+    }
+}
+
+/// A record lock on `inode` conflicting with `lock`, as reported by F_GETLK
+pub fn test_record(inode: &Arc<INode>, lock: &RecordLock) -> Option<RecordLock> {
+    let table = LOCKS.lock();
+    let file = table.files.get(&inode_key(inode))?;
+    file.records
+        .iter()
+        .find(|other| other.conflicts(lock))
+        .cloned()
+}
+
+/// Place a record lock on `inode`, replacing the locks of the owner in its range.
+/// Wait for the conflicting locks to be released if `wait`, or fail with EAGAIN.
+/// Fail with EDEADLK if waiting would never end.
+pub fn lock_record(inode: &Arc<INode>, lock: RecordLock, wait: bool) -> Result<(), SysError> {
+    let key = inode_key(inode);
+    let tid = thread::current().id();
+    let signals = process().signals.clone();
+    let caught = signals.caught();
+    let mut table = LOCKS.lock();
+    loop {
+        let owner = match table.files.get(&key) {
+            Some(file) => file
+                .records
+                .iter()
+                .find(|other| other.conflicts(&lock))
+                .map(|other| other.pid),
+            None => None,
+        };
+        let owner = match owner {
+            Some(owner) => owner,
+            None => break,
+        };
+        if !wait {
+            return Err(SysError::EAGAIN);
+        }
+        if table.would_deadlock(lock.pid, owner) {
+            return Err(SysError::EDEADLK);
+        }
+        table.waiting.insert(tid, (lock.pid, owner));
+        table = match wait_unlocked(table, &signals, caught) {
+            Ok(table) => table,
+            Err(err) => {
+                LOCKS.lock().waiting.remove(&tid);
+                return Err(err);
+            }
+        };
+        table.waiting.remove(&tid);
+    }
+    let file = table.files.entry(key).or_default();
+    file.remove_records(lock.pid, lock.start, lock.end);
+    file.insert_record(lock);
+    // a write lock may become a read lock
+    UNLOCKED.notify_all();
+    Ok(())
+}
+
+/// Release the record locks of `pid` on `inode` in `start..end`
+pub fn unlock_record(inode: &Arc<INode>, pid: usize, start: u64, end: u64) {
+    let key = inode_key(inode);
+    let mut table = LOCKS.lock();
+    let removed = match table.files.get_mut(&key) {
+        Some(file) => file.remove_records(pid, start, end),
+        None => false,
+    };
+    table.cleanup(key);
+    if removed {
+        UNLOCKED.notify_all();
+    }
+}
+
+/// Release all record locks of `pid` on `inode`, when it closes the file
+pub fn unlock_records(inode: &Arc<INode>, pid: usize) {
+    unlock_record(inode, pid, 0, u64::max_value());
+}
+
+/// Release all record locks of `pid`, when it exits
+pub fn release_record_locks(pid: usize) {
+    let mut table = LOCKS.lock();
+    let waiters: Vec<usize> = table
+        .waiting
+        .iter()
+        .filter(|(_, &(waiter, _))| waiter == pid)
+        .map(|(&tid, _)| tid)
+        .collect();
+    for tid in waiters {
+        table.waiting.remove(&tid);
+    }
+    let mut removed = false;
+    for file in table.files.values_mut() {
+        removed |= file.remove_records(pid, 0, u64::max_value());
+    }
+    let empty: Vec<usize> = table
+        .files
+        .iter()
+        .filter(|(_, file)| file.is_empty())
+        .map(|(&key, _)| key)
+        .collect();
+    for key in empty {
+        table.files.remove(&key);
+    }
+    if removed {
+        UNLOCKED.notify_all();
+    }
+}
@@ -1,21 +1,79 @@
-use alloc::{sync::Arc, vec::Vec};
+use alloc::{string::String, sync::Arc, vec::Vec};
 
 use rcore_fs::vfs::*;
-use rcore_fs_sfs::SimpleFileSystem;
 
 #[cfg(target_arch = "x86_64")]
 use crate::arch::driver::ide;
 
+pub use self::anon::{ANON_INODE_FS, PIPE_FS};
+pub use self::block_cache::*;
 pub use self::file::*;
+pub use self::devfs::{add_entropy, DEV_FS};
+pub use self::epoll::*;
+pub use self::eventfd::EventFd;
+pub use self::device::LoopDevice;
+pub use self::ext2::Ext2FS;
+pub use self::fat32::Fat32FS;
 pub use self::file_like::*;
-pub use self::pipe::Pipe;
+pub use self::lock::*;
+pub use self::mount::*;
+pub use self::pipe::{Pipe, PipeChunk, PIPE_MAX_SIZE};
+pub use self::poll::{
+    inode_wait_queue, is_pollable, poll_inode, try_read_inode, PollStatus, Pollable,
+};
+pub use self::procfs::PROC_FS;
+pub use self::sfs::SfsFS;
+pub use self::signalfd::SignalFd;
+pub use self::statfs::*;
 pub use self::stdio::{STDIN, STDOUT};
+pub use self::timerfd::{timerfd_tick, TimerFd};
+pub use self::tmpfs::TmpFS;
 
+// TODO: better way to provide default impl?
+/// Implement the methods of INode that the files in no directory, like pipes,
+/// don't support. With a file system, they are reported in it without metadata.
+macro_rules! impl_inode {
+    ($fs:expr) => {
+        fn metadata(&self) -> Result<Metadata> { Err(FsError::NotSupported) }
+        fn chmod(&self, _mode: u16) -> Result<()> { Ok(()) }
+        fn fs(&self) -> Arc<FileSystem> { $fs.clone() }
+        impl_inode!();
+    };
+    () => {
+        fn sync_all(&self) -> Result<()> { Ok(()) }
+        fn sync_data(&self) -> Result<()> { Ok(()) }
+        fn resize(&self, _len: usize) -> Result<()> { Err(FsError::NotSupported) }
+        fn create(&self, _name: &str, _type_: FileType, _mode: u32) -> Result<Arc<INode>> { Err(FsError::NotDir) }
+        fn unlink(&self, _name: &str) -> Result<()> { Err(FsError::NotDir) }
+        fn link(&self, _name: &str, _other: &Arc<INode>) -> Result<()> { Err(FsError::NotDir) }
+        fn move_(&self, _old_name: &str, _target: &Arc<INode>, _new_name: &str) -> Result<()> { Err(FsError::NotDir) }
+        fn find(&self, _name: &str) -> Result<Arc<INode>> { Err(FsError::NotDir) }
+        fn get_entry(&self, _id: usize) -> Result<String> { Err(FsError::NotDir) }
+        fn as_any_ref(&self) -> &Any { self }
+    };
+}
+
+mod anon;
+mod block_cache;
+mod devfs;
 mod device;
+mod epoll;
+mod eventfd;
+mod ext2;
+mod fat32;
 mod file;
 mod file_like;
+mod lock;
+mod mount;
 mod pipe;
+mod poll;
+mod procfs;
+mod sfs;
+mod signalfd;
+mod statfs;
 mod stdio;
+mod timerfd;
+mod tmpfs;
 
 /// Hard link user programs
 #[cfg(feature = "link_user")]
@@ -39,13 +97,11 @@ lazy_static! {
         let device = {
             #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
             {
-                crate::drivers::BLK_DRIVERS.read().iter()
-                    .next().expect("VirtIOBlk not found")
-                    .clone()
+                blk_device(0).expect("VirtIOBlk not found")
             }
             #[cfg(target_arch = "x86_64")]
             {
-                Arc::new(ide::IDE::new(1))
+                cache_device(Arc::new(ide::IDE::new(1)))
             }
         };
         #[cfg(feature = "link_user")]
@@ -57,12 +113,173 @@ lazy_static! {
             Arc::new(unsafe { device::MemBuf::new(_user_img_start, _user_img_end) })
         };
 
-        let sfs = SimpleFileSystem::open(device).expect("failed to open SFS");
-        sfs.root_inode()
+        let fs: Arc<FileSystem> = match SfsFS::open(device.clone()) {
+            Ok(sfs) => sfs,
+            Err(_) => {
+                set_root_fstype("ext2");
+                Ext2FS::open(device).expect("failed to open root file system")
+            }
+        };
+        fs.root_inode()
     };
 }
 
-pub const FOLLOW_MAX_DEPTH: usize = 1;
+/// Identity of the file system of `inode`
+pub fn fs_id(inode: &Arc<INode>) -> usize {
+    fs_addr(&*inode.fs())
+}
+
+fn fs_addr(fs: &FileSystem) -> usize {
+    fs as *const FileSystem as *const u8 as usize
+}
+
+/// Usage of the file system containing `inode`
+pub fn statfs(inode: &Arc<INode>) -> Result<FsStat> {
+    let any = inode.as_any_ref();
+    if let Some(inode) = any.downcast_ref::<tmpfs::LockedINode>() {
+        return Ok(inode.statfs());
+    }
+    if let Some(inode) = any.downcast_ref::<ext2::INodeImpl>() {
+        return Ok(inode.statfs());
+    }
+    if let Some(inode) = any.downcast_ref::<fat32::INodeImpl>() {
+        return inode.statfs();
+    }
+    if let Some(inode) = any.downcast_ref::<sfs::SfsINode>() {
+        return inode.statfs();
+    }
+    if let Some(fifo) = any.downcast_ref::<Pipe>().and_then(Pipe::fifo) {
+        return statfs(fifo);
+    }
+    // the pseudo file systems
+    let id = fs_id(inode);
+    if id == fs_addr(&**DEV_FS) {
+        return Ok(FsStat::pseudo(DEVFS_SUPER_MAGIC));
+    }
+    if id == fs_addr(&**PROC_FS) {
+        return Ok(FsStat::pseudo(PROC_SUPER_MAGIC));
+    }
+    if id == fs_addr(&**PIPE_FS) {
+        return Ok(PIPE_FS.statfs());
+    }
+    if id == fs_addr(&**ANON_INODE_FS) {
+        return Ok(ANON_INODE_FS.statfs());
+    }
+    Err(FsError::NotSupported)
+}
+
+/// Change the owner of `inode`, for the file systems which record it
+pub fn chown(inode: &Arc<INode>, uid: usize, gid: usize) -> Result<()> {
+    let inode = inode.as_any_ref();
+    if let Some(inode) = inode.downcast_ref::<tmpfs::LockedINode>() {
+        return inode.chown(uid, gid);
+    }
+    if let Some(inode) = inode.downcast_ref::<ext2::INodeImpl>() {
+        return inode.chown(uid, gid);
+    }
+    if let Some(inode) = inode.downcast_ref::<sfs::SfsINode>() {
+        return inode.chown(uid, gid);
+    }
+    Err(FsError::NotSupported)
+}
+
+/// Set the device number of the device file `inode`, for the file systems which record it
+pub fn set_rdev(inode: &Arc<INode>, rdev: usize) -> Result<()> {
+    let inode = inode.as_any_ref();
+    if let Some(inode) = inode.downcast_ref::<tmpfs::LockedINode>() {
+        return inode.set_rdev(rdev);
+    }
+    if let Some(inode) = inode.downcast_ref::<ext2::INodeImpl>() {
+        return inode.set_rdev(rdev);
+    }
+    if let Some(inode) = inode.downcast_ref::<sfs::SfsINode>() {
+        return inode.set_rdev(rdev);
+    }
+    Err(FsError::NotSupported)
+}
+
+/// The device number of the device file `inode`, or 0
+pub fn rdev(inode: &Arc<INode>) -> usize {
+    let inode = inode.as_any_ref();
+    if let Some(inode) = inode.downcast_ref::<tmpfs::LockedINode>() {
+        return inode.rdev();
+    }
+    if let Some(inode) = inode.downcast_ref::<ext2::INodeImpl>() {
+        return inode.rdev();
+    }
+    if let Some(inode) = inode.downcast_ref::<sfs::SfsINode>() {
+        return inode.rdev();
+    }
+    0
+}
+
+/// Set the access and modification times of `inode`, `None` keeps the current one.
+/// The file systems which record no times ignore them.
+pub fn set_times(
+    inode: &Arc<INode>,
+    atime: Option<Timespec>,
+    mtime: Option<Timespec>,
+) -> Result<()> {
+    let inode = inode.as_any_ref();
+    if let Some(inode) = inode.downcast_ref::<tmpfs::LockedINode>() {
+        return inode.set_times(atime, mtime);
+    }
+    if let Some(inode) = inode.downcast_ref::<ext2::INodeImpl>() {
+        return inode.set_times(atime, mtime);
+    }
+    if let Some(inode) = inode.downcast_ref::<fat32::INodeImpl>() {
+        return inode.set_times(atime, mtime);
+    }
+    if let Some(inode) = inode.downcast_ref::<sfs::SfsINode>() {
+        return inode.set_times(atime, mtime);
+    }
+    Ok(())
+}
+
+/// Whether reading a file should update its access time, like `relatime`:
+/// when it is not after the last modification or change, or a day has passed.
+/// All are seconds since the epoch.
+pub fn atime_outdated(atime: i64, mtime: i64, ctime: i64, now: i64) -> bool {
+    atime <= mtime || atime <= ctime || now - atime >= 24 * 60 * 60
+}
+
+/// Max number of symlinks followed in a lookup
+pub const FOLLOW_MAX_DEPTH: usize = 40;
+
+/// Max length of a path, including the terminating null
+pub const PATH_MAX: usize = 4096;
+
+/// Mount the pseudo and temporary file systems
+pub fn init() {
+    mount_at_root("proc", PROC_FS.clone(), "proc");
+    mount_at_root("dev", DEV_FS.clone(), "devfs");
+    mount_at_root("tmp", TmpFS::new(), "tmpfs");
+    let shm = DEV_FS
+        .root_inode()
+        .find("shm")
+        .expect("failed to find /dev/shm");
+    let fstype = String::from("tmpfs");
+    mount(shm, String::from("/dev/shm"), TmpFS::new(), fstype.clone(), fstype)
+        .expect("failed to mount");
+}
+
+/// Mount `fs` on directory `/<name>`, which is created if not exists
+fn mount_at_root(name: &str, fs: Arc<FileSystem>, fstype: &str) {
+    let dir = match ROOT_INODE.find(name) {
+        Ok(dir) => dir,
+        Err(_) => ROOT_INODE
+            .create(name, FileType::Dir, 0o755)
+            .expect("failed to create mount point"),
+    };
+    mount(
+        dir,
+        format!("/{}", name),
+        fs,
+        String::from(fstype),
+        String::from(fstype),
+    )
+    .expect("failed to mount");
+}
 
 pub trait INodeExt {
     fn read_as_vec(&self) -> Result<Vec<u8>>;
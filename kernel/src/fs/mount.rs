@@ -0,0 +1,242 @@
+//! Mount table and path lookup across mount points
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+use core::fmt::Write;
+
+use rcore_fs::vfs::*;
+use spin::RwLock;
+
+use super::procfs::proc_link;
+use super::{fs_id, sync_block_caches, FOLLOW_MAX_DEPTH, PATH_MAX, ROOT_INODE};
+use crate::process::{Access, Credentials, Process};
+use crate::syscall::SysError;
+
+/// A file system mounted on a directory
+pub struct MountPoint {
+    /// The directory covered by the file system
+    pub target: Arc<INode>,
+    /// Root of the mounted file system
+    pub root: Arc<INode>,
+    /// The mounted file system, kept alive while it is mounted,
+    /// since its inodes may refer to it weakly like those of tmpfs
+    pub fs: Arc<FileSystem>,
+    /// Absolute path of the target
+    pub path: String,
+    /// Where the file system comes from, e.g. a device
+    pub source: String,
+    pub fstype: String,
+    target_id: INodeId,
+    root_id: INodeId,
+}
+
+/// Identity of an INode: address of its file system and inode number
+pub(super) type INodeId = (usize, usize);
+
+/// Identity of `inode`, whose file system stays alive while it is mounted
+pub(super) fn inode_id(inode: &Arc<INode>) -> Result<INodeId> {
+    Ok((fs_id(inode), inode.metadata()?.inode))
+}
+
+lazy_static! {
+    /// Mounted file systems, the later ones hide the earlier ones on the same directory
+    pub static ref MOUNTS: RwLock<Vec<MountPoint>> = RwLock::new(Vec::new());
+}
+
+/// Type of the root file system
+static ROOT_FSTYPE: RwLock<&'static str> = RwLock::new("sfs");
+
+/// Record the type of the root file system for /proc/mounts
+pub fn set_root_fstype(fstype: &'static str) {
+    *ROOT_FSTYPE.write() = fstype;
+}
+
+/// Mount `fs` on directory `target` at `path`
+pub fn mount(
+    target: Arc<INode>,
+    path: String,
+    fs: Arc<FileSystem>,
+    source: String,
+    fstype: String,
+) -> Result<()> {
+    if target.metadata()?.type_ != FileType::Dir {
+        return Err(FsError::NotDir);
+    }
+    let root = fs.root_inode();
+    let target_id = inode_id(&target)?;
+    let root_id = inode_id(&root)?;
+    if target_id == root_id {
+        return Err(FsError::InvalidParam);
+    }
+    info!("mount {} ({}) on {}", source, fstype, path);
+    MOUNTS.write().push(MountPoint {
+        target,
+        root,
+        fs,
+        path,
+        source,
+        fstype,
+        target_id,
+        root_id,
+    });
+    Ok(())
+}
+
+/// Unmount the file system whose root is `root`
+pub fn umount(root: &Arc<INode>) -> Result<()> {
+    let id = inode_id(root)?;
+    let mut mounts = MOUNTS.write();
+    let index = mounts
+        .iter()
+        .rposition(|mount| mount.root_id == id)
+        .ok_or(FsError::InvalidParam)?;
+    let mount = mounts.remove(index);
+    info!("umount {}", mount.path);
+    Ok(())
+}
+
+/// Whether other file systems are mounted inside the file system of `root`
+pub fn has_submounts(root: &Arc<INode>) -> Result<bool> {
+    let (fs_addr, _) = inode_id(root)?;
+    Ok(MOUNTS
+        .read()
+        .iter()
+        .any(|mount| mount.target_id.0 == fs_addr))
+}
+
+/// Whether `inode` is in the file system of `root`.
+/// Anonymous files, like pipes and eventfds, are in their own ones.
+pub fn in_fs_of(inode: &Arc<INode>, root: &Arc<INode>) -> bool {
+    fs_id(inode) == fs_id(root)
+}
+
+/// Sync all mounted file systems, including the root, and write back the block caches
+pub fn sync_all() -> Result<()> {
+    ROOT_INODE.fs().sync()?;
+    for mount in MOUNTS.read().iter() {
+        mount.root.fs().sync()?;
+    }
+    sync_block_caches()
+}
+
+/// Generate the content of /proc/mounts
+pub fn mounts_info() -> String {
+    let mut s = format!("rootfs / {} rw 0 0\n", *ROOT_FSTYPE.read());
+    for mount in MOUNTS.read().iter() {
+        writeln!(s, "{} {} {} rw 0 0", mount.source, mount.path, mount.fstype).unwrap();
+    }
+    s
+}
+
+/// Find `name` in directory `dir`, crossing mount points
+fn find_child(dir: &Arc<INode>, name: &str) -> Result<Arc<INode>> {
+    let mounts = MOUNTS.read();
+    if mounts.is_empty() {
+        return dir.find(name);
+    }
+    if name == ".." {
+        // leave the mounted file systems from their roots
+        let mut dir = dir.clone();
+        let mut id = inode_id(&dir)?;
+        while let Some(mount) = mounts.iter().rev().find(|mount| mount.root_id == id) {
+            dir = mount.target.clone();
+            id = mount.target_id;
+        }
+        return dir.find("..");
+    }
+    // enter the file systems mounted on it
+    let mut inode = dir.find(name)?;
+    let mut id = inode_id(&inode)?;
+    while let Some(mount) = mounts.iter().rev().find(|mount| mount.target_id == id) {
+        inode = mount.root.clone();
+        id = mount.root_id;
+    }
+    Ok(inode)
+}
+
+/// Lookup `path` from directory `dir` across mount points.
+/// Symlinks in the middle of the path are always followed, the last one only if `follow`.
+/// Absolute paths start from `ROOT_INODE`.
+/// Directories on the way need search permission for `cred`.
+/// `current_proc` is the process locked by the caller, if any, see `read_link`.
+pub fn lookup_at(
+    dir: &Arc<INode>,
+    path: &str,
+    follow: bool,
+    cred: &Credentials,
+    current_proc: Option<&Process>,
+) -> core::result::Result<Arc<INode>, SysError> {
+    // "link/" refers to the directory the link points to
+    let follow = follow || path.ends_with('/');
+    let mut follow_times = 0;
+    let mut current = dir.clone();
+    let mut rest_path = String::from(path);
+    while rest_path != "" {
+        if current.metadata()?.type_ != FileType::Dir {
+            return Err(SysError::ENOTDIR);
+        }
+        if rest_path.starts_with('/') {
+            current = ROOT_INODE.clone();
+            rest_path = String::from(&rest_path[1..]);
+            continue;
+        }
+        let name = match rest_path.find('/') {
+            Some(pos) => {
+                let name = String::from(&rest_path[..pos]);
+                rest_path = String::from(&rest_path[pos + 1..]);
+                name
+            }
+            None => core::mem::replace(&mut rest_path, String::new()),
+        };
+        if name == "" || name == "." {
+            continue;
+        }
+        cred.check(&current.metadata()?, Access::EXEC)?;
+        let inode = find_child(&current, &name)?;
+        if inode.metadata()?.type_ == FileType::SymLink && (follow || rest_path != "") {
+            follow_times += 1;
+            if follow_times > FOLLOW_MAX_DEPTH {
+                return Err(SysError::ELOOP);
+            }
+            let target = read_link(&inode, current_proc)?;
+            if target == "" {
+                return Err(SysError::ENOENT);
+            }
+            // continue from `current` with the target in front of the rest
+            let mut new_path = target;
+            if rest_path != "" {
+                new_path += "/";
+                new_path += &rest_path;
+            }
+            rest_path = new_path;
+        } else {
+            current = inode;
+        }
+    }
+    Ok(current)
+}
+
+/// Read the target of symlink `inode`.
+/// The links of procfs about `current_proc`, the process locked by the caller if any,
+/// are read from it, since reading them would lock it again.
+pub fn read_link(
+    inode: &Arc<INode>,
+    current_proc: Option<&Process>,
+) -> core::result::Result<String, SysError> {
+    if let Some(target) = current_proc.and_then(|proc| proc_link(inode, proc)) {
+        return Ok(target?);
+    }
+    let mut content = vec![0u8; PATH_MAX];
+    let mut len = 0;
+    // the size in metadata is not reliable for pseudo file systems
+    while len < PATH_MAX {
+        match inode.read_at(len, &mut content[len..])? {
+            0 => break,
+            n => len += n,
+        }
+    }
+    if len == PATH_MAX {
+        return Err(SysError::ENAMETOOLONG);
+    }
+    content.truncate(len);
+    String::from_utf8(content).map_err(|_| SysError::EINVAL)
+}
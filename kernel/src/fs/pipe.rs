@@ -1,106 +1,569 @@
 //! Implement INode for Pipe
 
-use alloc::{collections::vec_deque::VecDeque, string::String, sync::Arc};
+use alloc::{
+    collections::{vec_deque::VecDeque, BTreeMap},
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
 use core::any::Any;
+use core::cmp::min;
 
 use rcore_fs::vfs::*;
+use rcore_memory::PAGE_SIZE;
 
+use super::mount::inode_id;
+use super::poll::{PollStatus, Pollable};
+use super::PIPE_FS;
 use crate::sync::Condvar;
 use crate::sync::SpinNoIrqLock as Mutex;
+use crate::syscall::SysError;
+
+/// Capacity of a new pipe
+pub const PIPE_DEFAULT_SIZE: usize = 0x10000;
+/// The largest capacity an unprivileged process may set
+pub const PIPE_MAX_SIZE: usize = 0x100000;
+/// Writes of at most this many bytes are not interleaved with other writes
+pub const PIPE_BUF: usize = 4096;
+
+lazy_static! {
+    /// Buffers of open FIFOs, by the address of the filesystem and the inode number
+    ///
+    /// An entry is replaced when its FIFO is opened after all its ends are closed.
+    static ref FIFOS: Mutex<BTreeMap<(usize, usize), Weak<PipeData>>> =
+        Mutex::new(BTreeMap::new());
+}
 
 #[derive(Clone)]
 pub enum PipeEnd {
     Read,
     Write,
+    /// A FIFO opened for both reading and writing
+    Both,
+}
+
+/// Data in a pipe, on a page which `tee` may share with other pipes
+#[derive(Clone)]
+pub struct PipeChunk {
+    page: Arc<Vec<u8>>,
+    start: usize,
+    end: usize,
+}
+
+impl PipeChunk {
+    pub fn new(data: Vec<u8>) -> Self {
+        PipeChunk {
+            start: 0,
+            end: data.len(),
+            page: Arc::new(data),
+        }
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.page[self.start..self.end]
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    /// Drop the first `len` bytes
+    pub fn advance(&mut self, len: usize) {
+        self.start = min(self.start + len, self.end);
+    }
+}
+
+struct PipeBuf {
+    chunks: VecDeque<PipeChunk>,
+    /// Bytes in `chunks`
+    len: usize,
+    capacity: usize,
+    /// Number of open read ends
+    readers: usize,
+    /// Number of open write ends
+    writers: usize,
+    /// Number of opens of read ends so far, so that a waiting writer sees
+    /// a reader which has come and gone
+    reader_opens: usize,
+    /// Number of opens of write ends so far
+    writer_opens: usize,
+    /// The data at the front is being written elsewhere by `Pipe::begin_read`,
+    /// others can't read meanwhile
+    reading: bool,
+}
+
+impl PipeBuf {
+    fn free(&self) -> usize {
+        self.capacity.saturating_sub(self.len)
+    }
+
+    /// Whether a read would not wait, returning data or end of file
+    fn readable(&self) -> bool {
+        !self.reading && (self.len > 0 || self.writers == 0)
+    }
+
+    /// Copy `buf` to the end, filling the last page if no other pipe shares it
+    fn push(&mut self, mut buf: &[u8]) {
+        self.len += buf.len();
+        if let Some(last) = self.chunks.back_mut() {
+            let end = last.end;
+            if let Some(page) = Arc::get_mut(&mut last.page) {
+                if page.len() == end && page.len() < PAGE_SIZE {
+                    let len = min(buf.len(), PAGE_SIZE - page.len());
+                    page.extend_from_slice(&buf[..len]);
+                    last.end += len;
+                    buf = &buf[len..];
+                }
+            }
+        }
+        for data in buf.chunks(PAGE_SIZE) {
+            let mut page = Vec::with_capacity(PAGE_SIZE);
+            page.extend_from_slice(data);
+            self.chunks.push_back(PipeChunk::new(page));
+        }
+    }
+
+    /// Move data from the front to `buf`
+    fn pop(&mut self, buf: &mut [u8]) -> usize {
+        let mut len = 0;
+        for chunk in self.peek(buf.len()) {
+            buf[len..len + chunk.len()].copy_from_slice(chunk.as_slice());
+            len += chunk.len();
+        }
+        self.consume(len);
+        len
+    }
+
+    /// The data at the front, up to `max` bytes
+    fn peek(&self, max: usize) -> Vec<PipeChunk> {
+        let mut chunks = Vec::new();
+        let mut len = 0;
+        for chunk in self.chunks.iter() {
+            if len == max {
+                break;
+            }
+            let mut chunk = chunk.clone();
+            chunk.end = min(chunk.end, chunk.start + max - len);
+            len += chunk.len();
+            chunks.push(chunk);
+        }
+        chunks
+    }
+
+    /// Append as much of `chunks` as fits, sharing their pages
+    fn push_chunks(&mut self, chunks: Vec<PipeChunk>) -> usize {
+        let mut len = 0;
+        for mut chunk in chunks {
+            let free = self.free();
+            if free == 0 {
+                break;
+            }
+            chunk.end = min(chunk.end, chunk.start + free);
+            len += chunk.len();
+            self.len += chunk.len();
+            self.chunks.push_back(chunk);
+        }
+        len
+    }
+
+    /// Remove `len` bytes from the front
+    fn consume(&mut self, mut len: usize) {
+        self.len -= len;
+        while len > 0 {
+            let front = self.chunks.front_mut().unwrap();
+            if front.len() > len {
+                front.start += len;
+                break;
+            }
+            len -= front.len();
+            self.chunks.pop_front();
+        }
+    }
 }
 
 pub struct PipeData {
-    buf: VecDeque<u8>,
-    new_data: Condvar,
+    buf: Mutex<PipeBuf>,
+    /// Notified when data is written or the last write end is closed
+    readable: Condvar,
+    /// Notified when data is read or the last read end is closed
+    writable: Condvar,
+    /// Notified along with either of the above, for FIFOs opened for both
+    changed: Condvar,
 }
 
-#[derive(Clone)]
+impl PipeData {
+    fn new(readers: usize, writers: usize) -> Self {
+        PipeData {
+            buf: Mutex::new(PipeBuf {
+                chunks: VecDeque::new(),
+                len: 0,
+                capacity: PIPE_DEFAULT_SIZE,
+                readers,
+                writers,
+                reader_opens: readers,
+                writer_opens: writers,
+                reading: false,
+            }),
+            readable: Condvar::new(),
+            writable: Condvar::new(),
+            changed: Condvar::new(),
+        }
+    }
+
+    fn notify_readable(&self) {
+        self.readable.notify_all();
+        self.changed.notify_all();
+    }
+
+    fn notify_writable(&self) {
+        self.writable.notify_all();
+        self.changed.notify_all();
+    }
+}
+
+/// One end of a pipe, closed when dropped
 pub struct Pipe {
-    data: Arc<Mutex<PipeData>>,
+    data: Arc<PipeData>,
     direction: PipeEnd,
+    /// The INode in the filesystem of a FIFO
+    fifo: Option<Arc<INode>>,
 }
 
 impl Pipe {
     /// Create a pair of INode: (read, write)
     pub fn create_pair() -> (Pipe, Pipe) {
-        let inner = PipeData {
-            buf: VecDeque::new(),
-            new_data: Condvar::new(),
-        };
-        let data = Arc::new(Mutex::new(inner));
+        let data = Arc::new(PipeData::new(1, 1));
         (
             Pipe {
                 data: data.clone(),
                 direction: PipeEnd::Read,
+                fifo: None,
             },
             Pipe {
                 data: data.clone(),
                 direction: PipeEnd::Write,
+                fifo: None,
             },
         )
     }
 
+    /// The INode of the FIFO in its file system, or None for an anonymous pipe
+    pub fn fifo(&self) -> Option<&Arc<INode>> {
+        self.fifo.as_ref()
+    }
+
+    /// Open the FIFO `inode`, waiting for the other end unless `nonblock`
+    ///
+    /// Every open of the same FIFO shares a buffer while any of them is open.
+    /// Opening for both reading and writing never waits.
+    /// The process must not be locked.
+    pub fn open_fifo(
+        inode: Arc<INode>,
+        read: bool,
+        write: bool,
+        nonblock: bool,
+    ) -> core::result::Result<Pipe, SysError> {
+        let key = inode_id(&inode)?;
+        let data = {
+            let mut fifos = FIFOS.lock();
+            match fifos.get(&key).and_then(|data| data.upgrade()) {
+                Some(data) => data,
+                None => {
+                    let data = Arc::new(PipeData::new(0, 0));
+                    fifos.insert(key, Arc::downgrade(&data));
+                    data
+                }
+            }
+        };
+        let direction = match (read, write) {
+            (true, true) => PipeEnd::Both,
+            (false, true) => PipeEnd::Write,
+            _ => PipeEnd::Read,
+        };
+
+        let mut buf = data.buf.lock();
+        match direction {
+            PipeEnd::Write if nonblock && buf.readers == 0 => return Err(SysError::ENXIO),
+            PipeEnd::Read => {
+                buf.readers += 1;
+                buf.reader_opens += 1;
+            }
+            PipeEnd::Write => {
+                buf.writers += 1;
+                buf.writer_opens += 1;
+            }
+            PipeEnd::Both => {
+                buf.readers += 1;
+                buf.writers += 1;
+                buf.reader_opens += 1;
+                buf.writer_opens += 1;
+            }
+        }
+        // the other end may have been opened and closed again before we wake up
+        let (reader_opens, writer_opens) = (buf.reader_opens, buf.writer_opens);
+        drop(buf);
+        data.notify_readable();
+        data.notify_writable();
+        let pipe = Pipe {
+            data,
+            direction,
+            fifo: Some(inode),
+        };
+
+        if !nonblock {
+            let mut buf = pipe.data.buf.lock();
+            loop {
+                buf = match pipe.direction {
+                    PipeEnd::Read if buf.writers == 0 && buf.writer_opens == writer_opens => {
+                        pipe.data.readable.wait(buf)
+                    }
+                    PipeEnd::Write if buf.readers == 0 && buf.reader_opens == reader_opens => {
+                        pipe.data.writable.wait(buf)
+                    }
+                    _ => break,
+                };
+            }
+        }
+        Ok(pipe)
+    }
+
     pub fn can_read(&self) -> bool {
-        if let PipeEnd::Read = self.direction {
-            self.data.lock().buf.len() > 0
-        } else {
+        if let PipeEnd::Write = self.direction {
             false
+        } else {
+            self.data.buf.lock().readable()
         }
     }
+
+    pub fn capacity(&self) -> usize {
+        self.data.buf.lock().capacity
+    }
+
+    /// Resize the buffer to at least `size` bytes, rounded up to a power of two pages
+    ///
+    /// Return the new capacity, or `None` if the data would not fit.
+    pub fn set_capacity(&self, size: usize) -> Option<usize> {
+        let capacity = size.max(PAGE_SIZE).next_power_of_two();
+        let mut buf = self.data.buf.lock();
+        if buf.len > capacity {
+            return None;
+        }
+        buf.capacity = capacity;
+        drop(buf);
+        self.data.notify_writable();
+        Some(capacity)
+    }
+
+    /// Room left in the buffer
+    pub fn free(&self) -> usize {
+        self.data.buf.lock().free()
+    }
+
+    /// Whether `other` is an end of the same pipe
+    pub fn same_pipe(&self, other: &Pipe) -> bool {
+        Arc::ptr_eq(&self.data, &other.data)
+    }
+
+    /// Start reading the data at the front, up to `max` bytes, which stays in the pipe
+    /// until `end_read` removes what is used of it
+    ///
+    /// Others can't read meanwhile, so that the data is neither read twice nor reordered.
+    /// Return `None` if there is nothing to read, as another read may have taken it,
+    /// or another such read is going on.
+    pub fn begin_read(&self, max: usize) -> Option<Vec<PipeChunk>> {
+        let mut buf = self.data.buf.lock();
+        if !buf.readable() {
+            return None;
+        }
+        buf.reading = true;
+        Some(buf.peek(max))
+    }
+
+    /// Remove the first `len` bytes of the data of `begin_read` and let others read
+    pub fn end_read(&self, len: usize) {
+        let mut buf = self.data.buf.lock();
+        buf.consume(len);
+        buf.reading = false;
+        drop(buf);
+        self.data.notify_readable();
+        self.data.notify_writable();
+    }
+
+    /// Append as much of `chunks` as fits, sharing their pages
+    ///
+    /// Return the number of bytes appended.
+    pub fn push_chunks(&self, chunks: Vec<PipeChunk>) -> usize {
+        let len = self.data.buf.lock().push_chunks(chunks);
+        self.data.notify_readable();
+        len
+    }
+
+    /// Move up to `max` bytes to the pipe `other`, as much as it has room for
+    ///
+    /// Return `None` if there is nothing to read, as for `begin_read`.
+    pub fn move_to(&self, other: &Pipe, max: usize) -> Option<usize> {
+        let len = self.transfer(other, max, true)?;
+        self.data.notify_writable();
+        other.data.notify_readable();
+        Some(len)
+    }
+
+    /// Copy up to `max` bytes to the pipe `other` like `move_to`, keeping them here
+    pub fn copy_to(&self, other: &Pipe, max: usize) -> usize {
+        let len = self.transfer(other, max, false).unwrap();
+        other.data.notify_readable();
+        len
+    }
+
+    /// Append the data at the front to `other` with both locked,
+    /// so that no read in between takes it too
+    fn transfer(&self, other: &Pipe, max: usize, remove: bool) -> Option<usize> {
+        // lock in the order of addresses, as the other side may do the reverse
+        let this_addr = &*self.data as *const PipeData as usize;
+        let that_addr = &*other.data as *const PipeData as usize;
+        let (mut this, mut that) = match this_addr < that_addr {
+            true => {
+                let this = self.data.buf.lock();
+                (this, other.data.buf.lock())
+            }
+            false => {
+                let that = other.data.buf.lock();
+                (self.data.buf.lock(), that)
+            }
+        };
+        if remove && !this.readable() {
+            return None;
+        }
+        let len = that.push_chunks(this.peek(max));
+        if remove {
+            this.consume(len);
+        }
+        Some(len)
+    }
 }
 
-// TODO: better way to provide default impl?
-macro_rules! impl_inode {
-    () => {
-        fn metadata(&self) -> Result<Metadata> { Err(FsError::NotSupported) }
-        fn sync_all(&self) -> Result<()> { Ok(()) }
-        fn sync_data(&self) -> Result<()> { Ok(()) }
-        fn resize(&self, _len: usize) -> Result<()> { Err(FsError::NotSupported) }
-        fn create(&self, _name: &str, _type_: FileType, _mode: u32) -> Result<Arc<INode>> { Err(FsError::NotDir) }
-        fn unlink(&self, _name: &str) -> Result<()> { Err(FsError::NotDir) }
-        fn link(&self, _name: &str, _other: &Arc<INode>) -> Result<()> { Err(FsError::NotDir) }
-        fn move_(&self, _old_name: &str, _target: &Arc<INode>, _new_name: &str) -> Result<()> { Err(FsError::NotDir) }
-        fn find(&self, _name: &str) -> Result<Arc<INode>> { Err(FsError::NotDir) }
-        fn get_entry(&self, _id: usize) -> Result<String> { Err(FsError::NotDir) }
-        fn fs(&self) -> Arc<FileSystem> { unimplemented!() }
-        fn as_any_ref(&self) -> &Any { self }
-        fn chmod(&self, _mode: u16) -> Result<()> { Ok(()) }
-    };
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        let mut buf = self.data.buf.lock();
+        match self.direction {
+            PipeEnd::Read => buf.readers -= 1,
+            PipeEnd::Write => buf.writers -= 1,
+            PipeEnd::Both => {
+                buf.readers -= 1;
+                buf.writers -= 1;
+            }
+        }
+        drop(buf);
+        self.data.notify_readable();
+        self.data.notify_writable();
+    }
 }
 
 impl INode for Pipe {
-    fn read_at(&self, _offset: usize, buf: &mut [u8]) -> Result<usize> {
-        if let PipeEnd::Read = self.direction {
-            let mut data = self.data.lock();
-            if let Some(ch) = data.buf.pop_front() {
-                buf[0] = ch;
-                Ok(1)
-            } else {
-                Ok(0)
+    /// Read what is available, waiting until there is some data or no writers
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            if let Some(result) = self.try_read(offset, buf) {
+                return result;
             }
-        } else {
-            Ok(0)
+            self.data.readable.wait_unless(|| self.poll().read);
         }
     }
 
+    /// Write as much as fits, waiting until there is some room
+    ///
+    /// Writes of at most `PIPE_BUF` bytes are written at once.
+    /// Return 0 if there are no readers.
     fn write_at(&self, _offset: usize, buf: &[u8]) -> Result<usize> {
-        if let PipeEnd::Write = self.direction {
-            if buf.len() > 0 {
-                let mut data = self.data.lock();
-                data.buf.push_back(buf[0]);
-                data.new_data.notify_all();
-                Ok(1)
-            } else {
-                Ok(0)
+        if let PipeEnd::Read = self.direction {
+            return Ok(0);
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut pipe = self.data.buf.lock();
+        loop {
+            if pipe.readers == 0 {
+                return Ok(0);
             }
-        } else {
-            Ok(0)
+            if pipe.free() >= min(buf.len(), PIPE_BUF) {
+                break;
+            }
+            pipe = self.data.writable.wait(pipe);
+        }
+        let len = min(buf.len(), pipe.free());
+        pipe.push(&buf[..len]);
+        drop(pipe);
+        self.data.notify_readable();
+        Ok(len)
+    }
+    /// A FIFO reports the INode in the filesystem
+    fn metadata(&self) -> Result<Metadata> {
+        match &self.fifo {
+            Some(inode) => inode.metadata(),
+            None => Err(FsError::NotSupported),
+        }
+    }
+    fn chmod(&self, mode: u16) -> Result<()> {
+        match &self.fifo {
+            Some(inode) => inode.chmod(mode),
+            None => Ok(()),
+        }
+    }
+    fn fs(&self) -> Arc<FileSystem> {
+        match &self.fifo {
+            Some(inode) => inode.fs(),
+            None => PIPE_FS.clone(),
         }
     }
     impl_inode!();
 }
+
+impl Pollable for Pipe {
+    fn poll(&self) -> PollStatus {
+        let buf = self.data.buf.lock();
+        let read = PollStatus {
+            read: buf.readable(),
+            hangup: buf.writers == 0,
+            ..PollStatus::default()
+        };
+        // writes fail at once without readers
+        let write = PollStatus {
+            write: buf.free() >= PIPE_BUF || buf.readers == 0,
+            error: buf.readers == 0,
+            ..PollStatus::default()
+        };
+        match self.direction {
+            PipeEnd::Read => read,
+            PipeEnd::Write => write,
+            PipeEnd::Both => PollStatus {
+                write: write.write,
+                ..read
+            },
+        }
+    }
+    fn wait_queue(&self) -> Option<&Condvar> {
+        match self.direction {
+            PipeEnd::Read => Some(&self.data.readable),
+            PipeEnd::Write => Some(&self.data.writable),
+            PipeEnd::Both => Some(&self.data.changed),
+        }
+    }
+    fn try_read(&self, _offset: usize, buf: &mut [u8]) -> Option<Result<usize>> {
+        if let PipeEnd::Write = self.direction {
+            return Some(Ok(0));
+        }
+        if buf.is_empty() {
+            return Some(Ok(0));
+        }
+        let mut pipe = self.data.buf.lock();
+        if !pipe.readable() {
+            return None;
+        }
+        // end of file without writers if empty
+        let len = pipe.pop(buf);
+        drop(pipe);
+        self.data.notify_writable();
+        Some(Ok(len))
+    }
+}
@@ -0,0 +1,91 @@
+//! Readiness of INodes for poll and select
+//!
+//! `INode` comes from rcore-fs, so INodes whose reads or writes may wait
+//! implement `Pollable` and are found by downcasting.
+
+use rcore_fs::vfs::{INode, Result};
+
+use super::devfs::DevINode;
+use super::eventfd::EventFd;
+use super::pipe::Pipe;
+use super::signalfd::SignalFd;
+use super::stdio::{Stdin, Stdout};
+use super::timerfd::TimerFd;
+use crate::sync::Condvar;
+
+/// What a read or write would do now
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PollStatus {
+    /// A read would not wait, which includes end of file
+    pub read: bool,
+    /// A write would not wait
+    pub write: bool,
+    pub error: bool,
+    /// The other end has been closed
+    pub hangup: bool,
+}
+
+impl PollStatus {
+    /// Both reads and writes complete at once, like on a regular file
+    pub const READY: PollStatus = PollStatus {
+        read: true,
+        write: true,
+        error: false,
+        hangup: false,
+    };
+}
+
+pub trait Pollable {
+    fn poll(&self) -> PollStatus;
+    /// Notified whenever the result of `poll` may have changed
+    fn wait_queue(&self) -> Option<&Condvar>;
+    /// Read like `read_at` if that would not wait, or return `None`
+    fn try_read(&self, offset: usize, buf: &mut [u8]) -> Option<Result<usize>>;
+}
+
+fn as_pollable(inode: &INode) -> Option<&Pollable> {
+    let any = inode.as_any_ref();
+    if let Some(pipe) = any.downcast_ref::<Pipe>() {
+        Some(pipe)
+    } else if let Some(stdin) = any.downcast_ref::<Stdin>() {
+        Some(stdin)
+    } else if let Some(stdout) = any.downcast_ref::<Stdout>() {
+        Some(stdout)
+    } else if let Some(dev) = any.downcast_ref::<DevINode>() {
+        Some(dev)
+    } else if let Some(eventfd) = any.downcast_ref::<EventFd>() {
+        Some(eventfd)
+    } else if let Some(timerfd) = any.downcast_ref::<TimerFd>() {
+        Some(timerfd)
+    } else if let Some(signalfd) = any.downcast_ref::<SignalFd>() {
+        Some(signalfd)
+    } else {
+        None
+    }
+}
+
+/// Whether `inode` may wait for reads or writes, which regular files never do
+pub fn is_pollable(inode: &INode) -> bool {
+    as_pollable(inode).is_some()
+}
+
+/// The readiness of `inode`; others than those implementing `Pollable` never wait
+pub fn poll_inode(inode: &INode) -> PollStatus {
+    match as_pollable(inode) {
+        Some(pollable) => pollable.poll(),
+        None => PollStatus::READY,
+    }
+}
+
+/// Read `inode` like `read_at` if that would not wait, or return `None`
+pub fn try_read_inode(inode: &INode, offset: usize, buf: &mut [u8]) -> Option<Result<usize>> {
+    match as_pollable(inode) {
+        Some(pollable) => pollable.try_read(offset, buf),
+        None => Some(inode.read_at(offset, buf)),
+    }
+}
+
+/// What to wait on until `poll_inode` may change
+pub fn inode_wait_queue(inode: &INode) -> Option<&Condvar> {
+    as_pollable(inode).and_then(|pollable| pollable.wait_queue())
+}
@@ -0,0 +1,537 @@
+//! Process information pseudo file system
+//!
+//! Every file is generated from the kernel state when it is read.
+//! Processes are locked meanwhile, so a syscall holding the lock of the current
+//! process reads its links through `proc_link` instead.
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+use core::any::Any;
+use core::fmt::Write;
+use core::sync::atomic::Ordering;
+
+use rcore_fs::vfs::*;
+use rcore_memory::PAGE_SIZE;
+
+use super::{mounts_info, FileLike};
+use crate::drivers::CMDLINE;
+use crate::memory::{TOTAL_FRAMES, USED_FRAMES};
+use crate::process::{current_thread, Process, PROCESSES};
+use crate::sync::SpinNoIrqLock as Mutex;
+use crate::trap::uptime_msec;
+use crate::{CPU_ONLINE, HEAP_ALLOCATOR};
+
+pub struct ProcFS;
+
+lazy_static! {
+    pub static ref PROC_FS: Arc<ProcFS> = Arc::new(ProcFS);
+}
+
+impl FileSystem for ProcFS {
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn root_inode(&self) -> Arc<INode> {
+        Arc::new(ProcINode::Root)
+    }
+
+    fn info(&self) -> &'static FsInfo {
+        static INFO: FsInfo = FsInfo { max_file_size: 0 };
+        &INFO
+    }
+}
+
+/// INode of procfs, which is just a path to the information
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum ProcINode {
+    /// /proc
+    Root,
+    /// /proc/self, a link to the directory of current process
+    SelfLink,
+    /// /proc/<file>
+    RootFile(RootFile),
+    /// /proc/<pid>
+    Pid(usize),
+    /// /proc/<pid>/<file>
+    PidFile(usize, PidFile),
+    /// /proc/<pid>/fd
+    FdDir(usize),
+    /// /proc/<pid>/fd/<fd>, a link to the path of the file
+    FdLink(usize, usize),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum RootFile {
+    Mounts,
+    Cpuinfo,
+    Meminfo,
+    Uptime,
+    Cmdline,
+}
+
+const ROOT_FILES: [(&str, RootFile); 5] = [
+    ("mounts", RootFile::Mounts),
+    ("cpuinfo", RootFile::Cpuinfo),
+    ("meminfo", RootFile::Meminfo),
+    ("uptime", RootFile::Uptime),
+    ("cmdline", RootFile::Cmdline),
+];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum PidFile {
+    Maps,
+    Smaps,
+    Status,
+    Stat,
+    Cmdline,
+    /// A link to the working directory
+    Cwd,
+    /// A link to the executable
+    Exe,
+}
+
+const PID_FILES: [(&str, PidFile); 7] = [
+    ("maps", PidFile::Maps),
+    ("smaps", PidFile::Smaps),
+    ("status", PidFile::Status),
+    ("stat", PidFile::Stat),
+    ("cmdline", PidFile::Cmdline),
+    ("cwd", PidFile::Cwd),
+    ("exe", PidFile::Exe),
+];
+
+impl ProcINode {
+    fn type_(&self) -> FileType {
+        match self {
+            ProcINode::Root | ProcINode::Pid(_) | ProcINode::FdDir(_) => FileType::Dir,
+            ProcINode::SelfLink
+            | ProcINode::PidFile(_, PidFile::Cwd)
+            | ProcINode::PidFile(_, PidFile::Exe)
+            | ProcINode::FdLink(..) => FileType::SymLink,
+            ProcINode::RootFile(_) | ProcINode::PidFile(..) => FileType::File,
+        }
+    }
+
+    fn inode_id(&self) -> usize {
+        match *self {
+            ProcINode::Root => 1,
+            ProcINode::SelfLink => 2,
+            ProcINode::RootFile(file) => 3 + file as usize,
+            ProcINode::Pid(pid) => (pid + 1) << 16,
+            ProcINode::PidFile(pid, file) => ((pid + 1) << 16) | (file as usize + 1),
+            ProcINode::FdDir(pid) => ((pid + 1) << 16) | 0xff,
+            ProcINode::FdLink(pid, fd) => ((pid + 1) << 16) | (fd + 0x100),
+        }
+    }
+
+    fn parent(&self) -> ProcINode {
+        match *self {
+            ProcINode::PidFile(pid, _) | ProcINode::FdDir(pid) => ProcINode::Pid(pid),
+            ProcINode::FdLink(pid, _) => ProcINode::FdDir(pid),
+            _ => ProcINode::Root,
+        }
+    }
+
+    fn entries(&self) -> Result<Vec<String>> {
+        let mut entries = vec![String::from("."), String::from("..")];
+        match *self {
+            ProcINode::Root => {
+                entries.push(String::from("self"));
+                entries.extend(ROOT_FILES.iter().map(|(name, _)| String::from(*name)));
+                let processes = PROCESSES.read();
+                for (pid, proc) in processes.iter() {
+                    if proc.upgrade().is_some() {
+                        entries.push(format!("{}", pid));
+                    }
+                }
+            }
+            ProcINode::Pid(_) => {
+                entries.push(String::from("fd"));
+                entries.extend(PID_FILES.iter().map(|(name, _)| String::from(*name)));
+            }
+            ProcINode::FdDir(pid) => {
+                let proc = get_process(pid)?;
+                let fds: Vec<usize> = proc.lock().files.keys().cloned().collect();
+                entries.extend(fds.into_iter().map(|fd| format!("{}", fd)));
+            }
+            _ => return Err(FsError::NotDir),
+        }
+        Ok(entries)
+    }
+
+    fn find_child(&self, name: &str) -> Result<ProcINode> {
+        match (*self, name) {
+            (ProcINode::Root, ".") | (ProcINode::Pid(_), ".") | (ProcINode::FdDir(_), ".") => {
+                Ok(*self)
+            }
+            (ProcINode::Root, "..") | (ProcINode::Pid(_), "..") | (ProcINode::FdDir(_), "..") => {
+                Ok(self.parent())
+            }
+            (ProcINode::Root, "self") => Ok(ProcINode::SelfLink),
+            (ProcINode::Pid(pid), "fd") => Ok(ProcINode::FdDir(pid)),
+            (ProcINode::Root, _) => {
+                let root_file = ROOT_FILES.iter().find(|(file_name, _)| *file_name == name);
+                if let Some(&(_, file)) = root_file {
+                    return Ok(ProcINode::RootFile(file));
+                }
+                let pid = name.parse::<usize>().map_err(|_| FsError::EntryNotFound)?;
+                get_process(pid)?;
+                Ok(ProcINode::Pid(pid))
+            }
+            (ProcINode::Pid(pid), _) => PID_FILES
+                .iter()
+                .find(|(file_name, _)| *file_name == name)
+                .map(|&(_, file)| ProcINode::PidFile(pid, file))
+                .ok_or(FsError::EntryNotFound),
+            // whether the fd is open is only checked when the link is read,
+            // since lookups may hold the lock of the process
+            (ProcINode::FdDir(pid), _) => {
+                let fd = name.parse::<usize>().map_err(|_| FsError::EntryNotFound)?;
+                Ok(ProcINode::FdLink(pid, fd))
+            }
+            _ => Err(FsError::NotDir),
+        }
+    }
+
+    /// Generate the content of file or link
+    fn content(&self) -> Result<String> {
+        match *self {
+            ProcINode::SelfLink => self.link_target(&current_thread().proc.lock()),
+            ProcINode::RootFile(file) => Ok(match file {
+                RootFile::Mounts => mounts_info(),
+                RootFile::Cpuinfo => cpuinfo(),
+                RootFile::Meminfo => meminfo(),
+                RootFile::Uptime => {
+                    let msec = uptime_msec();
+                    // idle time is not accounted
+                    format!("{}.{:02} 0.00\n", msec / 1000, msec % 1000 / 10)
+                }
+                RootFile::Cmdline => format!("{}\n", *CMDLINE.read()),
+            }),
+            ProcINode::PidFile(_, PidFile::Cwd)
+            | ProcINode::PidFile(_, PidFile::Exe)
+            | ProcINode::FdLink(..) => {
+                let proc = get_process(self.pid())?;
+                let proc = proc.lock();
+                self.link_target(&proc)
+            }
+            ProcINode::PidFile(pid, file) => {
+                let proc = get_process(pid)?;
+                let mut proc = proc.lock();
+                let proc = &mut *proc;
+                Ok(match file {
+                    PidFile::Maps => maps(proc, false),
+                    PidFile::Smaps => maps(proc, true),
+                    PidFile::Status => status(proc),
+                    PidFile::Stat => stat(proc),
+                    PidFile::Cmdline => {
+                        let mut s = String::new();
+                        for arg in proc.args.iter() {
+                            s += arg;
+                            s.push('\0');
+                        }
+                        s
+                    }
+                    PidFile::Cwd | PidFile::Exe => unreachable!(),
+                })
+            }
+            _ => Err(FsError::IsDir),
+        }
+    }
+
+    /// The process of a link, 0 for /proc/self
+    fn pid(&self) -> usize {
+        match *self {
+            ProcINode::PidFile(pid, _) | ProcINode::FdLink(pid, _) => pid,
+            _ => 0,
+        }
+    }
+
+    /// Target of the link, which is about `proc`
+    fn link_target(&self, proc: &Process) -> Result<String> {
+        match *self {
+            ProcINode::SelfLink => Ok(format!("{}", proc.pid.get())),
+            ProcINode::PidFile(_, PidFile::Cwd) => Ok(proc.cwd.clone()),
+            ProcINode::PidFile(_, PidFile::Exe) => Ok(proc.exec_path.clone()),
+            ProcINode::FdLink(_, fd) => match proc.files.get(&fd) {
+                Some(FileLike::File(file)) => Ok(String::from(file.path())),
+                Some(FileLike::Socket(_)) => Ok(String::from("socket")),
+                Some(FileLike::Epoll(_)) => Ok(String::from("anon_inode:[eventpoll]")),
+                None => Err(FsError::EntryNotFound),
+            },
+            _ => Err(FsError::InvalidParam),
+        }
+    }
+}
+
+/// Read the target of `inode` if it is a link of procfs about `current`,
+/// whose lock is held by the caller, so that it is not locked again
+pub fn proc_link(inode: &Arc<INode>, current: &Process) -> Option<Result<String>> {
+    let inode = inode.as_any_ref().downcast_ref::<ProcINode>()?;
+    match *inode {
+        ProcINode::SelfLink => {}
+        ProcINode::PidFile(pid, PidFile::Cwd)
+        | ProcINode::PidFile(pid, PidFile::Exe)
+        | ProcINode::FdLink(pid, _)
+            if pid == current.pid.get() => {}
+        _ => return None,
+    }
+    Some(inode.link_target(current))
+}
+
+impl INode for ProcINode {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let content = self.content()?;
+        let content = content.as_bytes();
+        if offset >= content.len() {
+            return Ok(0);
+        }
+        let len = buf.len().min(content.len() - offset);
+        buf[..len].copy_from_slice(&content[offset..offset + len]);
+        Ok(len)
+    }
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(FsError::NotSupported)
+    }
+    fn metadata(&self) -> Result<Metadata> {
+        let type_ = self.type_();
+        Ok(Metadata {
+            dev: 0,
+            inode: self.inode_id(),
+            size: 0,
+            blk_size: 0,
+            blocks: 0,
+            atime: Timespec { sec: 0, nsec: 0 },
+            mtime: Timespec { sec: 0, nsec: 0 },
+            ctime: Timespec { sec: 0, nsec: 0 },
+            type_,
+            mode: match type_ {
+                FileType::Dir => 0o555,
+                FileType::SymLink => 0o777,
+                _ => 0o444,
+            },
+            nlinks: 1,
+            uid: 0,
+            gid: 0,
+        })
+    }
+    fn chmod(&self, _mode: u16) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+    fn sync_all(&self) -> Result<()> {
+        Ok(())
+    }
+    fn sync_data(&self) -> Result<()> {
+        Ok(())
+    }
+    fn resize(&self, _len: usize) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+    fn create(&self, _name: &str, _type_: FileType, _mode: u32) -> Result<Arc<INode>> {
+        Err(FsError::NotSupported)
+    }
+    fn unlink(&self, _name: &str) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+    fn link(&self, _name: &str, _other: &Arc<INode>) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+    fn move_(&self, _old_name: &str, _target: &Arc<INode>, _new_name: &str) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+    fn find(&self, name: &str) -> Result<Arc<INode>> {
+        Ok(Arc::new(self.find_child(name)?))
+    }
+    fn get_entry(&self, id: usize) -> Result<String> {
+        self.entries()?
+            .into_iter()
+            .nth(id)
+            .ok_or(FsError::EntryNotFound)
+    }
+    fn fs(&self) -> Arc<FileSystem> {
+        PROC_FS.clone()
+    }
+    fn as_any_ref(&self) -> &Any {
+        self
+    }
+}
+
+fn get_process(pid: usize) -> Result<Arc<Mutex<Process>>> {
+    PROCESSES
+        .read()
+        .get(&pid)
+        .and_then(|weak| weak.upgrade())
+        .ok_or(FsError::EntryNotFound)
+}
+
+/// Size of resident pages in [`start`, `end`)
+fn resident_size(proc: &mut Process, start: usize, end: usize) -> usize {
+    proc.vm
+        .resident_pages(start, end)
+        .map_or(0, |pages| pages.iter().filter(|&&present| present).count())
+        * PAGE_SIZE
+}
+
+/// Generate /proc/<pid>/maps, and /proc/<pid>/smaps if `detail` is set
+fn maps(proc: &mut Process, detail: bool) -> String {
+    let mut areas: Vec<_> = proc
+        .vm
+        .iter()
+        .map(|area| (area.start_addr(), area.end_addr(), area.attr(), area.name()))
+        .collect();
+    areas.sort_by_key(|&(start, ..)| start);
+    let mut s = String::new();
+    for (start, end, attr, name) in areas {
+        let end = (end + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+        let user = attr.is_user();
+        write!(
+            s,
+            "{:08x}-{:08x} {}{}{}p 00000000 00:00 0",
+            start,
+            end,
+            if user { 'r' } else { '-' },
+            if user && !attr.is_readonly() { 'w' } else { '-' },
+            if user && attr.is_execute() { 'x' } else { '-' },
+        )
+        .unwrap();
+        match name {
+            "" => writeln!(s).unwrap(),
+            "user_stack" => writeln!(s, "{:20}[stack]", "").unwrap(),
+            "heap" => writeln!(s, "{:20}[heap]", "").unwrap(),
+            _ => writeln!(s, "{:20}{}", "", name).unwrap(),
+        }
+        if detail {
+            let rss = resident_size(proc, start, end) / 1024;
+            writeln!(s, "Size:           {:8} kB", (end - start) / 1024).unwrap();
+            writeln!(s, "Rss:            {:8} kB", rss).unwrap();
+            // nothing is shared, since fork copies all pages
+            writeln!(s, "Pss:            {:8} kB", rss).unwrap();
+        }
+    }
+    s
+}
+
+/// Sizes of the user address space
+struct VmStats {
+    size: usize,
+    rss: usize,
+    data: usize,
+    stack: usize,
+}
+
+fn vm_stats(proc: &mut Process) -> VmStats {
+    let mut stats = VmStats {
+        size: 0,
+        rss: 0,
+        data: 0,
+        stack: 0,
+    };
+    let areas: Vec<_> = proc
+        .vm
+        .iter()
+        .filter(|area| area.attr().is_user())
+        .map(|area| (area.start_addr(), area.end_addr(), area.name()))
+        .collect();
+    for (start, end, name) in areas {
+        let len = end - start;
+        stats.size += len;
+        match name {
+            "user_stack" => stats.stack += len,
+            "heap" | "mmap_anon" | "mmap_huge" => stats.data += len,
+            _ => {}
+        }
+        stats.rss += resident_size(proc, start, end);
+    }
+    stats
+}
+
+fn ppid(proc: &Process) -> usize {
+    match proc.parent {
+        Some(ref parent) => parent.lock().pid.get(),
+        None => 0,
+    }
+}
+
+/// Name of the executable, at most 15 characters like Linux
+fn comm(proc: &Process) -> &str {
+    let name = proc.exec_path.rsplit('/').next().unwrap_or("");
+    match name.char_indices().nth(15) {
+        Some((end, _)) => &name[..end],
+        None => name,
+    }
+}
+
+/// Generate /proc/<pid>/status
+fn status(proc: &mut Process) -> String {
+    let stats = vm_stats(proc);
+    let mut s = String::new();
+    writeln!(s, "Name:\t{}", comm(proc)).unwrap();
+    writeln!(s, "State:\tR (running)").unwrap();
+    writeln!(s, "Pid:\t{}", proc.pid).unwrap();
+    writeln!(s, "PPid:\t{}", ppid(proc)).unwrap();
+    writeln!(s, "Threads:\t{}", proc.threads.len()).unwrap();
+    writeln!(s, "VmSize:\t{:8} kB", stats.size / 1024).unwrap();
+    writeln!(s, "VmRSS:\t{:8} kB", stats.rss / 1024).unwrap();
+    writeln!(s, "VmData:\t{:8} kB", stats.data / 1024).unwrap();
+    writeln!(s, "VmStk:\t{:8} kB", stats.stack / 1024).unwrap();
+    s
+}
+
+/// Generate /proc/<pid>/stat, fields not tracked by the kernel are 0
+fn stat(proc: &mut Process) -> String {
+    let stats = vm_stats(proc);
+    let mut s = String::new();
+    // pid (comm) state ppid pgrp session tty_nr tpgid flags
+    write!(s, "{} ({}) R {} 0 0 0 0 0 ", proc.pid, comm(proc), ppid(proc)).unwrap();
+    // minflt cminflt majflt cmajflt utime stime cutime cstime priority nice
+    write!(s, "0 0 0 0 0 0 0 0 20 0 ").unwrap();
+    // num_threads itrealvalue starttime vsize rss
+    writeln!(
+        s,
+        "{} 0 0 {} {}",
+        proc.threads.len(),
+        stats.size,
+        stats.rss / PAGE_SIZE
+    )
+    .unwrap();
+    s
+}
+
+/// Generate /proc/cpuinfo
+fn cpuinfo() -> String {
+    let mut s = String::new();
+    for i in 0..CPU_ONLINE.load(Ordering::Relaxed) {
+        writeln!(s, "processor\t: {}", i).unwrap();
+        writeln!(s, "arch\t\t: {}", ARCH).unwrap();
+        writeln!(s).unwrap();
+    }
+    s
+}
+
+#[cfg(target_arch = "x86_64")]
+const ARCH: &str = "x86_64";
+#[cfg(target_arch = "riscv32")]
+const ARCH: &str = "riscv32";
+#[cfg(target_arch = "riscv64")]
+const ARCH: &str = "riscv64";
+#[cfg(target_arch = "aarch64")]
+const ARCH: &str = "aarch64";
+
+/// Generate /proc/meminfo
+fn meminfo() -> String {
+    let total = TOTAL_FRAMES.load(Ordering::Relaxed) * PAGE_SIZE / 1024;
+    let used = USED_FRAMES.load(Ordering::Relaxed) * PAGE_SIZE / 1024;
+    let (heap_total, heap_used) = {
+        let heap = HEAP_ALLOCATOR.lock();
+        (heap.stats_total_bytes() / 1024, heap.stats_alloc_actual() / 1024)
+    };
+    let mut s = String::new();
+    writeln!(s, "MemTotal:       {:8} kB", total).unwrap();
+    writeln!(s, "MemFree:        {:8} kB", total - used).unwrap();
+    writeln!(s, "MemAvailable:   {:8} kB", total - used).unwrap();
+    writeln!(s, "Buffers:        {:8} kB", 0).unwrap();
+    writeln!(s, "Cached:         {:8} kB", 0).unwrap();
+    writeln!(s, "KernelHeap:     {:8} kB", heap_total).unwrap();
+    writeln!(s, "KernelHeapUsed: {:8} kB", heap_used).unwrap();
+    s
+}
@@ -0,0 +1,224 @@
+//! Owners, mode bits, device numbers and times of SFS files
+//!
+//! The on-disk inode of SFS has none of them, so they are kept
+//! in memory by inode id while the file system is opened. Every SFS INode
+//! is wrapped to report and change them.
+
+use alloc::{
+    collections::BTreeMap,
+    string::String,
+    sync::{Arc, Weak},
+};
+use core::any::Any;
+
+use rcore_fs::dev::Device;
+use rcore_fs::vfs::*;
+use rcore_fs_sfs::SimpleFileSystem;
+use spin::RwLock;
+
+use super::FsStat;
+
+/// Attributes which SFS doesn't record
+#[derive(Debug, Clone, Copy)]
+struct Attrs {
+    mode: u16,
+    uid: usize,
+    gid: usize,
+    /// Device number of a device file
+    rdev: usize,
+    /// Times set by `set_times`, as SFS reports none
+    atime: Option<Timespec>,
+    mtime: Option<Timespec>,
+}
+
+pub struct SfsFS {
+    sfs: Arc<SimpleFileSystem>,
+    /// Where the superblock is
+    device: Arc<Device>,
+    /// Attributes by inode id, those without an entry are the defaults of SFS.
+    /// An id is given a new entry when it is reused by `create`.
+    attrs: RwLock<BTreeMap<usize, Attrs>>,
+    self_ptr: Weak<SfsFS>,
+}
+
+impl SfsFS {
+    pub fn open(device: Arc<Device>) -> Result<Arc<Self>> {
+        Ok(SfsFS {
+            sfs: SimpleFileSystem::open(device.clone())?,
+            device,
+            attrs: RwLock::new(BTreeMap::new()),
+            self_ptr: Weak::default(),
+        }
+        .wrap())
+    }
+
+    /// Wrap pure SfsFS with Arc, and set its weak pointer to itself
+    fn wrap(self) -> Arc<Self> {
+        let fs = Arc::new(self);
+        let weak = Arc::downgrade(&fs);
+        let ptr = Arc::into_raw(fs) as *mut Self;
+        unsafe {
+            (*ptr).self_ptr = weak;
+            Arc::from_raw(ptr)
+        }
+    }
+
+    /// Wrap an INode of SFS
+    fn inode(&self, inner: Arc<INode>) -> Arc<INode> {
+        Arc::new(SfsINode {
+            inner,
+            fs: self.self_ptr.upgrade().unwrap(),
+        })
+    }
+}
+
+impl FileSystem for SfsFS {
+    fn sync(&self) -> Result<()> {
+        self.sfs.sync()
+    }
+
+    fn root_inode(&self) -> Arc<INode> {
+        self.inode(self.sfs.root_inode())
+    }
+
+    fn info(&self) -> &'static FsInfo {
+        self.sfs.info()
+    }
+}
+
+pub struct SfsINode {
+    inner: Arc<INode>,
+    fs: Arc<SfsFS>,
+}
+
+impl SfsINode {
+    pub fn statfs(&self) -> Result<FsStat> {
+        // write the superblock with the current usage
+        self.fs.sfs.sync()?;
+        super::statfs::sfs_statfs(&self.fs.device)
+    }
+
+    pub fn chown(&self, uid: usize, gid: usize) -> Result<()> {
+        self.update(|attrs| {
+            attrs.uid = uid;
+            attrs.gid = gid;
+        })
+    }
+
+    pub fn set_times(&self, atime: Option<Timespec>, mtime: Option<Timespec>) -> Result<()> {
+        self.update(|attrs| {
+            if atime.is_some() {
+                attrs.atime = atime;
+            }
+            if mtime.is_some() {
+                attrs.mtime = mtime;
+            }
+        })
+    }
+
+    pub fn set_rdev(&self, rdev: usize) -> Result<()> {
+        self.update(|attrs| attrs.rdev = rdev)
+    }
+
+    pub fn rdev(&self) -> usize {
+        let id = match self.inner.metadata() {
+            Ok(info) => info.inode,
+            Err(_) => return 0,
+        };
+        self.fs.attrs.read().get(&id).map_or(0, |attrs| attrs.rdev)
+    }
+
+    /// Change the attributes, starting from the current ones
+    fn update(&self, f: impl FnOnce(&mut Attrs)) -> Result<()> {
+        let info = self.inner.metadata()?;
+        let mut table = self.fs.attrs.write();
+        let attrs = table.entry(info.inode).or_insert(Attrs {
+            mode: info.mode,
+            uid: info.uid,
+            gid: info.gid,
+            rdev: 0,
+            atime: None,
+            mtime: None,
+        });
+        f(attrs);
+        Ok(())
+    }
+
+    /// The SFS INode of `other`, for the operations on two INodes
+    fn unwrap(other: &Arc<INode>) -> Result<&Arc<INode>> {
+        match other.as_any_ref().downcast_ref::<SfsINode>() {
+            Some(other) => Ok(&other.inner),
+            None => Err(FsError::NotSameFs),
+        }
+    }
+}
+
+impl INode for SfsINode {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        self.inner.read_at(offset, buf)
+    }
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        self.inner.write_at(offset, buf)
+    }
+    fn metadata(&self) -> Result<Metadata> {
+        let mut info = self.inner.metadata()?;
+        if let Some(attrs) = self.fs.attrs.read().get(&info.inode) {
+            info.mode = attrs.mode;
+            info.uid = attrs.uid;
+            info.gid = attrs.gid;
+            info.atime = attrs.atime.unwrap_or(info.atime);
+            info.mtime = attrs.mtime.unwrap_or(info.mtime);
+        }
+        Ok(info)
+    }
+    fn chmod(&self, mode: u16) -> Result<()> {
+        self.update(|attrs| attrs.mode = mode)
+    }
+    fn sync_all(&self) -> Result<()> {
+        self.inner.sync_all()
+    }
+    fn sync_data(&self) -> Result<()> {
+        self.inner.sync_data()
+    }
+    fn resize(&self, len: usize) -> Result<()> {
+        self.inner.resize(len)
+    }
+    fn create(&self, name: &str, type_: FileType, mode: u32) -> Result<Arc<INode>> {
+        let inode = self.inner.create(name, type_, mode)?;
+        let id = inode.metadata()?.inode;
+        // the id may be of a deleted file
+        self.fs.attrs.write().insert(
+            id,
+            Attrs {
+                mode: mode as u16,
+                uid: 0,
+                gid: 0,
+                rdev: 0,
+                atime: None,
+                mtime: None,
+            },
+        );
+        Ok(self.fs.inode(inode))
+    }
+    fn unlink(&self, name: &str) -> Result<()> {
+        self.inner.unlink(name)
+    }
+    fn link(&self, name: &str, other: &Arc<INode>) -> Result<()> {
+        self.inner.link(name, Self::unwrap(other)?)
+    }
+    fn move_(&self, old_name: &str, target: &Arc<INode>, new_name: &str) -> Result<()> {
+        self.inner.move_(old_name, Self::unwrap(target)?, new_name)
+    }
+    fn find(&self, name: &str) -> Result<Arc<INode>> {
+        Ok(self.fs.inode(self.inner.find(name)?))
+    }
+    fn get_entry(&self, id: usize) -> Result<String> {
+        self.inner.get_entry(id)
+    }
+    fn fs(&self) -> Arc<FileSystem> {
+        self.fs.clone()
+    }
+    fn as_any_ref(&self) -> &Any {
+        self
+    }
+}
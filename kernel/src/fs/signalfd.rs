@@ -0,0 +1,97 @@
+//! Implement INode for signalfd, reading pending signals through a file
+
+use alloc::{string::String, sync::Arc};
+use core::any::Any;
+
+use rcore_fs::vfs::*;
+
+use super::poll::{PollStatus, Pollable};
+use super::ANON_INODE_FS;
+use crate::process::{sig_bit, SigInfo, SignalQueue, SIGKILL, SIGSTOP};
+use crate::sync::Condvar;
+use crate::sync::SpinNoIrqLock as Mutex;
+
+/// Size of `struct signalfd_siginfo`
+const SIGINFO_SIZE: usize = 128;
+
+pub struct SignalFd {
+    /// Pending signals of the process which created it
+    signals: Arc<SignalQueue>,
+    /// Signals to read
+    mask: Mutex<u64>,
+}
+
+impl SignalFd {
+    pub fn new(signals: Arc<SignalQueue>, mask: u64) -> Self {
+        SignalFd {
+            signals,
+            mask: Mutex::new(Self::valid_mask(mask)),
+        }
+    }
+
+    pub fn set_mask(&self, mask: u64) {
+        *self.mask.lock() = Self::valid_mask(mask);
+    }
+
+    /// SIGKILL and SIGSTOP can not be read
+    fn valid_mask(mask: u64) -> u64 {
+        mask & !(sig_bit(SIGKILL) | sig_bit(SIGSTOP))
+    }
+
+    fn write_siginfo(info: &SigInfo, buf: &mut [u8]) {
+        for b in buf[..SIGINFO_SIZE].iter_mut() {
+            *b = 0;
+        }
+        // ssi_code is 0 for SI_USER
+        buf[0..4].copy_from_slice(&(info.signo as u32).to_ne_bytes());
+        buf[12..16].copy_from_slice(&(info.pid as u32).to_ne_bytes());
+        buf[16..20].copy_from_slice(&(info.uid as u32).to_ne_bytes());
+    }
+}
+
+impl INode for SignalFd {
+    /// Read as many pending signals as fit, waiting for the first one
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            if let Some(result) = self.try_read(offset, buf) {
+                return result;
+            }
+            let mask = *self.mask.lock();
+            self.signals
+                .arrived
+                .wait_unless(|| self.signals.has_pending(mask));
+        }
+    }
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(FsError::InvalidParam)
+    }
+    impl_inode!(ANON_INODE_FS);
+}
+
+impl Pollable for SignalFd {
+    fn poll(&self) -> PollStatus {
+        PollStatus {
+            read: self.signals.has_pending(*self.mask.lock()),
+            ..PollStatus::default()
+        }
+    }
+    fn wait_queue(&self) -> Option<&Condvar> {
+        Some(&self.signals.arrived)
+    }
+    fn try_read(&self, _offset: usize, buf: &mut [u8]) -> Option<Result<usize>> {
+        if buf.len() < SIGINFO_SIZE {
+            return Some(Err(FsError::InvalidParam));
+        }
+        let first = self.signals.take(*self.mask.lock())?;
+        Self::write_siginfo(&first, buf);
+        let mut len = SIGINFO_SIZE;
+        while buf.len() - len >= SIGINFO_SIZE {
+            match self.signals.take(*self.mask.lock()) {
+                Some(info) => Self::write_siginfo(&info, &mut buf[len..]),
+                None => break,
+            }
+            len += SIGINFO_SIZE;
+        }
+        Some(Ok(len))
+    }
+}
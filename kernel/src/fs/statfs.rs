@@ -0,0 +1,81 @@
+//! Usage of file systems, reported by statfs
+//!
+//! `FileSystem::info` is static, so `super::statfs` queries the usage from
+//! the file system of an INode found by its concrete type.
+
+use alloc::sync::Arc;
+use rcore_fs::dev::Device;
+use rcore_fs::vfs::*;
+use rcore_memory::PAGE_SIZE;
+
+/// Magic numbers of the file system types
+pub const MSDOS_SUPER_MAGIC: usize = 0x4d44;
+pub const TMPFS_MAGIC: usize = 0x0102_1994;
+pub const PROC_SUPER_MAGIC: usize = 0x9fa0;
+pub const DEVFS_SUPER_MAGIC: usize = 0x1373;
+pub const PIPEFS_MAGIC: usize = 0x5049_5045;
+pub const SOCKFS_MAGIC: usize = 0x534f_434b;
+pub const ANON_INODE_FS_MAGIC: usize = 0x0904_1934;
+/// Also found at the start of the superblock
+pub const SFS_MAGIC: usize = 0x2f8d_be2a;
+
+/// Block size of SFS
+const SFS_BLOCK_SIZE: usize = 4096;
+/// Max length of a file name in SFS
+const SFS_MAX_NAME_LEN: usize = 255;
+/// Max length of a file name in the pseudo file systems
+const NAME_MAX: usize = 255;
+
+/// Usage of a file system
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FsStat {
+    /// Magic number of the file system type
+    pub magic: usize,
+    pub block_size: usize,
+    /// Total number of blocks
+    pub blocks: usize,
+    pub blocks_free: usize,
+    /// Free blocks available to unprivileged users
+    pub blocks_avail: usize,
+    /// Total number of inodes, 0 if unlimited
+    pub files: usize,
+    pub files_free: usize,
+    /// Max length of a file name
+    pub name_max: usize,
+}
+
+impl FsStat {
+    /// A file system without storage
+    pub fn pseudo(magic: usize) -> Self {
+        FsStat {
+            magic,
+            block_size: PAGE_SIZE,
+            name_max: NAME_MAX,
+            ..FsStat::default()
+        }
+    }
+}
+
+/// Usage of SFS read from the superblock on `device`, which is written when
+/// the file system is synced. SFS keeps no count of inodes, so none is reported.
+pub(super) fn sfs_statfs(device: &Arc<Device>) -> Result<FsStat> {
+    let mut sb = [0u8; 12];
+    match device.read_at(0, &mut sb) {
+        Some(len) if len == sb.len() => {}
+        _ => return Err(FsError::DeviceError),
+    }
+    let field = |i: usize| u32::from_le_bytes([sb[i], sb[i + 1], sb[i + 2], sb[i + 3]]) as usize;
+    if field(0) != SFS_MAGIC {
+        return Err(FsError::WrongFs);
+    }
+    Ok(FsStat {
+        magic: SFS_MAGIC,
+        block_size: SFS_BLOCK_SIZE,
+        blocks: field(4),
+        blocks_free: field(8),
+        blocks_avail: field(8),
+        files: 0,
+        files_free: 0,
+        name_max: SFS_MAX_NAME_LEN,
+    })
+}
@@ -5,6 +5,8 @@ use core::any::Any;
 
 use rcore_fs::vfs::*;
 
+use super::poll::{PollStatus, Pollable};
+use super::DEV_FS;
 use crate::sync::Condvar;
 use crate::sync::SpinNoIrqLock as Mutex;
 
@@ -17,7 +19,8 @@ pub struct Stdin {
 impl Stdin {
     pub fn push(&self, c: char) {
         self.buf.lock().push_back(c);
-        self.pushed.notify_one();
+        // wake pollers as well as readers
+        self.pushed.notify_all();
     }
     pub fn pop(&self) -> char {
         // QEMU v3.0 don't support M-mode external interrupt (bug?)
@@ -43,25 +46,6 @@ lazy_static! {
     pub static ref STDOUT: Arc<Stdout> = Arc::new(Stdout::default());
 }
 
-// TODO: better way to provide default impl?
-macro_rules! impl_inode {
-    () => {
-        fn metadata(&self) -> Result<Metadata> { Err(FsError::NotSupported) }
-        fn sync_all(&self) -> Result<()> { Ok(()) }
-        fn sync_data(&self) -> Result<()> { Ok(()) }
-        fn resize(&self, _len: usize) -> Result<()> { Err(FsError::NotSupported) }
-        fn create(&self, _name: &str, _type_: FileType, _mode: u32) -> Result<Arc<INode>> { Err(FsError::NotDir) }
-        fn unlink(&self, _name: &str) -> Result<()> { Err(FsError::NotDir) }
-        fn link(&self, _name: &str, _other: &Arc<INode>) -> Result<()> { Err(FsError::NotDir) }
-        fn move_(&self, _old_name: &str, _target: &Arc<INode>, _new_name: &str) -> Result<()> { Err(FsError::NotDir) }
-        fn find(&self, _name: &str) -> Result<Arc<INode>> { Err(FsError::NotDir) }
-        fn get_entry(&self, _id: usize) -> Result<String> { Err(FsError::NotDir) }
-        fn fs(&self) -> Arc<FileSystem> { unimplemented!() }
-        fn as_any_ref(&self) -> &Any { self }
-        fn chmod(&self, _mode: u16) -> Result<()> { Ok(()) }
-    };
-}
-
 impl INode for Stdin {
     fn read_at(&self, _offset: usize, buf: &mut [u8]) -> Result<usize> {
         buf[0] = self.pop() as u8;
@@ -70,7 +54,7 @@ impl INode for Stdin {
     fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
         unimplemented!()
     }
-    impl_inode!();
+    impl_inode!(DEV_FS);
 }
 
 impl INode for Stdout {
@@ -84,5 +68,37 @@ impl INode for Stdout {
         print!("{}", s);
         Ok(buf.len())
     }
-    impl_inode!();
+    impl_inode!(DEV_FS);
+}
+
+impl Pollable for Stdin {
+    fn poll(&self) -> PollStatus {
+        PollStatus {
+            read: self.can_read(),
+            ..PollStatus::default()
+        }
+    }
+    fn wait_queue(&self) -> Option<&Condvar> {
+        Some(&self.pushed)
+    }
+    fn try_read(&self, _offset: usize, buf: &mut [u8]) -> Option<Result<usize>> {
+        let c = self.buf.lock().pop_front()?;
+        buf[0] = c as u8;
+        Some(Ok(1))
+    }
+}
+
+impl Pollable for Stdout {
+    fn poll(&self) -> PollStatus {
+        PollStatus {
+            write: true,
+            ..PollStatus::default()
+        }
+    }
+    fn wait_queue(&self) -> Option<&Condvar> {
+        None
+    }
+    fn try_read(&self, _offset: usize, _buf: &mut [u8]) -> Option<Result<usize>> {
+        Some(Err(FsError::NotSupported))
+    }
 }
@@ -0,0 +1,171 @@
+//! Implement INode for timerfd, a timer notifying expirations through a file
+
+use alloc::{string::String, sync::Arc};
+use core::any::Any;
+use core::time::Duration;
+
+use rcore_fs::vfs::*;
+use rcore_thread::Timer;
+
+use super::poll::{PollStatus, Pollable};
+use super::ANON_INODE_FS;
+use crate::consts::USEC_PER_TICK;
+use crate::sync::Condvar;
+use crate::sync::SpinNoIrqLock as Mutex;
+
+lazy_static! {
+    /// Armed timerfds, ticked by the timer interrupt on cpu 0
+    static ref TIMERS: Mutex<Timer<TimerRef>> = Mutex::new(Timer::new());
+}
+
+/// Called on each tick to fire expired timerfds
+pub fn timerfd_tick() {
+    let mut timers = TIMERS.lock();
+    timers.tick();
+    while let Some(timer) = timers.pop() {
+        let mut state = timer.0.state.lock();
+        state.expirations += 1;
+        if state.interval == 0 {
+            state.deadline = None;
+        } else {
+            state.deadline = Some(now() + state.interval);
+            timers.start(state.interval, timer.clone());
+        }
+        drop(state);
+        timer.0.expired.notify_all();
+    }
+}
+
+fn now() -> usize {
+    unsafe { crate::trap::TICK }
+}
+
+/// Round up to whole ticks
+fn dur_to_ticks(dur: Duration) -> usize {
+    let usec = dur.as_micros() as usize;
+    (usec + USEC_PER_TICK - 1) / USEC_PER_TICK
+}
+
+fn ticks_to_dur(ticks: usize) -> Duration {
+    Duration::from_micros((ticks * USEC_PER_TICK) as u64)
+}
+
+#[derive(Default)]
+struct TimerFdState {
+    /// Expirations since the last read
+    expirations: u64,
+    /// Tick of the next expiration, or `None` if disarmed
+    deadline: Option<usize>,
+    /// Ticks between expirations, or 0 to fire once
+    interval: usize,
+}
+
+#[derive(Default)]
+struct TimerShared {
+    state: Mutex<TimerFdState>,
+    expired: Condvar,
+}
+
+#[derive(Clone)]
+struct TimerRef(Arc<TimerShared>);
+
+impl PartialEq for TimerRef {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+pub struct TimerFd {
+    shared: Arc<TimerShared>,
+}
+
+impl TimerFd {
+    pub fn new() -> Self {
+        TimerFd {
+            shared: Arc::new(TimerShared::default()),
+        }
+    }
+
+    /// Arm the timer to expire after `value` and then every `interval`,
+    /// or disarm it if `value` is zero
+    ///
+    /// Return the old setting as `get` does.
+    pub fn set(&self, value: Duration, interval: Duration) -> (Duration, Duration) {
+        let mut timers = TIMERS.lock();
+        let this = TimerRef(self.shared.clone());
+        timers.stop(this.clone());
+        let mut state = self.shared.state.lock();
+        let old = Self::setting(&state);
+        state.expirations = 0;
+        state.interval = dur_to_ticks(interval);
+        if value == Duration::default() {
+            state.deadline = None;
+        } else {
+            // a timer fires on a later tick
+            let ticks = dur_to_ticks(value).max(1);
+            state.deadline = Some(now() + ticks);
+            timers.start(ticks, this);
+        }
+        old
+    }
+
+    /// The time until the next expiration, zero if disarmed, and the interval
+    pub fn get(&self) -> (Duration, Duration) {
+        Self::setting(&self.shared.state.lock())
+    }
+
+    fn setting(state: &TimerFdState) -> (Duration, Duration) {
+        let remaining = match state.deadline {
+            Some(deadline) => ticks_to_dur(deadline.saturating_sub(now())),
+            None => Duration::default(),
+        };
+        (remaining, ticks_to_dur(state.interval))
+    }
+}
+
+impl Drop for TimerFd {
+    fn drop(&mut self) {
+        TIMERS.lock().stop(TimerRef(self.shared.clone()));
+    }
+}
+
+impl INode for TimerFd {
+    /// Read the expirations as a u64, waiting until there is one
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            if let Some(result) = self.try_read(offset, buf) {
+                return result;
+            }
+            self.shared.expired.wait_unless(|| self.poll().read);
+        }
+    }
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(FsError::InvalidParam)
+    }
+    impl_inode!(ANON_INODE_FS);
+}
+
+impl Pollable for TimerFd {
+    fn poll(&self) -> PollStatus {
+        PollStatus {
+            read: self.shared.state.lock().expirations > 0,
+            ..PollStatus::default()
+        }
+    }
+    fn wait_queue(&self) -> Option<&Condvar> {
+        Some(&self.shared.expired)
+    }
+    fn try_read(&self, _offset: usize, buf: &mut [u8]) -> Option<Result<usize>> {
+        if buf.len() < 8 {
+            return Some(Err(FsError::InvalidParam));
+        }
+        let mut state = self.shared.state.lock();
+        if state.expirations == 0 {
+            return None;
+        }
+        let expirations = state.expirations;
+        state.expirations = 0;
+        buf[..8].copy_from_slice(&expirations.to_ne_bytes());
+        Some(Ok(8))
+    }
+}
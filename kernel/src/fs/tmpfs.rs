@@ -0,0 +1,552 @@
+//! Temporary file system in memory
+//!
+//! File content is stored in pages allocated on write,
+//! so holes left by `resize` or writing past the end take no memory.
+
+use alloc::{
+    boxed::Box,
+    collections::BTreeMap,
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::any::Any;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use rcore_fs::vfs::*;
+use spin::{Mutex, RwLock};
+
+use super::{atime_outdated, FsStat, TMPFS_MAGIC};
+use crate::memory::{TOTAL_FRAMES, USED_FRAMES};
+use crate::syscall::current_time;
+
+const PAGE_SIZE: usize = 4096;
+/// Max length of a file name in bytes
+const MAX_NAME_LEN: usize = 255;
+
+pub struct TmpFS {
+    root: Arc<LockedINode>,
+    next_inode_id: AtomicUsize,
+    /// Held while a file is moved between directories
+    rename_lock: Mutex<()>,
+}
+
+impl TmpFS {
+    pub fn new() -> Arc<TmpFS> {
+        let root = TmpINode::new(1, FileType::Dir, 0o1777);
+        let root = Arc::new(LockedINode(RwLock::new(root)));
+        {
+            let mut inode = root.0.write();
+            inode.this = Arc::downgrade(&root);
+            inode.parent = Arc::downgrade(&root);
+            inode.nlinks = 2;
+        }
+        let fs = Arc::new(TmpFS {
+            root,
+            next_inode_id: AtomicUsize::new(2),
+            rename_lock: Mutex::new(()),
+        });
+        fs.root.0.write().fs = Arc::downgrade(&fs);
+        fs
+    }
+
+    /// Files share the physical memory, and their number is unlimited
+    pub fn statfs(&self) -> FsStat {
+        let total = TOTAL_FRAMES.load(Ordering::Relaxed);
+        let free = total - USED_FRAMES.load(Ordering::Relaxed);
+        FsStat {
+            magic: TMPFS_MAGIC,
+            block_size: PAGE_SIZE,
+            blocks: total,
+            blocks_free: free,
+            blocks_avail: free,
+            files: 0,
+            files_free: 0,
+            name_max: MAX_NAME_LEN,
+        }
+    }
+
+    fn new_inode(fs: &Arc<TmpFS>, type_: FileType, mode: u16) -> Arc<LockedINode> {
+        let id = fs.next_inode_id.fetch_add(1, Ordering::Relaxed);
+        let mut inode = TmpINode::new(id, type_, mode);
+        inode.fs = Arc::downgrade(fs);
+        let inode = Arc::new(LockedINode(RwLock::new(inode)));
+        inode.0.write().this = Arc::downgrade(&inode);
+        inode
+    }
+}
+
+impl FileSystem for TmpFS {
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn root_inode(&self) -> Arc<INode> {
+        self.root.clone()
+    }
+
+    fn info(&self) -> &'static FsInfo {
+        static INFO: FsInfo = FsInfo {
+            max_file_size: usize::max_value(),
+        };
+        &INFO
+    }
+}
+
+pub struct LockedINode(RwLock<TmpINode>);
+
+impl LockedINode {
+    pub fn chown(&self, uid: usize, gid: usize) -> Result<()> {
+        let mut inode = self.0.write();
+        inode.uid = uid;
+        inode.gid = gid;
+        inode.touch_changed();
+        Ok(())
+    }
+
+    pub fn set_times(&self, atime: Option<Timespec>, mtime: Option<Timespec>) -> Result<()> {
+        let mut inode = self.0.write();
+        if let Some(atime) = atime {
+            inode.atime = atime;
+        }
+        if let Some(mtime) = mtime {
+            inode.mtime = mtime;
+        }
+        inode.touch_changed();
+        Ok(())
+    }
+
+    pub fn set_rdev(&self, rdev: usize) -> Result<()> {
+        self.0.write().rdev = rdev;
+        Ok(())
+    }
+
+    pub fn rdev(&self) -> usize {
+        self.0.read().rdev
+    }
+
+    pub fn statfs(&self) -> FsStat {
+        let fs = self.0.read().fs.upgrade().expect("tmpfs has been dropped");
+        fs.statfs()
+    }
+}
+
+struct TmpINode {
+    id: usize,
+    type_: FileType,
+    mode: u16,
+    uid: usize,
+    gid: usize,
+    nlinks: usize,
+    /// File size in bytes
+    size: usize,
+    atime: Timespec,
+    mtime: Timespec,
+    ctime: Timespec,
+    /// Device number of a device file
+    rdev: usize,
+    /// Pages of a file or symlink, indexed by page number
+    pages: BTreeMap<usize, Box<[u8; PAGE_SIZE]>>,
+    /// Entries of a directory, without "." and ".."
+    children: BTreeMap<String, Arc<LockedINode>>,
+    this: Weak<LockedINode>,
+    /// The parent directory, itself for the root
+    parent: Weak<LockedINode>,
+    /// Kept alive by the mount point
+    fs: Weak<TmpFS>,
+}
+
+impl TmpINode {
+    fn new(id: usize, type_: FileType, mode: u16) -> Self {
+        let now = current_time();
+        TmpINode {
+            id,
+            type_,
+            mode,
+            uid: 0,
+            gid: 0,
+            nlinks: 1,
+            size: 0,
+            atime: now,
+            mtime: now,
+            ctime: now,
+            rdev: 0,
+            pages: BTreeMap::new(),
+            children: BTreeMap::new(),
+            this: Weak::new(),
+            parent: Weak::new(),
+            fs: Weak::new(),
+        }
+    }
+
+    fn check_dir(&self) -> Result<()> {
+        match self.type_ {
+            FileType::Dir => Ok(()),
+            _ => Err(FsError::NotDir),
+        }
+    }
+
+    fn check_file(&self) -> Result<()> {
+        match self.type_ {
+            FileType::Dir => Err(FsError::IsDir),
+            FileType::File | FileType::SymLink => Ok(()),
+            _ => Err(FsError::NotFile),
+        }
+    }
+
+    fn is_dir(&self) -> bool {
+        self.type_ == FileType::Dir
+    }
+
+    /// Record a modification of the content
+    fn touch_modified(&mut self) {
+        let now = current_time();
+        self.mtime = now;
+        self.ctime = now;
+    }
+
+    /// Record a change of the attributes
+    fn touch_changed(&mut self) {
+        self.ctime = current_time();
+    }
+
+    fn same_fs(&self, other: &TmpINode) -> bool {
+        match (self.fs.upgrade(), other.fs.upgrade()) {
+            (Some(a), Some(b)) => Arc::ptr_eq(&a, &b),
+            _ => false,
+        }
+    }
+
+    /// Free the pages after `len` and zero the tail of the last page
+    fn truncate(&mut self, len: usize) {
+        let first_freed = (len + PAGE_SIZE - 1) / PAGE_SIZE;
+        let freed: Vec<usize> = self.pages.range(first_freed..).map(|(&i, _)| i).collect();
+        for i in freed {
+            self.pages.remove(&i);
+        }
+        if len % PAGE_SIZE != 0 {
+            if let Some(page) = self.pages.get_mut(&(len / PAGE_SIZE)) {
+                page[len % PAGE_SIZE..].iter_mut().for_each(|x| *x = 0);
+            }
+        }
+    }
+}
+
+impl INode for LockedINode {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let inode = self.0.read();
+        inode.check_file()?;
+        if offset >= inode.size {
+            return Ok(0);
+        }
+        let end = inode.size.min(offset + buf.len());
+        let mut pos = offset;
+        while pos < end {
+            let page_offset = pos % PAGE_SIZE;
+            let len = (PAGE_SIZE - page_offset).min(end - pos);
+            let dst = &mut buf[pos - offset..pos - offset + len];
+            match inode.pages.get(&(pos / PAGE_SIZE)) {
+                Some(page) => dst.copy_from_slice(&page[page_offset..page_offset + len]),
+                // a hole
+                None => dst.iter_mut().for_each(|x| *x = 0),
+            }
+            pos += len;
+        }
+        let now = current_time();
+        let outdated = atime_outdated(inode.atime.sec, inode.mtime.sec, inode.ctime.sec, now.sec);
+        drop(inode);
+        if outdated {
+            self.0.write().atime = now;
+        }
+        Ok(end - offset)
+    }
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        let mut inode = self.0.write();
+        inode.check_file()?;
+        let end = offset + buf.len();
+        let mut pos = offset;
+        while pos < end {
+            let page_offset = pos % PAGE_SIZE;
+            let len = (PAGE_SIZE - page_offset).min(end - pos);
+            let page = inode
+                .pages
+                .entry(pos / PAGE_SIZE)
+                .or_insert_with(|| Box::new([0; PAGE_SIZE]));
+            page[page_offset..page_offset + len]
+                .copy_from_slice(&buf[pos - offset..pos - offset + len]);
+            pos += len;
+        }
+        if end > inode.size {
+            inode.size = end;
+        }
+        inode.touch_modified();
+        Ok(buf.len())
+    }
+    fn metadata(&self) -> Result<Metadata> {
+        let inode = self.0.read();
+        Ok(Metadata {
+            dev: 0,
+            inode: inode.id,
+            size: match inode.type_ {
+                FileType::Dir => inode.children.len() + 2,
+                _ => inode.size,
+            },
+            blk_size: PAGE_SIZE,
+            blocks: inode.pages.len(),
+            atime: inode.atime,
+            mtime: inode.mtime,
+            ctime: inode.ctime,
+            type_: inode.type_,
+            mode: inode.mode,
+            nlinks: inode.nlinks,
+            uid: inode.uid,
+            gid: inode.gid,
+        })
+    }
+    fn chmod(&self, mode: u16) -> Result<()> {
+        let mut inode = self.0.write();
+        inode.mode = mode;
+        inode.touch_changed();
+        Ok(())
+    }
+    fn sync_all(&self) -> Result<()> {
+        Ok(())
+    }
+    fn sync_data(&self) -> Result<()> {
+        Ok(())
+    }
+    fn resize(&self, len: usize) -> Result<()> {
+        let mut inode = self.0.write();
+        if inode.type_ != FileType::File {
+            return Err(FsError::NotFile);
+        }
+        if len < inode.size {
+            inode.truncate(len);
+        }
+        // growing only moves the end, the hole reads as zero
+        inode.size = len;
+        inode.touch_modified();
+        Ok(())
+    }
+    fn create(&self, name: &str, type_: FileType, mode: u32) -> Result<Arc<INode>> {
+        let mut dir = self.0.write();
+        dir.check_dir()?;
+        if name == "." || name == ".." || dir.children.contains_key(name) {
+            return Err(FsError::EntryExist);
+        }
+        let fs = dir.fs.upgrade().ok_or(FsError::DeviceError)?;
+        let inode = TmpFS::new_inode(&fs, type_, mode as u16);
+        if type_ == FileType::Dir {
+            let mut child = inode.0.write();
+            child.parent = dir.this.clone();
+            child.nlinks = 2;
+            dir.nlinks += 1;
+        }
+        dir.children.insert(String::from(name), inode.clone());
+        dir.touch_modified();
+        Ok(inode)
+    }
+    fn unlink(&self, name: &str) -> Result<()> {
+        let mut dir = self.0.write();
+        dir.check_dir()?;
+        if name == "." || name == ".." {
+            return Err(FsError::IsDir);
+        }
+        let inode = dir.children.get(name).ok_or(FsError::EntryNotFound)?.clone();
+        let mut child = inode.0.write();
+        if child.is_dir() {
+            if !child.children.is_empty() {
+                return Err(FsError::DirNotEmpty);
+            }
+            child.nlinks = 0;
+            dir.nlinks -= 1;
+        } else {
+            child.nlinks -= 1;
+        }
+        child.touch_changed();
+        drop(child);
+        // the content is freed when the last opened file is closed
+        dir.children.remove(name);
+        dir.touch_modified();
+        Ok(())
+    }
+    fn link(&self, name: &str, other: &Arc<INode>) -> Result<()> {
+        let other = other
+            .as_any_ref()
+            .downcast_ref::<LockedINode>()
+            .ok_or(FsError::NotSameFs)?;
+        // directories can't be linked, so `other` is not `self`
+        if other.0.read().is_dir() {
+            return Err(FsError::IsDir);
+        }
+        let mut dir = self.0.write();
+        dir.check_dir()?;
+        let other = {
+            let mut other = other.0.write();
+            if !dir.same_fs(&other) {
+                return Err(FsError::NotSameFs);
+            }
+            if name == "." || name == ".." || dir.children.contains_key(name) {
+                return Err(FsError::EntryExist);
+            }
+            other.nlinks += 1;
+            other.touch_changed();
+            other.this.upgrade().ok_or(FsError::EntryNotFound)?
+        };
+        dir.children.insert(String::from(name), other);
+        dir.touch_modified();
+        Ok(())
+    }
+    fn move_(&self, old_name: &str, target: &Arc<INode>, new_name: &str) -> Result<()> {
+        let target = target
+            .as_any_ref()
+            .downcast_ref::<LockedINode>()
+            .ok_or(FsError::NotSameFs)?;
+        if old_name == "." || old_name == ".." || new_name == "." || new_name == ".." {
+            return Err(FsError::IsDir);
+        }
+        if core::ptr::eq(self, target) {
+            let mut dir = self.0.write();
+            dir.check_dir()?;
+            let inode = dir
+                .children
+                .get(old_name)
+                .ok_or(FsError::EntryNotFound)?
+                .clone();
+            if old_name != new_name {
+                replace_entry(&mut dir, new_name, &inode)?;
+                dir.children.remove(old_name);
+                dir.touch_modified();
+                inode.0.write().touch_changed();
+            }
+            return Ok(());
+        }
+        // Moves between directories are serialized, so that the tree above them
+        // stays the same while it is checked without holding their locks.
+        let fs = self.0.read().fs.upgrade().ok_or(FsError::DeviceError)?;
+        match target.0.read().fs.upgrade() {
+            Some(target_fs) if Arc::ptr_eq(&fs, &target_fs) => {}
+            _ => return Err(FsError::NotSameFs),
+        }
+        let _rename = fs.rename_lock.lock();
+        let inode = {
+            let dir = self.0.read();
+            dir.check_dir()?;
+            dir.children
+                .get(old_name)
+                .ok_or(FsError::EntryNotFound)?
+                .clone()
+        };
+        // a directory can't be moved into itself or its descendants
+        if inode.0.read().is_dir() && is_ancestor(&inode, target) {
+            return Err(FsError::InvalidParam);
+        }
+        // lock both directories in the order of their addresses, against dead lock
+        let (mut dir, mut new_dir) = if (self as *const LockedINode) < (target as *const _) {
+            let dir = self.0.write();
+            (dir, target.0.write())
+        } else {
+            let new_dir = target.0.write();
+            (self.0.write(), new_dir)
+        };
+        new_dir.check_dir()?;
+        // the entry may be unlinked before the directories are locked
+        match dir.children.get(old_name) {
+            Some(current) if Arc::ptr_eq(current, &inode) => {}
+            _ => return Err(FsError::EntryNotFound),
+        }
+        // the old directory is an ancestor of the new one, so it is not empty
+        if let Some(old) = new_dir.children.get(new_name) {
+            if core::ptr::eq(&**old, self) {
+                return Err(FsError::DirNotEmpty);
+            }
+        }
+        replace_entry(&mut new_dir, new_name, &inode)?;
+        dir.children.remove(old_name);
+        dir.touch_modified();
+        new_dir.touch_modified();
+        let mut child = inode.0.write();
+        if child.is_dir() {
+            child.parent = new_dir.this.clone();
+            dir.nlinks -= 1;
+            new_dir.nlinks += 1;
+        }
+        child.touch_changed();
+        Ok(())
+    }
+    fn find(&self, name: &str) -> Result<Arc<INode>> {
+        let dir = self.0.read();
+        dir.check_dir()?;
+        match name {
+            "." => dir.this.upgrade().map(|inode| inode as Arc<INode>),
+            ".." => dir.parent.upgrade().map(|inode| inode as Arc<INode>),
+            _ => dir.children.get(name).map(|inode| inode.clone() as Arc<INode>),
+        }
+        .ok_or(FsError::EntryNotFound)
+    }
+    fn get_entry(&self, id: usize) -> Result<String> {
+        let dir = self.0.read();
+        dir.check_dir()?;
+        match id {
+            0 => Ok(String::from(".")),
+            1 => Ok(String::from("..")),
+            _ => dir
+                .children
+                .keys()
+                .nth(id - 2)
+                .cloned()
+                .ok_or(FsError::EntryNotFound),
+        }
+    }
+    fn fs(&self) -> Arc<FileSystem> {
+        self.0.read().fs.upgrade().expect("tmpfs has been dropped")
+    }
+    fn as_any_ref(&self) -> &Any {
+        self
+    }
+}
+
+/// Whether `inode` is directory `dir` or one of its ancestors.
+/// No other inode of the file system may be locked.
+fn is_ancestor(inode: &LockedINode, dir: &LockedINode) -> bool {
+    let mut current = match dir.0.read().this.upgrade() {
+        Some(current) => current,
+        None => return false,
+    };
+    loop {
+        if core::ptr::eq(&*current, inode) {
+            return true;
+        }
+        let parent = current.0.read().parent.upgrade();
+        match parent {
+            // the root is its own parent
+            Some(parent) if !Arc::ptr_eq(&parent, &current) => current = parent,
+            _ => return false,
+        }
+    }
+}
+
+/// Put `inode` at `name` of `dir`, replacing the existing entry like `rename`
+fn replace_entry(dir: &mut TmpINode, name: &str, inode: &Arc<LockedINode>) -> Result<()> {
+    if let Some(old) = dir.children.get(name) {
+        if Arc::ptr_eq(old, inode) {
+            return Ok(());
+        }
+        let mut old = old.0.write();
+        match (inode.0.read().is_dir(), old.is_dir()) {
+            (true, true) => {
+                if !old.children.is_empty() {
+                    return Err(FsError::DirNotEmpty);
+                }
+                old.nlinks = 0;
+                dir.nlinks -= 1;
+            }
+            (false, false) => old.nlinks -= 1,
+            (true, false) => return Err(FsError::NotDir),
+            (false, true) => return Err(FsError::IsDir),
+        }
+        old.touch_changed();
+    }
+    dir.children.insert(String::from(name), inode.clone());
+    Ok(())
+}
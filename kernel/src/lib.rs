@@ -18,6 +18,7 @@ extern crate lazy_static;
 
 pub use crate::process::{new_kernel_context, processor};
 use buddy_system_allocator::LockedHeap;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use rcore_thread::std_thread as thread;
 
 #[macro_use] // print!
@@ -49,7 +50,11 @@ pub mod arch;
 #[path = "arch/aarch64/mod.rs"]
 pub mod arch;
 
+/// Number of CPUs running the scheduler
+pub static CPU_ONLINE: AtomicUsize = AtomicUsize::new(0);
+
 pub fn kmain() -> ! {
+    CPU_ONLINE.fetch_add(1, Ordering::Relaxed);
     processor().run();
 }
 
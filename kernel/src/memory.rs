@@ -5,6 +5,7 @@ use crate::process::process_unsafe;
 use crate::sync::SpinNoIrqLock;
 use bit_allocator::BitAlloc;
 use buddy_system_allocator::LockedHeap;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use lazy_static::*;
 use log::*;
 pub use rcore_memory::memory_set::{handler::*, MemoryArea, MemoryAttr};
@@ -29,6 +30,11 @@ lazy_static! {
         SpinNoIrqLock::new(FrameAlloc::default());
 }
 
+/// Number of frames inserted to `FRAME_ALLOCATOR`
+pub static TOTAL_FRAMES: AtomicUsize = AtomicUsize::new(0);
+/// Number of frames allocated by `GlobalFrameAlloc`
+pub static USED_FRAMES: AtomicUsize = AtomicUsize::new(0);
+
 /// The only way to get active page table
 ///
 /// ## CHANGE LOG
@@ -58,6 +64,9 @@ impl FrameAllocator for GlobalFrameAlloc {
             .alloc()
             .map(|id| id * PAGE_SIZE + MEMORY_OFFSET);
         trace!("Allocate frame: {:x?}", ret);
+        if ret.is_some() {
+            USED_FRAMES.fetch_add(1, Ordering::Relaxed);
+        }
         ret
         // TODO: try to swap out when alloc failed
     }
@@ -66,6 +75,27 @@ impl FrameAllocator for GlobalFrameAlloc {
         FRAME_ALLOCATOR
             .lock()
             .dealloc((target - MEMORY_OFFSET) / PAGE_SIZE);
+        USED_FRAMES.fetch_sub(1, Ordering::Relaxed);
+    }
+    fn alloc_contiguous(&self, size: usize) -> Option<usize> {
+        let count = size / PAGE_SIZE;
+        let ret = FRAME_ALLOCATOR
+            .lock()
+            .alloc_contiguous(count, count.trailing_zeros() as usize)
+            .map(|id| id * PAGE_SIZE + MEMORY_OFFSET);
+        trace!("Allocate contiguous frames: {:x?} size {:#x}", ret, size);
+        if ret.is_some() {
+            USED_FRAMES.fetch_add(count, Ordering::Relaxed);
+        }
+        ret
+    }
+    fn dealloc_contiguous(&self, target: usize, size: usize) {
+        trace!("Deallocate contiguous frames: {:x} size {:#x}", target, size);
+        let start = (target - MEMORY_OFFSET) / PAGE_SIZE;
+        FRAME_ALLOCATOR
+            .lock()
+            .insert(start..start + size / PAGE_SIZE);
+        USED_FRAMES.fetch_sub(size / PAGE_SIZE, Ordering::Relaxed);
     }
 }
 
@@ -105,11 +135,28 @@ impl Drop for KernelStack {
 
 /// Handle page fault at `addr`.
 /// Return true to continue, false to halt.
+///
+/// A fault that can not be handled inside a memory area of the process,
+/// like a stack overflow beyond RLIMIT_STACK, kills the process with SIGSEGV.
+/// Faults below the user stack grow it first.
 pub fn handle_page_fault(addr: usize) -> bool {
     debug!("page fault @ {:#x}", addr);
 
     // This is safe as long as page fault never happens in page fault handler
-    unsafe { process_unsafe().vm.handle_page_fault(addr) }
+    let mut proc = unsafe { process_unsafe() };
+    if proc.vm.handle_page_fault(addr) || proc.grow_stack(addr) {
+        return true;
+    }
+    let area = proc.vm.iter().find(|area| area.contains(addr)).map(|area| area.name());
+    if let Some(name) = area {
+        error!(
+            "segmentation fault @ {:#x} in area {:?}, kill process {}",
+            addr, name, proc.pid
+        );
+        drop(proc);
+        crate::syscall::sys_exit_group(crate::process::SIGSEGV);
+    }
+    false
 }
 
 pub fn init_heap() {
@@ -3,6 +3,8 @@ use crate::drivers::{NET_DRIVERS, SOCKET_ACTIVITY};
 use crate::sync::SpinNoIrqLock as Mutex;
 use crate::syscall::*;
 use alloc::boxed::Box;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 use smoltcp::socket::*;
 use smoltcp::wire::*;
@@ -32,6 +34,14 @@ pub trait Socket: Send + Sync {
         None
     }
     fn box_clone(&self) -> Box<dyn Socket>;
+    /// Whether `O_NONBLOCK` is set, shared by the clones as by dup
+    fn nonblocking(&self) -> bool;
+    fn set_nonblocking(&mut self, nonblocking: bool);
+    /// Whether a read or accept now would fail with `EAGAIN`
+    fn read_would_block(&self) -> bool {
+        let (input, _, err) = self.poll();
+        self.nonblocking() && !input && !err
+    }
 }
 
 impl Clone for Box<dyn Socket> {
@@ -54,17 +64,20 @@ pub struct TcpSocketState {
     handle: GlobalSocketHandle,
     local_endpoint: Option<IpEndpoint>, // save local endpoint for bind()
     is_listening: bool,
+    nonblocking: Arc<AtomicBool>,
 }
 
 #[derive(Debug, Clone)]
 pub struct UdpSocketState {
     handle: GlobalSocketHandle,
     remote_endpoint: Option<IpEndpoint>, // remember remote endpoint for connect()
+    nonblocking: Arc<AtomicBool>,
 }
 
 #[derive(Debug, Clone)]
 pub struct RawSocketState {
     handle: GlobalSocketHandle,
+    nonblocking: Arc<AtomicBool>,
 }
 
 /// A wrapper for `SocketHandle`.
@@ -102,6 +115,7 @@ impl TcpSocketState {
             handle,
             local_endpoint: None,
             is_listening: false,
+            nonblocking: Arc::new(AtomicBool::new(false)),
         }
     }
 }
@@ -277,6 +291,7 @@ impl Socket for TcpSocketState {
                         handle: old_handle,
                         local_endpoint: self.local_endpoint,
                         is_listening: false,
+                        nonblocking: Arc::new(AtomicBool::new(false)),
                     })
                 };
 
@@ -318,6 +333,14 @@ impl Socket for TcpSocketState {
     fn box_clone(&self) -> Box<dyn Socket> {
         Box::new(self.clone())
     }
+
+    fn nonblocking(&self) -> bool {
+        self.nonblocking.load(Ordering::Relaxed)
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) {
+        self.nonblocking.store(nonblocking, Ordering::Relaxed);
+    }
 }
 
 impl UdpSocketState {
@@ -336,6 +359,7 @@ impl UdpSocketState {
         UdpSocketState {
             handle,
             remote_endpoint: None,
+            nonblocking: Arc::new(AtomicBool::new(false)),
         }
     }
 }
@@ -449,6 +473,14 @@ impl Socket for UdpSocketState {
     fn box_clone(&self) -> Box<dyn Socket> {
         Box::new(self.clone())
     }
+
+    fn nonblocking(&self) -> bool {
+        self.nonblocking.load(Ordering::Relaxed)
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) {
+        self.nonblocking.store(nonblocking, Ordering::Relaxed);
+    }
 }
 
 impl RawSocketState {
@@ -469,7 +501,10 @@ impl RawSocketState {
         );
         let handle = GlobalSocketHandle(SOCKETS.lock().add(socket));
 
-        RawSocketState { handle }
+        RawSocketState {
+            handle,
+            nonblocking: Arc::new(AtomicBool::new(false)),
+        }
     }
 }
 
@@ -538,7 +573,9 @@ impl Socket for RawSocketState {
     }
 
     fn poll(&self) -> (bool, bool, bool) {
-        unimplemented!()
+        let mut sockets = SOCKETS.lock();
+        let socket = sockets.get::<RawSocket>(self.handle.0);
+        (socket.can_recv(), socket.can_send(), false)
     }
 
     fn connect(&mut self, _endpoint: IpEndpoint) -> SysResult {
@@ -548,6 +585,14 @@ impl Socket for RawSocketState {
     fn box_clone(&self) -> Box<dyn Socket> {
         Box::new(self.clone())
     }
+
+    fn nonblocking(&self) -> bool {
+        self.nonblocking.load(Ordering::Relaxed)
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) {
+        self.nonblocking.store(nonblocking, Ordering::Relaxed);
+    }
 }
 
 fn get_ephemeral_port() -> u16 {
@@ -0,0 +1,253 @@
+//! User and group identities of a process, and file permission checks
+
+use alloc::vec::Vec;
+
+use bitflags::bitflags;
+use rcore_fs::vfs::{FileType, Metadata};
+
+use crate::syscall::SysError;
+
+/// Set-user-ID on execution
+pub const S_ISUID: u16 = 0o4000;
+/// Set-group-ID on execution, or inherit the group in a directory
+pub const S_ISGID: u16 = 0o2000;
+/// Only owners may delete entries in a directory
+pub const S_ISVTX: u16 = 0o1000;
+
+bitflags! {
+    /// Access to a file, the same bits as in mode and `access`
+    pub struct Access: u16 {
+        const READ = 4;
+        const WRITE = 2;
+        const EXEC = 1;
+    }
+}
+
+/// Real, effective, saved and file system ids, all 0 for root
+#[derive(Clone, Debug, Default)]
+pub struct Credentials {
+    pub uid: usize,
+    pub euid: usize,
+    pub suid: usize,
+    /// Used for permission checks, follows `euid` unless set by `setfsuid`
+    pub fsuid: usize,
+    pub gid: usize,
+    pub egid: usize,
+    pub sgid: usize,
+    pub fsgid: usize,
+    /// Supplementary groups
+    pub groups: Vec<usize>,
+}
+
+impl Credentials {
+    /// Whether permission checks are bypassed
+    pub fn is_root(&self) -> bool {
+        self.fsuid == 0
+    }
+
+    pub fn in_group(&self, gid: usize) -> bool {
+        self.fsgid == gid || self.groups.contains(&gid)
+    }
+
+    /// Whether it may change attributes of the file, i.e. it is the owner or root
+    pub fn owns(&self, info: &Metadata) -> bool {
+        self.is_root() || self.fsuid == info.uid
+    }
+
+    /// Check `access` to the file
+    pub fn check(&self, info: &Metadata, access: Access) -> Result<(), SysError> {
+        let permitted = if self.is_root() {
+            // root can execute only if anyone can
+            !access.contains(Access::EXEC) || info.type_ == FileType::Dir || info.mode & 0o111 != 0
+        } else {
+            let bits = if info.uid == self.fsuid {
+                info.mode >> 6
+            } else if self.in_group(info.gid) {
+                info.mode >> 3
+            } else {
+                info.mode
+            };
+            Access::from_bits_truncate(bits & 0o7).contains(access)
+        };
+        match permitted {
+            true => Ok(()),
+            false => Err(SysError::EACCES),
+        }
+    }
+
+    /// Check removing or renaming `child` in directory `dir`
+    pub fn check_delete(&self, dir: &Metadata, child: &Metadata) -> Result<(), SysError> {
+        self.check(dir, Access::WRITE | Access::EXEC)?;
+        if dir.mode & S_ISVTX != 0 && !self.owns(dir) && !self.owns(child) {
+            return Err(SysError::EPERM);
+        }
+        Ok(())
+    }
+
+    /// The credentials for `access`, which checks with the real ids
+    pub fn real(&self) -> Self {
+        Credentials {
+            fsuid: self.uid,
+            fsgid: self.gid,
+            ..self.clone()
+        }
+    }
+
+    fn may_set_uid(&self, id: usize) -> bool {
+        self.euid == 0 || id == self.uid || id == self.euid || id == self.suid
+    }
+
+    fn may_set_gid(&self, id: usize) -> bool {
+        self.euid == 0 || id == self.gid || id == self.egid || id == self.sgid
+    }
+
+    /// `setuid`: root sets all the user ids, others only the effective one
+    pub fn set_uid(&mut self, uid: usize) -> Result<(), SysError> {
+        if self.euid == 0 {
+            self.uid = uid;
+            self.suid = uid;
+        } else if uid != self.uid && uid != self.suid {
+            return Err(SysError::EPERM);
+        }
+        self.euid = uid;
+        self.fsuid = uid;
+        Ok(())
+    }
+
+    /// `setgid`: root sets all the group ids, others only the effective one
+    pub fn set_gid(&mut self, gid: usize) -> Result<(), SysError> {
+        if self.euid == 0 {
+            self.gid = gid;
+            self.sgid = gid;
+        } else if gid != self.gid && gid != self.sgid {
+            return Err(SysError::EPERM);
+        }
+        self.egid = gid;
+        self.fsgid = gid;
+        Ok(())
+    }
+
+    /// `setreuid`, the saved id follows the effective one if the real one may change
+    pub fn set_reuid(&mut self, ruid: Option<usize>, euid: Option<usize>) -> Result<(), SysError> {
+        if let Some(id) = ruid {
+            if self.euid != 0 && id != self.uid && id != self.euid {
+                return Err(SysError::EPERM);
+            }
+        }
+        if let Some(id) = euid {
+            if !self.may_set_uid(id) {
+                return Err(SysError::EPERM);
+            }
+        }
+        let old_uid = self.uid;
+        if let Some(id) = ruid {
+            self.uid = id;
+        }
+        if let Some(id) = euid {
+            self.euid = id;
+        }
+        if ruid.is_some() || euid.map_or(false, |id| id != old_uid) {
+            self.suid = self.euid;
+        }
+        self.fsuid = self.euid;
+        Ok(())
+    }
+
+    /// `setregid`, the saved id follows the effective one if the real one may change
+    pub fn set_regid(&mut self, rgid: Option<usize>, egid: Option<usize>) -> Result<(), SysError> {
+        if let Some(id) = rgid {
+            if self.euid != 0 && id != self.gid && id != self.egid {
+                return Err(SysError::EPERM);
+            }
+        }
+        if let Some(id) = egid {
+            if !self.may_set_gid(id) {
+                return Err(SysError::EPERM);
+            }
+        }
+        let old_gid = self.gid;
+        if let Some(id) = rgid {
+            self.gid = id;
+        }
+        if let Some(id) = egid {
+            self.egid = id;
+        }
+        if rgid.is_some() || egid.map_or(false, |id| id != old_gid) {
+            self.sgid = self.egid;
+        }
+        self.fsgid = self.egid;
+        Ok(())
+    }
+
+    /// `setresuid`, each id is one of the current ones unless root
+    pub fn set_resuid(
+        &mut self,
+        ruid: Option<usize>,
+        euid: Option<usize>,
+        suid: Option<usize>,
+    ) -> Result<(), SysError> {
+        let ids = [ruid, euid, suid];
+        if !ids
+            .iter()
+            .filter_map(|&id| id)
+            .all(|id| self.may_set_uid(id))
+        {
+            return Err(SysError::EPERM);
+        }
+        self.uid = ruid.unwrap_or(self.uid);
+        self.euid = euid.unwrap_or(self.euid);
+        self.suid = suid.unwrap_or(self.suid);
+        self.fsuid = self.euid;
+        Ok(())
+    }
+
+    /// `setresgid`, each id is one of the current ones unless root
+    pub fn set_resgid(
+        &mut self,
+        rgid: Option<usize>,
+        egid: Option<usize>,
+        sgid: Option<usize>,
+    ) -> Result<(), SysError> {
+        let ids = [rgid, egid, sgid];
+        if !ids
+            .iter()
+            .filter_map(|&id| id)
+            .all(|id| self.may_set_gid(id))
+        {
+            return Err(SysError::EPERM);
+        }
+        self.gid = rgid.unwrap_or(self.gid);
+        self.egid = egid.unwrap_or(self.egid);
+        self.sgid = sgid.unwrap_or(self.sgid);
+        self.fsgid = self.egid;
+        Ok(())
+    }
+
+    /// `setfsuid`, ignored if not permitted
+    pub fn set_fsuid(&mut self, fsuid: usize) {
+        if self.may_set_uid(fsuid) || fsuid == self.fsuid {
+            self.fsuid = fsuid;
+        }
+    }
+
+    /// `setfsgid`, ignored if not permitted
+    pub fn set_fsgid(&mut self, fsgid: usize) {
+        if self.may_set_gid(fsgid) || fsgid == self.fsgid {
+            self.fsgid = fsgid;
+        }
+    }
+
+    /// Take the owner of a set-user-ID or set-group-ID program on exec
+    pub fn exec(&mut self, info: &Metadata) {
+        if info.mode & S_ISUID != 0 {
+            self.euid = info.uid;
+        }
+        if info.mode & S_ISGID != 0 {
+            self.egid = info.gid;
+        }
+        self.suid = self.euid;
+        self.fsuid = self.euid;
+        self.sgid = self.egid;
+        self.fsgid = self.egid;
+    }
+}
@@ -1,3 +1,5 @@
+pub use self::cred::*;
+pub use self::signal::*;
 pub use self::structs::*;
 use crate::arch::cpu;
 use crate::consts::{MAX_CPU_NUM, MAX_PROCESS_NUM};
@@ -7,6 +9,8 @@ use log::*;
 pub use rcore_thread::*;
 
 mod abi;
+mod cred;
+mod signal;
 pub mod structs;
 
 pub fn init() {
@@ -20,6 +24,9 @@ pub fn init() {
         }
     }
 
+    processor()
+        .manager()
+        .add(Thread::new_kernel(crate::fs::writeback_thread, 0));
     crate::shell::run_user_shell();
 
     info!("process: init end");
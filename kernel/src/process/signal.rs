@@ -0,0 +1,123 @@
+//! Signals kept pending while blocked, to be read through signalfd,
+//! and caught ones, which interrupt waits
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::sync::Condvar;
+use crate::sync::SpinNoIrqLock as Mutex;
+
+pub const SIGKILL: usize = 9;
+pub const SIGSEGV: usize = 11;
+pub const SIGPIPE: usize = 13;
+pub const SIGCHLD: usize = 17;
+pub const SIGCONT: usize = 18;
+pub const SIGSTOP: usize = 19;
+pub const SIGTSTP: usize = 20;
+pub const SIGTTIN: usize = 21;
+pub const SIGTTOU: usize = 22;
+pub const SIGURG: usize = 23;
+pub const SIGWINCH: usize = 28;
+/// The largest signal number
+pub const SIGRTMAX: usize = 64;
+/// Signals below this are standard signals, which are pending at most once
+pub const SIGRTMIN: usize = 32;
+
+/// The bit of `signo` in a signal set
+pub fn sig_bit(signo: usize) -> u64 {
+    1 << (signo - 1)
+}
+
+/// Whether the default action of `signo` is to ignore it
+pub fn sig_default_ignored(signo: usize) -> bool {
+    match signo {
+        SIGCHLD | SIGCONT | SIGURG | SIGWINCH => true,
+        _ => false,
+    }
+}
+
+/// Whether the default action of `signo` is to stop the process
+pub fn sig_default_stops(signo: usize) -> bool {
+    match signo {
+        SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU => true,
+        _ => false,
+    }
+}
+
+pub const SIG_DFL: usize = 0;
+pub const SIG_IGN: usize = 1;
+
+/// `struct sigaction` of the kernel
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SigAction {
+    pub handler: usize,
+    pub flags: usize,
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    pub restorer: usize,
+    pub mask: u64,
+}
+
+/// The sender of a pending signal
+#[derive(Debug, Clone, Copy)]
+pub struct SigInfo {
+    pub signo: usize,
+    pub pid: usize,
+    pub uid: usize,
+}
+
+/// Signals sent to a process but blocked by its mask
+#[derive(Default)]
+pub struct SignalQueue {
+    pending: Mutex<Vec<SigInfo>>,
+    /// Notified when a signal becomes pending
+    pub arrived: Condvar,
+    /// Number of signals caught so far, which interrupt waits since handlers are not run
+    caught: AtomicUsize,
+    /// Notified when a signal is caught
+    pub interrupted: Condvar,
+}
+
+impl SignalQueue {
+    pub fn new() -> Self {
+        SignalQueue::default()
+    }
+
+    pub fn push(&self, info: SigInfo) {
+        let mut pending = self.pending.lock();
+        if info.signo < SIGRTMIN && pending.iter().any(|i| i.signo == info.signo) {
+            return;
+        }
+        pending.push(info);
+        drop(pending);
+        self.arrived.notify_all();
+    }
+
+    /// Remove and return the lowest pending signal in `mask`
+    pub fn take(&self, mask: u64) -> Option<SigInfo> {
+        let mut pending = self.pending.lock();
+        let (i, _) = pending
+            .iter()
+            .enumerate()
+            .filter(|(_, info)| mask & sig_bit(info.signo) != 0)
+            .min_by_key(|(_, info)| info.signo)?;
+        Some(pending.remove(i))
+    }
+
+    /// Record a caught signal and wake up the interruptible waits
+    pub fn interrupt(&self) {
+        self.caught.fetch_add(1, Ordering::Relaxed);
+        self.interrupted.notify_all();
+    }
+
+    /// Changed by every caught signal, to tell whether a wait is interrupted
+    pub fn caught(&self) -> usize {
+        self.caught.load(Ordering::Relaxed)
+    }
+
+    /// Whether a signal in `mask` is pending
+    pub fn has_pending(&self, mask: u64) -> bool {
+        let pending = self.pending.lock();
+        pending.iter().any(|info| mask & sig_bit(info.signo) != 0)
+    }
+}
@@ -1,4 +1,11 @@
-use alloc::{boxed::Box, collections::BTreeMap, string::String, sync::Arc, sync::Weak, vec::Vec};
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, BTreeSet},
+    string::String,
+    sync::Arc,
+    sync::Weak,
+    vec::Vec,
+};
 use core::fmt;
 
 use core::str;
@@ -13,12 +20,17 @@ use xmas_elf::{
 };
 
 use crate::arch::interrupt::{Context, TrapFrame};
-use crate::fs::{FileHandle, FileLike, INodeExt, OpenOptions, FOLLOW_MAX_DEPTH};
-use crate::memory::{ByFrame, GlobalFrameAlloc, KernelStack, MemoryAttr, MemorySet};
+use crate::consts::USER_STACK_SIZE;
+use crate::fs::{FileHandle, FileLike, INodeExt, OpenOptions};
+use crate::memory::{
+    ByFrame, GlobalFrameAlloc, GrowDown, Guard, KernelStack, MemoryAttr, MemorySet,
+};
 use crate::net::{Socket, SOCKETS};
 use crate::sync::{Condvar, SpinNoIrqLock as Mutex};
 
 use super::abi::{self, ProcInitInfo};
+use super::cred::Credentials;
+use super::signal::{SigAction, SignalQueue, SIG_DFL, SIG_IGN};
 
 // TODO: avoid pub
 pub struct Thread {
@@ -74,8 +86,31 @@ pub struct Process {
     // resources
     pub vm: MemorySet,
     pub files: BTreeMap<usize, FileLike>,
+    /// File descriptors closed by exec
+    pub cloexec_fds: BTreeSet<usize>,
     pub cwd: String,
+    /// Path of the executable
+    pub exec_path: String,
+    /// Arguments of the program
+    pub args: Vec<String>,
     futexes: BTreeMap<usize, Arc<Condvar>>,
+    /// Start of the heap, i.e. the end of the highest ELF `PT_LOAD` segment
+    pub brk_start: usize,
+    /// Current program break
+    pub brk: usize,
+    /// Top of the user stack, 0 if there is none
+    pub stack_top: usize,
+    /// Soft and hard limits of the stack size, i.e. RLIMIT_STACK
+    pub stack_limit: (usize, usize),
+    pub cred: Credentials,
+    /// Permission bits cleared from new files
+    pub umask: u16,
+    /// Blocked signals
+    pub sig_mask: u64,
+    /// Actions other than `SIG_DFL` by signal.
+    /// Ignored signals stay ignored across exec, while handlers are reset.
+    pub sig_actions: BTreeMap<usize, SigAction>,
+    pub signals: Arc<SignalQueue>,
 
     // relationship
     pub pid: Pid, // i.e. tgid, usually the tid of first thread
@@ -132,8 +167,20 @@ impl Thread {
             proc: Arc::new(Mutex::new(Process {
                 vm: MemorySet::new(),
                 files: BTreeMap::default(),
+                cloexec_fds: BTreeSet::default(),
                 cwd: String::from("/"),
+                exec_path: String::new(),
+                args: Vec::new(),
                 futexes: BTreeMap::default(),
+                brk_start: 0,
+                brk: 0,
+                stack_top: 0,
+                stack_limit: (USER_STACK_SIZE, USER_STACK_SIZE),
+                cred: Credentials::default(),
+                umask: 0o022,
+                sig_mask: 0,
+                sig_actions: BTreeMap::new(),
+                signals: Arc::new(SignalQueue::new()),
                 pid: Pid::uninitialized(),
                 parent: None,
                 children: Vec::new(),
@@ -156,8 +203,20 @@ impl Thread {
             proc: Arc::new(Mutex::new(Process {
                 vm,
                 files: BTreeMap::default(),
+                cloexec_fds: BTreeSet::default(),
                 cwd: String::from("/"),
+                exec_path: String::new(),
+                args: Vec::new(),
                 futexes: BTreeMap::default(),
+                brk_start: 0,
+                brk: 0,
+                stack_top: 0,
+                stack_limit: (USER_STACK_SIZE, USER_STACK_SIZE),
+                cred: Credentials::default(),
+                umask: 0o022,
+                sig_mask: 0,
+                sig_actions: BTreeMap::new(),
+                signals: Arc::new(SignalQueue::new()),
                 pid: Pid::uninitialized(),
                 parent: None,
                 children: Vec::new(),
@@ -190,7 +249,10 @@ impl Thread {
         // Check interpreter
         if let Ok(loader_path) = elf.get_interpreter() {
             // assuming absolute path
-            if let Ok(inode) = crate::fs::ROOT_INODE.lookup_follow(loader_path, FOLLOW_MAX_DEPTH) {
+            let root = Credentials::default();
+            if let Ok(inode) =
+                crate::fs::lookup_at(&crate::fs::ROOT_INODE, loader_path, true, &root, None)
+            {
                 if let Ok(buf) = inode.read_as_vec() {
                     debug!("using loader {}", &loader_path);
                     // Elf loader should not have INTERP
@@ -208,21 +270,36 @@ impl Thread {
 
         // Make page table
         let mut vm = elf.make_memory_set();
+        let brk_start = elf.get_brk_start();
 
         // User stack
-        use crate::consts::{USER32_STACK_OFFSET, USER_STACK_OFFSET, USER_STACK_SIZE};
+        // Only its top is mapped at first. It grows down on page faults up to RLIMIT_STACK,
+        // see `Process::grow_stack`, and a guard gap below it turns stack overflow into SIGSEGV.
+        use crate::consts::{
+            USER32_STACK_OFFSET, USER_STACK_GUARD_SIZE, USER_STACK_INIT_SIZE, USER_STACK_OFFSET,
+        };
+        let ustack_top_init = match is32 {
+            true => USER32_STACK_OFFSET + USER_STACK_SIZE,
+            false => USER_STACK_OFFSET + USER_STACK_SIZE,
+        };
         let mut ustack_top = {
-            let (ustack_buttom, ustack_top) = match is32 {
-                true => (USER32_STACK_OFFSET, USER32_STACK_OFFSET + USER_STACK_SIZE),
-                false => (USER_STACK_OFFSET, USER_STACK_OFFSET + USER_STACK_SIZE),
-            };
+            let ustack_top = ustack_top_init;
+            let ustack_buttom = ustack_top - USER_STACK_INIT_SIZE;
+            vm.push(
+                ustack_buttom - USER_STACK_GUARD_SIZE,
+                ustack_buttom,
+                MemoryAttr::default(),
+                Guard,
+                "user_stack_guard",
+            );
             vm.push(
                 ustack_buttom,
                 ustack_top,
                 MemoryAttr::default().user(),
-                ByFrame::new(GlobalFrameAlloc),
+                GrowDown::new(GlobalFrameAlloc, ustack_buttom),
                 "user_stack",
             );
+            vm.reserve_grow_down(ustack_top - USER_STACK_SIZE, ustack_top);
             ustack_top
         };
 
@@ -244,11 +321,13 @@ impl Thread {
         unsafe {
             vm.with(|| ustack_top = init_info.push_at(ustack_top));
         }
+        let args = init_info.args;
 
         trace!("{:#x?}", vm);
 
         let kstack = KernelStack::new();
 
+        let cloexec_fds = BTreeSet::new();
         let mut files = BTreeMap::new();
         files.insert(
             0,
@@ -258,7 +337,9 @@ impl Thread {
                     read: true,
                     write: false,
                     append: false,
+                    nonblock: false,
                 },
+                String::from("/dev/console"),
             )),
         );
         files.insert(
@@ -269,7 +350,9 @@ impl Thread {
                     read: false,
                     write: true,
                     append: false,
+                    nonblock: false,
                 },
+                String::from("/dev/console"),
             )),
         );
         files.insert(
@@ -280,7 +363,9 @@ impl Thread {
                     read: false,
                     write: true,
                     append: false,
+                    nonblock: false,
                 },
+                String::from("/dev/console"),
             )),
         );
 
@@ -295,8 +380,20 @@ impl Thread {
             proc: Arc::new(Mutex::new(Process {
                 vm,
                 files,
+                cloexec_fds,
                 cwd: String::from("/"),
+                exec_path: args.first().cloned().unwrap_or_default(),
+                args,
                 futexes: BTreeMap::default(),
+                brk_start,
+                brk: brk_start,
+                stack_top: ustack_top_init,
+                stack_limit: (USER_STACK_SIZE, USER_STACK_SIZE),
+                cred: Credentials::default(),
+                umask: 0o022,
+                sig_mask: 0,
+                sig_actions: BTreeMap::new(),
+                signals: Arc::new(SignalQueue::new()),
                 pid: Pid::uninitialized(),
                 parent: None,
                 children: Vec::new(),
@@ -310,19 +407,56 @@ impl Thread {
     /// Fork a new process from current one
     pub fn fork(&self, tf: &TrapFrame) -> Box<Thread> {
         // Clone memory set, make a new page table
-        let proc = self.proc.lock();
-        let vm = proc.vm.clone();
+        let mut proc = self.proc.lock();
+        let mut vm = proc.vm.clone_lazy();
         let files = proc.files.clone();
+        let cloexec_fds = proc.cloexec_fds.clone();
         let cwd = proc.cwd.clone();
-        drop(proc);
+        let exec_path = proc.exec_path.clone();
+        let args = proc.args.clone();
+        let brk_start = proc.brk_start;
+        let brk = proc.brk;
+        let stack_top = proc.stack_top;
+        let stack_limit = proc.stack_limit;
+        let cred = proc.cred.clone();
+        let umask = proc.umask;
+        let sig_mask = proc.sig_mask;
+        let sig_actions = proc.sig_actions.clone();
         let parent = Some(self.proc.clone());
+        drop(proc);
         debug!("fork: finish clone MemorySet");
 
-        // MMU:   copy data to the new space
-        // NoMMU: coping data has been done in `vm.clone()`
-        for area in vm.iter() {
-            let data = Vec::<u8>::from(unsafe { area.as_slice() });
-            unsafe { vm.with(|| area.as_slice_mut().copy_from_slice(data.as_slice())) }
+        // Copy data of the allocated pages to the new space,
+        // the others are still allocated on their first access.
+        // The parent is locked only while a page is copied, so that its other threads
+        // are not held up by the whole copy.
+        let ranges: Vec<_> = vm
+            .iter()
+            .map(|area| (area.start_addr(), area.end_addr()))
+            .collect();
+        let mut data = vec![0u8; PAGE_SIZE];
+        for (start, end) in ranges {
+            let resident = vm.resident_pages(start, end).unwrap();
+            let start = start & !(PAGE_SIZE - 1);
+            for (page, _) in (start..end)
+                .step_by(PAGE_SIZE)
+                .zip(resident)
+                .filter(|&(_, resident)| resident)
+            {
+                let mut proc = self.proc.lock();
+                // unmapped by another thread meanwhile
+                if proc.vm.resident_pages(page, page + PAGE_SIZE) != Some(vec![true]) {
+                    continue;
+                }
+                data.copy_from_slice(unsafe {
+                    core::slice::from_raw_parts(page as *const u8, PAGE_SIZE)
+                });
+                drop(proc);
+                vm.with(|| unsafe {
+                    core::slice::from_raw_parts_mut(page as *mut u8, PAGE_SIZE)
+                        .copy_from_slice(&data)
+                });
+            }
         }
 
         debug!("fork: temporary copy data!");
@@ -335,8 +469,20 @@ impl Thread {
             proc: Arc::new(Mutex::new(Process {
                 vm,
                 files,
+                cloexec_fds,
                 cwd,
+                exec_path,
+                args,
                 futexes: BTreeMap::default(),
+                brk_start,
+                brk,
+                stack_top,
+                stack_limit,
+                cred,
+                umask,
+                sig_mask,
+                sig_actions,
+                signals: Arc::new(SignalQueue::new()),
                 pid: Pid::uninitialized(),
                 parent,
                 children: Vec::new(),
@@ -368,7 +514,11 @@ impl Thread {
 
 impl Process {
     pub fn get_free_fd(&self) -> usize {
-        (0..).find(|i| !self.files.contains_key(i)).unwrap()
+        self.get_free_fd_from(0)
+    }
+    /// The lowest unused fd no less than `min`
+    pub fn get_free_fd_from(&self, min: usize) -> usize {
+        (min..).find(|i| !self.files.contains_key(i)).unwrap()
     }
     pub fn get_futex(&mut self, uaddr: usize) -> Arc<Condvar> {
         if !self.futexes.contains_key(&uaddr) {
@@ -376,10 +526,87 @@ impl Process {
         }
         self.futexes.get(&uaddr).unwrap().clone()
     }
+    /// Grow the user stack down to the page fault at `addr` within RLIMIT_STACK,
+    /// moving the guard gap below it. Return false if `addr` is not in the range.
+    pub fn grow_stack(&mut self, addr: usize) -> bool {
+        use crate::consts::USER_STACK_GUARD_SIZE;
+        let top = self.stack_top;
+        let bottom = match self.vm.iter().find(|area| area.end_addr() == top) {
+            Some(area) if top != 0 => area.start_addr(),
+            _ => return false,
+        };
+        let new_bottom = addr & !(PAGE_SIZE - 1);
+        if addr >= bottom || top - new_bottom > self.stack_limit.0 {
+            return false;
+        }
+        let new_guard = match new_bottom.checked_sub(USER_STACK_GUARD_SIZE) {
+            Some(new_guard) => new_guard,
+            None => return false,
+        };
+        let guard = bottom - USER_STACK_GUARD_SIZE;
+        let has_guard = self
+            .vm
+            .iter()
+            .any(|area| area.start_addr() == guard && area.end_addr() == bottom);
+        if has_guard {
+            self.vm.pop(guard, bottom);
+        }
+        if !self.vm.test_free_area(new_guard, bottom) {
+            if has_guard {
+                self.vm.push(
+                    guard,
+                    bottom,
+                    MemoryAttr::default(),
+                    Guard,
+                    "user_stack_guard",
+                );
+            }
+            return false;
+        }
+        assert!(self.vm.grow_down(bottom, new_bottom));
+        self.vm.push(
+            new_guard,
+            new_bottom,
+            MemoryAttr::default(),
+            Guard,
+            "user_stack_guard",
+        );
+        self.vm.handle_page_fault(addr)
+    }
+    /// Let the checks of user pointers accept the stack down to RLIMIT_STACK,
+    /// where it is grown on access
+    pub fn reserve_stack(&mut self) {
+        if self.stack_top != 0 {
+            let bottom = self.stack_top - self.stack_limit.0;
+            self.vm.reserve_grow_down(bottom, self.stack_top);
+        }
+    }
+    /// The handler of `sig`, or `SIG_DFL` or `SIG_IGN`
+    pub fn sig_handler(&self, sig: usize) -> usize {
+        self.sig_actions
+            .get(&sig)
+            .map_or(SIG_DFL, |action| action.handler)
+    }
     pub fn clone_for_exec(&mut self, other: &Self) {
+        self.pid = other.pid.clone();
         self.files = other.files.clone();
+        // closed like by close(), dropping their record locks and epoll interests
+        for &fd in other.cloexec_fds.iter() {
+            self.close_file(fd).ok();
+        }
         self.cwd = other.cwd.clone();
-        self.pid = other.pid.clone();
+        self.cred = other.cred.clone();
+        self.umask = other.umask;
+        self.stack_limit = other.stack_limit;
+        self.reserve_stack();
+        self.sig_mask = other.sig_mask;
+        self.sig_actions = other
+            .sig_actions
+            .iter()
+            .filter(|(_, action)| action.handler == SIG_IGN)
+            .map(|(&sig, &action)| (sig, action))
+            .collect();
+        self.signals = other.signals.clone();
         self.parent = other.parent.clone();
         self.threads = other.threads.clone();
     }
@@ -410,6 +637,10 @@ trait ElfExt {
 
     /// Get virtual address of PHDR section if it has.
     fn get_phdr_vaddr(&self) -> Option<u64>;
+
+    /// Get the page aligned end of the highest `PT_LOAD` segment,
+    /// where the program break starts.
+    fn get_brk_start(&self) -> usize;
 }
 
 impl ElfExt for ElfFile<'_> {
@@ -488,4 +719,14 @@ impl ElfExt for ElfFile<'_> {
             None
         }
     }
+
+    fn get_brk_start(&self) -> usize {
+        let end = self
+            .program_iter()
+            .filter(|ph| ph.get_type() == Ok(Type::Load))
+            .map(|ph| (ph.virtual_addr() + ph.mem_size()) as usize)
+            .max()
+            .unwrap_or(0);
+        (end + PAGE_SIZE - 1) & !(PAGE_SIZE - 1)
+    }
 }
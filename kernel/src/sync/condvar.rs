@@ -3,10 +3,14 @@ use crate::thread;
 use alloc::collections::VecDeque;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
 
 #[derive(Default)]
 pub struct Condvar {
     wait_queue: SpinNoIrqLock<VecDeque<Arc<thread::Thread>>>,
+    /// Number of notifications so far
+    generation: AtomicUsize,
 }
 
 impl Condvar {
@@ -26,6 +30,11 @@ impl Condvar {
     }
 
     pub fn wait_any(condvars: &[&Condvar]) {
+        if condvars.is_empty() {
+            // nothing could wake us up
+            thread::yield_now();
+            return;
+        }
         let token = Arc::new(thread::current());
         // Avoid racing in the same way as the function above
         let mut locks = Vec::new();
@@ -40,6 +49,75 @@ impl Condvar {
         });
     }
 
+    /// Like `wait_any`, but returns after `timeout` even if nothing is notified
+    pub fn wait_any_timeout(condvars: &[&Condvar], timeout: Duration) {
+        if condvars.is_empty() {
+            thread::sleep(timeout);
+            return;
+        }
+        let token = Arc::new(thread::current());
+        let mut locks = Vec::new();
+        locks.reserve(condvars.len());
+        for condvar in condvars {
+            let mut lock = condvar.wait_queue.lock();
+            lock.push_back(token.clone());
+            locks.push(lock);
+        }
+        thread::park_timeout_action(timeout, move || {
+            drop(locks);
+        });
+    }
+
+    /// Add `token` of the current thread to the wait queue without parking,
+    /// so that a notification before `wait_registered` is not missed.
+    pub fn register(&self, token: &Arc<thread::Thread>) {
+        self.wait_queue.lock().push_back(token.clone());
+    }
+
+    /// Remove `token` if it has not been notified
+    pub fn unregister(&self, token: &Arc<thread::Thread>) {
+        self.wait_queue.lock().retain(|t| !Arc::ptr_eq(t, token));
+    }
+
+    /// Park until notified, unless `ready` returns true once the current thread
+    /// is on the wait queue, so that a notification after the check is not missed
+    pub fn wait_unless(&self, ready: impl FnOnce() -> bool) {
+        let token = Arc::new(thread::current());
+        self.register(&token);
+        if !ready() {
+            Condvar::wait_registered(&[self], &token, None);
+        }
+        self.unregister(&token);
+    }
+
+    /// Park until any of `condvars`, on which `token` is registered, is notified,
+    /// or `timeout` passes. Return at once if one has been notified since.
+    pub fn wait_registered(
+        condvars: &[&Condvar],
+        token: &Arc<thread::Thread>,
+        timeout: Option<Duration>,
+    ) {
+        let mut locks = Vec::new();
+        locks.reserve(condvars.len());
+        for condvar in condvars {
+            let lock = condvar.wait_queue.lock();
+            if !lock.iter().any(|t| Arc::ptr_eq(t, token)) {
+                // notified, so the token was popped
+                return;
+            }
+            locks.push(lock);
+        }
+        match timeout {
+            Some(timeout) => thread::park_timeout_action(timeout, move || {
+                drop(locks);
+            }),
+            None if condvars.is_empty() => thread::yield_now(),
+            None => thread::park_action(move || {
+                drop(locks);
+            }),
+        }
+    }
+
     pub fn add_to_wait_queue(&self) -> MutexGuard<VecDeque<Arc<thread::Thread>>, SpinNoIrq> {
         let mut lock = self.wait_queue.lock();
         lock.push_back(Arc::new(thread::current()));
@@ -51,17 +129,28 @@ impl Condvar {
         S: MutexSupport,
     {
         let mutex = guard.mutex;
-        drop(guard);
-        self._wait();
+        // join the queue before unlocking, or a notification in between is missed
+        let lock = self.add_to_wait_queue();
+        thread::park_action(move || {
+            drop(lock);
+            drop(guard);
+        });
         mutex.lock()
     }
 
+    /// Changed by every notification, to tell whether any happened since
+    pub fn generation(&self) -> usize {
+        self.generation.load(Ordering::Acquire)
+    }
+
     pub fn notify_one(&self) {
+        self.generation.fetch_add(1, Ordering::Release);
         if let Some(t) = self.wait_queue.lock().pop_front() {
             t.unpark();
         }
     }
     pub fn notify_all(&self) {
+        self.generation.fetch_add(1, Ordering::Release);
         while let Some(t) = self.wait_queue.lock().pop_front() {
             t.unpark();
         }
@@ -69,6 +158,7 @@ impl Condvar {
     /// Notify up to `n` waiters.
     /// Return the number of waiters that were woken up.
     pub fn notify_n(&self, n: usize) -> usize {
+        self.generation.fetch_add(1, Ordering::Release);
         let mut count = 0;
         while count < n {
             if let Some(t) = self.wait_queue.lock().pop_front() {
@@ -1,41 +1,132 @@
 //! Syscalls for file system
 
-use core::cell::UnsafeCell;
 use core::cmp::min;
 use core::mem::size_of;
-use rcore_fs::vfs::Timespec;
+use core::time::Duration;
+use rcore_fs::dev::Device;
+use rcore_fs::vfs::{FileSystem, Timespec};
 
 use crate::drivers::SOCKET_ACTIVITY;
 use crate::fs::*;
 use crate::memory::MemorySet;
-use crate::sync::Condvar;
+use crate::sync::{Condvar, MutexGuard, SpinNoIrq};
 
 use super::*;
 
+/// Lock the current process once writing `fd` would not wait
+///
+/// Blocking files are waited on without the lock,
+/// so that other threads and processes may use this one meanwhile.
+fn process_when_writable(fd: usize) -> MutexGuard<'static, Process, SpinNoIrq> {
+    loop {
+        let proc = process();
+        let inode = match proc.files.get(&fd) {
+            Some(FileLike::File(file)) if !file.options().nonblock && !file.poll().write => {
+                file.inode()
+            }
+            _ => return proc,
+        };
+        drop(proc);
+        match inode_wait_queue(&*inode) {
+            Some(queue) => queue.wait_unless(|| poll_inode(&*inode).write),
+            None => return process(),
+        }
+    }
+}
+
+/// Run `f` on a copy of `fd` without the process locked, then keep the offset of a file
+///
+/// Blocking files and sockets wait, and files like those of procfs lock processes
+/// when they are read, so I/O is done without the lock. Concurrent reads of an fd
+/// may start at the same offset meanwhile.
+fn with_file_like_unlocked<T>(
+    fd: usize,
+    f: impl FnOnce(&mut FileLike) -> Result<T, SysError>,
+) -> Result<T, SysError> {
+    let mut file_like = process().get_file_like(fd)?.clone();
+    let ret = f(&mut file_like);
+    if let FileLike::File(file) = &mut file_like {
+        let offset = file.seek(SeekFrom::Current(0))?;
+        let mut proc = process();
+        // the fd may have been closed or replaced meanwhile
+        if let Ok(current) = proc.get_file(fd) {
+            if current.same_open_file(file) {
+                current.seek(SeekFrom::Start(offset))?;
+            }
+        }
+    }
+    ret
+}
+
+/// Run `f` on a copy of the file `fd` like `with_file_like_unlocked`
+fn with_file_unlocked<T>(
+    fd: usize,
+    f: impl FnOnce(&mut FileHandle) -> Result<T, SysError>,
+) -> Result<T, SysError> {
+    with_file_like_unlocked(fd, |file_like| match file_like {
+        FileLike::File(file) => f(file),
+        _ => Err(SysError::EBADF),
+    })
+}
+
+/// Read `fd` into `buf` without the process locked
+fn read_unlocked(fd: usize, buf: &mut [u8]) -> SysResult {
+    with_file_like_unlocked(fd, |file_like| file_like.read(buf))
+}
+
 pub fn sys_read(fd: usize, base: *mut u8, len: usize) -> SysResult {
-    let mut proc = process();
+    let proc = process();
     if !proc.pid.is_init() {
         // we trust pid 0 process
         info!("read: fd: {}, base: {:?}, len: {:#x}", fd, base, len);
     }
     proc.vm.check_write_array(base, len)?;
+    drop(proc);
     let slice = unsafe { slice::from_raw_parts_mut(base, len) };
-    let file_like = proc.get_file_like(fd)?;
-    let len = file_like.read(slice)?;
-    Ok(len)
+    read_unlocked(fd, slice)
+}
+
+/// Write `buf` to `fd`, continuing partial writes to blocking pipes until all is written
+///
+/// Raise SIGPIPE if nothing is written because the pipe has no readers.
+fn write_when_ready(fd: usize, buf: &[u8]) -> SysResult {
+    let mut written = 0;
+    loop {
+        let mut proc = process_when_writable(fd);
+        let file_like = proc.get_file_like(fd)?;
+        let ret = file_like.write(&buf[written..]);
+        let partial = match file_like {
+            FileLike::File(file) => !file.options().nonblock && is_pollable(&*file.inode()),
+            _ => false,
+        };
+        drop(proc);
+        match ret {
+            Ok(len) => {
+                written += len;
+                if !partial || len == 0 || written == buf.len() {
+                    return Ok(written);
+                }
+            }
+            Err(_) if written > 0 => return Ok(written),
+            Err(SysError::EPIPE) => {
+                raise_signal(SIGPIPE);
+                return Err(SysError::EPIPE);
+            }
+            Err(err) => return Err(err),
+        }
+    }
 }
 
 pub fn sys_write(fd: usize, base: *const u8, len: usize) -> SysResult {
-    let mut proc = process();
+    let proc = process();
     if !proc.pid.is_init() {
         // we trust pid 0 process
         info!("write: fd: {}, base: {:?}, len: {:#x}", fd, base, len);
     }
     proc.vm.check_read_array(base, len)?;
+    drop(proc);
     let slice = unsafe { slice::from_raw_parts(base, len) };
-    let file_like = proc.get_file_like(fd)?;
-    let len = file_like.write(slice)?;
-    Ok(len)
+    write_when_ready(fd, slice)
 }
 
 pub fn sys_pread(fd: usize, base: *mut u8, len: usize, offset: usize) -> SysResult {
@@ -43,12 +134,12 @@ pub fn sys_pread(fd: usize, base: *mut u8, len: usize, offset: usize) -> SysResu
         "pread: fd: {}, base: {:?}, len: {}, offset: {}",
         fd, base, len, offset
     );
-    let mut proc = process();
+    let proc = process();
     proc.vm.check_write_array(base, len)?;
+    drop(proc);
 
     let slice = unsafe { slice::from_raw_parts_mut(base, len) };
-    let len = proc.get_file(fd)?.read_at(offset, slice)?;
-    Ok(len)
+    with_file_unlocked(fd, |file| Ok(file.read_at(offset, slice)?))
 }
 
 pub fn sys_pwrite(fd: usize, base: *const u8, len: usize, offset: usize) -> SysResult {
@@ -71,49 +162,42 @@ pub fn sys_poll(ufds: *mut PollFd, nfds: usize, timeout_msecs: usize) -> SysResu
     );
     let proc = process();
     proc.vm.check_write_array(ufds, nfds)?;
-
-    let polls = unsafe { slice::from_raw_parts_mut(ufds, nfds) };
-    for poll in polls.iter() {
-        if proc.files.get(&(poll.fd as usize)).is_none() {
-            return Err(SysError::EINVAL);
-        }
-    }
     drop(proc);
+    let polls = unsafe { slice::from_raw_parts_mut(ufds, nfds) };
 
     let begin_time_ms = crate::trap::uptime_msec();
     loop {
         use PollEvents as PE;
         let proc = process();
+        let mut waiter = PollWaiter::new();
         let mut events = 0;
         for poll in polls.iter_mut() {
             poll.revents = PE::NONE;
+            if (poll.fd as i32) < 0 {
+                // ignored
+                continue;
+            }
             match proc.files.get(&(poll.fd as usize)) {
-                Some(FileLike::File(_)) => {
-                    // FIXME: assume it is stdin for now
-                    if poll.events.contains(PE::IN) && STDIN.can_read() {
-                        poll.revents = poll.revents | PE::IN;
-                        events = events + 1;
+                Some(file_like) => {
+                    waiter.add(file_like);
+                    let status = file_like.poll();
+                    if status.error {
+                        poll.revents |= PE::ERR;
                     }
-                }
-                Some(FileLike::Socket(socket)) => {
-                    let (input, output, err) = socket.poll();
-                    if err {
-                        poll.revents = poll.revents | PE::HUP;
-                        events = events + 1;
+                    if status.hangup {
+                        poll.revents |= PE::HUP;
                     }
-                    if input && poll.events.contains(PE::IN) {
-                        poll.revents = poll.revents | PE::IN;
-                        events = events + 1;
+                    if status.read && poll.events.contains(PE::IN) {
+                        poll.revents |= PE::IN;
                     }
-                    if output && poll.events.contains(PE::OUT) {
-                        poll.revents = poll.revents | PE::OUT;
-                        events = events + 1;
+                    if status.write && poll.events.contains(PE::OUT) {
+                        poll.revents |= PE::OUT;
                     }
                 }
-                None => {
-                    poll.revents = poll.revents | PE::ERR;
-                    events = events + 1;
-                }
+                None => poll.revents |= PE::INVAL,
+            }
+            if !poll.revents.is_empty() {
+                events += 1;
             }
         }
         drop(proc);
@@ -122,12 +206,15 @@ pub fn sys_poll(ufds: *mut PollFd, nfds: usize, timeout_msecs: usize) -> SysResu
             return Ok(events);
         }
 
-        let current_time_ms = crate::trap::uptime_msec();
-        if timeout_msecs < (1 << 31) && current_time_ms - begin_time_ms > timeout_msecs {
+        let elapsed_ms = crate::trap::uptime_msec() - begin_time_ms;
+        if timeout_msecs < (1 << 31) && elapsed_ms >= timeout_msecs {
             return Ok(0);
         }
 
-        Condvar::wait_any(&[&STDIN.pushed, &(*SOCKET_ACTIVITY)]);
+        waiter.wait(match timeout_msecs < (1 << 31) {
+            true => Some(timeout_msecs - elapsed_ms),
+            false => None,
+        });
     }
 }
 
@@ -159,34 +246,26 @@ pub fn sys_select(
     let begin_time_ms = crate::trap::uptime_msec();
     loop {
         let proc = process();
+        let mut waiter = PollWaiter::new();
         let mut events = 0;
         for (fd, file_like) in proc.files.iter() {
             if *fd < nfds {
-                match file_like {
-                    FileLike::File(_) => {
-                        // FIXME: assume it is stdin for now
-                        if STDIN.can_read() {
-                            if read_fds.is_set(*fd) {
-                                read_fds.set(*fd);
-                                events = events + 1;
-                            }
-                        }
-                    }
-                    FileLike::Socket(socket) => {
-                        let (input, output, err) = socket.poll();
-                        if err && err_fds.is_set(*fd) {
-                            err_fds.set(*fd);
-                            events = events + 1;
-                        }
-                        if input && read_fds.is_set(*fd) {
-                            read_fds.set(*fd);
-                            events = events + 1;
-                        }
-                        if output && write_fds.is_set(*fd) {
-                            write_fds.set(*fd);
-                            events = events + 1;
-                        }
-                    }
+                if !read_fds.is_set(*fd) && !write_fds.is_set(*fd) && !err_fds.is_set(*fd) {
+                    continue;
+                }
+                waiter.add(file_like);
+                let status = file_like.poll();
+                if (status.error || status.hangup) && err_fds.is_set(*fd) {
+                    err_fds.set(*fd);
+                    events = events + 1;
+                }
+                if status.read && read_fds.is_set(*fd) {
+                    read_fds.set(*fd);
+                    events = events + 1;
+                }
+                if status.write && write_fds.is_set(*fd) {
+                    write_fds.set(*fd);
+                    events = events + 1;
                 }
             }
         }
@@ -201,13 +280,269 @@ pub fn sys_select(
             return Ok(0);
         }
 
-        let current_time_ms = crate::trap::uptime_msec();
+        let elapsed_ms = crate::trap::uptime_msec() - begin_time_ms;
         // infinity check
-        if timeout_msecs < (1 << 31) && current_time_ms - begin_time_ms > timeout_msecs as usize {
+        if timeout_msecs < (1 << 31) && elapsed_ms >= timeout_msecs as usize {
             return Ok(0);
         }
 
-        Condvar::wait_any(&[&STDIN.pushed, &(*SOCKET_ACTIVITY)]);
+        waiter.wait(match timeout_msecs < (1 << 31) {
+            true => Some(timeout_msecs as usize - elapsed_ms),
+            false => None,
+        });
+    }
+}
+
+pub fn sys_epoll_create(size: usize) -> SysResult {
+    if size as isize <= 0 {
+        return Err(SysError::EINVAL);
+    }
+    sys_epoll_create1(0)
+}
+
+pub fn sys_epoll_create1(flags: usize) -> SysResult {
+    info!("epoll_create1: flags: {:#x}", flags);
+    if flags & !EPOLL_CLOEXEC != 0 {
+        return Err(SysError::EINVAL);
+    }
+    let epoll = Arc::new(Epoll::new());
+    let mut proc = process();
+    let fd = proc.get_free_fd();
+    proc.files.insert(fd, FileLike::Epoll(epoll));
+    proc.set_cloexec(fd, flags & EPOLL_CLOEXEC != 0);
+    Ok(fd)
+}
+
+pub fn sys_epoll_ctl(epfd: usize, op: usize, fd: usize, event: *const EpollEvent) -> SysResult {
+    info!(
+        "epoll_ctl: epfd: {}, op: {}, fd: {}, event: {:?}",
+        epfd, op, fd, event
+    );
+    let mut proc = process();
+    let epoll = proc.get_epoll(epfd)?;
+    match proc.get_file_like(fd)? {
+        FileLike::File(file) if !is_pollable(&*file.inode()) => return Err(SysError::EPERM),
+        // nested epoll instances are not supported
+        FileLike::Epoll(_) => return Err(SysError::EINVAL),
+        _ => {}
+    }
+    match op {
+        EPOLL_CTL_ADD | EPOLL_CTL_MOD => {
+            proc.vm.check_read_ptr(event)?;
+            let event = unsafe { event.read() };
+            match op {
+                EPOLL_CTL_ADD => epoll.add(fd, event)?,
+                _ => epoll.modify(fd, event)?,
+            }
+        }
+        EPOLL_CTL_DEL => epoll.delete(fd)?,
+        _ => return Err(SysError::EINVAL),
+    }
+    Ok(0)
+}
+
+pub fn sys_epoll_wait(
+    epfd: usize,
+    events: *mut EpollEvent,
+    maxevents: usize,
+    timeout_msecs: usize,
+) -> SysResult {
+    info!(
+        "epoll_wait: epfd: {}, events: {:?}, maxevents: {}, timeout_msecs: {}",
+        epfd, events, maxevents, timeout_msecs as isize
+    );
+    let mut proc = process();
+    if maxevents as isize <= 0 {
+        return Err(SysError::EINVAL);
+    }
+    proc.vm.check_write_array(events, maxevents)?;
+    let epoll = proc.get_epoll(epfd)?;
+    drop(proc);
+    // negative for infinity
+    let timeout_msecs = match timeout_msecs as i32 {
+        timeout if timeout < 0 => None,
+        timeout => Some(timeout as usize),
+    };
+
+    let begin_time_ms = crate::trap::uptime_msec();
+    loop {
+        let proc = process();
+        let mut waiter = PollWaiter::new();
+        for fd in epoll.fds() {
+            if let Some(file_like) = proc.files.get(&fd) {
+                waiter.add(file_like);
+            }
+        }
+        let ready = epoll.collect(maxevents, |fd| {
+            let file_like = proc.files.get(&fd)?;
+            Some((file_like.poll(), wait_generation(file_like)))
+        });
+        if !ready.is_empty() {
+            let events = unsafe { slice::from_raw_parts_mut(events, ready.len()) };
+            events.copy_from_slice(&ready);
+            return Ok(ready.len());
+        }
+        drop(proc);
+
+        let elapsed_ms = crate::trap::uptime_msec() - begin_time_ms;
+        if let Some(timeout_msecs) = timeout_msecs {
+            if elapsed_ms >= timeout_msecs {
+                return Ok(0);
+            }
+        }
+
+        waiter.wait(timeout_msecs.map(|timeout_msecs| timeout_msecs - elapsed_ms));
+    }
+}
+
+pub fn sys_epoll_pwait(
+    epfd: usize,
+    events: *mut EpollEvent,
+    maxevents: usize,
+    timeout_msecs: usize,
+    sigmask: usize,
+) -> SysResult {
+    if sigmask != 0 {
+        warn!("epoll_pwait: signal masks are unimplemented");
+    }
+    sys_epoll_wait(epfd, events, maxevents, timeout_msecs)
+}
+
+pub fn sys_eventfd(initval: usize) -> SysResult {
+    sys_eventfd2(initval, 0)
+}
+
+pub fn sys_eventfd2(initval: usize, flags: usize) -> SysResult {
+    info!("eventfd2: initval: {}, flags: {:#x}", initval, flags);
+    if flags & !(EFD_SEMAPHORE | EFD_NONBLOCK | EFD_CLOEXEC) != 0 {
+        return Err(SysError::EINVAL);
+    }
+    let eventfd = EventFd::new(initval as u32 as u64, flags & EFD_SEMAPHORE != 0);
+    let nonblock = flags & EFD_NONBLOCK != 0;
+    let cloexec = flags & EFD_CLOEXEC != 0;
+    let fd = process().add_anon_file(Arc::new(eventfd), "eventfd", nonblock, cloexec);
+    Ok(fd)
+}
+
+pub fn sys_signalfd(fd: usize, mask: *const u64, sizemask: usize) -> SysResult {
+    sys_signalfd4(fd, mask, sizemask, 0)
+}
+
+pub fn sys_signalfd4(fd: usize, mask: *const u64, sizemask: usize, flags: usize) -> SysResult {
+    info!(
+        "signalfd4: fd: {}, mask: {:?}, sizemask: {}, flags: {:#x}",
+        fd as isize, mask, sizemask, flags
+    );
+    if flags & !(SFD_NONBLOCK | SFD_CLOEXEC) != 0 || sizemask != size_of::<u64>() {
+        return Err(SysError::EINVAL);
+    }
+    let mut proc = process();
+    proc.vm.check_read_ptr(mask)?;
+    let mask = unsafe { mask.read() };
+    if fd as isize != -1 {
+        let inode = proc.get_file(fd).map_err(|_| SysError::EINVAL)?.inode();
+        let signalfd = inode
+            .as_any_ref()
+            .downcast_ref::<SignalFd>()
+            .ok_or(SysError::EINVAL)?;
+        signalfd.set_mask(mask);
+        return Ok(fd);
+    }
+    let signalfd = SignalFd::new(proc.signals.clone(), mask);
+    let nonblock = flags & SFD_NONBLOCK != 0;
+    let cloexec = flags & SFD_CLOEXEC != 0;
+    let fd = proc.add_anon_file(Arc::new(signalfd), "signalfd", nonblock, cloexec);
+    Ok(fd)
+}
+
+/// The generation of what `file_like` notifies when its status may change
+fn wait_generation(file_like: &FileLike) -> usize {
+    match file_like {
+        FileLike::File(file) => inode_wait_queue(&*file.inode()).map_or(0, Condvar::generation),
+        FileLike::Socket(_) => SOCKET_ACTIVITY.generation(),
+        FileLike::Epoll(_) => 0,
+    }
+}
+
+/// How often files whose changes notify no wait queue are polled again
+const POLL_INTERVAL_MS: usize = 10;
+
+/// The event sources of the files being polled
+///
+/// The current thread is put on their wait queues as the files are added,
+/// before their status is checked, so that no change after the check is missed.
+struct PollWaiter {
+    /// Kept so that their wait queues live until the wait ends
+    inodes: Vec<Arc<INode>>,
+    socket: bool,
+    /// Some file may change without notifying a wait queue
+    unwatched: bool,
+    token: Arc<thread::Thread>,
+}
+
+impl PollWaiter {
+    fn new() -> Self {
+        PollWaiter {
+            inodes: Vec::new(),
+            socket: false,
+            unwatched: false,
+            token: Arc::new(thread::current()),
+        }
+    }
+
+    fn add(&mut self, file_like: &FileLike) {
+        match file_like {
+            FileLike::File(file) => {
+                let inode = file.inode();
+                if self.inodes.iter().any(|i| Arc::ptr_eq(i, &inode)) {
+                    return;
+                }
+                match inode_wait_queue(&*inode) {
+                    Some(queue) => queue.register(&self.token),
+                    None => {
+                        self.unwatched |= is_pollable(&*inode);
+                        return;
+                    }
+                }
+                self.inodes.push(inode);
+            }
+            FileLike::Socket(_) if !self.socket => {
+                SOCKET_ACTIVITY.register(&self.token);
+                self.socket = true;
+            }
+            _ => {}
+        }
+    }
+
+    fn queues(&self) -> Vec<&Condvar> {
+        let mut queues: Vec<&Condvar> = self
+            .inodes
+            .iter()
+            .filter_map(|inode| inode_wait_queue(&**inode))
+            .collect();
+        if self.socket {
+            queues.push(&*SOCKET_ACTIVITY);
+        }
+        queues
+    }
+
+    /// Wait until the status of any of the files may have changed since it was added,
+    /// or `timeout_ms` passes
+    fn wait(&self, timeout_ms: Option<usize>) {
+        let timeout_ms = match self.unwatched {
+            true => Some(timeout_ms.map_or(POLL_INTERVAL_MS, |t| min(t, POLL_INTERVAL_MS))),
+            false => timeout_ms,
+        };
+        let timeout = timeout_ms.map(|timeout_ms| Duration::from_millis(timeout_ms as u64));
+        Condvar::wait_registered(&self.queues(), &self.token, timeout);
+    }
+}
+
+impl Drop for PollWaiter {
+    fn drop(&mut self) {
+        for queue in self.queues() {
+            queue.unregister(&self.token);
+        }
     }
 }
 
@@ -216,13 +551,13 @@ pub fn sys_readv(fd: usize, iov_ptr: *const IoVec, iov_count: usize) -> SysResul
         "readv: fd: {}, iov: {:?}, count: {}",
         fd, iov_ptr, iov_count
     );
-    let mut proc = process();
+    let proc = process();
     let mut iovs = IoVecs::check_and_new(iov_ptr, iov_count, &proc.vm, true)?;
+    drop(proc);
 
     // read all data to a buf
-    let file_like = proc.get_file_like(fd)?;
     let mut buf = iovs.new_buf(true);
-    let len = file_like.read(buf.as_mut_slice())?;
+    let len = read_unlocked(fd, buf.as_mut_slice())?;
     // copy data to user
     iovs.write_all_from_slice(&buf[..len]);
     Ok(len)
@@ -233,15 +568,12 @@ pub fn sys_writev(fd: usize, iov_ptr: *const IoVec, iov_count: usize) -> SysResu
         "writev: fd: {}, iov: {:?}, count: {}",
         fd, iov_ptr, iov_count
     );
-    let mut proc = process();
+    let proc = process();
     let iovs = IoVecs::check_and_new(iov_ptr, iov_count, &proc.vm, false)?;
+    drop(proc);
 
     let buf = iovs.read_all_to_vec();
-    let len = buf.len();
-
-    let file_like = proc.get_file_like(fd)?;
-    let len = file_like.write(buf.as_slice())?;
-    Ok(len)
+    write_when_ready(fd, buf.as_slice())
 }
 
 pub fn sys_open(path: *const u8, flags: usize, mode: usize) -> SysResult {
@@ -257,74 +589,108 @@ pub fn sys_openat(dir_fd: usize, path: *const u8, flags: usize, mode: usize) ->
         dir_fd as isize, path, flags, mode
     );
 
-    let inode = if dir_fd == AT_FDCWD {
-        // from process cwd
-        if flags.contains(OpenFlags::CREATE) {
-            let (dir_path, file_name) = split_path(&path);
-            // relative to cwd
-            let dir_inode = proc.lookup_inode(dir_path)?;
-            match dir_inode.find(file_name) {
+    let follow = !flags.contains(OpenFlags::NOFOLLOW);
+    let exclusive = flags.contains(OpenFlags::EXCLUSIVE);
+    let inode = if flags.contains(OpenFlags::CREATE) {
+        let (dir_path, file_name) = split_path(&path);
+        let mut dir_inode = proc.lookup_inode_at(dir_fd, dir_path, true)?;
+        let mut file_name = String::from(file_name);
+        let mut follow_times = 0;
+        loop {
+            match lookup_at(&dir_inode, &file_name, false, &proc.cred, Some(&*proc)) {
                 Ok(file_inode) => {
-                    if flags.contains(OpenFlags::EXCLUSIVE) {
+                    // O_EXCL doesn't follow symlinks, even dangling ones
+                    if exclusive {
                         return Err(SysError::EEXIST);
                     }
-                    file_inode
-                }
-                Err(FsError::EntryNotFound) => {
-                    dir_inode.create(file_name, FileType::File, mode as u32)?
-                }
-                Err(e) => return Err(SysError::from(e)),
-            }
-        } else {
-            proc.lookup_inode(&path)?
-        }
-    } else {
-        // relative to dir_fd
-        let dir_file = proc.get_file(dir_fd)?;
-        if flags.contains(OpenFlags::CREATE) {
-            let (dir_path, file_name) = split_path(&path);
-            // relative to cwd
-            let dir_inode = dir_file.lookup_follow(dir_path, FOLLOW_MAX_DEPTH)?;
-            match dir_inode.find(file_name) {
-                Ok(file_inode) => {
-                    if flags.contains(OpenFlags::EXCLUSIVE) {
-                        return Err(SysError::EEXIST);
+                    if follow && file_inode.metadata()?.type_ == FileType::SymLink {
+                        // open or create the target
+                        follow_times += 1;
+                        if follow_times > FOLLOW_MAX_DEPTH {
+                            return Err(SysError::ELOOP);
+                        }
+                        let target = read_link(&file_inode, Some(&*proc))?;
+                        let (target_dir, target_name) = split_path(&target);
+                        dir_inode =
+                            lookup_at(&dir_inode, target_dir, true, &proc.cred, Some(&*proc))?;
+                        file_name = String::from(target_name);
+                        continue;
                     }
-                    file_inode
+                    proc.check_open(&file_inode, flags)?;
+                    break file_inode;
                 }
-                Err(FsError::EntryNotFound) => {
-                    dir_inode.create(file_name, FileType::File, mode as u32)?
+                Err(SysError::ENOENT) => {
+                    let mode = mode as u16 & !proc.umask;
+                    match proc.create_inode(&dir_inode, &file_name, FileType::File, mode) {
+                        // created by another thread since the lookup, open it
+                        Err(SysError::EEXIST) if !exclusive && follow_times < FOLLOW_MAX_DEPTH => {
+                            follow_times += 1;
+                        }
+                        result => break result?,
+                    }
                 }
-                Err(e) => return Err(SysError::from(e)),
+                Err(e) => return Err(e),
             }
-        } else {
-            dir_file.lookup_follow(&path, FOLLOW_MAX_DEPTH)?
         }
+    } else {
+        let inode = proc.lookup_inode_at(dir_fd, &path, follow)?;
+        proc.check_open(&inode, flags)?;
+        inode
+    };
+    let options = flags.to_options();
+    let inode = if inode.metadata()?.type_ == FileType::NamedPipe {
+        // waiting for the other end must not hold the lock
+        drop(proc);
+        let fifo = Pipe::open_fifo(inode, options.read, options.write, options.nonblock)?;
+        proc = process();
+        Arc::new(fifo)
+    } else {
+        inode
     };
 
     let fd = proc.get_free_fd();
 
-    let file = FileHandle::new(inode, flags.to_options());
+    let path = if dir_fd == AT_FDCWD || path.starts_with('/') {
+        proc.absolute_path(&path)
+    } else {
+        format!("{}/{}", proc.get_file(dir_fd)?.path(), path)
+    };
+    let file = FileHandle::new(inode, options, path);
     proc.files.insert(fd, FileLike::File(file));
+    proc.set_cloexec(fd, flags.contains(OpenFlags::CLOEXEC));
     Ok(fd)
 }
 
 pub fn sys_close(fd: usize) -> SysResult {
     info!("close: fd: {:?}", fd);
     let mut proc = process();
-    proc.files.remove(&fd).ok_or(SysError::EBADF)?;
+    proc.close_file(fd)?;
     Ok(0)
 }
 
 pub fn sys_access(path: *const u8, mode: usize) -> SysResult {
-    // TODO: check permissions based on uid/gid
+    sys_faccessat(AT_FDCWD, path, mode, 0)
+}
+
+pub fn sys_faccessat(dir_fd: usize, path: *const u8, mode: usize, flags: usize) -> SysResult {
     let proc = process();
     let path = unsafe { proc.vm.check_and_clone_cstr(path)? };
     if !proc.pid.is_init() {
         // we trust pid 0 process
-        info!("access: path: {:?}, mode: {:#o}", path, mode);
+        info!(
+            "faccessat: dir_fd: {}, path: {:?}, mode: {:#o}, flags: {:#x}",
+            dir_fd as isize, path, mode, flags
+        );
+    }
+    let follow = flags & AT_SYMLINK_NOFOLLOW == 0;
+    let inode = proc.lookup_inode_at(dir_fd, &path, follow)?;
+    let access = Access::from_bits(mode as u16).ok_or(SysError::EINVAL)?;
+    let info = inode.metadata()?;
+    if flags & AT_EACCESS != 0 {
+        proc.cred.check(&info, access)?;
+    } else {
+        proc.cred.real().check(&info, access)?;
     }
-    let inode = proc.lookup_inode(&path)?;
     Ok(0)
 }
 
@@ -343,8 +709,7 @@ pub fn sys_getcwd(buf: *mut u8, len: usize) -> SysResult {
 }
 
 pub fn sys_stat(path: *const u8, stat_ptr: *mut Stat) -> SysResult {
-    warn!("stat is partial implemented as lstat");
-    sys_lstat(path, stat_ptr)
+    sys_fstatat(AT_FDCWD, path, stat_ptr, 0)
 }
 
 pub fn sys_fstat(fd: usize, stat_ptr: *mut Stat) -> SysResult {
@@ -352,8 +717,8 @@ pub fn sys_fstat(fd: usize, stat_ptr: *mut Stat) -> SysResult {
     let mut proc = process();
     proc.vm.check_write_ptr(stat_ptr)?;
     let file = proc.get_file(fd)?;
-    let stat = Stat::from(file.metadata()?);
-    // TODO: handle symlink
+    let mut stat = Stat::from(file.metadata()?);
+    stat.rdev = rdev(&file.inode()) as u64;
     unsafe {
         stat_ptr.write(stat);
     }
@@ -361,34 +726,119 @@ pub fn sys_fstat(fd: usize, stat_ptr: *mut Stat) -> SysResult {
 }
 
 pub fn sys_lstat(path: *const u8, stat_ptr: *mut Stat) -> SysResult {
+    sys_fstatat(AT_FDCWD, path, stat_ptr, AT_SYMLINK_NOFOLLOW)
+}
+
+pub fn sys_fstatat(dir_fd: usize, path: *const u8, stat_ptr: *mut Stat, flags: usize) -> SysResult {
     let proc = process();
     let path = unsafe { proc.vm.check_and_clone_cstr(path)? };
     proc.vm.check_write_ptr(stat_ptr)?;
-    info!("lstat: path: {:?}, stat_ptr: {:?}", path, stat_ptr);
+    info!(
+        "fstatat: dir_fd: {}, path: {:?}, stat_ptr: {:?}, flags: {:#x}",
+        dir_fd as isize, path, stat_ptr, flags
+    );
 
-    let inode = proc.lookup_inode(&path)?;
-    let stat = Stat::from(inode.metadata()?);
+    let follow = flags & AT_SYMLINK_NOFOLLOW == 0;
+    let inode = if path.is_empty() && flags & AT_EMPTY_PATH != 0 {
+        // the file referred by `dir_fd` itself
+        proc.dir_fd_inode(dir_fd)?
+    } else {
+        proc.lookup_inode_at(dir_fd, &path, follow)?
+    };
+    let mut stat = Stat::from(inode.metadata()?);
+    stat.rdev = rdev(&inode) as u64;
     unsafe {
         stat_ptr.write(stat);
     }
     Ok(0)
 }
 
+pub fn sys_statfs(path: *const u8, buf: *mut StatFs) -> SysResult {
+    let proc = process();
+    let path = unsafe { proc.vm.check_and_clone_cstr(path)? };
+    proc.vm.check_write_ptr(buf)?;
+    info!("statfs: path: {:?}, buf: {:?}", path, buf);
+
+    let inode = proc.lookup_inode(&path)?;
+    let stat = StatFs::from(statfs(&inode)?);
+    unsafe {
+        buf.write(stat);
+    }
+    Ok(0)
+}
+
+pub fn sys_fstatfs(fd: usize, buf: *mut StatFs) -> SysResult {
+    info!("fstatfs: fd: {}, buf: {:?}", fd, buf);
+    let mut proc = process();
+    proc.vm.check_write_ptr(buf)?;
+    let stat = match proc.get_file_like(fd)? {
+        FileLike::File(file) => statfs(&file.inode())?,
+        FileLike::Socket(_) => FsStat::pseudo(SOCKFS_MAGIC),
+        FileLike::Epoll(_) => FsStat::pseudo(ANON_INODE_FS_MAGIC),
+    };
+    unsafe {
+        buf.write(StatFs::from(stat));
+    }
+    Ok(0)
+}
+
 pub fn sys_readlink(path: *const u8, base: *mut u8, len: usize) -> SysResult {
+    sys_readlinkat(AT_FDCWD, path, base, len)
+}
+
+pub fn sys_readlinkat(dir_fd: usize, path: *const u8, base: *mut u8, len: usize) -> SysResult {
     let proc = process();
     let path = unsafe { proc.vm.check_and_clone_cstr(path)? };
     proc.vm.check_write_array(base, len)?;
-    info!("readlink: path: {:?}, base: {:?}, len: {}", path, base, len);
+    info!(
+        "readlinkat: dir_fd: {}, path: {:?}, base: {:?}, len: {}",
+        dir_fd as isize, path, base, len
+    );
 
-    let inode = proc.lookup_inode(&path)?;
-    if inode.metadata()?.type_ == FileType::SymLink {
-        // TODO: recursive link resolution and loop detection
-        let mut slice = unsafe { slice::from_raw_parts_mut(base, len) };
-        let len = inode.read_at(0, &mut slice)?;
-        Ok(len)
-    } else {
-        Err(SysError::EINVAL)
+    let inode = proc.lookup_inode_at(dir_fd, &path, false)?;
+    if inode.metadata()?.type_ != FileType::SymLink {
+        return Err(SysError::EINVAL);
     }
+    let target = read_link(&inode, Some(&*proc))?;
+    let len = min(len, target.len());
+    let slice = unsafe { slice::from_raw_parts_mut(base, len) };
+    slice.copy_from_slice(&target.as_bytes()[..len]);
+    Ok(len)
+}
+
+pub fn sys_symlink(target: *const u8, linkpath: *const u8) -> SysResult {
+    sys_symlinkat(target, AT_FDCWD, linkpath)
+}
+
+pub fn sys_symlinkat(target: *const u8, new_dir_fd: usize, linkpath: *const u8) -> SysResult {
+    let proc = process();
+    let target = unsafe { proc.vm.check_and_clone_cstr(target)? };
+    let linkpath = unsafe { proc.vm.check_and_clone_cstr(linkpath)? };
+    info!(
+        "symlinkat: target: {:?}, new_dir_fd: {}, linkpath: {:?}",
+        target, new_dir_fd as isize, linkpath
+    );
+    if target.is_empty() {
+        return Err(SysError::ENOENT);
+    }
+    if target.len() >= PATH_MAX {
+        return Err(SysError::ENAMETOOLONG);
+    }
+
+    let (dir_path, file_name) = split_path(&linkpath);
+    let dir_inode = proc.lookup_inode_at(new_dir_fd, dir_path, true)?;
+    // an existing file fails the creation with EEXIST
+    let inode = match proc.create_inode(&dir_inode, file_name, FileType::SymLink, 0o777) {
+        Ok(inode) => inode,
+        // the file system doesn't support symlinks
+        Err(SysError::ENOSYS) => return Err(SysError::EPERM),
+        Err(e) => return Err(e),
+    };
+    if let Err(e) = inode.write_at(0, target.as_bytes()) {
+        dir_inode.unlink(file_name)?;
+        return Err(SysError::from(e));
+    }
+    Ok(0)
 }
 
 pub fn sys_lseek(fd: usize, offset: i64, whence: u8) -> SysResult {
@@ -409,12 +859,15 @@ pub fn sys_lseek(fd: usize, offset: i64, whence: u8) -> SysResult {
 pub fn sys_fsync(fd: usize) -> SysResult {
     info!("fsync: fd: {}", fd);
     process().get_file(fd)?.sync_all()?;
+    // the block caches don't tell the pages of each file apart
+    sync_block_caches()?;
     Ok(0)
 }
 
 pub fn sys_fdatasync(fd: usize) -> SysResult {
     info!("fdatasync: fd: {}", fd);
     process().get_file(fd)?.sync_data()?;
+    sync_block_caches()?;
     Ok(0)
 }
 
@@ -422,7 +875,9 @@ pub fn sys_truncate(path: *const u8, len: usize) -> SysResult {
     let proc = process();
     let path = unsafe { proc.vm.check_and_clone_cstr(path)? };
     info!("truncate: path: {:?}, len: {}", path, len);
-    proc.lookup_inode(&path)?.resize(len)?;
+    let inode = proc.lookup_inode(&path)?;
+    proc.cred.check(&inode.metadata()?, Access::WRITE)?;
+    inode.resize(len)?;
     Ok(0)
 }
 
@@ -437,60 +892,381 @@ pub fn sys_getdents64(fd: usize, buf: *mut LinuxDirent64, buf_size: usize) -> Sy
         "getdents64: fd: {}, ptr: {:?}, buf_size: {}",
         fd, buf, buf_size
     );
-    let mut proc = process();
+    let proc = process();
     proc.vm.check_write_array(buf as *mut u8, buf_size)?;
-    let file = proc.get_file(fd)?;
-    let info = file.metadata()?;
-    if info.type_ != FileType::Dir {
-        return Err(SysError::ENOTDIR);
-    }
-    let mut writer = unsafe { DirentBufWriter::new(buf, buf_size) };
-    loop {
-        let name = match file.read_entry() {
-            Err(FsError::EntryNotFound) => break,
-            r => r,
-        }?;
-        // TODO: get ino from dirent
-        let ok = writer.try_write(0, DirentType::from_type(&info.type_).bits(), &name);
-        if !ok {
-            break;
+    drop(proc);
+    // the entries of procfs lock processes
+    with_file_unlocked(fd, |file| {
+        let info = file.metadata()?;
+        if info.type_ != FileType::Dir {
+            return Err(SysError::ENOTDIR);
         }
-    }
-    Ok(writer.written_size)
+        let mut writer = unsafe { DirentBufWriter::new(buf, buf_size) };
+        loop {
+            let name = match file.read_entry() {
+                Err(FsError::EntryNotFound) => break,
+                r => r,
+            }?;
+            // TODO: get ino from dirent
+            let ok = writer.try_write(0, DirentType::from_type(&info.type_).bits(), &name);
+            if !ok {
+                break;
+            }
+        }
+        Ok(writer.written_size)
+    })
+}
+
+pub fn sys_dup(fd: usize) -> SysResult {
+    info!("dup: fd: {}", fd);
+    let mut proc = process();
+    let file_like = proc.get_file_like(fd)?.clone();
+    let new_fd = proc.get_free_fd();
+    proc.files.insert(new_fd, file_like);
+    Ok(new_fd)
 }
 
 pub fn sys_dup2(fd1: usize, fd2: usize) -> SysResult {
     info!("dup2: from {} to {}", fd1, fd2);
-    let mut proc = process();
-    // close fd2 first if it is opened
-    proc.files.remove(&fd2);
+    if fd1 == fd2 {
+        process().get_file_like(fd1)?;
+        return Ok(fd2);
+    }
+    dup_to(fd1, fd2, false)
+}
+
+pub fn sys_dup3(fd1: usize, fd2: usize, flags: usize) -> SysResult {
+    info!("dup3: from {} to {}, flags: {:#x}", fd1, fd2, flags);
+    let flags = OpenFlags::from_bits(flags).ok_or(SysError::EINVAL)?;
+    if fd1 == fd2 || !(flags - OpenFlags::CLOEXEC).is_empty() {
+        return Err(SysError::EINVAL);
+    }
+    dup_to(fd1, fd2, flags.contains(OpenFlags::CLOEXEC))
+}
 
+/// Make `fd2` refer to the file of `fd1`, closing `fd2` first if it is open
+fn dup_to(fd1: usize, fd2: usize, cloexec: bool) -> SysResult {
+    let mut proc = process();
     let file_like = proc.get_file_like(fd1)?.clone();
+    proc.close_file(fd2).ok();
     proc.files.insert(fd2, file_like);
+    proc.set_cloexec(fd2, cloexec);
     Ok(fd2)
 }
 
+pub fn sys_fcntl(fd: usize, cmd: usize, arg: usize) -> SysResult {
+    info!("fcntl: fd: {}, cmd: {}, arg: {:#x}", fd, cmd, arg);
+    let mut proc = process();
+    match cmd {
+        F_DUPFD | F_DUPFD_CLOEXEC => {
+            let file_like = proc.get_file_like(fd)?.clone();
+            let new_fd = proc.get_free_fd_from(arg);
+            proc.files.insert(new_fd, file_like);
+            proc.set_cloexec(new_fd, cmd == F_DUPFD_CLOEXEC);
+            Ok(new_fd)
+        }
+        F_GETFD => {
+            proc.get_file_like(fd)?;
+            match proc.cloexec_fds.contains(&fd) {
+                true => Ok(FD_CLOEXEC),
+                false => Ok(0),
+            }
+        }
+        F_SETFD => {
+            proc.get_file_like(fd)?;
+            proc.set_cloexec(fd, arg & FD_CLOEXEC != 0);
+            Ok(0)
+        }
+        F_GETFL => {
+            let flags = match proc.get_file_like(fd)? {
+                FileLike::File(file) => OpenFlags::from_options(&file.options()),
+                FileLike::Socket(socket) => match socket.nonblocking() {
+                    true => OpenFlags::RDWR | OpenFlags::NONBLOCK,
+                    false => OpenFlags::RDWR,
+                },
+                FileLike::Epoll(_) => OpenFlags::RDWR,
+            };
+            Ok(flags.bits())
+        }
+        F_SETFL => {
+            // the access mode and creation flags are ignored
+            let flags = OpenFlags::from_bits_truncate(arg);
+            let nonblock = flags.contains(OpenFlags::NONBLOCK);
+            match proc.get_file_like(fd)? {
+                FileLike::File(file) => {
+                    file.set_status_flags(flags.contains(OpenFlags::APPEND), nonblock)
+                }
+                FileLike::Socket(socket) => socket.set_nonblocking(nonblock),
+                FileLike::Epoll(_) => {}
+            }
+            Ok(0)
+        }
+        F_GETPIPE_SZ | F_SETPIPE_SZ => {
+            let euid = proc.cred.euid;
+            let inode = proc.get_file(fd)?.inode();
+            let pipe = inode
+                .as_any_ref()
+                .downcast_ref::<Pipe>()
+                .ok_or(SysError::EBADF)?;
+            if cmd == F_GETPIPE_SZ {
+                return Ok(pipe.capacity());
+            }
+            if arg > PIPE_MAX_SIZE && euid != 0 {
+                return Err(SysError::EPERM);
+            }
+            pipe.set_capacity(arg).ok_or(SysError::EBUSY)
+        }
+        F_GETLK | F_SETLK | F_SETLKW => {
+            let ptr = arg as *mut Flock;
+            proc.vm.check_write_ptr(ptr)?;
+            let mut flock = unsafe { ptr.read() };
+            let pid = proc.pid.get();
+            let file = proc.get_file(fd)?;
+            let (start, end) = flock.range(file)?;
+            let write = match flock.type_ {
+                F_RDLCK => false,
+                F_WRLCK => true,
+                F_UNLCK if cmd != F_GETLK => {
+                    unlock_record(&file.inode(), pid, start, end);
+                    return Ok(0);
+                }
+                _ => return Err(SysError::EINVAL),
+            };
+            let lock = RecordLock {
+                pid,
+                start,
+                end,
+                write,
+            };
+            if cmd == F_GETLK {
+                match test_record(&file.inode(), &lock) {
+                    Some(other) => flock.describe(&other),
+                    None => flock.type_ = F_UNLCK,
+                }
+                unsafe { ptr.write(flock) };
+                return Ok(0);
+            }
+            let options = file.options();
+            if (write && !options.write) || (!write && !options.read) {
+                return Err(SysError::EBADF);
+            }
+            // wait without the process lock
+            let inode = file.inode();
+            drop(proc);
+            lock_record(&inode, lock, cmd == F_SETLKW)?;
+            Ok(0)
+        }
+        _ => {
+            warn!("fcntl: unsupported cmd {}", cmd);
+            Err(SysError::EINVAL)
+        }
+    }
+}
+
+/// Apply or remove an advisory lock on the whole file
+pub fn sys_flock(fd: usize, operation: usize) -> SysResult {
+    info!("flock: fd: {}, operation: {:#x}", fd, operation);
+    // wait without the process lock
+    let owner = process().get_file(fd)?.flock_owner();
+    let nonblock = operation & LOCK_NB != 0;
+    match operation & !LOCK_NB {
+        LOCK_SH => flock(&owner, false, nonblock)?,
+        LOCK_EX => flock(&owner, true, nonblock)?,
+        LOCK_UN => unlock_flock(&owner),
+        _ => return Err(SysError::EINVAL),
+    }
+    Ok(0)
+}
+
 pub fn sys_chdir(path: *const u8) -> SysResult {
     let mut proc = process();
     let path = unsafe { proc.vm.check_and_clone_cstr(path)? };
-    if !proc.pid.is_init() {
-        // we trust pid 0 process
-        info!("chdir: path: {:?}", path);
-    }
+    if !proc.pid.is_init() {
+        // we trust pid 0 process
+        info!("chdir: path: {:?}", path);
+    }
+
+    let inode = proc.lookup_inode(&path)?;
+    let info = inode.metadata()?;
+    if info.type_ != FileType::Dir {
+        return Err(SysError::ENOTDIR);
+    }
+    proc.cred.check(&info, Access::EXEC)?;
+
+    if path.len() > 0 && path.as_bytes()[0] == b'/' {
+        // absolute
+        proc.cwd = path;
+    } else {
+        // relative
+        proc.cwd += &path;
+    }
+    Ok(0)
+}
+
+pub fn sys_umask(mask: usize) -> SysResult {
+    let mut proc = process();
+    info!("umask: mask: {:#o}", mask);
+    let old = proc.umask;
+    proc.umask = mask as u16 & 0o777;
+    Ok(old as usize)
+}
+
+pub fn sys_chmod(path: *const u8, mode: usize) -> SysResult {
+    sys_fchmodat(AT_FDCWD, path, mode, 0)
+}
+
+pub fn sys_fchmod(fd: usize, mode: usize) -> SysResult {
+    info!("fchmod: fd: {}, mode: {:#o}", fd, mode);
+    let mut proc = process();
+    let inode = proc.get_file(fd)?.inode();
+    chmod_inode(&proc.cred, &inode, mode as u16)
+}
+
+pub fn sys_fchmodat(dir_fd: usize, path: *const u8, mode: usize, flags: usize) -> SysResult {
+    let proc = process();
+    let path = unsafe { proc.vm.check_and_clone_cstr(path)? };
+    info!(
+        "fchmodat: dir_fd: {}, path: {:?}, mode: {:#o}, flags: {:#x}",
+        dir_fd as isize, path, mode, flags
+    );
+    let inode = proc.lookup_inode_at(dir_fd, &path, true)?;
+    chmod_inode(&proc.cred, &inode, mode as u16)
+}
+
+fn chmod_inode(cred: &Credentials, inode: &Arc<INode>, mode: u16) -> SysResult {
+    let info = inode.metadata()?;
+    if !cred.owns(&info) {
+        return Err(SysError::EPERM);
+    }
+    let mut mode = mode & 0o7777;
+    if !cred.is_root() && !cred.in_group(info.gid) {
+        mode &= !S_ISGID;
+    }
+    inode.chmod(mode)?;
+    Ok(0)
+}
+
+pub fn sys_chown(path: *const u8, uid: usize, gid: usize) -> SysResult {
+    sys_fchownat(AT_FDCWD, path, uid, gid, 0)
+}
+
+pub fn sys_lchown(path: *const u8, uid: usize, gid: usize) -> SysResult {
+    sys_fchownat(AT_FDCWD, path, uid, gid, AT_SYMLINK_NOFOLLOW)
+}
+
+pub fn sys_fchown(fd: usize, uid: usize, gid: usize) -> SysResult {
+    info!(
+        "fchown: fd: {}, uid: {}, gid: {}",
+        fd, uid as i32, gid as i32
+    );
+    let mut proc = process();
+    let inode = proc.get_file(fd)?.inode();
+    chown_inode(&proc.cred, &inode, id_arg(uid), id_arg(gid))
+}
+
+pub fn sys_fchownat(
+    dir_fd: usize,
+    path: *const u8,
+    uid: usize,
+    gid: usize,
+    flags: usize,
+) -> SysResult {
+    let proc = process();
+    let path = unsafe { proc.vm.check_and_clone_cstr(path)? };
+    info!(
+        "fchownat: dir_fd: {}, path: {:?}, uid: {}, gid: {}, flags: {:#x}",
+        dir_fd as isize, path, uid as i32, gid as i32, flags
+    );
+    let inode = if path.is_empty() && flags & AT_EMPTY_PATH != 0 {
+        proc.dir_fd_inode(dir_fd)?
+    } else {
+        let follow = flags & AT_SYMLINK_NOFOLLOW == 0;
+        proc.lookup_inode_at(dir_fd, &path, follow)?
+    };
+    chown_inode(&proc.cred, &inode, id_arg(uid), id_arg(gid))
+}
 
-    let inode = proc.lookup_inode(&path)?;
+/// Change the owner of `inode`, `None` keeps the current one
+fn chown_inode(
+    cred: &Credentials,
+    inode: &Arc<INode>,
+    uid: Option<usize>,
+    gid: Option<usize>,
+) -> SysResult {
     let info = inode.metadata()?;
-    if info.type_ != FileType::Dir {
-        return Err(SysError::ENOTDIR);
+    let new_uid = uid.unwrap_or(info.uid);
+    let new_gid = gid.unwrap_or(info.gid);
+    // the owner can only change the group to one of its groups
+    if !cred.is_root()
+        && (new_uid != info.uid
+            || !cred.owns(&info)
+            || (new_gid != info.gid && !cred.in_group(new_gid)))
+    {
+        return Err(SysError::EPERM);
     }
+    match chown(inode, new_uid, new_gid) {
+        Ok(()) => {}
+        // the file system has no owners
+        Err(FsError::NotSupported) => return Err(SysError::EPERM),
+        Err(e) => return Err(SysError::from(e)),
+    }
+    // a new owner doesn't inherit the privilege
+    if info.type_ != FileType::Dir && info.mode & (S_ISUID | S_ISGID) != 0 {
+        inode.chmod(info.mode & !(S_ISUID | S_ISGID))?;
+    }
+    Ok(0)
+}
 
-    if path.len() > 0 && path.as_bytes()[0] == b'/' {
-        // absolute
-        proc.cwd = path;
+/// Set the access and modification times of a file.
+/// A null `path` means the file `dir_fd` itself, which is how futimens works.
+pub fn sys_utimensat(
+    dir_fd: usize,
+    path: *const u8,
+    times: *const TimeSpec,
+    flags: usize,
+) -> SysResult {
+    let mut proc = process();
+    info!(
+        "utimensat: dir_fd: {}, path: {:?}, times: {:?}, flags: {:#x}",
+        dir_fd as isize, path, times, flags
+    );
+    let times = if times.is_null() {
+        [TimeSpec::utime_now(); 2]
     } else {
-        // relative
-        proc.cwd += &path;
+        proc.vm.check_read_array(times, 2)?;
+        unsafe { [*times, *times.add(1)] }
+    };
+    if times
+        .iter()
+        .any(|time| !time.is_valid() && !time.is_utime_special())
+    {
+        return Err(SysError::EINVAL);
+    }
+    let inode = if path.is_null() {
+        proc.get_file(dir_fd)?.inode()
+    } else {
+        let path = unsafe { proc.vm.check_and_clone_cstr(path)? };
+        if path.is_empty() && flags & AT_EMPTY_PATH != 0 {
+            proc.dir_fd_inode(dir_fd)?
+        } else {
+            let follow = flags & AT_SYMLINK_NOFOLLOW == 0;
+            proc.lookup_inode_at(dir_fd, &path, follow)?
+        }
+    };
+    let (atime, mtime) = (times[0].to_utime(), times[1].to_utime());
+    if atime.is_none() && mtime.is_none() {
+        return Ok(0);
+    }
+    // setting the current time only needs write permission
+    let info = inode.metadata()?;
+    if times.iter().all(|time| time.is_utime_special()) {
+        if !proc.cred.owns(&info) {
+            proc.cred.check(&info, Access::WRITE)?;
+        }
+    } else if !proc.cred.owns(&info) {
+        return Err(SysError::EPERM);
     }
+    set_times(&inode, atime, mtime)?;
     Ok(0)
 }
 
@@ -514,74 +1290,160 @@ pub fn sys_renameat(
 
     let (old_dir_path, old_file_name) = split_path(&oldpath);
     let (new_dir_path, new_file_name) = split_path(&newpath);
-    let old_dir_inode = if olddirfd == AT_FDCWD {
-        proc.lookup_inode(old_dir_path)?
-    } else {
-        proc.get_file(olddirfd)?
-            .lookup_follow(old_dir_path, FOLLOW_MAX_DEPTH)?
-    };
-    let new_dir_inode = if newdirfd == AT_FDCWD {
-        proc.lookup_inode(new_dir_path)?
-    } else {
-        proc.get_file(newdirfd)?
-            .lookup_follow(new_dir_path, FOLLOW_MAX_DEPTH)?
-    };
+    let old_dir_inode = proc.lookup_inode_at(olddirfd, old_dir_path, true)?;
+    let new_dir_inode = proc.lookup_inode_at(newdirfd, new_dir_path, true)?;
+    let old_dir_info = old_dir_inode.metadata()?;
+    let new_dir_info = new_dir_inode.metadata()?;
+    let info = old_dir_inode.find(old_file_name)?.metadata()?;
+    proc.cred.check_delete(&old_dir_info, &info)?;
+    match new_dir_inode.find(new_file_name) {
+        Ok(inode) => proc.cred.check_delete(&new_dir_info, &inode.metadata()?)?,
+        Err(_) => proc
+            .cred
+            .check(&new_dir_info, Access::WRITE | Access::EXEC)?,
+    }
+    if info.type_ == FileType::Dir && old_dir_info.inode != new_dir_info.inode {
+        // its ".." will be changed
+        proc.cred.check(&info, Access::WRITE)?;
+    }
     old_dir_inode.move_(old_file_name, &new_dir_inode, new_file_name)?;
     Ok(0)
 }
 
 pub fn sys_mkdir(path: *const u8, mode: usize) -> SysResult {
+    sys_mkdirat(AT_FDCWD, path, mode)
+}
+
+pub fn sys_mkdirat(dir_fd: usize, path: *const u8, mode: usize) -> SysResult {
     let proc = process();
     let path = unsafe { proc.vm.check_and_clone_cstr(path)? };
     // TODO: check pathname
-    info!("mkdir: path: {:?}, mode: {:#o}", path, mode);
+    info!(
+        "mkdirat: dir_fd: {}, path: {:?}, mode: {:#o}",
+        dir_fd as isize, path, mode
+    );
 
     let (dir_path, file_name) = split_path(&path);
-    let inode = proc.lookup_inode(dir_path)?;
+    let inode = proc.lookup_inode_at(dir_fd, dir_path, true)?;
     if inode.find(file_name).is_ok() {
         return Err(SysError::EEXIST);
     }
-    inode.create(file_name, FileType::Dir, mode as u32)?;
+    let mode = mode as u16 & !proc.umask;
+    proc.create_inode(&inode, file_name, FileType::Dir, mode)?;
     Ok(0)
 }
 
-pub fn sys_rmdir(path: *const u8) -> SysResult {
+pub fn sys_mknod(path: *const u8, mode: usize, dev: usize) -> SysResult {
+    sys_mknodat(AT_FDCWD, path, mode, dev)
+}
+
+/// Create a FIFO, a device node, a socket node or a regular file
+pub fn sys_mknodat(dir_fd: usize, path: *const u8, mode: usize, dev: usize) -> SysResult {
     let proc = process();
     let path = unsafe { proc.vm.check_and_clone_cstr(path)? };
-    info!("rmdir: path: {:?}", path);
+    info!(
+        "mknodat: dir_fd: {}, path: {:?}, mode: {:#o}, dev: {:#x}",
+        dir_fd as isize, path, mode, dev
+    );
+
+    let type_ = match StatMode::from_bits_truncate(mode as u32) & StatMode::TYPE_MASK {
+        StatMode::NULL | StatMode::FILE => FileType::File,
+        StatMode::FIFO => FileType::NamedPipe,
+        StatMode::CHAR => FileType::CharDevice,
+        StatMode::BLOCK => FileType::BlockDevice,
+        StatMode::SOCKET => FileType::Socket,
+        StatMode::DIR => return Err(SysError::EPERM),
+        _ => return Err(SysError::EINVAL),
+    };
+    let device = match type_ {
+        FileType::CharDevice | FileType::BlockDevice => true,
+        _ => false,
+    };
+    if device && proc.cred.euid != 0 {
+        return Err(SysError::EPERM);
+    }
 
     let (dir_path, file_name) = split_path(&path);
-    let dir_inode = proc.lookup_inode(dir_path)?;
-    let file_inode = dir_inode.find(file_name)?;
-    if file_inode.metadata()?.type_ != FileType::Dir {
-        return Err(SysError::ENOTDIR);
+    let dir = proc.lookup_inode_at(dir_fd, dir_path, true)?;
+    if dir.find(file_name).is_ok() {
+        return Err(SysError::EEXIST);
+    }
+    let mode = mode as u16 & 0o7777 & !proc.umask;
+    let inode = proc.create_inode(&dir, file_name, type_, mode)?;
+    if device {
+        if let Err(e) = set_rdev(&inode, dev) {
+            // a device file without its number is of no use
+            dir.unlink(file_name).ok();
+            return Err(match e {
+                FsError::NotSupported => SysError::EPERM,
+                e => SysError::from(e),
+            });
+        }
     }
-    dir_inode.unlink(file_name)?;
     Ok(0)
 }
 
+pub fn sys_rmdir(path: *const u8) -> SysResult {
+    sys_unlinkat(AT_FDCWD, path, AT_REMOVEDIR)
+}
+
 pub fn sys_link(oldpath: *const u8, newpath: *const u8) -> SysResult {
+    sys_linkat(AT_FDCWD, oldpath, AT_FDCWD, newpath, 0)
+}
+
+pub fn sys_linkat(
+    old_dir_fd: usize,
+    oldpath: *const u8,
+    new_dir_fd: usize,
+    newpath: *const u8,
+    flags: usize,
+) -> SysResult {
     let proc = process();
     let oldpath = unsafe { proc.vm.check_and_clone_cstr(oldpath)? };
     let newpath = unsafe { proc.vm.check_and_clone_cstr(newpath)? };
-    info!("link: oldpath: {:?}, newpath: {:?}", oldpath, newpath);
+    info!(
+        "linkat: old_dir_fd: {}, oldpath: {:?}, new_dir_fd: {}, newpath: {:?}, flags: {:#x}",
+        old_dir_fd as isize, oldpath, new_dir_fd as isize, newpath, flags
+    );
 
     let (new_dir_path, new_file_name) = split_path(&newpath);
-    let inode = proc.lookup_inode(&oldpath)?;
-    let new_dir_inode = proc.lookup_inode(new_dir_path)?;
+    let inode = if oldpath.is_empty() && flags & AT_EMPTY_PATH != 0 {
+        proc.dir_fd_inode(old_dir_fd)?
+    } else {
+        let follow = flags & AT_SYMLINK_FOLLOW != 0;
+        proc.lookup_inode_at(old_dir_fd, &oldpath, follow)?
+    };
+    let new_dir_inode = proc.lookup_inode_at(new_dir_fd, new_dir_path, true)?;
+    let new_dir_info = new_dir_inode.metadata()?;
+    proc.cred
+        .check(&new_dir_info, Access::WRITE | Access::EXEC)?;
     new_dir_inode.link(new_file_name, &inode)?;
     Ok(0)
 }
 
 pub fn sys_unlink(path: *const u8) -> SysResult {
+    sys_unlinkat(AT_FDCWD, path, 0)
+}
+
+pub fn sys_unlinkat(dir_fd: usize, path: *const u8, flags: usize) -> SysResult {
     let proc = process();
     let path = unsafe { proc.vm.check_and_clone_cstr(path)? };
-    info!("unlink: path: {:?}", path);
+    info!(
+        "unlinkat: dir_fd: {}, path: {:?}, flags: {:#x}",
+        dir_fd as isize, path, flags
+    );
 
     let (dir_path, file_name) = split_path(&path);
-    let dir_inode = proc.lookup_inode(dir_path)?;
+    let dir_inode = proc.lookup_inode_at(dir_fd, dir_path, true)?;
     let file_inode = dir_inode.find(file_name)?;
-    if file_inode.metadata()?.type_ == FileType::Dir {
+    let info = file_inode.metadata()?;
+    proc.cred.check_delete(&dir_inode.metadata()?, &info)?;
+    let is_dir = info.type_ == FileType::Dir;
+    if flags & AT_REMOVEDIR != 0 {
+        if !is_dir {
+            return Err(SysError::ENOTDIR);
+        }
+    } else if is_dir {
         return Err(SysError::EISDIR);
     }
     dir_inode.unlink(file_name)?;
@@ -589,7 +1451,17 @@ pub fn sys_unlink(path: *const u8) -> SysResult {
 }
 
 pub fn sys_pipe(fds: *mut u32) -> SysResult {
-    info!("pipe: fds: {:?}", fds);
+    sys_pipe2(fds, 0)
+}
+
+pub fn sys_pipe2(fds: *mut u32, flags: usize) -> SysResult {
+    info!("pipe2: fds: {:?}, flags: {:#x}", fds, flags);
+    let flags = OpenFlags::from_bits(flags).ok_or(SysError::EINVAL)?;
+    if !(flags - OpenFlags::NONBLOCK - OpenFlags::CLOEXEC).is_empty() {
+        return Err(SysError::EINVAL);
+    }
+    let nonblock = flags.contains(OpenFlags::NONBLOCK);
+    let cloexec = flags.contains(OpenFlags::CLOEXEC);
 
     let mut proc = process();
     proc.vm.check_write_array(fds, 2)?;
@@ -604,9 +1476,12 @@ pub fn sys_pipe(fds: *mut u32) -> SysResult {
                 read: true,
                 write: false,
                 append: false,
+                nonblock,
             },
+            String::from("pipe"),
         )),
     );
+    proc.set_cloexec(read_fd, cloexec);
 
     let write_fd = proc.get_free_fd();
     proc.files.insert(
@@ -617,9 +1492,12 @@ pub fn sys_pipe(fds: *mut u32) -> SysResult {
                 read: false,
                 write: true,
                 append: false,
+                nonblock,
             },
+            String::from("pipe"),
         )),
     );
+    proc.set_cloexec(write_fd, cloexec);
 
     unsafe {
         *fds = read_fd as u32;
@@ -632,72 +1510,404 @@ pub fn sys_pipe(fds: *mut u32) -> SysResult {
 }
 
 pub fn sys_sync() -> SysResult {
-    ROOT_INODE.fs().sync()?;
+    sync_all()?;
+    Ok(0)
+}
+
+pub fn sys_mount(
+    source: *const u8,
+    target: *const u8,
+    fstype: *const u8,
+    flags: usize,
+    data: *const u8,
+) -> SysResult {
+    let proc = process();
+    let source = unsafe { proc.vm.check_and_clone_cstr(source)? };
+    let target = unsafe { proc.vm.check_and_clone_cstr(target)? };
+    let fstype = unsafe { proc.vm.check_and_clone_cstr(fstype)? };
+    info!(
+        "mount: source: {:?}, target: {:?}, fstype: {:?}, flags: {:#x}",
+        source, target, fstype, flags
+    );
+    if proc.cred.euid != 0 {
+        return Err(SysError::EPERM);
+    }
+    // the magic number in the upper bits is from old versions of mount
+    let flags = match flags & MS_MGC_MSK {
+        MS_MGC_VAL => flags & !MS_MGC_MSK,
+        _ => flags,
+    };
+    // no flag or option of the file systems is supported
+    if MountFlags::from_bits(flags).is_none() {
+        return Err(SysError::EINVAL);
+    }
+    if !data.is_null() {
+        let data = unsafe { proc.vm.check_and_clone_cstr(data)? };
+        if !data.is_empty() {
+            return Err(SysError::EINVAL);
+        }
+    }
+
+    let fs: Arc<FileSystem> = match fstype.as_str() {
+        "proc" => PROC_FS.clone(),
+        "devfs" => DEV_FS.clone(),
+        "tmpfs" | "ramfs" => TmpFS::new(),
+        "sfs" => SfsFS::open(proc.open_device(&source)?)?,
+        "ext2" => Ext2FS::open(proc.open_device(&source)?)?,
+        "vfat" | "fat32" => Fat32FS::open(proc.open_device(&source)?)?,
+        _ => return Err(SysError::ENODEV),
+    };
+    let target_inode = proc.lookup_inode(&target)?;
+    mount(
+        target_inode,
+        proc.absolute_path(&target),
+        fs,
+        source,
+        fstype,
+    )?;
+    Ok(0)
+}
+
+pub fn sys_umount2(target: *const u8, flags: usize) -> SysResult {
+    let proc = process();
+    let target = unsafe { proc.vm.check_and_clone_cstr(target)? };
+    info!("umount2: target: {:?}, flags: {:#x}", target, flags);
+    if proc.cred.euid != 0 {
+        return Err(SysError::EPERM);
+    }
+
+    // the root of the mounted file system hides the target
+    let root = proc.lookup_inode(&target)?;
+    let path = proc.absolute_path(&target);
+    drop(proc);
+    if has_submounts(&root)? || fs_in_use(&root, &path) {
+        return Err(SysError::EBUSY);
+    }
+    root.fs().sync()?;
+    sync_block_caches()?;
+    umount(&root)?;
     Ok(0)
 }
 
+/// Whether a process has a file open, or its working directory,
+/// in the file system of `root` mounted at `path`.
+/// The current process must not be locked.
+fn fs_in_use(root: &Arc<INode>, path: &str) -> bool {
+    let procs: Vec<_> = PROCESSES
+        .read()
+        .values()
+        .filter_map(|proc| proc.upgrade())
+        .collect();
+    let dir = format!("{}/", path);
+    procs.iter().any(|proc| {
+        let proc = proc.lock();
+        let cwd_inside = proc.cwd == path || proc.cwd.starts_with(&dir);
+        cwd_inside
+            || proc.files.values().any(|file| match file {
+                FileLike::File(file) => in_fs_of(&file.inode(), root),
+                _ => false,
+            })
+    })
+}
+
+/// Send up to `count` bytes from `in_fd` to `out_fd` through a pipe, as splice does
+///
+/// The data is copied to the pipe and from it, like read and write without the copies
+/// to user memory. The process is not locked meanwhile.
 pub fn sys_sendfile(out_fd: usize, in_fd: usize, offset: *mut usize, count: usize) -> SysResult {
     info!(
         "sendfile: out: {}, in: {}, offset: {:?}, count: {}",
         out_fd, in_fd, offset, count
     );
-    let proc = process();
-    // We know it's save, pacify the borrow checker
-    let proc_cell = UnsafeCell::new(proc);
-    let proc_in = unsafe { &mut *proc_cell.get() };
-    let proc_out = unsafe { &mut *proc_cell.get() };
-    //let in_file: &mut FileHandle = unsafe { &mut *UnsafeCell::new(proc.get_file(in_fd)?).get() };
-    //let out_file: &mut FileHandle = unsafe { &mut *UnsafeCell::new(proc.get_file(out_fd)?).get() };
-    let in_file = proc_in.get_file(in_fd)?;
-    let out_file = proc_out.get_file(out_fd)?;
-    let mut buffer = [0u8; 1024];
-    if offset.is_null() {
-        // read from current file offset
-        let mut bytes_read = 0;
-        while bytes_read < count {
-            let len = min(buffer.len(), count - bytes_read);
-            let read_len = in_file.read(&mut buffer[..len])?;
-            if read_len == 0 {
-                break;
+    let mut read_offset = process().read_offset(offset)?;
+    let (pipe_read, pipe_write) = Pipe::create_pair();
+    let pipe_read: Arc<INode> = Arc::new(pipe_read);
+    let (sent, error) = with_file_like_unlocked(in_fd, |in_file| {
+        with_file_like_unlocked(out_fd, |out_file| {
+            let mut sent = 0;
+            let mut error = None;
+            while sent < count {
+                let len = match splice_to_pipe(
+                    in_file,
+                    read_offset.as_mut(),
+                    &pipe_write,
+                    count - sent,
+                ) {
+                    Ok(len) => len,
+                    Err(err) => {
+                        error = Some(err);
+                        break;
+                    }
+                };
+                if len == 0 {
+                    break;
+                }
+                let mut written = 0;
+                while written < len {
+                    match splice_from_pipe(&pipe_read, out_file, None, len - written, false) {
+                        Ok(0) => error = Some(SysError::EBADF),
+                        Ok(write_len) => written += write_len,
+                        Err(err) => error = Some(err),
+                    }
+                    if error.is_some() {
+                        break;
+                    }
+                }
+                sent += written;
+                if written < len {
+                    // read but not written, so leave it to be read again where possible
+                    let unsent = len - written;
+                    match (read_offset.as_mut(), &mut *in_file) {
+                        (Some(read_offset), _) => *read_offset -= unsent,
+                        (None, FileLike::File(file)) => {
+                            file.seek(SeekFrom::Current(-(unsent as i64))).ok();
+                        }
+                        _ => {}
+                    }
+                    break;
+                }
+            }
+            Ok((sent, error))
+        })
+    })?;
+    process().write_offset(offset, read_offset);
+    match error {
+        // report what has been sent before the error
+        Some(err) if sent == 0 => Err(err),
+        _ => Ok(sent),
+    }
+}
+
+pub fn sys_splice(
+    fd_in: usize,
+    off_in: *mut usize,
+    fd_out: usize,
+    off_out: *mut usize,
+    len: usize,
+    flags: usize,
+) -> SysResult {
+    info!(
+        "splice: fd_in: {}, off_in: {:?}, fd_out: {}, off_out: {:?}, len: {}, flags: {:#x}",
+        fd_in, off_in, fd_out, off_out, len, flags
+    );
+    let mut proc = process();
+    let pipe_in = proc.get_pipe(fd_in, true)?;
+    let pipe_out = proc.get_pipe(fd_out, false)?;
+    let nonblock = flags & SPLICE_F_NONBLOCK != 0;
+    match (pipe_in, pipe_out) {
+        (Some(pipe_in), Some(pipe_out)) => {
+            if !off_in.is_null() || !off_out.is_null() {
+                return Err(SysError::ESPIPE);
+            }
+            if as_pipe(&pipe_in).same_pipe(as_pipe(&pipe_out)) {
+                return Err(SysError::EINVAL);
             }
-            bytes_read += read_len;
-            let mut bytes_written = 0;
-            while bytes_written < read_len {
-                let write_len = out_file.write(&buffer[bytes_written..])?;
-                if write_len == 0 {
-                    return Err(SysError::EBADF);
+            drop(proc);
+            loop {
+                wait_pipe(&pipe_in, nonblock, |status| status.read)?;
+                wait_pipe(&pipe_out, nonblock, |status| status.write)?;
+                // read by others meanwhile
+                if let Some(len) = as_pipe(&pipe_in).move_to(as_pipe(&pipe_out), len) {
+                    return Ok(len);
                 }
-                bytes_written += write_len;
             }
         }
-        return Ok(bytes_read);
+        (None, Some(pipe)) => {
+            if !off_out.is_null() {
+                return Err(SysError::ESPIPE);
+            }
+            let mut offset = proc.read_offset(off_in)?;
+            drop(proc);
+            wait_pipe(&pipe, nonblock, |status| status.write)?;
+            let len = with_file_like_unlocked(fd_in, |file_like| {
+                splice_to_pipe(file_like, offset.as_mut(), as_pipe(&pipe), len)
+            })?;
+            process().write_offset(off_in, offset);
+            Ok(len)
+        }
+        (Some(pipe), None) => {
+            if !off_in.is_null() {
+                return Err(SysError::ESPIPE);
+            }
+            let mut offset = proc.read_offset(off_out)?;
+            drop(proc);
+            let len = with_file_like_unlocked(fd_out, |file_like| {
+                splice_from_pipe(&pipe, file_like, offset.as_mut(), len, nonblock)
+            })?;
+            process().write_offset(off_out, offset);
+            Ok(len)
+        }
+        (None, None) => Err(SysError::EINVAL),
+    }
+}
+
+/// Copy data from one pipe to another, leaving it in the first
+pub fn sys_tee(fd_in: usize, fd_out: usize, len: usize, flags: usize) -> SysResult {
+    info!(
+        "tee: fd_in: {}, fd_out: {}, len: {}, flags: {:#x}",
+        fd_in, fd_out, len, flags
+    );
+    let mut proc = process();
+    let pipe_in = proc.get_pipe(fd_in, true)?.ok_or(SysError::EINVAL)?;
+    let pipe_out = proc.get_pipe(fd_out, false)?.ok_or(SysError::EINVAL)?;
+    if as_pipe(&pipe_in).same_pipe(as_pipe(&pipe_out)) {
+        return Err(SysError::EINVAL);
+    }
+    drop(proc);
+    let nonblock = flags & SPLICE_F_NONBLOCK != 0;
+    wait_pipe(&pipe_in, nonblock, |status| status.read)?;
+    wait_pipe(&pipe_out, nonblock, |status| status.write)?;
+    Ok(as_pipe(&pipe_in).copy_to(as_pipe(&pipe_out), len))
+}
+
+/// Copy user memory to a pipe, or from a pipe opened for reading
+pub fn sys_vmsplice(fd: usize, iov_ptr: *const IoVec, iov_count: usize, flags: usize) -> SysResult {
+    info!(
+        "vmsplice: fd: {}, iov: {:?}, count: {}, flags: {:#x}",
+        fd, iov_ptr, iov_count, flags
+    );
+    let mut proc = process();
+    let writing = proc.get_file(fd)?.options().write;
+    let pipe = proc.get_pipe(fd, !writing)?.ok_or(SysError::EBADF)?;
+    let mut iovs = IoVecs::check_and_new(iov_ptr, iov_count, &proc.vm, !writing)?;
+    drop(proc);
+    let nonblock = flags & SPLICE_F_NONBLOCK != 0;
+    let pipe_ref = as_pipe(&pipe);
+    if writing {
+        wait_pipe(&pipe, nonblock, |status| status.write)?;
+        let mut buf = iovs.read_all_to_vec();
+        buf.truncate(pipe_ref.free());
+        Ok(pipe_ref.push_chunks(vec![PipeChunk::new(buf)]))
     } else {
-        let proc_mem = unsafe { &mut *proc_cell.get() };
-        proc_mem.vm.check_read_ptr(offset)?;
-        let mut read_offset = unsafe { *offset };
-        // read from specified offset and write new offset back
-        let mut bytes_read = 0;
-        while bytes_read < count {
-            let len = min(buffer.len(), count - bytes_read);
-            let read_len = in_file.read_at(read_offset, &mut buffer[..len])?;
-            if read_len == 0 {
+        let mut buf = iovs.new_buf(true);
+        let mut len = 0;
+        for chunk in begin_read_pipe(&pipe, nonblock, buf.len())? {
+            buf[len..len + chunk.len()].copy_from_slice(chunk.as_slice());
+            len += chunk.len();
+        }
+        pipe_ref.end_read(len);
+        iovs.write_all_from_slice(&buf[..len]);
+        Ok(len)
+    }
+}
+
+fn as_pipe(inode: &Arc<INode>) -> &Pipe {
+    inode.as_any_ref().downcast_ref::<Pipe>().unwrap()
+}
+
+/// Wait without the process lock until `ready` holds for `pipe`,
+/// or fail with EAGAIN if `nonblock`
+///
+/// Raise SIGPIPE if it has no readers.
+fn wait_pipe(
+    pipe: &Arc<INode>,
+    nonblock: bool,
+    ready: impl Fn(PollStatus) -> bool,
+) -> Result<(), SysError> {
+    loop {
+        let status = poll_inode(&**pipe);
+        if status.error {
+            raise_signal(SIGPIPE);
+            return Err(SysError::EPIPE);
+        }
+        if ready(status) {
+            return Ok(());
+        }
+        if nonblock {
+            return Err(SysError::EAGAIN);
+        }
+        match inode_wait_queue(&**pipe) {
+            Some(queue) => queue.wait_unless(|| {
+                let status = poll_inode(&**pipe);
+                status.error || ready(status)
+            }),
+            None => return Ok(()),
+        }
+    }
+}
+
+/// Move up to `len` bytes from a file or socket to `pipe`, as much as it has room for
+///
+/// Read at `offset` and advance it if given.
+fn splice_to_pipe(
+    file_like: &mut FileLike,
+    offset: Option<&mut usize>,
+    pipe: &Pipe,
+    len: usize,
+) -> SysResult {
+    let mut buf = vec![0u8; min(len, pipe.free())];
+    let len = match offset {
+        Some(offset) => {
+            let len = match file_like {
+                FileLike::File(file) => file.read_at(*offset, &mut buf)?,
+                _ => return Err(SysError::ESPIPE),
+            };
+            *offset += len;
+            len
+        }
+        None => file_like.read(&mut buf)?,
+    };
+    buf.truncate(len);
+    Ok(pipe.push_chunks(vec![PipeChunk::new(buf)]))
+}
+
+/// Wait until `pipe` is readable and start reading up to `len` bytes by `Pipe::begin_read`
+fn begin_read_pipe(
+    pipe: &Arc<INode>,
+    nonblock: bool,
+    len: usize,
+) -> Result<Vec<PipeChunk>, SysError> {
+    loop {
+        wait_pipe(pipe, nonblock, |status| status.read)?;
+        // read by others meanwhile
+        if let Some(chunks) = as_pipe(pipe).begin_read(len) {
+            return Ok(chunks);
+        }
+    }
+}
+
+/// Move up to `len` bytes from `pipe` to a file or socket, writing from the pages in the pipe
+///
+/// Write at `offset` and advance it if given. Only what is written is removed from the pipe,
+/// others can't read it meanwhile.
+fn splice_from_pipe(
+    pipe: &Arc<INode>,
+    file_like: &mut FileLike,
+    mut offset: Option<&mut usize>,
+    len: usize,
+    nonblock: bool,
+) -> SysResult {
+    let mut written = 0;
+    let mut error = None;
+    for chunk in begin_read_pipe(pipe, nonblock, len)? {
+        let ret = match offset.as_mut() {
+            Some(offset) => match file_like {
+                FileLike::File(file) => file
+                    .write_at(**offset, chunk.as_slice())
+                    .map_err(SysError::from),
+                _ => Err(SysError::ESPIPE),
+            },
+            None => file_like.write(chunk.as_slice()),
+        };
+        let len = match ret {
+            Ok(len) => len,
+            Err(err) => {
+                error = Some(err);
                 break;
             }
-            bytes_read += read_len;
-            read_offset += read_len;
-            let mut bytes_written = 0;
-            while bytes_written < read_len {
-                let write_len = out_file.write(&buffer[bytes_written..])?;
-                if write_len == 0 {
-                    return Err(SysError::EBADF);
-                }
-                bytes_written += write_len;
-            }
+        };
+        if let Some(offset) = offset.as_mut() {
+            **offset += len;
         }
-        unsafe {
-            *offset = read_offset;
+        written += len;
+        if len < chunk.len() {
+            break;
         }
-        return Ok(bytes_read);
+    }
+    as_pipe(pipe).end_read(written);
+    match error {
+        Some(err) if written == 0 => Err(err),
+        _ => Ok(written),
     }
 }
 
@@ -705,6 +1915,94 @@ impl Process {
     pub fn get_file_like(&mut self, fd: usize) -> Result<&mut FileLike, SysError> {
         self.files.get_mut(&fd).ok_or(SysError::EBADF)
     }
+    /// Remove `fd` from the file table along with its descriptor flags
+    pub fn close_file(&mut self, fd: usize) -> Result<FileLike, SysError> {
+        self.cloexec_fds.remove(&fd);
+        let file_like = self.files.remove(&fd).ok_or(SysError::EBADF)?;
+        if let FileLike::File(file) = &file_like {
+            // closing any fd of the file releases the record locks of the process
+            unlock_records(&file.inode(), self.pid.get());
+        }
+        // the fd may be reused for another file, which must not inherit the interests
+        for other in self.files.values() {
+            if let FileLike::Epoll(epoll) = other {
+                epoll.delete(fd).ok();
+            }
+        }
+        Ok(file_like)
+    }
+    /// Close all fds by `close_file` when the process exits
+    ///
+    /// The files are returned to be dropped once the process is unlocked.
+    pub fn close_all_files(&mut self) -> Vec<FileLike> {
+        let fds: Vec<usize> = self.files.keys().cloned().collect();
+        fds.into_iter()
+            .filter_map(|fd| self.close_file(fd).ok())
+            .collect()
+    }
+    pub fn set_cloexec(&mut self, fd: usize, cloexec: bool) {
+        match cloexec {
+            true => self.cloexec_fds.insert(fd),
+            false => self.cloexec_fds.remove(&fd),
+        };
+    }
+    /// Install a file without a path, such as an eventfd, readable and writable
+    pub fn add_anon_file(
+        &mut self,
+        inode: Arc<INode>,
+        name: &str,
+        nonblock: bool,
+        cloexec: bool,
+    ) -> usize {
+        let options = OpenOptions {
+            read: true,
+            write: true,
+            append: false,
+            nonblock,
+        };
+        let path = format!("anon_inode:[{}]", name);
+        let fd = self.get_free_fd();
+        self.files
+            .insert(fd, FileLike::File(FileHandle::new(inode, options, path)));
+        self.set_cloexec(fd, cloexec);
+        fd
+    }
+    fn get_epoll(&mut self, fd: usize) -> Result<Arc<Epoll>, SysError> {
+        match self.get_file_like(fd)? {
+            FileLike::Epoll(epoll) => Ok(epoll.clone()),
+            _ => Err(SysError::EINVAL),
+        }
+    }
+    /// The INode of `fd` if it is a pipe, checking it is open for reading or writing
+    fn get_pipe(&mut self, fd: usize, read: bool) -> Result<Option<Arc<INode>>, SysError> {
+        let file = match self.get_file_like(fd)? {
+            FileLike::File(file) => file,
+            _ => return Ok(None),
+        };
+        if (read && !file.options().read) || (!read && !file.options().write) {
+            return Err(SysError::EBADF);
+        }
+        let inode = file.inode();
+        match inode.as_any_ref().downcast_ref::<Pipe>() {
+            Some(_) => Ok(Some(inode)),
+            None => Ok(None),
+        }
+    }
+    /// Read the offset at `ptr`, or `None` if it is null
+    fn read_offset(&self, ptr: *mut usize) -> Result<Option<usize>, SysError> {
+        if ptr.is_null() {
+            return Ok(None);
+        }
+        self.vm.check_read_ptr(ptr)?;
+        self.vm.check_write_ptr(ptr)?;
+        Ok(Some(unsafe { ptr.read() }))
+    }
+    /// Write back an offset got by `read_offset`
+    fn write_offset(&self, ptr: *mut usize, offset: Option<usize>) {
+        if let Some(offset) = offset {
+            unsafe { ptr.write(offset) };
+        }
+    }
     pub fn get_file(&mut self, fd: usize) -> Result<&mut FileHandle, SysError> {
         match self.get_file_like(fd)? {
             FileLike::File(file) => Ok(file),
@@ -712,10 +2010,108 @@ impl Process {
         }
     }
     pub fn lookup_inode(&self, path: &str) -> Result<Arc<INode>, SysError> {
-        debug!("lookup_inode: cwd {} path {}", self.cwd, path);
-        Ok(ROOT_INODE
-            .lookup(&self.cwd)?
-            .lookup_follow(path, FOLLOW_MAX_DEPTH)?)
+        self.lookup_inode_at(AT_FDCWD, path, true)
+    }
+    /// Lookup `path` relative to the directory `dir_fd`, or cwd if it is `AT_FDCWD`,
+    /// following the last symlink if `follow`
+    pub fn lookup_inode_at(
+        &self,
+        dir_fd: usize,
+        path: &str,
+        follow: bool,
+    ) -> Result<Arc<INode>, SysError> {
+        debug!(
+            "lookup_inode_at: dir_fd {} cwd {} path {}",
+            dir_fd as isize, self.cwd, path
+        );
+        if path.is_empty() {
+            return Err(SysError::ENOENT);
+        }
+        let dir = if path.starts_with('/') {
+            ROOT_INODE.clone()
+        } else {
+            self.dir_fd_inode(dir_fd)?
+        };
+        lookup_at(&dir, path, follow, &self.cred, Some(self))
+    }
+    /// Get the INode of `dir_fd`, or cwd if it is `AT_FDCWD`
+    fn dir_fd_inode(&self, dir_fd: usize) -> Result<Arc<INode>, SysError> {
+        if dir_fd == AT_FDCWD {
+            return lookup_at(&ROOT_INODE, &self.cwd, true, &self.cred, Some(self));
+        }
+        match self.files.get(&dir_fd) {
+            Some(FileLike::File(file)) => Ok(file.inode()),
+            Some(_) => Err(SysError::ENOTDIR),
+            None => Err(SysError::EBADF),
+        }
+    }
+    /// Check the permission to open an existing `inode` with `flags`
+    fn check_open(&self, inode: &Arc<INode>, flags: OpenFlags) -> Result<(), SysError> {
+        let info = inode.metadata()?;
+        if flags.contains(OpenFlags::NOFOLLOW) && info.type_ == FileType::SymLink {
+            return Err(SysError::ELOOP);
+        }
+        let mut access = Access::empty();
+        if flags.readable() {
+            access |= Access::READ;
+        }
+        if flags.writable() || flags.contains(OpenFlags::TRUNCATE) {
+            if info.type_ == FileType::Dir {
+                return Err(SysError::EISDIR);
+            }
+            access |= Access::WRITE;
+        }
+        self.cred.check(&info, access)
+    }
+    /// Create `name` in directory `dir`, owned by the process
+    fn create_inode(
+        &self,
+        dir: &Arc<INode>,
+        name: &str,
+        type_: FileType,
+        mode: u16,
+    ) -> Result<Arc<INode>, SysError> {
+        let dir_info = dir.metadata()?;
+        self.cred.check(&dir_info, Access::WRITE | Access::EXEC)?;
+        let inode = dir.create(name, type_, mode as u32)?;
+        // files in a set-group-ID directory belong to its group
+        let gid = match dir_info.mode & S_ISGID {
+            0 => self.cred.fsgid,
+            _ => dir_info.gid,
+        };
+        match chown(&inode, self.cred.fsuid, gid) {
+            // it doesn't record owners
+            Ok(()) | Err(FsError::NotSupported) => Ok(inode),
+            Err(e) => Err(SysError::from(e)),
+        }
+    }
+    /// Get the absolute form of `path` relative to cwd
+    pub fn absolute_path(&self, path: &str) -> String {
+        if path.starts_with('/') {
+            return String::from(path);
+        }
+        let mut abs = self.cwd.clone();
+        if !abs.ends_with('/') {
+            abs += "/";
+        }
+        abs += path;
+        abs
+    }
+    /// Get the device of a block source to mount:
+    /// a block driver named like `/dev/vda`, or an image file as loop device
+    fn open_device(&self, source: &str) -> Result<Arc<Device>, SysError> {
+        // /dev/vda is the first block driver, /dev/vdb the second, and so on
+        if let [b'/', b'd', b'e', b'v', b'/', b'v', b'd', letter] = source.as_bytes() {
+            if let b'a'..=b'z' = letter {
+                let index = (letter - b'a') as usize;
+                return blk_device(index).ok_or(SysError::ENXIO);
+            }
+        }
+        let inode = self.lookup_inode(source)?;
+        if inode.metadata()?.type_ != FileType::File {
+            return Err(SysError::ENOTBLK);
+        }
+        Ok(Arc::new(LoopDevice::new(inode)))
     }
 }
 
@@ -766,9 +2162,20 @@ bitflags! {
         const TRUNCATE = 1 << 9;
         /// append on each write
         const APPEND = 1 << 10;
+        /// reads and writes fail with EAGAIN instead of waiting
+        const NONBLOCK = 1 << 11;
+        /// fail if the last component of the path is a symlink
+        const NOFOLLOW = O_NOFOLLOW;
+        /// close on exec
+        const CLOEXEC = 1 << 19;
     }
 }
 
+#[cfg(target_arch = "aarch64")]
+const O_NOFOLLOW: usize = 0o100000;
+#[cfg(not(target_arch = "aarch64"))]
+const O_NOFOLLOW: usize = 0o400000;
+
 impl OpenFlags {
     fn readable(&self) -> bool {
         let b = self.bits() & 0b11;
@@ -783,8 +2190,19 @@ impl OpenFlags {
             read: self.readable(),
             write: self.writable(),
             append: self.contains(OpenFlags::APPEND),
+            nonblock: self.contains(OpenFlags::NONBLOCK),
         }
     }
+    fn from_options(options: &OpenOptions) -> Self {
+        let mut flags = match (options.read, options.write) {
+            (true, true) => OpenFlags::RDWR,
+            (false, true) => OpenFlags::WRONLY,
+            _ => OpenFlags::RDONLY,
+        };
+        flags.set(OpenFlags::APPEND, options.append);
+        flags.set(OpenFlags::NONBLOCK, options.nonblock);
+        flags
+    }
 }
 
 #[derive(Debug)]
@@ -951,6 +2369,18 @@ pub struct Stat {
     ctime: Timespec,
 }
 
+bitflags! {
+    struct MountFlags: usize {
+        /// Don't print some warnings
+        const SILENT = 1 << 15;
+    }
+}
+
+/// Mask of the magic number in the flags of mount
+const MS_MGC_MSK: usize = 0xffff_0000;
+/// Magic number in the flags of mount, ignored
+const MS_MGC_VAL: usize = 0xc0ed_0000;
+
 bitflags! {
     pub struct StatMode: u32 {
         const NULL  = 0;
@@ -975,6 +2405,8 @@ bitflags! {
         const SET_UID = 0o4000;
         /// Set-group-ID on execution.
         const SET_GID = 0o2000;
+        /// Restricted deletion in a directory.
+        const STICKY = 0o1000;
 
         /// Read, write, execute/search by owner.
         const OWNER_MASK = 0o700;
@@ -1064,10 +2496,149 @@ impl From<Metadata> for Stat {
     }
 }
 
+/// `struct statfs` with words of the native size
+#[repr(C)]
+#[derive(Debug)]
+pub struct StatFs {
+    /// type of file system
+    type_: usize,
+    /// optimal transfer block size
+    bsize: usize,
+    /// total data blocks in file system
+    blocks: usize,
+    /// free blocks in file system
+    bfree: usize,
+    /// free blocks available to unprivileged user
+    bavail: usize,
+    /// total inodes in file system
+    files: usize,
+    /// free inodes in file system
+    ffree: usize,
+    /// file system ID
+    fsid: [u32; 2],
+    /// maximum length of filenames
+    namelen: usize,
+    /// fragment size
+    frsize: usize,
+    /// mount flags of file system
+    flags: usize,
+    /// padding
+    spare: [usize; 4],
+}
+
+impl From<FsStat> for StatFs {
+    fn from(stat: FsStat) -> Self {
+        StatFs {
+            type_: stat.magic,
+            bsize: stat.block_size,
+            blocks: stat.blocks,
+            bfree: stat.blocks_free,
+            bavail: stat.blocks_avail,
+            files: stat.files,
+            ffree: stat.files_free,
+            fsid: [0; 2],
+            namelen: stat.name_max,
+            frsize: stat.block_size,
+            flags: 0,
+            spare: [0; 4],
+        }
+    }
+}
+
+/// `struct flock` describing a record lock for fcntl
+#[repr(C)]
+#[derive(Debug)]
+pub struct Flock {
+    type_: i16,
+    whence: i16,
+    start: i64,
+    /// 0 for the bytes until the end of the file, or negative for the ones before `start`
+    len: i64,
+    pid: i32,
+}
+
+impl Flock {
+    /// The bytes `start..end` of `file` covered by the lock
+    fn range(&self, file: &mut FileHandle) -> Result<(u64, u64), SysError> {
+        let base = match self.whence as u8 {
+            SEEK_SET => 0,
+            SEEK_CUR => file.seek(SeekFrom::Current(0))? as i64,
+            SEEK_END => file.metadata()?.size as i64,
+            _ => return Err(SysError::EINVAL),
+        };
+        let start = base.checked_add(self.start).ok_or(SysError::EINVAL)?;
+        let (start, end) = match self.len {
+            0 => (start, None),
+            len if len > 0 => (start, start.checked_add(len)),
+            len => (start.checked_add(len).ok_or(SysError::EINVAL)?, Some(start)),
+        };
+        if start < 0 {
+            return Err(SysError::EINVAL);
+        }
+        let end = match end {
+            Some(end) => end as u64,
+            None => u64::max_value(),
+        };
+        Ok((start as u64, end))
+    }
+
+    /// Report `lock` for F_GETLK
+    fn describe(&mut self, lock: &RecordLock) {
+        self.type_ = if lock.write { F_WRLCK } else { F_RDLCK };
+        self.whence = SEEK_SET as i16;
+        self.start = lock.start as i64;
+        self.len = match lock.end {
+            end if end == u64::max_value() => 0,
+            end => (end - lock.start) as i64,
+        };
+        self.pid = lock.pid as i32;
+    }
+}
+
 const SEEK_SET: u8 = 0;
 const SEEK_CUR: u8 = 1;
 const SEEK_END: u8 = 2;
 
+const F_DUPFD: usize = 0;
+const F_GETFD: usize = 1;
+const F_SETFD: usize = 2;
+const F_GETFL: usize = 3;
+const F_SETFL: usize = 4;
+const F_GETLK: usize = 5;
+const F_SETLK: usize = 6;
+const F_SETLKW: usize = 7;
+const F_DUPFD_CLOEXEC: usize = 1030;
+const F_SETPIPE_SZ: usize = 1031;
+const F_GETPIPE_SZ: usize = 1032;
+
+const FD_CLOEXEC: usize = 1;
+
+const F_RDLCK: i16 = 0;
+const F_WRLCK: i16 = 1;
+const F_UNLCK: i16 = 2;
+
+const LOCK_SH: usize = 1;
+const LOCK_EX: usize = 2;
+/// Fail with EWOULDBLOCK instead of waiting for the lock
+const LOCK_NB: usize = 4;
+const LOCK_UN: usize = 8;
+
+/// Fail with EAGAIN instead of waiting for pipes
+const SPLICE_F_NONBLOCK: usize = 2;
+
+const EPOLL_CLOEXEC: usize = 0o2000000;
+const EPOLL_CTL_ADD: usize = 1;
+const EPOLL_CTL_DEL: usize = 2;
+const EPOLL_CTL_MOD: usize = 3;
+
+/// Reads take 1 from the counter instead of all of it
+const EFD_SEMAPHORE: usize = 1;
+const EFD_NONBLOCK: usize = 0o4000;
+const EFD_CLOEXEC: usize = 0o2000000;
+
+const SFD_NONBLOCK: usize = 0o4000;
+const SFD_CLOEXEC: usize = 0o2000000;
+
 #[derive(Debug, Copy, Clone)]
 #[repr(C)]
 pub struct IoVec {
@@ -1224,3 +2795,9 @@ impl FdSet {
 }
 
 const AT_FDCWD: usize = -100isize as usize;
+const AT_SYMLINK_NOFOLLOW: usize = 0x100;
+const AT_REMOVEDIR: usize = 0x200;
+/// Check `faccessat` with the effective ids, the same value as `AT_REMOVEDIR`
+const AT_EACCESS: usize = 0x200;
+const AT_SYMLINK_FOLLOW: usize = 0x400;
+const AT_EMPTY_PATH: usize = 0x1000;
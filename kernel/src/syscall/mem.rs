@@ -1,8 +1,6 @@
-use rcore_memory::memory_set::handler::{ByFrame, Delay};
+use rcore_memory::memory_set::handler::{ByFrame, Delay, Huge};
 use rcore_memory::memory_set::MemoryAttr;
-use rcore_memory::paging::PageTable;
-use rcore_memory::Page;
-use rcore_memory::PAGE_SIZE;
+use rcore_memory::{HUGE_PAGE_SIZE, PAGE_SIZE};
 
 use crate::memory::GlobalFrameAlloc;
 
@@ -10,7 +8,7 @@ use super::*;
 
 pub fn sys_mmap(
     mut addr: usize,
-    len: usize,
+    mut len: usize,
     prot: usize,
     flags: usize,
     fd: usize,
@@ -31,17 +29,51 @@ pub fn sys_mmap(
         addr = PAGE_SIZE;
     }
 
+    // huge pages are only used by anonymous memory, aligned to the huge page size
+    let huge = flags.contains(MmapFlags::HUGETLB);
+    let align = if huge {
+        if !flags.contains(MmapFlags::ANONYMOUS) {
+            return Err(SysError::EINVAL);
+        }
+        HUGE_PAGE_SIZE
+    } else {
+        PAGE_SIZE
+    };
+    len = (len + align - 1) & !(align - 1);
+
     if flags.contains(MmapFlags::FIXED) {
+        if addr % align != 0 {
+            return Err(SysError::EINVAL);
+        }
         // we have to map it to addr, so remove the old mapping first
+        if !proc.vm.can_split_at(addr) || !proc.vm.can_split_at(addr + len) {
+            return Err(SysError::EINVAL);
+        }
         proc.vm.pop_with_split(addr, addr + len);
     } else {
-        addr = proc.vm.find_free_area(addr, len);
+        addr = proc.vm.find_free_area_aligned(addr, len, align);
     }
 
     if flags.contains(MmapFlags::ANONYMOUS) {
         if flags.contains(MmapFlags::SHARED) {
             return Err(SysError::EINVAL);
         }
+        if huge {
+            proc.vm.push(
+                addr,
+                addr + len,
+                prot.to_attr(),
+                Huge::new(GlobalFrameAlloc),
+                "mmap_huge",
+            );
+            // huge frames are left not present when they run out
+            let resident = proc.vm.resident_pages(addr, addr + len).unwrap();
+            if resident.iter().any(|&present| !present) {
+                proc.vm.pop(addr, addr + len);
+                return Err(SysError::ENOMEM);
+            }
+            return Ok(addr);
+        }
         proc.vm.push(
             addr,
             addr + len,
@@ -73,43 +105,154 @@ pub fn sys_mmap(
     }
 }
 
-pub fn sys_mprotect(addr: usize, len: usize, prot: usize) -> SysResult {
+pub fn sys_mprotect(addr: usize, mut len: usize, prot: usize) -> SysResult {
     let prot = MmapProt::from_bits_truncate(prot);
     info!(
         "mprotect: addr={:#x}, size={:#x}, prot={:?}",
         addr, len, prot
     );
 
+    if addr % PAGE_SIZE != 0 {
+        return Err(SysError::EINVAL);
+    }
+    len = (len + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
     let mut proc = process();
     let attr = prot.to_attr();
+    // huge pages can't be protected partly
+    if !proc.vm.can_split_at(addr) || !proc.vm.can_split_at(addr + len) {
+        return Err(SysError::EINVAL);
+    }
 
-    // FIXME: properly set the attribute of the area
-    //        now some mut ptr check is fault
-    let memory_area = proc
-        .vm
-        .iter()
-        .find(|area| area.is_overlap_with(addr, addr + len));
-    if memory_area.is_none() {
+    // areas are split at `addr` and `addr + len` to hold the new attribute,
+    // which makes PROT_NONE guard pages of thread stacks inaccessible
+    if !proc.vm.protect(addr, addr + len, attr) {
         return Err(SysError::ENOMEM);
     }
-    proc.vm.edit(|pt| {
-        for page in Page::range_of(addr, addr + len) {
-            let entry = pt
-                .get_entry(page.start_address())
-                .expect("failed to get entry");
-            attr.apply(entry);
-        }
-    });
     Ok(0)
 }
 
-pub fn sys_munmap(addr: usize, len: usize) -> SysResult {
+pub fn sys_munmap(addr: usize, mut len: usize) -> SysResult {
     info!("munmap addr={:#x}, size={:#x}", addr, len);
+    if addr % PAGE_SIZE != 0 {
+        return Err(SysError::EINVAL);
+    }
+    len = (len + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
     let mut proc = process();
+    // huge pages can't be unmapped partly
+    if !proc.vm.can_split_at(addr) || !proc.vm.can_split_at(addr + len) {
+        return Err(SysError::EINVAL);
+    }
     proc.vm.pop_with_split(addr, addr + len);
     Ok(0)
 }
 
+pub fn sys_mremap(
+    old_addr: usize,
+    old_size: usize,
+    new_size: usize,
+    flags: usize,
+    new_addr: usize,
+) -> SysResult {
+    let flags = MremapFlags::from_bits_truncate(flags);
+    info!(
+        "mremap: old_addr={:#x}, old_size={:#x}, new_size={:#x}, flags={:?}, new_addr={:#x}",
+        old_addr, old_size, new_size, flags, new_addr
+    );
+    if old_addr % PAGE_SIZE != 0 || new_size == 0 {
+        return Err(SysError::EINVAL);
+    }
+    let fixed = if flags.contains(MremapFlags::FIXED) {
+        if !flags.contains(MremapFlags::MAYMOVE) || new_addr % PAGE_SIZE != 0 {
+            return Err(SysError::EINVAL);
+        }
+        Some(new_addr)
+    } else {
+        None
+    };
+    let mut proc = process();
+    let may_move = flags.contains(MremapFlags::MAYMOVE);
+    let addr = proc
+        .vm
+        .remap(old_addr, old_size, new_size, fixed, may_move)?;
+    Ok(addr)
+}
+
+pub fn sys_madvise(addr: usize, len: usize, advice: usize) -> SysResult {
+    info!(
+        "madvise: addr={:#x}, size={:#x}, advice={}",
+        addr, len, advice
+    );
+    if addr % PAGE_SIZE != 0 {
+        return Err(SysError::EINVAL);
+    }
+    if len == 0 {
+        return Ok(0);
+    }
+    let mut proc = process();
+    match advice {
+        MADV_DONTNEED => proc.vm.discard(addr, addr + len),
+        MADV_WILLNEED => proc.vm.prefault(addr, addr + len),
+        // other advices are only hints
+        _ => {}
+    }
+    Ok(0)
+}
+
+pub fn sys_mincore(addr: usize, len: usize, vec: *mut u8) -> SysResult {
+    info!("mincore: addr={:#x}, size={:#x}, vec={:?}", addr, len, vec);
+    if addr % PAGE_SIZE != 0 {
+        return Err(SysError::EINVAL);
+    }
+    if len == 0 {
+        return Ok(0);
+    }
+    let mut proc = process();
+    let pages = (len + PAGE_SIZE - 1) / PAGE_SIZE;
+    proc.vm.check_write_array(vec, pages)?;
+    let resident = proc
+        .vm
+        .resident_pages(addr, addr + len)
+        .ok_or(SysError::ENOMEM)?;
+    let vec = unsafe { slice::from_raw_parts_mut(vec, pages) };
+    for (v, &present) in vec.iter_mut().zip(resident.iter()) {
+        *v = present as u8;
+    }
+    Ok(0)
+}
+
+/// Set the program break to `addr`, growing or shrinking the "heap" area.
+/// Return the new break, or the current one if `addr` is invalid or out of memory.
+pub fn sys_brk(addr: usize) -> SysResult {
+    info!("brk: addr: {:#x}", addr);
+    let mut proc = process();
+    if addr < proc.brk_start {
+        // brk(0) is used to query the current break
+        return Ok(proc.brk);
+    }
+    let heap_start = proc.brk_start;
+    let old_end = (proc.brk + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+    let new_end = (addr + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+    if old_end == heap_start {
+        // the heap area does not exist yet
+        if new_end > heap_start {
+            if !proc.vm.test_free_area(heap_start, new_end) {
+                return Ok(proc.brk);
+            }
+            proc.vm.push(
+                heap_start,
+                new_end,
+                MemoryAttr::default().user(),
+                Delay::new(GlobalFrameAlloc),
+                "heap",
+            );
+        }
+    } else if !proc.vm.resize(heap_start, new_end) {
+        return Ok(proc.brk);
+    }
+    proc.brk = addr;
+    Ok(addr)
+}
+
 bitflags! {
     pub struct MmapProt: usize {
         /// Data cannot be accessed
@@ -133,11 +276,31 @@ bitflags! {
         const FIXED = 1 << 4;
         /// The mapping is not backed by any file. (non-POSIX)
         const ANONYMOUS = 1 << 5;
+        /// Allocate the mapping using huge pages
+        const HUGETLB = 1 << 18;
     }
 }
 
+bitflags! {
+    pub struct MremapFlags: usize {
+        /// The mapping can be moved to a new address
+        const MAYMOVE = 1 << 0;
+        /// Move the mapping to `new_addr`, which implies MAYMOVE
+        const FIXED = 1 << 1;
+    }
+}
+
+/// Expect access in the near future
+const MADV_WILLNEED: usize = 3;
+/// Do not expect access in the near future, the pages can be freed
+const MADV_DONTNEED: usize = 4;
+
 impl MmapProt {
     fn to_attr(self) -> MemoryAttr {
+        if self == MmapProt::NONE {
+            // not accessible from user mode
+            return MemoryAttr::default();
+        }
         let mut attr = MemoryAttr::default().user();
         if self.contains(MmapProt::EXEC) {
             attr = attr.execute();
@@ -1,5 +1,6 @@
 use super::*;
 use crate::arch::cpu;
+use core::cmp::min;
 use core::mem::size_of;
 use core::sync::atomic::{AtomicI32, Ordering};
 use crate::consts::USER_STACK_SIZE;
@@ -148,19 +149,36 @@ pub fn sys_prlimit64(
     new_limit: *const RLimit,
     old_limit: *mut RLimit,
 ) -> SysResult {
-    let proc = process();
+    let mut proc = process();
     info!(
         "prlimit64: pid: {}, resource: {}, new_limit: {:x?}, old_limit: {:x?}",
         pid, resource, new_limit, old_limit
     );
     match resource {
         RLIMIT_STACK => {
+            let (cur, max) = proc.stack_limit;
+            if !new_limit.is_null() {
+                proc.vm.check_read_ptr(new_limit)?;
+                let new = unsafe { &*new_limit };
+                if new.cur > new.max {
+                    return Err(SysError::EINVAL);
+                }
+                // the address space reserved for the stack can't grow,
+                // larger limits like RLIM_INFINITY are clamped to it
+                let new_cur = min(new.cur, USER_STACK_SIZE as u64) as usize;
+                let new_max = min(new.max, USER_STACK_SIZE as u64) as usize;
+                if new_max > max && proc.cred.euid != 0 {
+                    return Err(SysError::EPERM);
+                }
+                proc.stack_limit = (new_cur, new_max);
+                proc.reserve_stack();
+            }
             if !old_limit.is_null() {
                 proc.vm.check_write_ptr(old_limit)?;
                 unsafe {
                     *old_limit = RLimit {
-                        cur: USER_STACK_SIZE as u64,
-                        max: USER_STACK_SIZE as u64,
+                        cur: cur as u64,
+                        max: max as u64,
                     };
                 }
             }
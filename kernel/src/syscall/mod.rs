@@ -10,6 +10,7 @@ use rcore_memory::VMError;
 use crate::arch::cpu;
 use crate::arch::interrupt::TrapFrame;
 use crate::arch::syscall::*;
+use crate::fs::EpollEvent;
 use crate::process::*;
 use crate::sync::Condvar;
 use crate::thread;
@@ -23,6 +24,9 @@ use self::net::*;
 use self::proc::*;
 use self::time::*;
 
+pub use self::proc::sys_exit_group;
+pub use self::time::current_time;
+
 mod custom;
 mod fs;
 mod mem;
@@ -58,17 +62,14 @@ pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> isize {
         // 10
         SYS_MPROTECT => sys_mprotect(args[0], args[1], args[2]),
         SYS_MUNMAP => sys_munmap(args[0], args[1]),
-        SYS_BRK => {
-            warn!("sys_brk is unimplemented");
-            Ok(0)
-        }
-        SYS_RT_SIGACTION => {
-            warn!("sys_sigaction is unimplemented");
-            Ok(0)
-        }
+        SYS_BRK => sys_brk(args[0]),
+        SYS_RT_SIGACTION => sys_rt_sigaction(
+            args[0],
+            args[1] as *const SigAction,
+            args[2] as *mut SigAction,
+        ),
         SYS_RT_SIGPROCMASK => {
-            warn!("sys_sigprocmask is unimplemented");
-            Ok(0)
+            sys_rt_sigprocmask(args[0], args[1] as *const u64, args[2] as *mut u64, args[3])
         }
         SYS_IOCTL => {
             warn!("sys_ioctl is unimplemented");
@@ -80,10 +81,10 @@ pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> isize {
         // 20
         SYS_WRITEV => sys_writev(args[0], args[1] as *const IoVec, args[2]),
         SYS_SCHED_YIELD => sys_yield(),
-        SYS_MADVISE => {
-            warn!("sys_madvise is unimplemented");
-            Ok(0)
-        }
+        SYS_MREMAP => sys_mremap(args[0], args[1], args[2], args[3], args[4]),
+        SYS_MINCORE => sys_mincore(args[0], args[1], args[2] as *mut u8),
+        SYS_MADVISE => sys_madvise(args[0], args[1], args[2]),
+        SYS_DUP => sys_dup(args[0]),
         SYS_NANOSLEEP => sys_nanosleep(args[0] as *const TimeSpec),
         SYS_SETITIMER => {
             warn!("sys_setitimer is unimplemented");
@@ -91,7 +92,7 @@ pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> isize {
         }
         SYS_GETPID => sys_getpid(),
         // 40
-        SYS_SENDFILE => sys_sendfile(args[0], args[1], args[3] as *mut usize, args[4]),
+        SYS_SENDFILE => sys_sendfile(args[0], args[1], args[2] as *mut usize, args[3]),
         SYS_SOCKET => sys_socket(args[0], args[1], args[2]),
         SYS_CONNECT => sys_connect(args[0], args[1] as *const SockAddr, args[2]),
         SYS_ACCEPT => sys_accept(args[0], args[1] as *mut SockAddr, args[2] as *mut u32),
@@ -146,14 +147,8 @@ pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> isize {
         SYS_WAIT4 => sys_wait4(args[0] as isize, args[1] as *mut i32), // TODO: wait4
         SYS_KILL => sys_kill(args[0], args[1]),
         SYS_UNAME => sys_uname(args[0] as *mut u8),
-        SYS_FCNTL => {
-            warn!("sys_fcntl is unimplemented");
-            Ok(0)
-        }
-        SYS_FLOCK => {
-            warn!("sys_flock is unimplemented");
-            Ok(0)
-        }
+        SYS_FCNTL => sys_fcntl(args[0], args[1], args[2]),
+        SYS_FLOCK => sys_flock(args[0], args[1]),
         SYS_FSYNC => sys_fsync(args[0]),
         SYS_FDATASYNC => sys_fdatasync(args[0]),
         SYS_TRUNCATE => sys_truncate(args[0] as *const u8, args[1]),
@@ -161,42 +156,37 @@ pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> isize {
         SYS_GETCWD => sys_getcwd(args[0] as *mut u8, args[1]),
         // 80
         SYS_CHDIR => sys_chdir(args[0] as *const u8),
-        SYS_FCHMOD => {
-            warn!("sys_fchmod is unimplemented");
-            Ok(0)
-        }
-        SYS_FCHOWN => {
-            warn!("sys_fchown is unimplemented");
-            Ok(0)
-        }
-        SYS_UMASK => {
-            warn!("sys_umask is unimplemented");
-            Ok(0o777)
-        }
+        SYS_FCHMOD => sys_fchmod(args[0], args[1]),
+        SYS_FCHOWN => sys_fchown(args[0], args[1], args[2]),
+        SYS_UMASK => sys_umask(args[0]),
         SYS_GETTIMEOFDAY => sys_gettimeofday(args[0] as *mut TimeVal, args[1] as *const u8),
         //        SYS_GETRLIMIT => sys_getrlimit(),
         SYS_GETRUSAGE => sys_getrusage(args[0], args[1] as *mut RUsage),
         SYS_SYSINFO => sys_sysinfo(args[0] as *mut SysInfo),
-        SYS_GETUID => {
-            warn!("sys_getuid is unimplemented");
-            Ok(0)
-        }
-        SYS_GETGID => {
-            warn!("sys_getgid is unimplemented");
-            Ok(0)
-        }
-        SYS_SETUID => {
-            warn!("sys_setuid is unimplemented");
-            Ok(0)
-        }
-        SYS_GETEUID => {
-            warn!("sys_geteuid is unimplemented");
-            Ok(0)
-        }
-        SYS_GETEGID => {
-            warn!("sys_getegid is unimplemented");
-            Ok(0)
-        }
+        SYS_GETUID => sys_getuid(),
+        SYS_GETGID => sys_getgid(),
+        SYS_SETUID => sys_setuid(args[0]),
+        SYS_SETGID => sys_setgid(args[0]),
+        SYS_GETEUID => sys_geteuid(),
+        SYS_GETEGID => sys_getegid(),
+        SYS_SETREUID => sys_setreuid(args[0], args[1]),
+        SYS_SETREGID => sys_setregid(args[0], args[1]),
+        SYS_GETGROUPS => sys_getgroups(args[0], args[1] as *mut u32),
+        SYS_SETGROUPS => sys_setgroups(args[0], args[1] as *const u32),
+        SYS_SETRESUID => sys_setresuid(args[0], args[1], args[2]),
+        SYS_GETRESUID => sys_getresuid(
+            args[0] as *mut u32,
+            args[1] as *mut u32,
+            args[2] as *mut u32,
+        ),
+        SYS_SETRESGID => sys_setresgid(args[0], args[1], args[2]),
+        SYS_GETRESGID => sys_getresgid(
+            args[0] as *mut u32,
+            args[1] as *mut u32,
+            args[2] as *mut u32,
+        ),
+        SYS_SETFSUID => sys_setfsuid(args[0]),
+        SYS_SETFSGID => sys_setfsgid(args[0]),
         // 110
         SYS_GETPPID => sys_getppid(),
         SYS_SETSID => {
@@ -207,25 +197,19 @@ pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> isize {
             warn!("sys_sigaltstack is unimplemented");
             Ok(0)
         }
-        SYS_STATFS => {
-            warn!("statfs is unimplemented");
-            Err(SysError::EACCES)
-        }
-        SYS_FSTATFS => {
-            warn!("fstatfs is unimplemented");
-            Err(SysError::EACCES)
-        }
+        SYS_STATFS => sys_statfs(args[0] as *const u8, args[1] as *mut StatFs),
+        SYS_FSTATFS => sys_fstatfs(args[0], args[1] as *mut StatFs),
         SYS_SETPRIORITY => sys_set_priority(args[0]),
         //        SYS_SETRLIMIT => sys_setrlimit(),
         SYS_SYNC => sys_sync(),
-        SYS_MOUNT => {
-            warn!("mount is unimplemented");
-            Err(SysError::EACCES)
-        }
-        SYS_UMOUNT2 => {
-            warn!("umount2 is unimplemented");
-            Err(SysError::EACCES)
-        }
+        SYS_MOUNT => sys_mount(
+            args[0] as *const u8,
+            args[1] as *const u8,
+            args[2] as *const u8,
+            args[3],
+            args[4] as *const u8,
+        ),
+        SYS_UMOUNT2 => sys_umount2(args[0] as *const u8, args[1]),
         SYS_REBOOT => sys_reboot(
             args[0] as u32,
             args[1] as u32,
@@ -247,32 +231,72 @@ pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> isize {
         }
         SYS_CLOCK_GETTIME => sys_clock_gettime(args[0], args[1] as *mut TimeSpec),
         SYS_EXIT_GROUP => sys_exit_group(args[0]),
-        SYS_OPENAT => sys_openat(args[0], args[1] as *const u8, args[2], args[3]), // TODO: handle `dfd`
-        SYS_MKDIRAT => sys_mkdir(args[1] as *const u8, args[2]), // TODO: handle `dfd`
-        //        SYS_MKNODAT => sys_mknod(),
+        SYS_EPOLL_CTL => sys_epoll_ctl(args[0], args[1], args[2], args[3] as *const EpollEvent),
+        SYS_OPENAT => sys_openat(args[0], args[1] as *const u8, args[2], args[3]),
+        SYS_MKDIRAT => sys_mkdirat(args[0], args[1] as *const u8, args[2]),
+        SYS_MKNODAT => sys_mknodat(args[0], args[1] as *const u8, args[2], args[3]),
         // 260
-        SYS_FCHOWNAT => {
-            warn!("sys_fchownat is unimplemented");
-            Ok(0)
-        }
-        SYS_NEWFSTATAT => sys_stat(args[1] as *const u8, args[2] as *mut Stat), // TODO: handle `dfd`, `flag`
-        SYS_UNLINKAT => sys_unlink(args[1] as *const u8), // TODO: handle `dfd`, `flag`
-        SYS_RENAMEAT => sys_renameat(args[0], args[1] as *const u8, args[2], args[3] as *const u8), // TODO: handle `olddfd`, `newdfd`
-        SYS_LINKAT => sys_link(args[1] as *const u8, args[3] as *const u8), // TODO: handle `olddfd`, `newdfd`, `flags`
-        SYS_SYMLINKAT => Err(SysError::EACCES),
-        SYS_FACCESSAT => sys_access(args[1] as *const u8, args[2]), // TODO: handle `dfd`
+        SYS_FCHOWNAT => sys_fchownat(args[0], args[1] as *const u8, args[2], args[3], args[4]),
+        SYS_NEWFSTATAT => sys_fstatat(args[0], args[1] as *const u8, args[2] as *mut Stat, args[3]),
+        SYS_UNLINKAT => sys_unlinkat(args[0], args[1] as *const u8, args[2]),
+        SYS_RENAMEAT => sys_renameat(args[0], args[1] as *const u8, args[2], args[3] as *const u8),
+        SYS_LINKAT => sys_linkat(
+            args[0],
+            args[1] as *const u8,
+            args[2],
+            args[3] as *const u8,
+            args[4],
+        ),
+        SYS_SYMLINKAT => sys_symlinkat(args[0] as *const u8, args[1], args[2] as *const u8),
+        SYS_READLINKAT => {
+            sys_readlinkat(args[0], args[1] as *const u8, args[2] as *mut u8, args[3])
+        }
+        SYS_FCHMODAT => sys_fchmodat(args[0], args[1] as *const u8, args[2], 0),
+        // the syscall has no `flags`, it is handled by libc
+        SYS_FACCESSAT => sys_faccessat(args[0], args[1] as *const u8, args[2], 0),
+        SYS_SPLICE => sys_splice(
+            args[0],
+            args[1] as *mut usize,
+            args[2],
+            args[3] as *mut usize,
+            args[4],
+            args[5],
+        ),
+        SYS_TEE => sys_tee(args[0], args[1], args[2], args[3]),
+        SYS_VMSPLICE => sys_vmsplice(args[0], args[1] as *const IoVec, args[2], args[3]),
         // 280
-        SYS_UTIMENSAT => {
-            warn!("sys_utimensat is unimplemented");
-            Ok(0)
-        }
-        SYS_ACCEPT4 => sys_accept(args[0], args[1] as *mut SockAddr, args[2] as *mut u32), // use accept for accept4
-        SYS_EPOLL_CREATE1 => {
-            warn!("sys_epoll_create1 is unimplemented");
-            Err(SysError::ENOSYS)
-        }
-        SYS_DUP3 => sys_dup2(args[0], args[1]), // TODO: handle `flags`
-        SYS_PIPE2 => sys_pipe(args[0] as *mut u32), // TODO: handle `flags`
+        SYS_EPOLL_PWAIT => sys_epoll_pwait(
+            args[0],
+            args[1] as *mut EpollEvent,
+            args[2],
+            args[3],
+            args[4],
+        ),
+        SYS_UTIMENSAT => sys_utimensat(
+            args[0],
+            args[1] as *const u8,
+            args[2] as *const TimeSpec,
+            args[3],
+        ),
+        SYS_TIMERFD_CREATE => sys_timerfd_create(args[0], args[1]),
+        SYS_TIMERFD_SETTIME => sys_timerfd_settime(
+            args[0],
+            args[1],
+            args[2] as *const ITimerSpec,
+            args[3] as *mut ITimerSpec,
+        ),
+        SYS_TIMERFD_GETTIME => sys_timerfd_gettime(args[0], args[1] as *mut ITimerSpec),
+        SYS_ACCEPT4 => sys_accept4(
+            args[0],
+            args[1] as *mut SockAddr,
+            args[2] as *mut u32,
+            args[3],
+        ),
+        SYS_SIGNALFD4 => sys_signalfd4(args[0], args[1] as *const u64, args[2], args[3]),
+        SYS_EVENTFD2 => sys_eventfd2(args[0], args[1]),
+        SYS_EPOLL_CREATE1 => sys_epoll_create1(args[0]),
+        SYS_DUP3 => sys_dup3(args[0], args[1], args[2]),
+        SYS_PIPE2 => sys_pipe2(args[0] as *mut u32, args[1]),
         SYS_PRLIMIT64 => sys_prlimit64(
             args[0],
             args[1],
@@ -335,26 +359,23 @@ fn x86_64_syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> Option<Sys
         SYS_RMDIR => sys_rmdir(args[0] as *const u8),
         SYS_LINK => sys_link(args[0] as *const u8, args[1] as *const u8),
         SYS_UNLINK => sys_unlink(args[0] as *const u8),
+        SYS_SYMLINK => sys_symlink(args[0] as *const u8, args[1] as *const u8),
         SYS_READLINK => sys_readlink(args[0] as *const u8, args[1] as *mut u8, args[2]),
         // 90
-        SYS_CHMOD => {
-            warn!("sys_chmod is unimplemented");
-            Ok(0)
-        }
+        SYS_CHMOD => sys_chmod(args[0] as *const u8, args[1]),
         SYS_ARCH_PRCTL => sys_arch_prctl(args[0] as i32, args[1], tf),
         SYS_TIME => sys_time(args[0] as *mut u64),
         SYS_ALARM => {
             warn!("sys_alarm is unimplemented");
             Ok(0)
         }
-        SYS_CHOWN => {
-            warn!("sys_chown is unimplemented");
-            Ok(0)
-        }
-        SYS_EPOLL_CREATE => {
-            warn!("sys_epoll_create is unimplemented");
-            Err(SysError::ENOSYS)
-        }
+        SYS_CHOWN => sys_chown(args[0] as *const u8, args[1], args[2]),
+        SYS_LCHOWN => sys_lchown(args[0] as *const u8, args[1], args[2]),
+        SYS_EPOLL_CREATE => sys_epoll_create(args[0]),
+        SYS_EPOLL_WAIT => sys_epoll_wait(args[0], args[1] as *mut EpollEvent, args[2], args[3]),
+        SYS_MKNOD => sys_mknod(args[0] as *const u8, args[1], args[2]),
+        SYS_SIGNALFD => sys_signalfd(args[0], args[1] as *const u64, args[2]),
+        SYS_EVENTFD => sys_eventfd(args[0]),
         _ => {
             return None;
         }
@@ -408,6 +429,7 @@ pub enum SysError {
     ENOLCK = 37,
     ENOSYS = 38,
     ENOTEMPTY = 39,
+    ELOOP = 40,
     ENOTSOCK = 80,
     ENOPROTOOPT = 92,
     EPFNOSUPPORT = 96,
@@ -465,6 +487,7 @@ impl fmt::Display for SysError {
                 ENOLCK => "No record locks available",
                 ENOSYS => "Function not implemented",
                 ENOTEMPTY => "Directory not empty",
+                ELOOP => "Too many levels of symbolic links",
                 ENOTSOCK => "Socket operation on non-socket",
                 ENOPROTOOPT => "Protocol not available",
                 EPFNOSUPPORT => "Protocol family not supported",
@@ -480,8 +503,12 @@ impl fmt::Display for SysError {
 }
 
 impl From<VMError> for SysError {
-    fn from(_: VMError) -> Self {
-        SysError::EFAULT
+    fn from(error: VMError) -> Self {
+        match error {
+            VMError::InvalidPtr => SysError::EFAULT,
+            VMError::InvalidArg => SysError::EINVAL,
+            VMError::NoMemory => SysError::ENOMEM,
+        }
     }
 }
 
@@ -16,7 +16,7 @@ pub fn sys_socket(domain: usize, socket_type: usize, protocol: usize) -> SysResu
         domain, socket_type, protocol
     );
     let mut proc = process();
-    let socket: Box<dyn Socket> = match domain {
+    let mut socket: Box<dyn Socket> = match domain {
         AF_INET | AF_UNIX => match socket_type & SOCK_TYPE_MASK {
             SOCK_STREAM => Box::new(TcpSocketState::new()),
             SOCK_DGRAM => Box::new(UdpSocketState::new()),
@@ -25,8 +25,10 @@ pub fn sys_socket(domain: usize, socket_type: usize, protocol: usize) -> SysResu
         },
         _ => return Err(SysError::EAFNOSUPPORT),
     };
+    socket.set_nonblocking(socket_type & SOCK_NONBLOCK != 0);
     let fd = proc.get_free_fd();
     proc.files.insert(fd, FileLike::Socket(socket));
+    proc.set_cloexec(fd, socket_type & SOCK_CLOEXEC != 0);
     Ok(fd)
 }
 
@@ -144,6 +146,9 @@ pub fn sys_recvfrom(
     proc.vm.check_write_array(base, len)?;
 
     let socket = proc.get_socket(fd)?;
+    if socket.read_would_block() {
+        return Err(SysError::EAGAIN);
+    }
     let mut slice = unsafe { slice::from_raw_parts_mut(base, len) };
     let (result, endpoint) = socket.read(&mut slice);
 
@@ -187,19 +192,31 @@ pub fn sys_shutdown(fd: usize, how: usize) -> SysResult {
 }
 
 pub fn sys_accept(fd: usize, addr: *mut SockAddr, addr_len: *mut u32) -> SysResult {
+    sys_accept4(fd, addr, addr_len, 0)
+}
+
+pub fn sys_accept4(fd: usize, addr: *mut SockAddr, addr_len: *mut u32, flags: usize) -> SysResult {
     info!(
-        "sys_accept: fd: {} addr: {:?} addr_len: {:?}",
-        fd, addr, addr_len
+        "sys_accept4: fd: {} addr: {:?} addr_len: {:?} flags: {:#x}",
+        fd, addr, addr_len, flags
     );
+    if flags & !(SOCK_NONBLOCK | SOCK_CLOEXEC) != 0 {
+        return Err(SysError::EINVAL);
+    }
     // smoltcp tcp sockets do not support backlog
     // open multiple sockets for each connection
     let mut proc = process();
 
     let socket = proc.get_socket(fd)?;
-    let (new_socket, remote_endpoint) = socket.accept()?;
+    if socket.read_would_block() {
+        return Err(SysError::EAGAIN);
+    }
+    let (mut new_socket, remote_endpoint) = socket.accept()?;
+    new_socket.set_nonblocking(flags & SOCK_NONBLOCK != 0);
 
     let new_fd = proc.get_free_fd();
     proc.files.insert(new_fd, FileLike::Socket(new_socket));
+    proc.set_cloexec(new_fd, flags & SOCK_CLOEXEC != 0);
 
     if !addr.is_null() {
         let sockaddr_in = SockAddr::from(remote_endpoint);
@@ -376,6 +393,8 @@ const SOCK_STREAM: usize = 1;
 const SOCK_DGRAM: usize = 2;
 const SOCK_RAW: usize = 3;
 const SOCK_TYPE_MASK: usize = 0xf;
+const SOCK_NONBLOCK: usize = 0o4000;
+const SOCK_CLOEXEC: usize = 0o2000000;
 
 const IPPROTO_IP: usize = 0;
 const IPPROTO_ICMP: usize = 1;
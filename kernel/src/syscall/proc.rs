@@ -1,7 +1,9 @@
 //! Syscalls for process
 
 use super::*;
-use crate::fs::INodeExt;
+use crate::fs::{release_record_locks, INodeExt};
+use crate::sync::SpinNoIrqLock as Mutex;
+use core::mem::size_of;
 
 /// Fork the current process. Return the child's PID.
 pub fn sys_fork(tf: &TrapFrame) -> SysResult {
@@ -150,12 +152,19 @@ pub fn sys_exec(
     // Read program file
     let path = args[0].as_str();
     let inode = proc.lookup_inode(path)?;
+    let info = inode.metadata()?;
+    if info.type_ != FileType::File {
+        return Err(SysError::EACCES);
+    }
+    proc.cred.check(&info, Access::EXEC)?;
     let buf = inode.read_as_vec()?;
 
     // Make new Thread
     let iter = args.iter().map(|s| s.as_str());
     let mut thread = Thread::new_user(buf.as_slice(), iter);
     thread.proc.lock().clone_for_exec(&proc);
+    thread.proc.lock().cred.exec(&info);
+    thread.proc.lock().exec_path = proc.absolute_path(path);
 
     // Activate new page table
     unsafe {
@@ -185,32 +194,197 @@ pub fn sys_kill(pid: usize, sig: usize) -> SysResult {
         pid,
         sig
     );
-    let current_pid = process().pid.get().clone();
-    if current_pid == pid {
+    if sig > SIGRTMAX {
+        return Err(SysError::EINVAL);
+    }
+    let (current_pid, uid) = {
+        let proc = process();
+        (proc.pid.get(), proc.cred.uid)
+    };
+    let proc_arc = match current_pid == pid {
+        true => current_thread().proc.clone(),
+        false => PROCESSES
+            .read()
+            .get(&pid)
+            .and_then(|weak| weak.upgrade())
+            .ok_or(SysError::EINVAL)?,
+    };
+    if sig == 0 {
+        // only check the existence
+        return Ok(0);
+    }
+    let info = SigInfo {
+        signo: sig,
+        pid: current_pid,
+        uid,
+    };
+    send_signal(proc_arc, info);
+    Ok(0)
+}
+
+/// Send a signal to the current process
+pub fn raise_signal(sig: usize) {
+    let info = {
+        let proc = process();
+        SigInfo {
+            signo: sig,
+            pid: proc.pid.get(),
+            uid: proc.cred.uid,
+        }
+    };
+    send_signal(current_thread().proc.clone(), info);
+}
+
+/// Ignore the signal, keep it pending if blocked, or deliver it
+///
+/// The process must not be locked.
+fn send_signal(proc_arc: Arc<Mutex<Process>>, info: SigInfo) {
+    {
+        let proc = proc_arc.lock();
+        if proc.sig_handler(info.signo) != SIG_IGN && proc.sig_mask & sig_bit(info.signo) != 0 {
+            // blocked, keep it for signalfd
+            proc.signals.push(info);
+            return;
+        }
+    }
+    deliver_signal(proc_arc, info);
+}
+
+/// Take the action of a signal which is not blocked,
+/// terminating the process unless the signal is ignored or caught
+///
+/// Processes can't be stopped, so the signals which stop them are ignored.
+/// Caught signals only interrupt waits since handlers are not run,
+/// e.g. a write raising a caught SIGPIPE fails with EPIPE like an ignored one.
+///
+/// The process must not be locked.
+fn deliver_signal(proc_arc: Arc<Mutex<Process>>, info: SigInfo) {
+    let handler = proc_arc.lock().sig_handler(info.signo);
+    match handler {
+        SIG_IGN => return,
+        SIG_DFL if sig_default_ignored(info.signo) => return,
+        SIG_DFL if sig_default_stops(info.signo) => {
+            warn!("signal {} would stop the process, ignored", info.signo);
+            return;
+        }
+        SIG_DFL => {}
+        _ => {
+            warn!(
+                "signal {} is caught, but handlers are not run, so it only interrupts waits",
+                info.signo
+            );
+            let signals = proc_arc.lock().signals.clone();
+            signals.interrupt();
+            return;
+        }
+    }
+    let sig = info.signo;
+    if Arc::ptr_eq(&proc_arc, &current_thread().proc) {
         // killing myself
         sys_exit_group(sig);
-    } else {
-        if let Some(proc_arc) = PROCESSES.read().get(&pid).and_then(|weak| weak.upgrade()) {
-            let proc = proc_arc.lock();
-            // quit all threads
-            for tid in proc.threads.iter() {
-                processor().manager().exit(*tid, sig);
+    }
+    let mut proc = proc_arc.lock();
+    // quit all threads
+    for tid in proc.threads.iter() {
+        processor().manager().exit(*tid, sig);
+    }
+    let files = proc.close_all_files();
+    // notify parent and fill exit code
+    // avoid deadlock
+    let proc_parent = proc.parent.clone();
+    let pid = proc.pid.get();
+    drop(proc);
+    drop(files);
+    release_record_locks(pid);
+    if let Some(parent) = proc_parent {
+        let mut parent = parent.lock();
+        parent.child_exit_code.insert(pid, sig);
+        parent.child_exit.notify_one();
+    }
+}
+
+/// Change the action of a signal
+///
+/// Signal handlers are remembered but not run, so caught signals only interrupt waits.
+pub fn sys_rt_sigaction(sig: usize, act: *const SigAction, oldact: *mut SigAction) -> SysResult {
+    info!(
+        "rt_sigaction: sig: {}, act: {:?}, oldact: {:?}",
+        sig, act, oldact
+    );
+    if sig == 0 || sig > SIGRTMAX {
+        return Err(SysError::EINVAL);
+    }
+    if !act.is_null() && (sig == SIGKILL || sig == SIGSTOP) {
+        return Err(SysError::EINVAL);
+    }
+    let mut proc = process();
+    if !act.is_null() {
+        proc.vm.check_read_ptr(act)?;
+    }
+    if !oldact.is_null() {
+        proc.vm.check_write_ptr(oldact)?;
+    }
+    let old = proc.sig_actions.get(&sig).cloned().unwrap_or_default();
+    if !act.is_null() {
+        let act = unsafe { act.read() };
+        match act.handler {
+            SIG_DFL => {
+                proc.sig_actions.remove(&sig);
             }
-            // notify parent and fill exit code
-            // avoid deadlock
-            let proc_parent = proc.parent.clone();
-            let pid = proc.pid.get();
-            drop(proc);
-            if let Some(parent) = proc_parent {
-                let mut parent = parent.lock();
-                parent.child_exit_code.insert(pid, sig);
-                parent.child_exit.notify_one();
+            handler => {
+                if handler != SIG_IGN {
+                    warn!("rt_sigaction: signal handlers are unimplemented");
+                }
+                proc.sig_actions.insert(sig, act);
             }
-            Ok(0)
-        } else {
-            Err(SysError::EINVAL)
         }
     }
+    if !oldact.is_null() {
+        unsafe { oldact.write(old) };
+    }
+    Ok(0)
+}
+
+/// Examine and change the blocked signals
+pub fn sys_rt_sigprocmask(
+    how: usize,
+    set: *const u64,
+    oldset: *mut u64,
+    sigsetsize: usize,
+) -> SysResult {
+    info!(
+        "rt_sigprocmask: how: {}, set: {:?}, oldset: {:?}, sigsetsize: {}",
+        how, set, oldset, sigsetsize
+    );
+    if sigsetsize != size_of::<u64>() {
+        return Err(SysError::EINVAL);
+    }
+    let mut proc = process();
+    if !oldset.is_null() {
+        proc.vm.check_write_ptr(oldset)?;
+        unsafe { oldset.write(proc.sig_mask) };
+    }
+    if set.is_null() {
+        return Ok(0);
+    }
+    proc.vm.check_read_ptr(set)?;
+    let set = unsafe { set.read() };
+    let mask = match how {
+        SIG_BLOCK => proc.sig_mask | set,
+        SIG_UNBLOCK => proc.sig_mask & !set,
+        SIG_SETMASK => set,
+        _ => return Err(SysError::EINVAL),
+    };
+    // SIGKILL and SIGSTOP can not be blocked
+    proc.sig_mask = mask & !(sig_bit(SIGKILL) | sig_bit(SIGSTOP));
+    // the signals kept pending while blocked arrive now
+    let unblocked = !proc.sig_mask;
+    let signals = proc.signals.clone();
+    drop(proc);
+    while let Some(info) = signals.take(unblocked) {
+        deliver_signal(current_thread().proc.clone(), info);
+    }
+    Ok(0)
 }
 
 /// Get the current process id
@@ -231,6 +405,160 @@ pub fn sys_getppid() -> SysResult {
     Ok(process().parent.as_ref().unwrap().lock().pid.get())
 }
 
+pub fn sys_getuid() -> SysResult {
+    Ok(process().cred.uid)
+}
+
+pub fn sys_geteuid() -> SysResult {
+    Ok(process().cred.euid)
+}
+
+pub fn sys_getgid() -> SysResult {
+    Ok(process().cred.gid)
+}
+
+pub fn sys_getegid() -> SysResult {
+    Ok(process().cred.egid)
+}
+
+/// Parse a user or group id argument, -1 means unchanged
+pub fn id_arg(id: usize) -> Option<usize> {
+    match id as u32 {
+        core::u32::MAX => None,
+        id => Some(id as usize),
+    }
+}
+
+pub fn sys_setuid(uid: usize) -> SysResult {
+    info!("setuid: uid: {}", uid);
+    process().cred.set_uid(uid as u32 as usize)?;
+    Ok(0)
+}
+
+pub fn sys_setgid(gid: usize) -> SysResult {
+    info!("setgid: gid: {}", gid);
+    process().cred.set_gid(gid as u32 as usize)?;
+    Ok(0)
+}
+
+pub fn sys_setreuid(ruid: usize, euid: usize) -> SysResult {
+    info!("setreuid: ruid: {}, euid: {}", ruid as i32, euid as i32);
+    process().cred.set_reuid(id_arg(ruid), id_arg(euid))?;
+    Ok(0)
+}
+
+pub fn sys_setregid(rgid: usize, egid: usize) -> SysResult {
+    info!("setregid: rgid: {}, egid: {}", rgid as i32, egid as i32);
+    process().cred.set_regid(id_arg(rgid), id_arg(egid))?;
+    Ok(0)
+}
+
+pub fn sys_setresuid(ruid: usize, euid: usize, suid: usize) -> SysResult {
+    info!(
+        "setresuid: ruid: {}, euid: {}, suid: {}",
+        ruid as i32, euid as i32, suid as i32
+    );
+    process()
+        .cred
+        .set_resuid(id_arg(ruid), id_arg(euid), id_arg(suid))?;
+    Ok(0)
+}
+
+pub fn sys_setresgid(rgid: usize, egid: usize, sgid: usize) -> SysResult {
+    info!(
+        "setresgid: rgid: {}, egid: {}, sgid: {}",
+        rgid as i32, egid as i32, sgid as i32
+    );
+    process()
+        .cred
+        .set_resgid(id_arg(rgid), id_arg(egid), id_arg(sgid))?;
+    Ok(0)
+}
+
+pub fn sys_getresuid(ruid: *mut u32, euid: *mut u32, suid: *mut u32) -> SysResult {
+    let proc = process();
+    proc.vm.check_write_ptr(ruid)?;
+    proc.vm.check_write_ptr(euid)?;
+    proc.vm.check_write_ptr(suid)?;
+    unsafe {
+        ruid.write(proc.cred.uid as u32);
+        euid.write(proc.cred.euid as u32);
+        suid.write(proc.cred.suid as u32);
+    }
+    Ok(0)
+}
+
+pub fn sys_getresgid(rgid: *mut u32, egid: *mut u32, sgid: *mut u32) -> SysResult {
+    let proc = process();
+    proc.vm.check_write_ptr(rgid)?;
+    proc.vm.check_write_ptr(egid)?;
+    proc.vm.check_write_ptr(sgid)?;
+    unsafe {
+        rgid.write(proc.cred.gid as u32);
+        egid.write(proc.cred.egid as u32);
+        sgid.write(proc.cred.sgid as u32);
+    }
+    Ok(0)
+}
+
+/// Set the user id for permission checks, return the old one
+pub fn sys_setfsuid(fsuid: usize) -> SysResult {
+    info!("setfsuid: fsuid: {}", fsuid);
+    let mut proc = process();
+    let old = proc.cred.fsuid;
+    proc.cred.set_fsuid(fsuid as u32 as usize);
+    Ok(old)
+}
+
+/// Set the group id for permission checks, return the old one
+pub fn sys_setfsgid(fsgid: usize) -> SysResult {
+    info!("setfsgid: fsgid: {}", fsgid);
+    let mut proc = process();
+    let old = proc.cred.fsgid;
+    proc.cred.set_fsgid(fsgid as u32 as usize);
+    Ok(old)
+}
+
+pub fn sys_getgroups(size: usize, list: *mut u32) -> SysResult {
+    let proc = process();
+    let groups = &proc.cred.groups;
+    if size == 0 {
+        return Ok(groups.len());
+    }
+    if size < groups.len() {
+        return Err(SysError::EINVAL);
+    }
+    proc.vm.check_write_array(list, groups.len())?;
+    for (i, &gid) in groups.iter().enumerate() {
+        unsafe {
+            list.add(i).write(gid as u32);
+        }
+    }
+    Ok(groups.len())
+}
+
+pub fn sys_setgroups(size: usize, list: *const u32) -> SysResult {
+    info!("setgroups: size: {}, list: {:?}", size, list);
+    let mut proc = process();
+    if proc.cred.euid != 0 {
+        return Err(SysError::EPERM);
+    }
+    if size > NGROUPS_MAX {
+        return Err(SysError::EINVAL);
+    }
+    proc.vm.check_read_array(list, size)?;
+    let groups = unsafe { slice::from_raw_parts(list, size) };
+    proc.cred.groups = groups.iter().map(|&gid| gid as usize).collect();
+    Ok(0)
+}
+
+/// Max number of supplementary groups
+const NGROUPS_MAX: usize = 65536;
+
+const SIG_BLOCK: usize = 0;
+const SIG_UNBLOCK: usize = 1;
+const SIG_SETMASK: usize = 2;
+
 /// Exit the current thread
 pub fn sys_exit(exit_code: usize) -> ! {
     let tid = thread::current().id();
@@ -242,10 +570,16 @@ pub fn sys_exit(exit_code: usize) -> ! {
     // notify parent and fill exit code
     // avoid deadlock
     let exit = proc.threads.len() == 0;
+    let files = match exit {
+        true => proc.close_all_files(),
+        false => Vec::new(),
+    };
     let proc_parent = proc.parent.clone();
     let pid = proc.pid.get();
     drop(proc);
+    drop(files);
     if exit {
+        release_record_locks(pid);
         if let Some(parent) = proc_parent {
             let mut parent = parent.lock();
             parent.child_exit_code.insert(pid, exit_code);
@@ -273,19 +607,22 @@ pub fn sys_exit(exit_code: usize) -> ! {
 
 /// Exit the current thread group (i.e. process)
 pub fn sys_exit_group(exit_code: usize) -> ! {
-    let proc = process();
+    let mut proc = process();
     info!("exit_group: {}, code: {}", proc.pid, exit_code);
 
     // quit all threads
     for tid in proc.threads.iter() {
         processor().manager().exit(*tid, exit_code);
     }
+    let files = proc.close_all_files();
 
     // notify parent and fill exit code
     // avoid deadlock
     let proc_parent = proc.parent.clone();
     let pid = proc.pid.get();
     drop(proc);
+    drop(files);
+    release_record_locks(pid);
     if let Some(parent) = proc_parent {
         let mut parent = parent.lock();
         parent.child_exit_code.insert(pid, exit_code);
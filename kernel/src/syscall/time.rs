@@ -2,8 +2,10 @@
 
 use super::*;
 use crate::consts::USEC_PER_TICK;
+use crate::fs::TimerFd;
 use core::time::Duration;
 use lazy_static::lazy_static;
+use rcore_fs::vfs::Timespec;
 
 /// should be initialized together
 lazy_static! {
@@ -19,6 +21,7 @@ const USEC_PER_SEC: u64 = 1_000_000;
 const MSEC_PER_SEC: u64 = 1_000;
 const USEC_PER_MSEC: u64 = 1_000;
 const NSEC_PER_USEC: u64 = 1_000;
+const NSEC_PER_SEC: u64 = 1_000_000_000;
 
 /// Get time since epoch in usec
 fn get_epoch_usec() -> u64 {
@@ -29,6 +32,15 @@ fn get_epoch_usec() -> u64 {
     (tick - tick_base) * USEC_PER_TICK as u64 + epoch_base * USEC_PER_SEC
 }
 
+/// Current time since epoch, recorded in inodes
+pub fn current_time() -> Timespec {
+    let usec = get_epoch_usec();
+    Timespec {
+        sec: (usec / USEC_PER_SEC) as i64,
+        nsec: (usec % USEC_PER_SEC * NSEC_PER_USEC) as i32,
+    }
+}
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
 pub struct TimeVal {
@@ -62,6 +74,42 @@ impl TimeSpec {
         Duration::new(self.sec, self.nsec as u32)
     }
 
+    pub fn from_duration(dur: Duration) -> Self {
+        TimeSpec {
+            sec: dur.as_secs(),
+            nsec: dur.subsec_nanos() as u64,
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.nsec < NSEC_PER_SEC
+    }
+
+    /// `UTIME_NOW` of utimensat
+    pub fn utime_now() -> Self {
+        TimeSpec {
+            sec: 0,
+            nsec: UTIME_NOW,
+        }
+    }
+
+    /// Whether it is `UTIME_NOW` or `UTIME_OMIT` rather than a time
+    pub fn is_utime_special(&self) -> bool {
+        self.nsec == UTIME_NOW || self.nsec == UTIME_OMIT
+    }
+
+    /// The time to set by utimensat, `None` to keep the current one
+    pub fn to_utime(&self) -> Option<Timespec> {
+        match self.nsec {
+            UTIME_OMIT => None,
+            UTIME_NOW => Some(current_time()),
+            _ => Some(Timespec {
+                sec: self.sec as i64,
+                nsec: self.nsec as i32,
+            }),
+        }
+    }
+
     pub fn get_epoch() -> Self {
         let usec = get_epoch_usec();
         TimeSpec {
@@ -112,6 +160,110 @@ pub fn sys_time(time: *mut u64) -> SysResult {
     Ok(sec as usize)
 }
 
+/// `struct itimerspec`
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct ITimerSpec {
+    interval: TimeSpec,
+    value: TimeSpec,
+}
+
+pub fn sys_timerfd_create(clock: usize, flags: usize) -> SysResult {
+    info!("timerfd_create: clock: {}, flags: {:#x}", clock, flags);
+    if clock != CLOCK_REALTIME && clock != CLOCK_MONOTONIC {
+        return Err(SysError::EINVAL);
+    }
+    if flags & !(TFD_NONBLOCK | TFD_CLOEXEC) != 0 {
+        return Err(SysError::EINVAL);
+    }
+    let nonblock = flags & TFD_NONBLOCK != 0;
+    let cloexec = flags & TFD_CLOEXEC != 0;
+    let timerfd = Arc::new(TimerFd::new());
+    let fd = process().add_anon_file(timerfd, "timerfd", nonblock, cloexec);
+    Ok(fd)
+}
+
+pub fn sys_timerfd_settime(
+    fd: usize,
+    flags: usize,
+    new_value: *const ITimerSpec,
+    old_value: *mut ITimerSpec,
+) -> SysResult {
+    info!(
+        "timerfd_settime: fd: {}, flags: {:#x}, new_value: {:?}, old_value: {:?}",
+        fd, flags, new_value, old_value
+    );
+    if flags & !TFD_TIMER_ABSTIME != 0 {
+        return Err(SysError::EINVAL);
+    }
+    let mut proc = process();
+    proc.vm.check_read_ptr(new_value)?;
+    if !old_value.is_null() {
+        proc.vm.check_write_ptr(old_value)?;
+    }
+    let new_value = unsafe { new_value.read() };
+    if !new_value.value.is_valid() || !new_value.interval.is_valid() {
+        return Err(SysError::EINVAL);
+    }
+    let inode = proc.get_file(fd)?.inode();
+    let timerfd = inode
+        .as_any_ref()
+        .downcast_ref::<TimerFd>()
+        .ok_or(SysError::EINVAL)?;
+
+    let mut value = new_value.value.to_duration();
+    if flags & TFD_TIMER_ABSTIME != 0 && value != Duration::default() {
+        // all clocks count from the epoch, as in `clock_gettime`
+        let now = TimeSpec::get_epoch().to_duration();
+        // a time already passed expires at once
+        value = value
+            .checked_sub(now)
+            .filter(|dur| *dur != Duration::default())
+            .unwrap_or(Duration::from_nanos(1));
+    }
+    let (remaining, interval) = timerfd.set(value, new_value.interval.to_duration());
+    if !old_value.is_null() {
+        unsafe {
+            *old_value = ITimerSpec {
+                interval: TimeSpec::from_duration(interval),
+                value: TimeSpec::from_duration(remaining),
+            };
+        }
+    }
+    Ok(0)
+}
+
+pub fn sys_timerfd_gettime(fd: usize, curr_value: *mut ITimerSpec) -> SysResult {
+    info!("timerfd_gettime: fd: {}, curr_value: {:?}", fd, curr_value);
+    let mut proc = process();
+    proc.vm.check_write_ptr(curr_value)?;
+    let inode = proc.get_file(fd)?.inode();
+    let timerfd = inode
+        .as_any_ref()
+        .downcast_ref::<TimerFd>()
+        .ok_or(SysError::EINVAL)?;
+    let (remaining, interval) = timerfd.get();
+    unsafe {
+        *curr_value = ITimerSpec {
+            interval: TimeSpec::from_duration(interval),
+            value: TimeSpec::from_duration(remaining),
+        };
+    }
+    Ok(0)
+}
+
+/// Special values in `TimeSpec::nsec` for utimensat
+const UTIME_NOW: u64 = (1 << 30) - 1;
+const UTIME_OMIT: u64 = (1 << 30) - 2;
+
+const CLOCK_REALTIME: usize = 0;
+const CLOCK_MONOTONIC: usize = 1;
+
+const TFD_NONBLOCK: usize = 0o4000;
+const TFD_CLOEXEC: usize = 0o2000000;
+/// The expiration time is absolute instead of relative
+const TFD_TIMER_ABSTIME: usize = 1;
+
 // ignore other fields for now
 #[repr(C)]
 pub struct RUsage {
@@ -6,14 +6,16 @@ use log::*;
 pub static mut TICK: usize = 0;
 
 pub fn uptime_msec() -> usize {
-    unsafe { crate::trap::TICK / crate::consts::USEC_PER_TICK / 1000 }
+    unsafe { crate::trap::TICK * crate::consts::USEC_PER_TICK / 1000 }
 }
 
 pub fn timer() {
+    crate::fs::add_entropy(0);
     if cpu::id() == 0 {
         unsafe {
             TICK += 1;
         }
+        crate::fs::timerfd_tick();
     }
     processor().tick();
 }
@@ -29,6 +31,7 @@ pub fn error(tf: &TrapFrame) -> ! {
 }
 
 pub fn serial(c: char) {
+    crate::fs::add_entropy(c as u64);
     if c == '\r' {
         // in linux, we use '\n' instead
         crate::fs::STDIN.push('\n');